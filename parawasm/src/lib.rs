@@ -9,3 +9,6 @@ pub trait Compiler {
 }
 
 pub mod x86_64;
+
+// Types of the public API, such as `FuncType`, come from the parser
+pub use wasmparser_nostd;
@@ -0,0 +1,438 @@
+use crate::x86_64::context::{slot_size, FunctionContext, ModuleContext};
+use crate::x86_64::Error;
+use crate::x86_64::{float, simd};
+use alloc::format;
+use alloc::vec::Vec;
+use iced_x86::code_asm::{
+    ptr, qword_ptr, r11, r8, r9, rax, rbp, rcx, rdi, rdx, rsi, rsp, xmm0, xmm1, xmm2, xmm3, xmm4,
+    xmm5, xmm6, xmm7, xmmword_ptr, AsmRegister64, AsmRegisterXmm, CodeAssembler,
+};
+use iced_x86::IcedError;
+use wasmparser_nostd::{FuncType, Type};
+
+/// Where a parameter or result is passed
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Location {
+    Integer(AsmRegister64),
+    Float(AsmRegisterXmm),
+    /// All of an XMM register, for v128 values
+    Vector(AsmRegisterXmm),
+    /// At this offset in the stack arguments area for parameters, or in the
+    /// return area for results
+    Stack(u32),
+}
+
+/// Placement of the parameters and results of a function type, following
+/// the System V AMD64 ABI:
+///
+/// * integer and reference parameters go in RDI, RSI, RDX, RCX, R8 and R9,
+///   float and v128 ones in XMM0 to XMM7, and the others on the stack in
+///   8-byte slots, 16-byte aligned ones for v128, the first one at the
+///   lowest address, right above the return address
+/// * up to two scalar results are returned in RAX and RDX, or XMM0 and XMM1,
+///   and a single v128 result in XMM0
+/// * other results are stored in slots of a return area provided by the
+///   caller, laid out like stack parameters, whose address is passed in RDI
+///   ahead of the parameters and returned in RAX
+///
+/// This is what C does with scalar and `__m128i` parameters, and with
+/// a `#[repr(C)]` struct of `u64`, `f64` and `__m128i` fields as the result,
+/// so host functions can be written in Rust with `extern "sysv64"`.
+#[derive(Debug, Clone)]
+pub(crate) struct Signature {
+    pub(crate) params: Vec<Location>,
+    pub(crate) results: Vec<Location>,
+    /// Size of the stack arguments area, a multiple of 16 bytes
+    pub(crate) stack_size: u32,
+    /// Size of the return area, if results are returned in memory
+    pub(crate) return_area: Option<u32>,
+}
+
+const INTEGER_PARAMS: [AsmRegister64; 6] = [rdi, rsi, rdx, rcx, r8, r9];
+const FLOAT_PARAMS: [AsmRegisterXmm; 8] = [xmm0, xmm1, xmm2, xmm3, xmm4, xmm5, xmm6, xmm7];
+
+/// Register class of a value type
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Class {
+    Integer,
+    Float,
+    Vector,
+}
+
+fn class(module: &ModuleContext, ty: Type) -> Result<Class, Error> {
+    match ty {
+        Type::I64 | Type::I32 | Type::FuncRef | Type::ExternRef => Ok(Class::Integer),
+        Type::F32 | Type::F64 => Ok(Class::Float),
+        Type::V128 => Ok(Class::Vector),
+        ty => Err(module.unsupported(format!("{:?} parameter or result", ty))),
+    }
+}
+
+/// Allocates a stack slot for a value of type `ty` at the end of an area of
+/// `size` bytes, returning its offset
+fn stack_slot(size: &mut u32, ty: &Type) -> u32 {
+    let slot = slot_size(ty);
+    let offset = (*size + slot - 1) & !(slot - 1);
+    *size = offset + slot;
+    offset
+}
+
+pub(crate) fn signature(module: &ModuleContext, ty: &FuncType) -> Result<Signature, Error> {
+    let vectors = ty.returns.contains(&Type::V128);
+    let return_area = if ty.returns.len() > 2 || (vectors && ty.returns.len() > 1) {
+        let mut size = 0;
+        for result in ty.returns.iter() {
+            stack_slot(&mut size, result);
+        }
+        Some((size + 15) & !15)
+    } else {
+        None
+    };
+    let mut integers = INTEGER_PARAMS[return_area.map_or(0, |_| 1)..].iter();
+    let mut floats = FLOAT_PARAMS.iter();
+    let mut stack_size = 0;
+    let mut params = Vec::with_capacity(ty.params.len());
+    for param in ty.params.iter() {
+        let register = match class(module, *param)? {
+            Class::Integer => integers.next().map(|reg| Location::Integer(*reg)),
+            Class::Float => floats.next().map(|reg| Location::Float(*reg)),
+            Class::Vector => floats.next().map(|reg| Location::Vector(*reg)),
+        };
+        params
+            .push(register.unwrap_or_else(|| Location::Stack(stack_slot(&mut stack_size, param))));
+    }
+    let mut integers = [rax, rdx].into_iter();
+    let mut floats = [xmm0, xmm1].into_iter();
+    let mut results = Vec::with_capacity(ty.returns.len());
+    let mut return_size = 0;
+    for result in ty.returns.iter() {
+        let class = class(module, *result)?;
+        results.push(match return_area {
+            Some(_) => Location::Stack(stack_slot(&mut return_size, result)),
+            // Two scalar results of the same class or a v128 one at most,
+            // they fit
+            None if class == Class::Integer => Location::Integer(integers.next().unwrap()),
+            None if class == Class::Float => Location::Float(floats.next().unwrap()),
+            None => Location::Vector(floats.next().unwrap()),
+        });
+    }
+    Ok(Signature {
+        params,
+        results,
+        stack_size: (stack_size + 15) & !15,
+        return_area,
+    })
+}
+
+/// Stores the parameters to their locals, and the address of the return
+/// area, if any, to its slot
+pub(crate) fn prologue(
+    assembler: &mut CodeAssembler,
+    function: &FunctionContext,
+    signature: &Signature,
+) -> Result<(), Error> {
+    for (index, location) in signature.params.iter().enumerate() {
+        let (offset, ty) = function.locals[index];
+        match *location {
+            Location::Integer(reg) => assembler.mov(qword_ptr(rbp - offset), reg)?,
+            Location::Float(reg) => assembler.movq(qword_ptr(rbp - offset), reg)?,
+            Location::Vector(reg) => assembler.movdqu(xmmword_ptr(rbp - offset), reg)?,
+            Location::Stack(argument) => {
+                // Past the saved RBP and the return address
+                for word in (0..slot_size(&ty)).step_by(8) {
+                    assembler.mov(r11, qword_ptr(rbp + 16 + argument + word))?;
+                    assembler.mov(qword_ptr(rbp - offset + word), r11)?;
+                }
+            }
+        }
+    }
+    if let Some(offset) = function.return_area {
+        assembler.mov(qword_ptr(rbp - offset), rdi)?;
+    }
+    Ok(())
+}
+
+/// Moves the results, on top of the operand stack, to where the caller
+/// expects them
+pub(crate) fn epilogue(
+    assembler: &mut CodeAssembler,
+    function: &FunctionContext,
+    ty: &FuncType,
+    signature: &Signature,
+) -> Result<(), Error> {
+    if let Some(offset) = function.return_area {
+        assembler.mov(rcx, qword_ptr(rbp - offset))?;
+    }
+    // The last result is on top of the stack
+    for (location, result) in signature.results.iter().zip(ty.returns.iter()).rev() {
+        match *location {
+            Location::Integer(reg) => assembler.pop(reg)?,
+            Location::Float(reg) => {
+                assembler.pop(r11)?;
+                assembler.movq(reg, r11)?;
+            }
+            Location::Vector(reg) => {
+                assembler.movdqu(reg, xmmword_ptr(rsp))?;
+                assembler.add(rsp, 16)?;
+            }
+            Location::Stack(offset) => {
+                for word in (0..slot_size(result)).step_by(8) {
+                    assembler.pop(r11)?;
+                    assembler.mov(qword_ptr(rcx + offset + word), r11)?;
+                }
+            }
+        }
+    }
+    if function.return_area.is_some() {
+        assembler.mov(rax, rcx)?;
+    }
+    Ok(())
+}
+
+/// Calls a function of type `ty` with the call instruction emitted by
+/// `emit`, which must leave the parameter registers alone. Arguments are
+/// taken from the operand stack and replaced by the results.
+pub(crate) fn call<F>(
+    assembler: &mut CodeAssembler,
+    module: &ModuleContext,
+    function: &mut FunctionContext,
+    ty: &FuncType,
+    emit: F,
+) -> Result<(), Error>
+where
+    F: FnOnce(&mut CodeAssembler) -> Result<(), IcedError>,
+{
+    let signature = signature(module, ty)?;
+    if signature.stack_size == 0 && signature.return_area.is_none() {
+        // The last argument is on top of the stack
+        for location in signature.params.iter().rev() {
+            match *location {
+                Location::Integer(reg) => function.pop(assembler, reg)?,
+                Location::Float(reg) => float::pop(assembler, function, reg)?,
+                Location::Vector(reg) => simd::pop(assembler, function, reg)?,
+                // Not without a stack arguments area
+                Location::Stack(_) => (),
+            }
+        }
+        let aligned = function.is_aligned();
+        if !aligned {
+            assembler.sub(rsp, 8)?;
+        }
+        emit(assembler)?;
+        if !aligned {
+            assembler.add(rsp, 8)?;
+        }
+        function.outgoing = function.outgoing.max(8);
+        return push_results(assembler, function, ty, &signature);
+    }
+
+    let first = function.stack.len() - ty.params.len();
+    let arguments: u32 = function.stack[first..].iter().map(slot_size).sum();
+    let return_area = signature.return_area.unwrap_or(0);
+    // Below the operand stack are the stack arguments, then the return area.
+    // Results get copied from there to where the arguments were, the two
+    // areas are kept apart.
+    let results: u32 = ty.returns.iter().map(slot_size).sum();
+    let mut reserved = signature.stack_size + return_area + results.saturating_sub(arguments);
+    // RSP is 16-byte aligned at the call
+    let depth = function.stack_offset(function.stack.len());
+    reserved += (16 - (depth + reserved) % 16) % 16;
+    function.outgoing = function.outgoing.max(reserved);
+    assembler.sub(rsp, reserved as i32)?;
+    for (index, location) in signature.params.iter().enumerate() {
+        let above: u32 = function.stack[first + index + 1..]
+            .iter()
+            .map(slot_size)
+            .sum();
+        let argument = rsp + reserved + above;
+        match *location {
+            Location::Integer(reg) => assembler.mov(reg, qword_ptr(argument))?,
+            Location::Float(reg) => assembler.movq(reg, qword_ptr(argument))?,
+            Location::Vector(reg) => assembler.movdqu(reg, xmmword_ptr(argument))?,
+            Location::Stack(offset) => {
+                for word in (0..slot_size(&ty.params[index])).step_by(8) {
+                    assembler.mov(r11, qword_ptr(argument + word))?;
+                    assembler.mov(qword_ptr(rsp + offset + word), r11)?;
+                }
+            }
+        }
+    }
+    if signature.return_area.is_some() {
+        assembler.lea(rdi, ptr(rsp + signature.stack_size))?;
+    }
+    emit(assembler)?;
+    function.stack.truncate(first);
+    if signature.return_area.is_none() {
+        assembler.add(rsp, (reserved + arguments) as i32)?;
+        return push_results(assembler, function, ty, &signature);
+    }
+    // The first result ends up the deepest on the operand stack
+    let top = reserved + arguments - results;
+    for (location, result) in signature.results.iter().zip(ty.returns.iter()) {
+        function.stack.push(*result);
+        if let Location::Stack(offset) = *location {
+            let slot = top + results + function.stack_offset(first)
+                - function.stack_offset(function.stack.len());
+            for word in (0..slot_size(result)).step_by(8) {
+                assembler.mov(r11, qword_ptr(rsp + signature.stack_size + offset + word))?;
+                assembler.mov(qword_ptr(rsp + slot + word), r11)?;
+            }
+        }
+    }
+    assembler.add(rsp, top as i32)?;
+    Ok(())
+}
+
+/// Calls a function of type `ty` in place of the current one, which has the
+/// same results. Arguments go in registers and in the current function's
+/// stack arguments area, then the frame is torn down and `emit` jumps to
+/// the callee. The callee returns to the caller of the current function, to
+/// the same return area if any.
+///
+/// When the callee takes more stack arguments than the current function
+/// got, the return address and saved RBP are moved down to make room for
+/// the largest area of the module, and the callee returns through the
+/// module's tail call thunk, which gives the caller back its RSP. Frames
+/// returning through it already have that room.
+pub(crate) fn tail_call<F>(
+    assembler: &mut CodeAssembler,
+    module: &mut ModuleContext,
+    function: &mut FunctionContext,
+    ty: &FuncType,
+    emit: F,
+) -> Result<(), Error>
+where
+    F: FnOnce(&mut CodeAssembler) -> Result<(), IcedError>,
+{
+    let signature = signature(module, ty)?;
+    let first = function.stack.len() - ty.params.len();
+    // Stack arguments as (offset from RBP of the operand, offset in the
+    // area, size). Registers are loaded first, moving the frame may
+    // overwrite the operands.
+    let mut stack_arguments = Vec::new();
+    for (index, location) in signature.params.iter().enumerate() {
+        let operand = -(function.stack_offset(first + index + 1) as i32);
+        let argument = rbp + operand;
+        match *location {
+            Location::Integer(reg) => assembler.mov(reg, qword_ptr(argument))?,
+            Location::Float(reg) => assembler.movq(reg, qword_ptr(argument))?,
+            Location::Vector(reg) => assembler.movdqu(reg, xmmword_ptr(argument))?,
+            Location::Stack(offset) => {
+                stack_arguments.push((operand, offset as i32, slot_size(&ty.params[index])))
+            }
+        }
+    }
+    if let Some(offset) = function.return_area {
+        assembler.mov(rdi, qword_ptr(rbp - offset))?;
+    }
+    if signature.stack_size <= function.stack_arguments {
+        for (operand, offset, size) in stack_arguments.iter() {
+            copy(assembler, *operand, 16 + offset, *size)?;
+        }
+    } else {
+        move_frame(assembler, module, function, &stack_arguments, &signature)?;
+    }
+    assembler.mov(rsp, rbp)?;
+    assembler.pop(rbp)?;
+    emit(assembler)?;
+    function.stack.truncate(first);
+    function.reachable = false;
+    Ok(())
+}
+
+/// Copies `size` bytes from `from` to `to`, both offsets from RBP, through
+/// R11
+fn copy(assembler: &mut CodeAssembler, from: i32, to: i32, size: u32) -> Result<(), Error> {
+    for word in (0..size as i32).step_by(8) {
+        assembler.mov(r11, qword_ptr(rbp + from + word))?;
+        assembler.mov(qword_ptr(rbp + to + word), r11)?;
+    }
+    Ok(())
+}
+
+/// Stores the stack arguments of a tail call taking more of them than the
+/// current function got, in an area of [`ModuleContext::stack_arguments`]
+/// bytes, moving the frame down unless it returns through the tail call
+/// thunk already. Above the moved area are kept the return address and the
+/// RSP to return with. Leaves RBP where the callee can take over the frame.
+fn move_frame(
+    assembler: &mut CodeAssembler,
+    module: &mut ModuleContext,
+    function: &mut FunctionContext,
+    stack_arguments: &[(i32, i32, u32)],
+    signature: &Signature,
+) -> Result<(), Error> {
+    let thunk = *module
+        .tail_call_thunk
+        .get_or_insert_with(|| assembler.create_label());
+    let own = function.stack_arguments as i32;
+    let area = module.stack_arguments as i32;
+    let moved = assembler.create_label();
+    let done = assembler.create_label();
+    assembler.lea(r11, ptr(thunk))?;
+    assembler.cmp(qword_ptr(rbp + 8), r11)?;
+    assembler.jne(moved)?;
+    for (operand, offset, size) in stack_arguments.iter() {
+        copy(assembler, *operand, 16 + offset, *size)?;
+    }
+    assembler.jmp(done)?;
+
+    module.bind(assembler, moved);
+    // The arguments are staged below both the operands and the moved frame
+    let depth = function.stack_offset(function.stack.len()) as i32;
+    let staging = depth.max(area + 16 - own) + signature.stack_size as i32;
+    function.outgoing = function.outgoing.max((staging - depth) as u32);
+    assembler.lea(rsp, ptr(rbp - staging))?;
+    for (operand, offset, size) in stack_arguments.iter() {
+        copy(assembler, *operand, offset - staging, *size)?;
+    }
+    let frame = own - area - 16;
+    assembler.mov(r11, qword_ptr(rbp))?;
+    assembler.mov(qword_ptr(rbp + frame), r11)?;
+    assembler.mov(r11, qword_ptr(rbp + 8))?;
+    assembler.mov(qword_ptr(rbp + own), r11)?;
+    assembler.lea(r11, ptr(rbp + 16))?;
+    assembler.mov(qword_ptr(rbp + own + 8), r11)?;
+    for (_, offset, size) in stack_arguments.iter() {
+        copy(assembler, offset - staging, frame + 16 + offset, *size)?;
+    }
+    assembler.lea(r11, ptr(thunk))?;
+    assembler.mov(qword_ptr(rbp + frame + 8), r11)?;
+    assembler.lea(rbp, ptr(rbp + frame))?;
+    module.bind(assembler, done);
+    Ok(())
+}
+
+/// Emits the tail call thunk, if tail calls moved frames: it returns to the
+/// address kept above the stack arguments area, with the RSP kept there
+pub(crate) fn tail_call_thunk(
+    assembler: &mut CodeAssembler,
+    module: &mut ModuleContext,
+) -> Result<(), Error> {
+    if let Some(thunk) = module.tail_call_thunk {
+        let area = module.stack_arguments as i32;
+        module.bind(assembler, thunk);
+        assembler.mov(r11, qword_ptr(rsp + area))?;
+        assembler.mov(rsp, qword_ptr(rsp + area + 8))?;
+        assembler.jmp(r11)?;
+    }
+    Ok(())
+}
+
+fn push_results(
+    assembler: &mut CodeAssembler,
+    function: &mut FunctionContext,
+    ty: &FuncType,
+    signature: &Signature,
+) -> Result<(), Error> {
+    for (location, result) in signature.results.iter().zip(ty.returns.iter()) {
+        match *location {
+            Location::Integer(reg) => function.push(assembler, reg, *result)?,
+            Location::Float(reg) => float::push(assembler, function, reg, *result)?,
+            Location::Vector(reg) => simd::push(assembler, function, reg)?,
+            // Not without a return area
+            Location::Stack(_) => (),
+        }
+    }
+    Ok(())
+}
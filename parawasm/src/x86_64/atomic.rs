@@ -0,0 +1,252 @@
+use crate::x86_64::context::{FunctionContext, ModuleContext};
+use crate::x86_64::memory::{self, NOTIFY, WAIT};
+use crate::x86_64::trap::{self, TrapCode};
+use crate::x86_64::Error;
+use iced_x86::code_asm::{
+    al, ax, byte_ptr, cl, cx, dl, dword_ptr, dx, eax, ecx, edx, qword_ptr, r11, r8, r8d, rax, rcx,
+    rdi, rdx, rsi, rsp, word_ptr, CodeAssembler, CodeLabel,
+};
+use wasmparser_nostd::{MemoryImmediate, Type};
+
+/// Read-modify-write operation of an atomic instruction
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Rmw {
+    Add,
+    Sub,
+    And,
+    Or,
+    Xor,
+    Xchg,
+}
+
+/// Zero-extends the lower `size` bytes of RAX
+fn zero_extend(assembler: &mut CodeAssembler, size: u32) -> Result<(), Error> {
+    match size {
+        1 => assembler.movzx(eax, al)?,
+        2 => assembler.movzx(eax, ax)?,
+        4 => assembler.mov(eax, eax)?,
+        _ => (),
+    }
+    Ok(())
+}
+
+/// Atomic loads. Plain loads are sequentially consistent on x86 since
+/// atomic stores are `XCHG`s.
+pub(crate) fn load(
+    assembler: &mut CodeAssembler,
+    module: &mut ModuleContext,
+    function: &mut FunctionContext,
+    memarg: MemoryImmediate,
+    ty: Type,
+    size: u32,
+) -> Result<(), Error> {
+    memory::address(assembler, module, function, memarg, size, true)?;
+    match size {
+        8 => assembler.mov(rax, qword_ptr(rax))?,
+        4 => assembler.mov(eax, dword_ptr(rax))?,
+        2 => assembler.movzx(eax, word_ptr(rax))?,
+        _ => assembler.movzx(eax, byte_ptr(rax))?,
+    }
+    function.push(assembler, rax, ty)
+}
+
+/// Atomic stores, as `XCHG`s which are implicitly locked
+pub(crate) fn store(
+    assembler: &mut CodeAssembler,
+    module: &mut ModuleContext,
+    function: &mut FunctionContext,
+    memarg: MemoryImmediate,
+    size: u32,
+) -> Result<(), Error> {
+    function.pop(assembler, rdx)?;
+    memory::address(assembler, module, function, memarg, size, true)?;
+    match size {
+        8 => assembler.xchg(qword_ptr(rax), rdx)?,
+        4 => assembler.xchg(dword_ptr(rax), edx)?,
+        2 => assembler.xchg(word_ptr(rax), dx)?,
+        _ => assembler.xchg(byte_ptr(rax), dl)?,
+    }
+    Ok(())
+}
+
+/// Atomic read-modify-write instructions, pushing the old value
+/// zero-extended. Additions, subtractions and exchanges have instructions
+/// of their own, bitwise operations loop on `LOCK CMPXCHG` until no other
+/// write got in between.
+pub(crate) fn rmw(
+    assembler: &mut CodeAssembler,
+    module: &mut ModuleContext,
+    function: &mut FunctionContext,
+    memarg: MemoryImmediate,
+    ty: Type,
+    size: u32,
+    op: Rmw,
+) -> Result<(), Error> {
+    function.pop(assembler, rdx)?;
+    memory::address(assembler, module, function, memarg, size, true)?;
+    match op {
+        Rmw::Xchg => {
+            match size {
+                8 => assembler.xchg(qword_ptr(rax), rdx)?,
+                4 => assembler.xchg(dword_ptr(rax), edx)?,
+                2 => assembler.xchg(word_ptr(rax), dx)?,
+                _ => assembler.xchg(byte_ptr(rax), dl)?,
+            }
+            assembler.mov(rax, rdx)?;
+        }
+        Rmw::Add | Rmw::Sub => {
+            if op == Rmw::Sub {
+                assembler.neg(rdx)?;
+            }
+            match size {
+                8 => assembler.lock().xadd(qword_ptr(rax), rdx)?,
+                4 => assembler.lock().xadd(dword_ptr(rax), edx)?,
+                2 => assembler.lock().xadd(word_ptr(rax), dx)?,
+                _ => assembler.lock().xadd(byte_ptr(rax), dl)?,
+            }
+            assembler.mov(rax, rdx)?;
+        }
+        Rmw::And | Rmw::Or | Rmw::Xor => {
+            let retry = assembler.create_label();
+            assembler.mov(rsi, rax)?;
+            match size {
+                8 => assembler.mov(rax, qword_ptr(rsi))?,
+                4 => assembler.mov(eax, dword_ptr(rsi))?,
+                2 => assembler.movzx(eax, word_ptr(rsi))?,
+                _ => assembler.movzx(eax, byte_ptr(rsi))?,
+            }
+            // A failed CMPXCHG leaves the current value in RAX. Narrow ones
+            // only write the lower bytes, the upper ones stay zero.
+            module.bind(assembler, retry);
+            assembler.mov(rcx, rax)?;
+            match op {
+                Rmw::And => assembler.and(rcx, rdx)?,
+                Rmw::Or => assembler.or(rcx, rdx)?,
+                _ => assembler.xor(rcx, rdx)?,
+            }
+            match size {
+                8 => assembler.lock().cmpxchg(qword_ptr(rsi), rcx)?,
+                4 => assembler.lock().cmpxchg(dword_ptr(rsi), ecx)?,
+                2 => assembler.lock().cmpxchg(word_ptr(rsi), cx)?,
+                _ => assembler.lock().cmpxchg(byte_ptr(rsi), cl)?,
+            }
+            assembler.jne(retry)?;
+        }
+    }
+    zero_extend(assembler, size)?;
+    function.push(assembler, rax, ty)
+}
+
+/// Atomic compare-exchange, pushing the old value zero-extended. Narrow
+/// ones only compare the lower bytes of the expected value.
+pub(crate) fn cmpxchg(
+    assembler: &mut CodeAssembler,
+    module: &mut ModuleContext,
+    function: &mut FunctionContext,
+    memarg: MemoryImmediate,
+    ty: Type,
+    size: u32,
+) -> Result<(), Error> {
+    function.pop(assembler, rdx)?;
+    function.pop(assembler, rsi)?;
+    memory::address(assembler, module, function, memarg, size, true)?;
+    assembler.mov(rcx, rax)?;
+    assembler.mov(rax, rsi)?;
+    match size {
+        8 => assembler.lock().cmpxchg(qword_ptr(rcx), rdx)?,
+        4 => assembler.lock().cmpxchg(dword_ptr(rcx), edx)?,
+        2 => assembler.lock().cmpxchg(word_ptr(rcx), dx)?,
+        _ => assembler.lock().cmpxchg(byte_ptr(rcx), dl)?,
+    }
+    zero_extend(assembler, size)?;
+    function.push(assembler, rax, ty)
+}
+
+/// `atomic.fence`
+pub(crate) fn fence(assembler: &mut CodeAssembler) -> Result<(), Error> {
+    assembler.mfence()?;
+    Ok(())
+}
+
+/// Calls the hook at offset `hook` of the descriptor in RDI, or jumps to
+/// `missing` if there is none
+fn call_hook(
+    assembler: &mut CodeAssembler,
+    function: &FunctionContext,
+    hook: i32,
+    missing: CodeLabel,
+) -> Result<(), Error> {
+    assembler.mov(r11, qword_ptr(rdi + hook))?;
+    assembler.test(r11, r11)?;
+    assembler.jz(missing)?;
+    let aligned = function.is_aligned();
+    if !aligned {
+        assembler.sub(rsp, 8)?;
+    }
+    assembler.call(r11)?;
+    if !aligned {
+        assembler.add(rsp, 8)?;
+    }
+    Ok(())
+}
+
+/// `memory.atomic.wait32` and `memory.atomic.wait64`, calling the memory's
+/// [`WaitHook`](crate::x86_64::WaitHook)
+pub(crate) fn wait(
+    assembler: &mut CodeAssembler,
+    module: &mut ModuleContext,
+    function: &mut FunctionContext,
+    memarg: MemoryImmediate,
+    size: u32,
+) -> Result<(), Error> {
+    let memory_type = memory::lookup(module, memarg.memory);
+    function.pop(assembler, r8)?;
+    function.pop(assembler, rdx)?;
+    memory::address(assembler, module, function, memarg, size, true)?;
+    if memory_type.shared {
+        let missing = assembler.create_label();
+        let done = assembler.create_label();
+        if size == 4 {
+            assembler.mov(edx, edx)?;
+        }
+        assembler.mov(rcx, r8)?;
+        assembler.mov(r8d, size)?;
+        assembler.mov(rsi, rax)?;
+        memory::load_descriptor(assembler, module, memarg.memory, rdi)?;
+        call_hook(assembler, function, WAIT, missing)?;
+        assembler.mov(eax, eax)?;
+        assembler.jmp(done)?;
+        module.bind(assembler, missing);
+        trap::trap(assembler, module, TrapCode::CannotWait)?;
+        module.bind(assembler, done);
+    } else {
+        // Only other agents could notify the waiter
+        trap::trap(assembler, module, TrapCode::CannotWait)?;
+    }
+    function.push(assembler, rax, Type::I32)
+}
+
+/// `memory.atomic.notify`, calling the memory's
+/// [`NotifyHook`](crate::x86_64::NotifyHook). Without one nothing can be
+/// waiting, so no waiter is woken up.
+pub(crate) fn notify(
+    assembler: &mut CodeAssembler,
+    module: &mut ModuleContext,
+    function: &mut FunctionContext,
+    memarg: MemoryImmediate,
+) -> Result<(), Error> {
+    let missing = assembler.create_label();
+    let done = assembler.create_label();
+    function.pop(assembler, rdx)?;
+    memory::address(assembler, module, function, memarg, 4, true)?;
+    assembler.mov(edx, edx)?;
+    assembler.mov(rsi, rax)?;
+    memory::load_descriptor(assembler, module, memarg.memory, rdi)?;
+    call_hook(assembler, function, NOTIFY, missing)?;
+    assembler.mov(eax, eax)?;
+    assembler.jmp(done)?;
+    module.bind(assembler, missing);
+    assembler.xor(eax, eax)?;
+    module.bind(assembler, done);
+    function.push(assembler, rax, Type::I32)
+}
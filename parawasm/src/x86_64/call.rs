@@ -0,0 +1,106 @@
+use crate::x86_64::abi;
+use crate::x86_64::context::{FunctionContext, ModuleContext};
+use crate::x86_64::exception;
+use crate::x86_64::table;
+use crate::x86_64::trap::{trap_unless, TrapCode};
+use crate::x86_64::Error;
+use iced_x86::code_asm::{eax, ptr, qword_ptr, r10, r11, rax, CodeAssembler, CodeLabel};
+use wasmparser_nostd::FuncType;
+
+/// Calls a function of type `ty`, at `label` or else at the address in R10.
+/// Tail calls reuse the frame, see [`abi::tail_call`].
+fn call(
+    assembler: &mut CodeAssembler,
+    module: &mut ModuleContext,
+    function: &mut FunctionContext,
+    ty: &FuncType,
+    label: Option<CodeLabel>,
+    tail: bool,
+) -> Result<(), Error> {
+    if tail {
+        let depth = function.frames.len() as u32 - 1;
+        exception::unlink(assembler, function, depth)?;
+        return abi::tail_call(assembler, module, function, ty, |a| match label {
+            Some(label) => a.jmp(label),
+            None => a.jmp(r10),
+        });
+    }
+    abi::call(assembler, module, function, ty, |a| match label {
+        Some(label) => a.call(label),
+        None => a.call(r10),
+    })
+}
+
+/// `call` and `return_call`
+pub(crate) fn direct(
+    assembler: &mut CodeAssembler,
+    module: &mut ModuleContext,
+    function: &mut FunctionContext,
+    function_index: u32,
+    tail: bool,
+) -> Result<(), Error> {
+    let called_function_type = module
+        .function_type(function_index)
+        .cloned()
+        .ok_or_else(|| module.unsupported("call to a function without a type"))?;
+    let label = match module.got.get(&function_index) {
+        Some(label) => Some(*label),
+        None => {
+            let import_label = module
+                .ils
+                .get(&function_index)
+                .ok_or_else(|| module.unsupported("call to an unknown function"))?;
+            assembler.mov(r10, ptr(*import_label))?;
+            None
+        }
+    };
+    call(
+        assembler,
+        module,
+        function,
+        &called_function_type,
+        label,
+        tail,
+    )
+}
+
+/// `call_indirect` and `return_call_indirect`: traps if the index is out of
+/// the table's bounds, if the entry is null or if the function's signature
+/// is not `type_index`
+pub(crate) fn indirect(
+    assembler: &mut CodeAssembler,
+    module: &mut ModuleContext,
+    function: &mut FunctionContext,
+    type_index: u32,
+    table_index: u32,
+    tail: bool,
+) -> Result<(), Error> {
+    let called_function_type = module.function_typedefs[&type_index].clone();
+    let descriptor = module.tables[table_index as usize].descriptor;
+    function.pop(assembler, rax)?;
+    assembler.mov(eax, eax)?;
+    assembler.lea(r11, ptr(descriptor))?;
+    assembler.cmp(rax, qword_ptr(r11 + table::LENGTH))?;
+    trap_unless(assembler, module, TrapCode::TableOutOfBounds, |a, ok| {
+        a.jb(ok)
+    })?;
+    assembler.mov(r11, qword_ptr(r11 + table::BASE))?;
+    assembler.mov(r10, qword_ptr(r11 + rax * 8))?;
+    assembler.test(r10, r10)?;
+    trap_unless(assembler, module, TrapCode::IndirectCallToNull, |a, ok| {
+        a.jnz(ok)
+    })?;
+    let record = table::signature_record(module, type_index);
+    assembler.mov(rax, qword_ptr(record))?;
+    assembler.cmp(qword_ptr(r10 + table::SIGNATURE), rax)?;
+    trap_unless(assembler, module, TrapCode::BadSignature, |a, ok| a.je(ok))?;
+    assembler.mov(r10, qword_ptr(r10 + table::CODE))?;
+    call(
+        assembler,
+        module,
+        function,
+        &called_function_type,
+        None,
+        tail,
+    )
+}
@@ -0,0 +1,184 @@
+use crate::x86_64::context::{FunctionContext, ModuleContext};
+use crate::x86_64::{simd, Error};
+use iced_x86::code_asm::{al, eax, ecx, rax, rcx, AsmRegister64, CodeAssembler, CodeLabel};
+use wasmparser_nostd::Type;
+
+/// Outcome of an integer comparison, as x86 condition codes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Condition {
+    Eq,
+    Ne,
+    LtS,
+    LtU,
+    GtS,
+    GtU,
+    LeS,
+    LeU,
+    GeS,
+    GeU,
+}
+
+impl Condition {
+    pub(crate) fn negate(self) -> Self {
+        match self {
+            Condition::Eq => Condition::Ne,
+            Condition::Ne => Condition::Eq,
+            Condition::LtS => Condition::GeS,
+            Condition::LtU => Condition::GeU,
+            Condition::GtS => Condition::LeS,
+            Condition::GtU => Condition::LeU,
+            Condition::LeS => Condition::GtS,
+            Condition::LeU => Condition::GtU,
+            Condition::GeS => Condition::LtS,
+            Condition::GeU => Condition::LtU,
+        }
+    }
+}
+
+/// Jumps to `label` if `condition` holds
+pub(crate) fn jump(
+    assembler: &mut CodeAssembler,
+    condition: Condition,
+    label: CodeLabel,
+) -> Result<(), Error> {
+    match condition {
+        Condition::Eq => assembler.je(label)?,
+        Condition::Ne => assembler.jne(label)?,
+        Condition::LtS => assembler.jl(label)?,
+        Condition::LtU => assembler.jb(label)?,
+        Condition::GtS => assembler.jg(label)?,
+        Condition::GtU => assembler.ja(label)?,
+        Condition::LeS => assembler.jle(label)?,
+        Condition::LeU => assembler.jbe(label)?,
+        Condition::GeS => assembler.jge(label)?,
+        Condition::GeU => assembler.jae(label)?,
+    }
+    Ok(())
+}
+
+/// Moves `src` into `dst` if `condition` holds
+fn cmov(
+    assembler: &mut CodeAssembler,
+    condition: Condition,
+    dst: AsmRegister64,
+    src: AsmRegister64,
+) -> Result<(), Error> {
+    match condition {
+        Condition::Eq => assembler.cmove(dst, src)?,
+        Condition::Ne => assembler.cmovne(dst, src)?,
+        Condition::LtS => assembler.cmovl(dst, src)?,
+        Condition::LtU => assembler.cmovb(dst, src)?,
+        Condition::GtS => assembler.cmovg(dst, src)?,
+        Condition::GtU => assembler.cmova(dst, src)?,
+        Condition::LeS => assembler.cmovle(dst, src)?,
+        Condition::LeU => assembler.cmovbe(dst, src)?,
+        Condition::GeS => assembler.cmovge(dst, src)?,
+        Condition::GeU => assembler.cmovae(dst, src)?,
+    }
+    Ok(())
+}
+
+/// Sets AL to 1 if `condition` holds, to 0 otherwise
+fn set(assembler: &mut CodeAssembler, condition: Condition) -> Result<(), Error> {
+    match condition {
+        Condition::Eq => assembler.sete(al)?,
+        Condition::Ne => assembler.setne(al)?,
+        Condition::LtS => assembler.setl(al)?,
+        Condition::LtU => assembler.setb(al)?,
+        Condition::GtS => assembler.setg(al)?,
+        Condition::GtU => assembler.seta(al)?,
+        Condition::LeS => assembler.setle(al)?,
+        Condition::LeU => assembler.setbe(al)?,
+        Condition::GeS => assembler.setge(al)?,
+        Condition::GeU => assembler.setae(al)?,
+    }
+    Ok(())
+}
+
+/// Compares the two operands on top of the stack.
+///
+/// The boolean result is not materialized right away: the flags are kept
+/// so that a following `if`, `br_if` or `select` can use them directly.
+pub(crate) fn compare(
+    assembler: &mut CodeAssembler,
+    function: &mut FunctionContext,
+    ty: Type,
+    condition: Condition,
+) -> Result<(), Error> {
+    function.pop(assembler, rcx)?;
+    function.pop(assembler, rax)?;
+    match ty {
+        Type::I32 => assembler.cmp(eax, ecx)?,
+        _ => assembler.cmp(rax, rcx)?,
+    }
+    function.stack.push(Type::I32);
+    function.condition = Some(condition);
+    Ok(())
+}
+
+/// Compares the operand on top of the stack with zero
+pub(crate) fn eqz(
+    assembler: &mut CodeAssembler,
+    function: &mut FunctionContext,
+    ty: Type,
+) -> Result<(), Error> {
+    function.pop(assembler, rax)?;
+    match ty {
+        Type::I32 => assembler.test(eax, eax)?,
+        _ => assembler.test(rax, rax)?,
+    }
+    function.stack.push(Type::I32);
+    function.condition = Some(Condition::Eq);
+    Ok(())
+}
+
+/// Pushes the pending comparison result, if any, as a 0 or 1 i32
+pub(crate) fn materialize(
+    assembler: &mut CodeAssembler,
+    function: &mut FunctionContext,
+) -> Result<(), Error> {
+    if let Some(condition) = function.condition.take() {
+        set(assembler, condition)?;
+        assembler.movzx(eax, al)?;
+        // The result is already accounted for on the operand stack
+        assembler.push(rax)?;
+    }
+    Ok(())
+}
+
+/// Pops the i32 on top of the stack, returning the condition under which
+/// it is true. Flags of a pending comparison are used as is, any other
+/// value is tested against zero.
+pub(crate) fn take_condition(
+    assembler: &mut CodeAssembler,
+    function: &mut FunctionContext,
+) -> Result<Condition, Error> {
+    match function.condition.take() {
+        Some(condition) => {
+            function.stack.pop();
+            Ok(condition)
+        }
+        None => {
+            function.pop(assembler, rax)?;
+            assembler.test(eax, eax)?;
+            Ok(Condition::Ne)
+        }
+    }
+}
+
+/// `select`: picks the first or the second operand depending on the condition
+pub(crate) fn select(
+    assembler: &mut CodeAssembler,
+    module: &mut ModuleContext,
+    function: &mut FunctionContext,
+) -> Result<(), Error> {
+    let condition = take_condition(assembler, function)?;
+    if function.stack.last() == Some(&Type::V128) {
+        return simd::select(assembler, module, function, condition);
+    }
+    function.pop(assembler, rcx)?;
+    let ty = *function.stack.last().unwrap();
+    function.pop(assembler, rax)?;
+    cmov(assembler, condition.negate(), rax, rcx)?;
+    function.push(assembler, rax, ty)
+}
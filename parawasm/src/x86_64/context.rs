@@ -0,0 +1,234 @@
+use crate::x86_64::compare::Condition;
+use crate::x86_64::control::ControlFrame;
+use crate::x86_64::cpu::CpuFeatures;
+use crate::x86_64::data::DataSegment;
+use crate::x86_64::exception::TagSlot;
+use crate::x86_64::global::{GlobalSlot, Initializer};
+use crate::x86_64::memory::MemorySlot;
+use crate::x86_64::table::{ElementSegment, TableSlot};
+use crate::x86_64::trap::Trap;
+use crate::x86_64::{EncodingSize, Error};
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::vec::Vec;
+use iced_x86::code_asm::{AsmRegister64, CodeAssembler, CodeLabel};
+use wasmparser_nostd::{FuncType, Type};
+
+/// Module-wide state shared by all function bodies during code generation
+pub(crate) struct ModuleContext {
+    /// Labels of function bodies defined in this module
+    pub(crate) got: BTreeMap<u32, CodeLabel>,
+    /// Labels of imported function address slots
+    pub(crate) ils: BTreeMap<u32, CodeLabel>,
+    /// Labels of the [`FunctionReference`](crate::x86_64::FunctionReference)
+    /// of every function
+    pub(crate) function_references: BTreeMap<u32, CodeLabel>,
+    pub(crate) function_typedefs: BTreeMap<u32, FuncType>,
+    pub(crate) function_types: BTreeMap<u32, u32>,
+    /// Size of the stack arguments area tail calls move frames down to make
+    /// room for, the largest of the module's function types
+    pub(crate) stack_arguments: u32,
+    /// Label of the code frames moved by tail calls return through
+    pub(crate) tail_call_thunk: Option<CodeLabel>,
+    /// Labels of the signature records, one per distinct function type
+    pub(crate) signatures: Vec<(FuncType, CodeLabel)>,
+    /// Memories, by memory index
+    pub(crate) memories: Vec<MemorySlot>,
+    /// Global slots, by global index
+    pub(crate) globals: Vec<GlobalSlot>,
+    /// Globals initialized from other globals or function references on
+    /// instantiation, as (global index, initial value)
+    pub(crate) global_initializers: Vec<(u32, Initializer)>,
+    /// Data segments, by segment index
+    pub(crate) data_segments: Vec<DataSegment>,
+    /// Active data segments, as (segment index, memory index, offset)
+    pub(crate) active_data: Vec<(u32, u32, Initializer)>,
+    /// Tables, by table index
+    pub(crate) tables: Vec<TableSlot>,
+    /// Active element segments, as (table index, offset, items)
+    pub(crate) active_elements: Vec<(u32, Initializer, Vec<Initializer>)>,
+    /// Element segments, by segment index
+    pub(crate) element_segments: Vec<ElementSegment>,
+    /// Labels to be bound to instruction indices once the code is optimized
+    pub(crate) label_indices: Vec<(usize, CodeLabel)>,
+    /// Labels of trapping instructions and what they stand for
+    pub(crate) traps: Vec<(CodeLabel, Trap)>,
+    /// Function code is being generated for, `None` for the module initializer
+    pub(crate) function_index: Option<u32>,
+    /// Offset in the WebAssembly binary of the instruction code is being
+    /// generated for
+    pub(crate) wasm_offset: usize,
+    /// Label of the stack limit slot
+    pub(crate) stack_limit: CodeLabel,
+    /// Label of the slot of the address of the
+    /// [`ExceptionContextHook`](crate::x86_64::ExceptionContextHook) in use
+    pub(crate) exception_context_hook: CodeLabel,
+    /// Label of the module's own exception context
+    pub(crate) default_exception_context: CodeLabel,
+    /// Label of the module's own hook, returning its own context
+    pub(crate) default_exception_context_hook: CodeLabel,
+    /// Tags, by tag index
+    pub(crate) tags: Vec<TagSlot>,
+    /// Labels of 16-byte constants used by vector code, emitted after the
+    /// module initializer
+    pub(crate) constants: BTreeMap<u128, CodeLabel>,
+    /// Instruction set extensions code may use
+    pub(crate) cpu: CpuFeatures,
+    /// Whether relaxed SIMD instructions take their deterministic forms
+    pub(crate) deterministic: bool,
+}
+
+impl ModuleContext {
+    pub(crate) fn new(
+        assembler: &mut CodeAssembler,
+        cpu: CpuFeatures,
+        deterministic: bool,
+    ) -> Self {
+        Self {
+            got: BTreeMap::new(),
+            ils: BTreeMap::new(),
+            function_references: BTreeMap::new(),
+            function_typedefs: BTreeMap::new(),
+            function_types: BTreeMap::new(),
+            stack_arguments: 0,
+            tail_call_thunk: None,
+            signatures: Vec::new(),
+            memories: Vec::new(),
+            globals: Vec::new(),
+            global_initializers: Vec::new(),
+            data_segments: Vec::new(),
+            active_data: Vec::new(),
+            tables: Vec::new(),
+            active_elements: Vec::new(),
+            element_segments: Vec::new(),
+            label_indices: Vec::new(),
+            traps: Vec::new(),
+            function_index: None,
+            wasm_offset: 0,
+            stack_limit: assembler.create_label(),
+            exception_context_hook: assembler.create_label(),
+            default_exception_context: assembler.create_label(),
+            default_exception_context_hook: assembler.create_label(),
+            tags: Vec::new(),
+            constants: BTreeMap::new(),
+            cpu,
+            deterministic,
+        }
+    }
+
+    /// [`Error::Unsupported`] for `feature`, used where code is being
+    /// generated
+    pub(crate) fn unsupported(&self, feature: impl Into<String>) -> Error {
+        Error::unsupported(feature, self.function_index, self.wasm_offset)
+    }
+
+    /// Binds `label` to the next instruction emitted by `assembler`
+    pub(crate) fn bind(&mut self, assembler: &CodeAssembler, label: CodeLabel) {
+        self.label_indices
+            .push((assembler.instructions().len(), label));
+    }
+
+    /// Returns the type of a function from the function index space
+    pub(crate) fn function_type(&self, function_index: u32) -> Option<&FuncType> {
+        self.function_types
+            .get(&function_index)
+            .and_then(|t| self.function_typedefs.get(t))
+    }
+}
+
+/// Size of the stack slot occupied by a value of type `ty`, be it
+/// a local or an operand stack entry. Slots are multiples of 8 bytes.
+pub(crate) fn slot_size(ty: &Type) -> u32 {
+    (ty.encoding_size() + 7) & !7
+}
+
+/// State of the function body being compiled
+///
+/// Frame layout below RBP is: locals (parameters first), then the operand
+/// stack, which grows down with RSP. Since every value occupies a slot of
+/// known size, the position of every operand is known statically.
+pub(crate) struct FunctionContext {
+    /// Offsets (below RBP) and types of parameters and locals
+    pub(crate) locals: Vec<(u32, Type)>,
+    /// Size of the locals area
+    pub(crate) locals_size: u32,
+    /// Types of the values on the operand stack, top last
+    pub(crate) stack: Vec<Type>,
+    /// Control frames, innermost last. The outermost frame is the function body.
+    pub(crate) frames: Vec<ControlFrame>,
+    /// Whether the code being generated can be reached
+    pub(crate) reachable: bool,
+    /// Comparison whose result is on top of the operand stack, but is only
+    /// held in the flags so far
+    pub(crate) condition: Option<Condition>,
+    /// Offset below RBP of the slot keeping the address of the return area,
+    /// for functions returning results in memory
+    pub(crate) return_area: Option<u32>,
+    /// Most stack reserved below the operand stack by a call
+    pub(crate) outgoing: u32,
+    /// Size of the area the function's stack arguments are in
+    pub(crate) stack_arguments: u32,
+    /// Stack taken by the handler records of `try` blocks and the exceptions
+    /// kept by their clauses, which the validator doesn't know about
+    pub(crate) hidden: u32,
+}
+
+impl FunctionContext {
+    pub(crate) fn new() -> Self {
+        Self {
+            locals: Vec::new(),
+            locals_size: 0,
+            stack: Vec::new(),
+            frames: Vec::new(),
+            reachable: true,
+            condition: None,
+            return_area: None,
+            outgoing: 0,
+            stack_arguments: 0,
+            hidden: 0,
+        }
+    }
+
+    /// Adds a parameter or a local, returning its offset below RBP
+    pub(crate) fn add_local(&mut self, ty: Type) -> u32 {
+        self.locals_size += slot_size(&ty);
+        self.locals.push((self.locals_size, ty));
+        self.locals_size
+    }
+
+    /// Size of the locals area, keeping RSP 16-byte aligned
+    pub(crate) fn frame_size(&self) -> u32 {
+        (self.locals_size + 15) & !15
+    }
+
+    /// Offset below RBP of the end of the first `depth` operand stack values
+    pub(crate) fn stack_offset(&self, depth: usize) -> u32 {
+        self.frame_size() + self.stack[..depth].iter().map(slot_size).sum::<u32>()
+    }
+
+    /// Whether RSP is 16-byte aligned, as required at call sites
+    pub(crate) fn is_aligned(&self) -> bool {
+        self.stack_offset(self.stack.len()) & 15 == 0
+    }
+
+    pub(crate) fn push(
+        &mut self,
+        assembler: &mut CodeAssembler,
+        reg: AsmRegister64,
+        ty: Type,
+    ) -> Result<(), Error> {
+        assembler.push(reg)?;
+        self.stack.push(ty);
+        Ok(())
+    }
+
+    pub(crate) fn pop(
+        &mut self,
+        assembler: &mut CodeAssembler,
+        reg: AsmRegister64,
+    ) -> Result<(), Error> {
+        assembler.pop(reg)?;
+        self.stack.pop();
+        Ok(())
+    }
+}
@@ -0,0 +1,290 @@
+use crate::x86_64::compare::{self, Condition};
+use crate::x86_64::context::{slot_size, FunctionContext, ModuleContext};
+use crate::x86_64::exception;
+use crate::x86_64::Error;
+use alloc::vec::Vec;
+use iced_x86::code_asm::{eax, ptr, qword_ptr, r11, r11d, rax, rbp, rsp, CodeAssembler, CodeLabel};
+use wasmparser_nostd::{BrTable, Type, TypeOrFuncType};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum FrameKind {
+    Function,
+    Block,
+    Loop,
+    If,
+    Else,
+    Try,
+    Catch,
+    CatchAll,
+}
+
+/// A structured control instruction (or the function body itself) being compiled
+pub(crate) struct ControlFrame {
+    pub(crate) kind: FrameKind,
+    pub(crate) params: Vec<Type>,
+    pub(crate) results: Vec<Type>,
+    /// Operand stack depth at frame entry, not counting the parameters
+    pub(crate) height: usize,
+    /// Branch target: loop header for loops, end of the frame otherwise
+    pub(crate) label: CodeLabel,
+    /// Start of the `else` arm, while the `then` arm of an `if` is compiled.
+    /// For `try`, the landing pad while the body is compiled, then the
+    /// check of the next `catch` clause.
+    pub(crate) else_label: Option<CodeLabel>,
+    /// The frame was entered in unreachable code, no code is generated for it
+    pub(crate) dead: bool,
+    /// The frame is a `try` whose body is being compiled, with its handler
+    /// record right above `height`
+    pub(crate) handler: bool,
+}
+
+impl ControlFrame {
+    pub(crate) fn function(label: CodeLabel, results: Vec<Type>) -> Self {
+        Self {
+            kind: FrameKind::Function,
+            params: Vec::new(),
+            results,
+            height: 0,
+            label,
+            else_label: None,
+            dead: false,
+            handler: false,
+        }
+    }
+
+    /// Types of the values carried by a branch targeting this frame
+    pub(crate) fn branch_types(&self) -> &[Type] {
+        match self.kind {
+            FrameKind::Loop => &self.params,
+            _ => &self.results,
+        }
+    }
+}
+
+fn block_type(module: &ModuleContext, ty: TypeOrFuncType) -> (Vec<Type>, Vec<Type>) {
+    match ty {
+        TypeOrFuncType::Type(Type::EmptyBlockType) => (Vec::new(), Vec::new()),
+        TypeOrFuncType::Type(ty) => (Vec::new(), alloc::vec![ty]),
+        TypeOrFuncType::FuncType(index) => {
+            // Type index has been checked by the validator
+            let func_type = module.function_typedefs.get(&index).unwrap();
+            (func_type.params.to_vec(), func_type.returns.to_vec())
+        }
+    }
+}
+
+/// Opens a `block`, `loop` or `if` frame. For `if`, `condition` tells when
+/// the `then` arm is taken.
+pub(crate) fn enter(
+    assembler: &mut CodeAssembler,
+    module: &mut ModuleContext,
+    function: &mut FunctionContext,
+    kind: FrameKind,
+    ty: TypeOrFuncType,
+    condition: Option<Condition>,
+) -> Result<(), Error> {
+    let (params, results) = block_type(module, ty);
+    let mut frame = ControlFrame {
+        kind,
+        height: function.stack.len().saturating_sub(params.len()),
+        params,
+        results,
+        label: assembler.create_label(),
+        else_label: None,
+        dead: !function.reachable,
+        handler: false,
+    };
+    if !frame.dead {
+        match kind {
+            FrameKind::Loop => module.bind(assembler, frame.label),
+            FrameKind::If => {
+                let else_label = assembler.create_label();
+                let condition = condition.unwrap_or(Condition::Ne);
+                compare::jump(assembler, condition.negate(), else_label)?;
+                frame.else_label = Some(else_label);
+            }
+            _ => (),
+        }
+    }
+    function.frames.push(frame);
+    Ok(())
+}
+
+pub(crate) fn else_(
+    assembler: &mut CodeAssembler,
+    module: &mut ModuleContext,
+    function: &mut FunctionContext,
+) -> Result<(), Error> {
+    let reachable = function.reachable;
+    let frame = function.frames.last_mut().unwrap();
+    if frame.dead {
+        return Ok(());
+    }
+    if reachable {
+        assembler.jmp(frame.label)?;
+    }
+    if let Some(else_label) = frame.else_label.take() {
+        module.bind(assembler, else_label);
+    }
+    frame.kind = FrameKind::Else;
+    function.stack.truncate(frame.height);
+    function.stack.extend_from_slice(&frame.params);
+    function.reachable = true;
+    Ok(())
+}
+
+pub(crate) fn end(
+    assembler: &mut CodeAssembler,
+    module: &mut ModuleContext,
+    function: &mut FunctionContext,
+) -> Result<(), Error> {
+    match function.frames.last().unwrap().kind {
+        FrameKind::Try | FrameKind::Catch | FrameKind::CatchAll => {
+            exception::end(assembler, module, function)?
+        }
+        _ => (),
+    }
+    let frame = function.frames.pop().unwrap();
+    if frame.dead {
+        return Ok(());
+    }
+    // `if` without `else` passes its parameters through as results
+    if let Some(else_label) = frame.else_label {
+        module.bind(assembler, else_label);
+    }
+    if frame.kind != FrameKind::Loop {
+        module.bind(assembler, frame.label);
+    }
+    function.stack.truncate(frame.height);
+    function.stack.extend_from_slice(&frame.results);
+    function.reachable = true;
+    Ok(())
+}
+
+/// Whether a branch to the frame at `relative_depth` has to move operands,
+/// or leaves a `try` body
+fn needs_unwind(function: &FunctionContext, relative_depth: u32) -> bool {
+    let first = function.frames.len() - 1 - relative_depth as usize;
+    let frame = &function.frames[first];
+    function.stack.len() - frame.branch_types().len() != frame.height
+        || function.frames[first..].iter().any(|frame| frame.handler)
+}
+
+/// Moves the values carried by a branch to the frame at `relative_depth`
+/// right above the frame's entry height and resets RSP accordingly. The
+/// handlers of the `try` bodies left are unlinked.
+///
+/// Returns the label to jump to.
+pub(crate) fn unwind(
+    assembler: &mut CodeAssembler,
+    function: &FunctionContext,
+    relative_depth: u32,
+) -> Result<CodeLabel, Error> {
+    exception::unlink(assembler, function, relative_depth)?;
+    let frame = &function.frames[function.frames.len() - 1 - relative_depth as usize];
+    let from = function.stack.len() - frame.branch_types().len();
+    if from != frame.height {
+        // Copying bottom-up never overwrites a value that is yet to be copied,
+        // as the destination is always above the source
+        let mut dst = function.stack_offset(frame.height);
+        for index in from..function.stack.len() {
+            let size = slot_size(&function.stack[index]);
+            let src = function.stack_offset(index + 1);
+            dst += size;
+            for word in (0..size).step_by(8) {
+                assembler.mov(rax, qword_ptr(rbp - src + word))?;
+                assembler.mov(qword_ptr(rbp - dst + word), rax)?;
+            }
+        }
+        assembler.lea(rsp, ptr(rbp - dst))?;
+    }
+    Ok(frame.label)
+}
+
+pub(crate) fn branch(
+    assembler: &mut CodeAssembler,
+    function: &mut FunctionContext,
+    relative_depth: u32,
+) -> Result<(), Error> {
+    let label = unwind(assembler, function, relative_depth)?;
+    assembler.jmp(label)?;
+    function.reachable = false;
+    Ok(())
+}
+
+/// Branches if `condition` holds
+pub(crate) fn branch_if(
+    assembler: &mut CodeAssembler,
+    module: &mut ModuleContext,
+    function: &mut FunctionContext,
+    relative_depth: u32,
+    condition: Condition,
+) -> Result<(), Error> {
+    if needs_unwind(function, relative_depth) {
+        let skip = assembler.create_label();
+        compare::jump(assembler, condition.negate(), skip)?;
+        let label = unwind(assembler, function, relative_depth)?;
+        assembler.jmp(label)?;
+        module.bind(assembler, skip);
+    } else {
+        let label = function.frames[function.frames.len() - 1 - relative_depth as usize].label;
+        compare::jump(assembler, condition, label)?;
+    }
+    Ok(())
+}
+
+/// Branches to the target selected by EAX.
+///
+/// Dispatch goes through a table of fixed-size `lea r11, [rip + target]; jmp r11`
+/// entries, which keeps the code position-independent. Targets that need
+/// operands moved get a stub that does so before jumping to the frame's label.
+pub(crate) fn branch_table(
+    assembler: &mut CodeAssembler,
+    module: &mut ModuleContext,
+    function: &mut FunctionContext,
+    table: BrTable,
+) -> Result<(), Error> {
+    let mut depths = Vec::with_capacity(table.len() as usize + 1);
+    for target in table.targets() {
+        depths.push(target?);
+    }
+    depths.push(table.default());
+
+    // Out of range indices select the default target
+    assembler.mov(r11d, depths.len() as u32 - 1)?;
+    assembler.cmp(eax, r11d)?;
+    assembler.cmova(eax, r11d)?;
+
+    let jump_table = assembler.create_label();
+    assembler.lea(r11, ptr(jump_table))?;
+    // Entries are 10 bytes long: 7 for `lea` and 3 for `jmp`
+    assembler.lea(rax, ptr(rax + rax * 4))?;
+    assembler.lea(r11, ptr(r11 + rax * 2))?;
+    assembler.jmp(r11)?;
+
+    let mut stubs: Vec<(u32, CodeLabel)> = Vec::new();
+    module.bind(assembler, jump_table);
+    for depth in depths.iter() {
+        let target = if needs_unwind(function, *depth) {
+            match stubs.iter().find(|(d, _)| d == depth) {
+                Some((_, stub)) => *stub,
+                None => {
+                    let stub = assembler.create_label();
+                    stubs.push((*depth, stub));
+                    stub
+                }
+            }
+        } else {
+            function.frames[function.frames.len() - 1 - *depth as usize].label
+        };
+        assembler.lea(r11, ptr(target))?;
+        assembler.jmp(r11)?;
+    }
+    for (depth, stub) in stubs {
+        module.bind(assembler, stub);
+        let label = unwind(assembler, function, depth)?;
+        assembler.jmp(label)?;
+    }
+    function.reachable = false;
+    Ok(())
+}
@@ -0,0 +1,277 @@
+use crate::x86_64::context::{FunctionContext, ModuleContext};
+use crate::x86_64::trap::{trap_unless, TrapCode};
+use crate::x86_64::{float, Error};
+use iced_x86::code_asm::{al, ax, eax, rax, rcx, xmm0, xmm1, AsmRegisterXmm, CodeAssembler};
+use wasmparser_nostd::Type;
+
+/// Conversions that keep the bit pattern (`i32.wrap_i64` and the
+/// reinterpret casts) only change the type of the value on top of the stack
+pub(crate) fn retype(function: &mut FunctionContext, ty: Type) {
+    function.stack.pop();
+    function.stack.push(ty);
+}
+
+/// Loads the float constant `value` of type `ty` into `xmm`, through RAX
+fn constant(
+    assembler: &mut CodeAssembler,
+    xmm: AsmRegisterXmm,
+    ty: Type,
+    value: f64,
+) -> Result<(), Error> {
+    match ty {
+        Type::F32 => assembler.mov(eax, (value as f32).to_bits())?,
+        _ => assembler.mov(rax, value.to_bits())?,
+    }
+    assembler.movq(xmm, rax)?;
+    Ok(())
+}
+
+fn ucomis(
+    assembler: &mut CodeAssembler,
+    ty: Type,
+    lhs: AsmRegisterXmm,
+    rhs: AsmRegisterXmm,
+) -> Result<(), Error> {
+    match ty {
+        Type::F32 => assembler.ucomiss(lhs, rhs)?,
+        _ => assembler.ucomisd(lhs, rhs)?,
+    }
+    Ok(())
+}
+
+/// Truncates XMM0 into RAX (`bits` being 64) or EAX, as a signed integer.
+/// Out of range values give the "integer indefinite" value, the smallest
+/// signed integer.
+fn truncate_signed(assembler: &mut CodeAssembler, from: Type, bits: u32) -> Result<(), Error> {
+    match (from, bits) {
+        (Type::F32, 64) => assembler.cvttss2si(rax, xmm0)?,
+        (Type::F32, _) => assembler.cvttss2si(eax, xmm0)?,
+        (_, 64) => assembler.cvttsd2si(rax, xmm0)?,
+        _ => assembler.cvttsd2si(eax, xmm0)?,
+    }
+    Ok(())
+}
+
+/// Truncates XMM0, known to be in `[0, 2^bits)`, into RAX as an unsigned
+/// integer
+fn truncate_unsigned(
+    assembler: &mut CodeAssembler,
+    module: &mut ModuleContext,
+    from: Type,
+    bits: u32,
+) -> Result<(), Error> {
+    if bits == 32 {
+        // Fits in a signed 64-bit integer
+        return truncate_signed(assembler, from, 64);
+    }
+    // Values from 2^63 are brought down by 2^63 first, then the top bit set
+    let large = assembler.create_label();
+    let done = assembler.create_label();
+    constant(assembler, xmm1, from, 9223372036854775808.0)?;
+    ucomis(assembler, from, xmm0, xmm1)?;
+    assembler.jae(large)?;
+    truncate_signed(assembler, from, 64)?;
+    assembler.jmp(done)?;
+    module.bind(assembler, large);
+    match from {
+        Type::F32 => assembler.subss(xmm0, xmm1)?,
+        _ => assembler.subsd(xmm0, xmm1)?,
+    }
+    truncate_signed(assembler, from, 64)?;
+    assembler.btc(rax, 63)?;
+    module.bind(assembler, done);
+    Ok(())
+}
+
+/// `trunc`: traps on NaN and on values out of the range of the result
+pub(crate) fn truncate(
+    assembler: &mut CodeAssembler,
+    module: &mut ModuleContext,
+    function: &mut FunctionContext,
+    from: Type,
+    to: Type,
+    signed: bool,
+) -> Result<(), Error> {
+    let bits = if to == Type::I64 { 64 } else { 32 };
+    float::pop(assembler, function, xmm0)?;
+    ucomis(assembler, from, xmm0, xmm0)?;
+    trap_unless(
+        assembler,
+        module,
+        TrapCode::BadConversionToInteger,
+        |a, ok| a.jnp(ok),
+    )?;
+    let limit = if signed {
+        let min = -((1u64 << (bits - 1)) as f64);
+        if from == Type::F64 && bits == 32 {
+            // Values between MIN - 1 and MIN truncate to MIN
+            constant(assembler, xmm1, from, min - 1.0)?;
+            ucomis(assembler, from, xmm0, xmm1)?;
+            trap_unless(assembler, module, TrapCode::IntegerOverflow, |a, ok| {
+                a.ja(ok)
+            })?;
+        } else {
+            // There are no such values at this precision
+            constant(assembler, xmm1, from, min)?;
+            ucomis(assembler, from, xmm0, xmm1)?;
+            trap_unless(assembler, module, TrapCode::IntegerOverflow, |a, ok| {
+                a.jae(ok)
+            })?;
+        }
+        -min
+    } else {
+        constant(assembler, xmm1, from, -1.0)?;
+        ucomis(assembler, from, xmm0, xmm1)?;
+        trap_unless(assembler, module, TrapCode::IntegerOverflow, |a, ok| {
+            a.ja(ok)
+        })?;
+        (1u64 << (bits - 1)) as f64 * 2.0
+    };
+    constant(assembler, xmm1, from, limit)?;
+    ucomis(assembler, from, xmm1, xmm0)?;
+    trap_unless(assembler, module, TrapCode::IntegerOverflow, |a, ok| {
+        a.ja(ok)
+    })?;
+    if signed {
+        truncate_signed(assembler, from, bits)?;
+    } else {
+        truncate_unsigned(assembler, module, from, bits)?;
+    }
+    function.push(assembler, rax, to)
+}
+
+/// `trunc_sat`: NaN gives zero, and values out of range the closest bound
+pub(crate) fn truncate_saturating(
+    assembler: &mut CodeAssembler,
+    module: &mut ModuleContext,
+    function: &mut FunctionContext,
+    from: Type,
+    to: Type,
+    signed: bool,
+) -> Result<(), Error> {
+    let bits = if to == Type::I64 { 64 } else { 32 };
+    float::pop(assembler, function, xmm0)?;
+    let zero = assembler.create_label();
+    let max = assembler.create_label();
+    let done = assembler.create_label();
+    if signed {
+        // Values below the range already give the smallest integer
+        constant(assembler, xmm1, from, (1u64 << (bits - 1)) as f64)?;
+        truncate_signed(assembler, from, bits)?;
+        ucomis(assembler, from, xmm0, xmm1)?;
+        assembler.jp(zero)?;
+        assembler.jb(done)?;
+    } else {
+        // NaN and values up to zero give zero
+        assembler.xorps(xmm1, xmm1)?;
+        ucomis(assembler, from, xmm0, xmm1)?;
+        assembler.jbe(zero)?;
+        constant(assembler, xmm1, from, (1u64 << (bits - 1)) as f64 * 2.0)?;
+        ucomis(assembler, from, xmm0, xmm1)?;
+        assembler.jae(max)?;
+        truncate_unsigned(assembler, module, from, bits)?;
+        assembler.jmp(done)?;
+    }
+    module.bind(assembler, max);
+    match (bits, signed) {
+        (64, true) => assembler.mov(rax, i64::MAX)?,
+        (64, false) => assembler.mov(rax, u64::MAX)?,
+        (_, true) => assembler.mov(eax, i32::MAX)?,
+        (_, false) => assembler.mov(eax, u32::MAX)?,
+    }
+    assembler.jmp(done)?;
+    module.bind(assembler, zero);
+    assembler.xor(eax, eax)?;
+    module.bind(assembler, done);
+    function.push(assembler, rax, to)
+}
+
+/// `convert`: integer to float
+pub(crate) fn convert(
+    assembler: &mut CodeAssembler,
+    module: &mut ModuleContext,
+    function: &mut FunctionContext,
+    from: Type,
+    to: Type,
+    signed: bool,
+) -> Result<(), Error> {
+    function.pop(assembler, rax)?;
+    let convert = |a: &mut CodeAssembler, source| match to {
+        Type::F32 => a.cvtsi2ss(xmm0, source),
+        _ => a.cvtsi2sd(xmm0, source),
+    };
+    match (from, signed) {
+        (Type::I32, true) => match to {
+            Type::F32 => assembler.cvtsi2ss(xmm0, eax)?,
+            _ => assembler.cvtsi2sd(xmm0, eax)?,
+        },
+        (Type::I32, false) => {
+            // Fits in a signed 64-bit integer once zero-extended
+            assembler.mov(eax, eax)?;
+            convert(assembler, rax)?;
+        }
+        (_, true) => convert(assembler, rax)?,
+        (_, false) => {
+            // Values from 2^63 are halved, keeping the lowest bit for
+            // correct rounding, converted then doubled
+            let large = assembler.create_label();
+            let done = assembler.create_label();
+            assembler.test(rax, rax)?;
+            assembler.js(large)?;
+            convert(assembler, rax)?;
+            assembler.jmp(done)?;
+            module.bind(assembler, large);
+            assembler.mov(rcx, rax)?;
+            assembler.shr(rcx, 1)?;
+            assembler.and(eax, 1)?;
+            assembler.or(rcx, rax)?;
+            convert(assembler, rcx)?;
+            match to {
+                Type::F32 => assembler.addss(xmm0, xmm0)?,
+                _ => assembler.addsd(xmm0, xmm0)?,
+            }
+            module.bind(assembler, done);
+        }
+    }
+    float::push(assembler, function, xmm0, to)
+}
+
+/// `f32.demote_f64` and `f64.promote_f32`
+pub(crate) fn demote_promote(
+    assembler: &mut CodeAssembler,
+    function: &mut FunctionContext,
+    to: Type,
+) -> Result<(), Error> {
+    float::unary(assembler, function, to, |a| match to {
+        Type::F32 => a.cvtsd2ss(xmm0, xmm0),
+        _ => a.cvtss2sd(xmm0, xmm0),
+    })
+}
+
+/// Sign-extends the lowest `bits` bits of the value on top of the stack
+pub(crate) fn extend_signed(
+    assembler: &mut CodeAssembler,
+    function: &mut FunctionContext,
+    ty: Type,
+    bits: u32,
+) -> Result<(), Error> {
+    function.pop(assembler, rax)?;
+    match (ty, bits) {
+        (Type::I32, 8) => assembler.movsx(eax, al)?,
+        (Type::I32, _) => assembler.movsx(eax, ax)?,
+        (_, 8) => assembler.movsx(rax, al)?,
+        (_, 16) => assembler.movsx(rax, ax)?,
+        _ => assembler.movsxd(rax, eax)?,
+    }
+    function.push(assembler, rax, ty)
+}
+
+/// `i64.extend_i32_u`
+pub(crate) fn extend_unsigned(
+    assembler: &mut CodeAssembler,
+    function: &mut FunctionContext,
+) -> Result<(), Error> {
+    function.pop(assembler, rax)?;
+    assembler.mov(eax, eax)?;
+    function.push(assembler, rax, Type::I64)
+}
@@ -0,0 +1,103 @@
+/// Instruction set extensions code may use, beyond baseline x86-64 (which
+/// includes SSE2). Instructions from extensions that aren't available are
+/// replaced by equivalent sequences.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CpuFeatures {
+    pub sse3: bool,
+    pub ssse3: bool,
+    /// `ROUNDSS`/`ROUNDSD` for `ceil`, `floor`, `trunc` and `nearest`
+    pub sse4_1: bool,
+    pub sse4_2: bool,
+    /// `POPCNT` for `popcnt`
+    pub popcnt: bool,
+    /// `LZCNT` for `clz`
+    pub lzcnt: bool,
+    /// `TZCNT` for `ctz`
+    pub bmi1: bool,
+    /// `SHLX`, `SARX` and `SHRX` for shifts
+    pub bmi2: bool,
+    /// 32-byte vector loops for `memory.copy` and `memory.fill`
+    pub avx: bool,
+    pub avx2: bool,
+    /// Fused multiply-add for relaxed SIMD
+    pub fma: bool,
+    /// Enhanced `REP MOVSB`/`REP STOSB`, fast enough to be used for
+    /// `memory.copy` and `memory.fill` of any size. Not part of any
+    /// microarchitecture level.
+    pub erms: bool,
+}
+
+impl CpuFeatures {
+    /// Baseline x86-64, the default
+    pub fn baseline() -> Self {
+        Self::default()
+    }
+
+    /// The x86-64-v2 microarchitecture level: up to SSE4.2 and POPCNT
+    pub fn x86_64_v2() -> Self {
+        Self {
+            sse3: true,
+            ssse3: true,
+            sse4_1: true,
+            sse4_2: true,
+            popcnt: true,
+            ..Self::baseline()
+        }
+    }
+
+    /// The x86-64-v3 microarchitecture level: x86-64-v2 with AVX2, BMI1,
+    /// BMI2, FMA and LZCNT
+    pub fn x86_64_v3() -> Self {
+        Self {
+            lzcnt: true,
+            bmi1: true,
+            bmi2: true,
+            avx: true,
+            avx2: true,
+            fma: true,
+            ..Self::x86_64_v2()
+        }
+    }
+
+    /// Features of the CPU this runs on, as reported by CPUID. AVX, AVX2 and
+    /// FMA also need the OS to have enabled saving of the YMM registers.
+    #[cfg(target_arch = "x86_64")]
+    // CPUID intrinsics are only safe to call in recent Rust versions
+    #[allow(unused_unsafe)]
+    pub fn detect() -> Self {
+        use core::arch::x86_64::{__cpuid, __cpuid_count, _xgetbv};
+
+        let bit = |register: u32, bit: u32| register & (1 << bit) != 0;
+        // SAFETY: CPUID is available on every x86-64 CPU
+        let max_leaf = unsafe { __cpuid(0) }.eax;
+        let max_extended_leaf = unsafe { __cpuid(0x8000_0000) }.eax;
+        let leaf1 = unsafe { __cpuid(1) };
+        let leaf7 = if max_leaf >= 7 {
+            unsafe { __cpuid_count(7, 0) }.ebx
+        } else {
+            0
+        };
+        let extended = if max_extended_leaf >= 0x8000_0001 {
+            unsafe { __cpuid(0x8000_0001) }.ecx
+        } else {
+            0
+        };
+        // SAFETY: XGETBV is available when OSXSAVE is set
+        let ymm = bit(leaf1.ecx, 27) && unsafe { _xgetbv(0) } & 0b110 == 0b110;
+        let avx = ymm && bit(leaf1.ecx, 28);
+        Self {
+            sse3: bit(leaf1.ecx, 0),
+            ssse3: bit(leaf1.ecx, 9),
+            sse4_1: bit(leaf1.ecx, 19),
+            sse4_2: bit(leaf1.ecx, 20),
+            popcnt: bit(leaf1.ecx, 23),
+            lzcnt: bit(extended, 5),
+            bmi1: bit(leaf7, 3),
+            bmi2: bit(leaf7, 8),
+            avx,
+            avx2: avx && bit(leaf7, 5),
+            fma: avx && bit(leaf1.ecx, 12),
+            erms: bit(leaf7, 9),
+        }
+    }
+}
@@ -0,0 +1,116 @@
+use crate::x86_64::context::{FunctionContext, ModuleContext};
+use crate::x86_64::global::{self, Initializer};
+use crate::x86_64::memory;
+use crate::x86_64::trap::{trap_unless, TrapCode};
+use crate::x86_64::Error;
+use iced_x86::code_asm::{
+    ecx, edi, esi, ptr, qword_ptr, rax, rcx, rdi, rsi, CodeAssembler, CodeLabel,
+};
+use wasmparser_nostd::Type;
+
+/// Labels of a data segment in the module binary
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct DataSegment {
+    /// Current length of the segment, zeroed once it is dropped
+    pub(crate) length: CodeLabel,
+    pub(crate) bytes: CodeLabel,
+}
+
+impl DataSegment {
+    pub(crate) fn new(assembler: &mut CodeAssembler) -> Self {
+        Self {
+            length: assembler.create_label(),
+            bytes: assembler.create_label(),
+        }
+    }
+}
+
+/// Emits data segment `index`. Its labels may already be in use, as the
+/// data count section lets code refer to segments before they are defined.
+pub(crate) fn segment(
+    assembler: &mut CodeAssembler,
+    module: &mut ModuleContext,
+    index: u32,
+    data: &[u8],
+) -> Result<(), Error> {
+    while module.data_segments.len() <= index as usize {
+        module.data_segments.push(DataSegment::new(assembler));
+    }
+    let segment = module.data_segments[index as usize];
+    module.bind(assembler, segment.length);
+    assembler.dq(&[data.len() as u64])?;
+    module.bind(assembler, segment.bytes);
+    assembler.db(data)?;
+    Ok(())
+}
+
+/// Copies RCX bytes at offset RSI of data segment `segment` to address RDI
+/// of memory `memory`, trapping if either range is out of bounds
+fn copy(
+    assembler: &mut CodeAssembler,
+    module: &mut ModuleContext,
+    segment: u32,
+    memory: u32,
+) -> Result<(), Error> {
+    let segment = module.data_segments[segment as usize];
+    // Operands are 32-bit, so the sum can't overflow
+    assembler.lea(rax, ptr(rsi + rcx))?;
+    assembler.cmp(rax, qword_ptr(segment.length))?;
+    trap_unless(assembler, module, TrapCode::MemoryOutOfBounds, |a, ok| {
+        a.jbe(ok)
+    })?;
+    memory::range(assembler, module, memory, rdi)?;
+    assembler.lea(rax, ptr(segment.bytes))?;
+    assembler.add(rsi, rax)?;
+    assembler.rep().movsb()?;
+    Ok(())
+}
+
+/// `memory.init`, whose destination is an i64 for 64-bit memories
+pub(crate) fn init(
+    assembler: &mut CodeAssembler,
+    module: &mut ModuleContext,
+    function: &mut FunctionContext,
+    segment: u32,
+    memory: u32,
+) -> Result<(), Error> {
+    function.pop(assembler, rcx)?;
+    function.pop(assembler, rsi)?;
+    function.pop(assembler, rdi)?;
+    assembler.mov(ecx, ecx)?;
+    assembler.mov(esi, esi)?;
+    if memory::index_type(module, memory) == Type::I32 {
+        assembler.mov(edi, edi)?;
+    }
+    copy(assembler, module, segment, memory)
+}
+
+/// `data.drop`
+pub(crate) fn drop(
+    assembler: &mut CodeAssembler,
+    module: &mut ModuleContext,
+    segment: u32,
+) -> Result<(), Error> {
+    let segment = module.data_segments[segment as usize];
+    assembler.mov(qword_ptr(segment.length), 0)?;
+    Ok(())
+}
+
+/// Copies active data segment `segment` to memory `memory` at `offset`,
+/// then drops it
+pub(crate) fn apply(
+    assembler: &mut CodeAssembler,
+    module: &mut ModuleContext,
+    segment: u32,
+    memory: u32,
+    offset: Initializer,
+) -> Result<(), Error> {
+    global::load_offset(assembler, module, offset)?;
+    assembler.xor(esi, esi)?;
+    assembler.mov(
+        rcx,
+        qword_ptr(module.data_segments[segment as usize].length),
+    )?;
+    copy(assembler, module, segment, memory)?;
+    drop(assembler, module, segment)
+}
@@ -0,0 +1,459 @@
+use crate::x86_64::context::{slot_size, FunctionContext, ModuleContext};
+use crate::x86_64::control::{self, FrameKind};
+use crate::x86_64::trap::{trap_unless, TrapCode};
+use crate::x86_64::Error;
+use alloc::vec::Vec;
+use iced_x86::code_asm::{
+    ptr, qword_ptr, r11, r12, r13, r14, r15, rax, rbp, rbx, rsp, AsmRegister64, CodeAssembler,
+    CodeLabel,
+};
+use wasmparser_nostd::{Type, TypeOrFuncType};
+
+/// Where exceptions are thrown to, one per thread running code of the
+/// module, as returned by its [`ExceptionContextHook`].
+///
+/// Every `try` block being executed has a handler in the native stack frame
+/// of its function, and handlers are chained, innermost first. Throwing an
+/// exception stores it here, then resumes execution at the innermost
+/// handler, skipping the frames in between, those of host functions
+/// included. Like `longjmp`, this restores the callee-saved registers as
+/// they were when entering the `try`, but host functions skipped that way
+/// get no chance to clean up. Modules throwing exceptions to each other
+/// have to use the same contexts, see
+/// [`link_exception_context_hook`](crate::x86_64::AssembledModule::link_exception_context_hook).
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExceptionContext {
+    /// Address of the innermost handler, zero if there is none. Traps leave
+    /// the handlers of the frames they discard in the chain: this has to be
+    /// reset to zero before running code again.
+    pub handler: u64,
+    /// Identity of the tag of the exception being thrown, the address of the
+    /// tag in the module defining it
+    pub tag: u64,
+    /// Values carried by the exception, in 8-byte slots, first one first
+    pub payload: [u64; 8],
+}
+
+const HANDLER: i32 = 0;
+const TAG: i32 = 8;
+const PAYLOAD: i32 = 16;
+const PAYLOAD_SIZE: u32 = 64;
+
+/// Host function returning the [`ExceptionContext`] of the calling thread.
+/// Compiled code calls it on entering a `try` and on `throw`.
+pub type ExceptionContextHook = extern "sysv64" fn() -> *mut ExceptionContext;
+
+// Handler record of a `try`, kept on the operand stack below the block
+// parameters: the handler it shadows, the context it is linked in, the
+// frame of its function, where to resume execution and the callee-saved
+// registers to restore there
+const PREVIOUS: u32 = 0;
+const CONTEXT: u32 = 8;
+const FRAME: u32 = 16;
+const LANDING: u32 = 24;
+const SAVED: u32 = 32;
+const RECORD_SLOTS: usize = 9;
+/// Registers the System V ABI has callees preserve, besides RBP and RSP.
+/// Compiled code leaves them alone, but host functions in the frames a
+/// throw skips may not have restored them yet.
+const CALLEE_SAVED: [AsmRegister64; 5] = [rbx, r12, r13, r14, r15];
+/// A caught exception is kept on the operand stack as the address of the
+/// context, then a copy of its tag and payload
+const EXCEPTION_SLOTS: usize = 10;
+
+/// A tag in the module binary, whose address is its identity. Imported tags
+/// get a slot for the address of the tag they are linked to.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct TagSlot {
+    pub(crate) label: CodeLabel,
+    /// Index of the function type of the tag
+    pub(crate) ty: u32,
+    pub(crate) imported: bool,
+}
+
+/// Emits the slot of the address of the [`ExceptionContextHook`] in use, to
+/// be linked by the embedder, followed by the module's own context and the
+/// module's own hook, which returns it
+pub(crate) fn context(
+    assembler: &mut CodeAssembler,
+    module: &mut ModuleContext,
+) -> Result<(), Error> {
+    module.bind(assembler, module.exception_context_hook);
+    assembler.dq(&[0])?;
+    module.bind(assembler, module.default_exception_context);
+    assembler.dq(&[0; 10])?;
+    module.bind(assembler, module.default_exception_context_hook);
+    assembler.lea(rax, ptr(module.default_exception_context))?;
+    assembler.ret()?;
+    Ok(())
+}
+
+/// Points the exception context hook slot at the module's own hook, unless
+/// another one was linked
+pub(crate) fn setup(
+    assembler: &mut CodeAssembler,
+    module: &mut ModuleContext,
+) -> Result<(), Error> {
+    let linked = assembler.create_label();
+    assembler.mov(rax, qword_ptr(module.exception_context_hook))?;
+    assembler.test(rax, rax)?;
+    assembler.jnz(linked)?;
+    assembler.lea(rax, ptr(module.default_exception_context_hook))?;
+    assembler.mov(qword_ptr(module.exception_context_hook), rax)?;
+    module.bind(assembler, linked);
+    Ok(())
+}
+
+/// Leaves the address of the calling thread's context in R11. Clobbers the
+/// registers the hook may.
+fn load_context(
+    assembler: &mut CodeAssembler,
+    module: &ModuleContext,
+    function: &FunctionContext,
+) -> Result<(), Error> {
+    let aligned = function.is_aligned();
+    if !aligned {
+        assembler.sub(rsp, 8)?;
+    }
+    assembler.call(qword_ptr(module.exception_context_hook))?;
+    if !aligned {
+        assembler.add(rsp, 8)?;
+    }
+    assembler.mov(r11, rax)?;
+    Ok(())
+}
+
+/// Emits a tag of function type `ty`, defined by the module or imported
+pub(crate) fn tag(
+    assembler: &mut CodeAssembler,
+    module: &mut ModuleContext,
+    ty: u32,
+    imported: bool,
+) -> Result<(), Error> {
+    let label = assembler.create_label();
+    module.bind(assembler, label);
+    assembler.dq(&[0])?;
+    module.tags.push(TagSlot {
+        label,
+        ty,
+        imported,
+    });
+    Ok(())
+}
+
+/// Leaves the identity of tag `index` in RAX
+fn load_tag(
+    assembler: &mut CodeAssembler,
+    module: &ModuleContext,
+    index: u32,
+) -> Result<(), Error> {
+    let tag = module.tags[index as usize];
+    if tag.imported {
+        assembler.mov(rax, qword_ptr(tag.label))?;
+    } else {
+        assembler.lea(rax, ptr(tag.label))?;
+    }
+    Ok(())
+}
+
+/// Offsets in the payload and types of the values carried by exceptions of
+/// tag `index`
+fn payload(module: &ModuleContext, index: u32) -> Result<Vec<(i32, Type)>, Error> {
+    let ty = &module.function_typedefs[&module.tags[index as usize].ty];
+    let mut offset = 0;
+    let mut payload = Vec::with_capacity(ty.params.len());
+    for param in ty.params.iter() {
+        match param {
+            Type::I32 | Type::I64 | Type::F32 | Type::F64 | Type::FuncRef | Type::ExternRef => (),
+            ty => return Err(module.unsupported(alloc::format!("{:?} exception payload", ty))),
+        }
+        payload.push((PAYLOAD + offset as i32, *param));
+        offset += slot_size(param);
+    }
+    if offset > PAYLOAD_SIZE {
+        return Err(module.unsupported("exception payload over 64 bytes"));
+    }
+    Ok(payload)
+}
+
+/// Resumes execution at the innermost handler of the context in R11, for
+/// the exception in it. Traps if there is none.
+fn throw_to_handler(
+    assembler: &mut CodeAssembler,
+    module: &mut ModuleContext,
+) -> Result<(), Error> {
+    assembler.mov(rax, qword_ptr(r11 + HANDLER))?;
+    assembler.test(rax, rax)?;
+    trap_unless(assembler, module, TrapCode::UncaughtException, |a, ok| {
+        a.jnz(ok)
+    })?;
+    for (index, register) in CALLEE_SAVED.into_iter().enumerate() {
+        assembler.mov(register, qword_ptr(rax + SAVED + index as u32 * 8))?;
+    }
+    assembler.mov(rbp, qword_ptr(rax + FRAME))?;
+    assembler.jmp(qword_ptr(rax + LANDING))?;
+    Ok(())
+}
+
+/// Offset below RBP of the handler record of the `try` frame entered at
+/// operand stack depth `height`
+fn record(function: &FunctionContext, height: usize) -> u32 {
+    function.stack_offset(height + RECORD_SLOTS)
+}
+
+/// Restores the handler the outermost `try` body being left by a branch to
+/// the frame at `relative_depth` shadowed
+pub(crate) fn unlink(
+    assembler: &mut CodeAssembler,
+    function: &FunctionContext,
+    relative_depth: u32,
+) -> Result<(), Error> {
+    let first = function.frames.len() - 1 - relative_depth as usize;
+    if let Some(frame) = function.frames[first..].iter().find(|frame| frame.handler) {
+        let record = record(function, frame.height);
+        assembler.mov(rax, qword_ptr(rbp - record + PREVIOUS))?;
+        assembler.mov(r11, qword_ptr(rbp - record + CONTEXT))?;
+        assembler.mov(qword_ptr(r11 + HANDLER), rax)?;
+    }
+    Ok(())
+}
+
+/// `try`: makes room for the handler record below the block parameters, and
+/// makes it the innermost handler of the calling thread's context. Its
+/// landing pad is bound with the first `catch`.
+pub(crate) fn try_(
+    assembler: &mut CodeAssembler,
+    module: &mut ModuleContext,
+    function: &mut FunctionContext,
+    ty: TypeOrFuncType,
+) -> Result<(), Error> {
+    control::enter(assembler, module, function, FrameKind::Try, ty, None)?;
+    if !function.reachable {
+        return Ok(());
+    }
+    let height = function.frames.last().unwrap().height;
+    let size = (RECORD_SLOTS * 8) as u32;
+    assembler.sub(rsp, size as i32)?;
+    // The top parameter first, the destination is always below the source
+    for index in (height..function.stack.len()).rev() {
+        let src = function.stack_offset(index + 1);
+        for word in (0..slot_size(&function.stack[index])).step_by(8) {
+            assembler.mov(rax, qword_ptr(rbp - src + word))?;
+            assembler.mov(qword_ptr(rbp - src - size + word), rax)?;
+        }
+    }
+    function
+        .stack
+        .splice(height..height, [Type::I64; RECORD_SLOTS]);
+    function.hidden += size;
+
+    let record = record(function, height);
+    let landing = assembler.create_label();
+    load_context(assembler, module, function)?;
+    assembler.mov(rax, qword_ptr(r11 + HANDLER))?;
+    assembler.mov(qword_ptr(rbp - record + PREVIOUS), rax)?;
+    assembler.mov(qword_ptr(rbp - record + CONTEXT), r11)?;
+    assembler.mov(qword_ptr(rbp - record + FRAME), rbp)?;
+    assembler.lea(rax, ptr(landing))?;
+    assembler.mov(qword_ptr(rbp - record + LANDING), rax)?;
+    for (index, register) in CALLEE_SAVED.into_iter().enumerate() {
+        assembler.mov(qword_ptr(rbp - record + SAVED + index as u32 * 8), register)?;
+    }
+    assembler.lea(rax, ptr(rbp - record))?;
+    assembler.mov(qword_ptr(r11 + HANDLER), rax)?;
+
+    let frame = function.frames.last_mut().unwrap();
+    frame.handler = true;
+    frame.else_label = Some(landing);
+    Ok(())
+}
+
+/// Ends the body of a `try`, or one of its `catch` clauses: results are
+/// moved to the end of the block, and the landing pad or the next clause
+/// follows. The landing pad unlinks the handler, leaving the context in R11
+/// for the clauses.
+fn end_clause(
+    assembler: &mut CodeAssembler,
+    module: &mut ModuleContext,
+    function: &mut FunctionContext,
+) -> Result<(), Error> {
+    if function.reachable {
+        control::branch(assembler, function, 0)?;
+    }
+    let frame = function.frames.last_mut().unwrap();
+    let height = frame.height;
+    if let Some(label) = frame.else_label.take() {
+        module.bind(assembler, label);
+    }
+    if core::mem::replace(&mut frame.handler, false) {
+        // Execution resumes here with RSP anywhere below the record
+        let record = record(function, height);
+        assembler.lea(rsp, ptr(rbp - record))?;
+        assembler.mov(rax, qword_ptr(rsp + PREVIOUS))?;
+        assembler.mov(r11, qword_ptr(rsp + CONTEXT))?;
+        assembler.mov(qword_ptr(r11 + HANDLER), rax)?;
+        assembler.lea(rsp, ptr(rbp - function.stack_offset(height)))?;
+    }
+    function.stack.truncate(height);
+    Ok(())
+}
+
+/// `catch` and `catch_all`, `tag` being `None` for the latter. Exceptions
+/// of other tags go to the next clause, with the context still in R11. The
+/// caught exception is kept on the operand stack below the values it
+/// carries, for `rethrow`.
+pub(crate) fn catch(
+    assembler: &mut CodeAssembler,
+    module: &mut ModuleContext,
+    function: &mut FunctionContext,
+    tag: Option<u32>,
+) -> Result<(), Error> {
+    let frame = function.frames.last_mut().unwrap();
+    frame.kind = match tag {
+        Some(_) => FrameKind::Catch,
+        None => FrameKind::CatchAll,
+    };
+    if frame.dead {
+        return Ok(());
+    }
+    let payload = match tag {
+        Some(tag) => payload(module, tag)?,
+        None => Vec::new(),
+    };
+    end_clause(assembler, module, function)?;
+    function.reachable = true;
+
+    if let Some(tag) = tag {
+        let next = assembler.create_label();
+        load_tag(assembler, module, tag)?;
+        assembler.cmp(qword_ptr(r11 + TAG), rax)?;
+        assembler.jne(next)?;
+        function.frames.last_mut().unwrap().else_label = Some(next);
+    }
+    function.push(assembler, r11, Type::I64)?;
+    for slot in 1..EXCEPTION_SLOTS {
+        assembler.mov(rax, qword_ptr(r11 + TAG + (slot as i32 - 1) * 8))?;
+        function.push(assembler, rax, Type::I64)?;
+    }
+    function.hidden += EXCEPTION_SLOTS as u32 * 8;
+    for (offset, ty) in payload {
+        assembler.mov(rax, qword_ptr(r11 + offset))?;
+        function.push(assembler, rax, ty)?;
+    }
+    Ok(())
+}
+
+/// `end` of a `try` frame, before it is popped: exceptions no clause
+/// caught are thrown to the outer handler
+pub(crate) fn end(
+    assembler: &mut CodeAssembler,
+    module: &mut ModuleContext,
+    function: &mut FunctionContext,
+) -> Result<(), Error> {
+    let frame = function.frames.last().unwrap();
+    if frame.dead {
+        return Ok(());
+    }
+    if frame.kind == FrameKind::CatchAll {
+        // Nothing left to throw, only the kept exception to drop
+        if function.reachable {
+            control::unwind(assembler, function, 0)?;
+        }
+        return Ok(());
+    }
+    end_clause(assembler, module, function)?;
+    throw_to_handler(assembler, module)?;
+    function.reachable = false;
+    Ok(())
+}
+
+/// `delegate`: exceptions thrown in the body go to the handler of the frame
+/// at `relative_depth`, counted from outside the `try`, or to the first one
+/// outside of it. Then the frame ends.
+pub(crate) fn delegate(
+    assembler: &mut CodeAssembler,
+    module: &mut ModuleContext,
+    function: &mut FunctionContext,
+    relative_depth: u32,
+) -> Result<(), Error> {
+    if !function.frames.last().unwrap().dead {
+        if function.reachable {
+            control::branch(assembler, function, 0)?;
+        }
+        let frame = function.frames.last_mut().unwrap();
+        frame.handler = false;
+        let (height, landing) = (frame.height, frame.else_label.take().unwrap());
+        module.bind(assembler, landing);
+        let record = record(function, height);
+        assembler.lea(rsp, ptr(rbp - record))?;
+
+        let outer = &function.frames[..function.frames.len() - 1];
+        let target = outer.len() - 1 - relative_depth as usize;
+        match outer[..=target].iter().rev().find(|frame| frame.handler) {
+            Some(frame) => {
+                let record = self::record(function, frame.height);
+                assembler.lea(rax, ptr(rbp - record))?;
+            }
+            // The one the outermost `try` body being executed shadowed,
+            // possibly this one
+            None => {
+                let height = outer
+                    .iter()
+                    .find(|frame| frame.handler)
+                    .map_or(height, |frame| frame.height);
+                let record = self::record(function, height);
+                assembler.mov(rax, qword_ptr(rbp - record + PREVIOUS))?;
+            }
+        }
+        assembler.mov(r11, qword_ptr(rsp + CONTEXT))?;
+        assembler.mov(qword_ptr(r11 + HANDLER), rax)?;
+        throw_to_handler(assembler, module)?;
+        function.reachable = false;
+    }
+    control::end(assembler, module, function)
+}
+
+/// `throw`: stores the exception in the calling thread's context, taking
+/// the values it carries from the operand stack
+pub(crate) fn throw(
+    assembler: &mut CodeAssembler,
+    module: &mut ModuleContext,
+    function: &mut FunctionContext,
+    index: u32,
+) -> Result<(), Error> {
+    let payload = payload(module, index)?;
+    let first = function.stack.len() - payload.len();
+    load_context(assembler, module, function)?;
+    for (position, (offset, _)) in payload.iter().enumerate() {
+        let value = function.stack_offset(first + position + 1);
+        assembler.mov(rax, qword_ptr(rbp - value))?;
+        assembler.mov(qword_ptr(r11 + *offset), rax)?;
+    }
+    load_tag(assembler, module, index)?;
+    assembler.mov(qword_ptr(r11 + TAG), rax)?;
+    function.stack.truncate(first);
+    throw_to_handler(assembler, module)?;
+    function.reachable = false;
+    Ok(())
+}
+
+/// `rethrow`: throws again the exception caught by the clause at
+/// `relative_depth`
+pub(crate) fn rethrow(
+    assembler: &mut CodeAssembler,
+    module: &mut ModuleContext,
+    function: &mut FunctionContext,
+    relative_depth: u32,
+) -> Result<(), Error> {
+    let height = function.frames[function.frames.len() - 1 - relative_depth as usize].height;
+    assembler.mov(r11, qword_ptr(rbp - function.stack_offset(height + 1)))?;
+    for slot in 1..EXCEPTION_SLOTS {
+        let value = function.stack_offset(height + slot + 1);
+        assembler.mov(rax, qword_ptr(rbp - value))?;
+        assembler.mov(qword_ptr(r11 + TAG + (slot as i32 - 1) * 8), rax)?;
+    }
+    throw_to_handler(assembler, module)?;
+    function.reachable = false;
+    Ok(())
+}
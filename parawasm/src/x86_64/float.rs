@@ -0,0 +1,343 @@
+use crate::x86_64::compare::Condition;
+use crate::x86_64::context::{FunctionContext, ModuleContext};
+use crate::x86_64::Error;
+use iced_x86::code_asm::{
+    al, cl, eax, ecx, edx, rax, rcx, rdx, xmm0, xmm1, AsmRegisterXmm, CodeAssembler,
+};
+use iced_x86::IcedError;
+use wasmparser_nostd::Type;
+
+/// Floats are kept on the operand stack as their bit pattern. This pops
+/// one into `xmm`, through RAX.
+pub(crate) fn pop(
+    assembler: &mut CodeAssembler,
+    function: &mut FunctionContext,
+    xmm: AsmRegisterXmm,
+) -> Result<(), Error> {
+    function.pop(assembler, rax)?;
+    assembler.movq(xmm, rax)?;
+    Ok(())
+}
+
+/// Pushes the float in `xmm`, through RAX
+pub(crate) fn push(
+    assembler: &mut CodeAssembler,
+    function: &mut FunctionContext,
+    xmm: AsmRegisterXmm,
+    ty: Type,
+) -> Result<(), Error> {
+    assembler.movq(rax, xmm)?;
+    function.push(assembler, rax, ty)
+}
+
+/// Pops the operands into XMM0 (left) and XMM1 (right), applies `op` and
+/// pushes XMM0 as the result
+pub(crate) fn binary<F>(
+    assembler: &mut CodeAssembler,
+    function: &mut FunctionContext,
+    ty: Type,
+    op: F,
+) -> Result<(), Error>
+where
+    F: FnOnce(&mut CodeAssembler) -> Result<(), IcedError>,
+{
+    pop(assembler, function, xmm1)?;
+    pop(assembler, function, xmm0)?;
+    op(assembler)?;
+    push(assembler, function, xmm0, ty)
+}
+
+/// Pops the operand into XMM0, applies `op` and pushes XMM0 as the result
+pub(crate) fn unary<F>(
+    assembler: &mut CodeAssembler,
+    function: &mut FunctionContext,
+    ty: Type,
+    op: F,
+) -> Result<(), Error>
+where
+    F: FnOnce(&mut CodeAssembler) -> Result<(), IcedError>,
+{
+    pop(assembler, function, xmm0)?;
+    op(assembler)?;
+    push(assembler, function, xmm0, ty)
+}
+
+/// Rounding modes of ROUNDSS/ROUNDSD, with the precision exception suppressed
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Rounding {
+    Nearest = 8,
+    Floor = 9,
+    Ceil = 10,
+    Trunc = 11,
+}
+
+/// `ceil`, `floor`, `trunc` and `nearest`, with ROUNDSS/ROUNDSD when SSE4.1
+/// is available
+pub(crate) fn round(
+    assembler: &mut CodeAssembler,
+    module: &mut ModuleContext,
+    function: &mut FunctionContext,
+    ty: Type,
+    rounding: Rounding,
+) -> Result<(), Error> {
+    if module.cpu.sse4_1 {
+        return unary(assembler, function, ty, |a| match ty {
+            Type::F32 => a.roundss(xmm0, xmm0, rounding as i32),
+            _ => a.roundsd(xmm0, xmm0, rounding as i32),
+        });
+    }
+    // Values from 2^23 (f32) or 2^52 (f64) up, infinities and NaNs are left
+    // as they are, NaNs being made quiet. Others are rounded as their
+    // absolute value and the sign put back, giving -0 where expected.
+    let small = assembler.create_label();
+    let done = assembler.create_label();
+    function.pop(assembler, rax)?;
+    match ty {
+        Type::F32 => {
+            assembler.mov(ecx, eax)?;
+            assembler.btr(ecx, 31)?;
+            assembler.cmp(ecx, 0x4B000000)?;
+            assembler.jb(small)?;
+            assembler.cmp(ecx, 0x7F800000)?;
+            assembler.jbe(done)?;
+            assembler.bts(eax, 22)?;
+        }
+        _ => {
+            assembler.mov(rcx, rax)?;
+            assembler.btr(rcx, 63)?;
+            assembler.mov(rdx, 0x4330000000000000u64)?;
+            assembler.cmp(rcx, rdx)?;
+            assembler.jb(small)?;
+            assembler.mov(rdx, 0x7FF0000000000000u64)?;
+            assembler.cmp(rcx, rdx)?;
+            assembler.jbe(done)?;
+            assembler.bts(rax, 51)?;
+        }
+    }
+    assembler.jmp(done)?;
+    module.bind(assembler, small);
+    // Constants go through RDX
+    let constant = |a: &mut CodeAssembler, xmm, value: f64| {
+        match ty {
+            Type::F32 => a.mov(edx, (value as f32).to_bits())?,
+            _ => a.mov(rdx, value.to_bits())?,
+        }
+        a.movq(xmm, rdx)
+    };
+    let add = |a: &mut CodeAssembler, lhs, rhs| match ty {
+        Type::F32 => a.addss(lhs, rhs),
+        _ => a.addsd(lhs, rhs),
+    };
+    assembler.movq(xmm0, rcx)?;
+    if let Rounding::Nearest = rounding {
+        // Adding then subtracting 2^23 or 2^52 rounds to nearest, ties to
+        // even, in the default rounding mode
+        let shift = if ty == Type::F32 {
+            8388608.0
+        } else {
+            4503599627370496.0
+        };
+        constant(assembler, xmm1, shift)?;
+        add(assembler, xmm0, xmm1)?;
+        match ty {
+            Type::F32 => assembler.subss(xmm0, xmm1)?,
+            _ => assembler.subsd(xmm0, xmm1)?,
+        }
+    } else {
+        // Truncated through a 64-bit integer, then brought away from zero
+        // for floor of negative and ceil of positive non-integers
+        let truncated = assembler.create_label();
+        assembler.movaps(xmm1, xmm0)?;
+        match ty {
+            Type::F32 => {
+                assembler.cvttss2si(rcx, xmm0)?;
+                assembler.cvtsi2ss(xmm0, rcx)?;
+                assembler.test(eax, eax)?;
+            }
+            _ => {
+                assembler.cvttsd2si(rcx, xmm0)?;
+                assembler.cvtsi2sd(xmm0, rcx)?;
+                assembler.test(rax, rax)?;
+            }
+        }
+        match rounding {
+            Rounding::Floor => assembler.jns(truncated)?,
+            Rounding::Ceil => assembler.js(truncated)?,
+            _ => assembler.jmp(truncated)?,
+        }
+        match ty {
+            Type::F32 => assembler.ucomiss(xmm0, xmm1)?,
+            _ => assembler.ucomisd(xmm0, xmm1)?,
+        }
+        assembler.je(truncated)?;
+        constant(assembler, xmm1, 1.0)?;
+        add(assembler, xmm0, xmm1)?;
+        module.bind(assembler, truncated);
+    }
+    assembler.movq(rcx, xmm0)?;
+    match ty {
+        Type::F32 => {
+            assembler.shr(eax, 31)?;
+            assembler.shl(eax, 31)?;
+            assembler.or(eax, ecx)?;
+        }
+        _ => {
+            assembler.shr(rax, 63)?;
+            assembler.shl(rax, 63)?;
+            assembler.or(rax, rcx)?;
+        }
+    }
+    module.bind(assembler, done);
+    function.push(assembler, rax, ty)
+}
+
+/// `min` and `max`. MINSS and friends return the second operand when either
+/// is NaN or both are zeros, while WebAssembly wants NaN, and -0 to be less
+/// than +0.
+pub(crate) fn min_max(
+    assembler: &mut CodeAssembler,
+    module: &mut ModuleContext,
+    function: &mut FunctionContext,
+    ty: Type,
+    max: bool,
+) -> Result<(), Error> {
+    pop(assembler, function, xmm1)?;
+    pop(assembler, function, xmm0)?;
+    let unordered = assembler.create_label();
+    let different = assembler.create_label();
+    let done = assembler.create_label();
+    match ty {
+        Type::F32 => assembler.ucomiss(xmm0, xmm1)?,
+        _ => assembler.ucomisd(xmm0, xmm1)?,
+    }
+    assembler.jp(unordered)?;
+    assembler.jne(different)?;
+    // Equal operands only differ if they are zeros of different signs
+    if max {
+        assembler.andps(xmm0, xmm1)?;
+    } else {
+        assembler.orps(xmm0, xmm1)?;
+    }
+    assembler.jmp(done)?;
+    module.bind(assembler, unordered);
+    match ty {
+        Type::F32 => assembler.addss(xmm0, xmm1)?,
+        _ => assembler.addsd(xmm0, xmm1)?,
+    }
+    assembler.jmp(done)?;
+    module.bind(assembler, different);
+    match (ty, max) {
+        (Type::F32, false) => assembler.minss(xmm0, xmm1)?,
+        (Type::F32, true) => assembler.maxss(xmm0, xmm1)?,
+        (_, false) => assembler.minsd(xmm0, xmm1)?,
+        (_, true) => assembler.maxsd(xmm0, xmm1)?,
+    }
+    module.bind(assembler, done);
+    push(assembler, function, xmm0, ty)
+}
+
+/// `abs`, `neg` and `copysign` only touch the sign bit, so they are done on
+/// the bit pattern in general purpose registers
+pub(crate) fn abs(
+    assembler: &mut CodeAssembler,
+    function: &mut FunctionContext,
+    ty: Type,
+) -> Result<(), Error> {
+    function.pop(assembler, rax)?;
+    match ty {
+        Type::F32 => assembler.btr(eax, 31)?,
+        _ => assembler.btr(rax, 63)?,
+    }
+    function.push(assembler, rax, ty)
+}
+
+pub(crate) fn neg(
+    assembler: &mut CodeAssembler,
+    function: &mut FunctionContext,
+    ty: Type,
+) -> Result<(), Error> {
+    function.pop(assembler, rax)?;
+    match ty {
+        Type::F32 => assembler.btc(eax, 31)?,
+        _ => assembler.btc(rax, 63)?,
+    }
+    function.push(assembler, rax, ty)
+}
+
+pub(crate) fn copysign(
+    assembler: &mut CodeAssembler,
+    function: &mut FunctionContext,
+    ty: Type,
+) -> Result<(), Error> {
+    function.pop(assembler, rcx)?;
+    function.pop(assembler, rax)?;
+    match ty {
+        Type::F32 => {
+            assembler.mov(edx, 0x7FFFFFFF)?;
+            assembler.and(eax, edx)?;
+            assembler.not(edx)?;
+            assembler.and(ecx, edx)?;
+            assembler.or(eax, ecx)?;
+        }
+        _ => {
+            assembler.mov(rdx, 0x7FFFFFFFFFFFFFFFu64)?;
+            assembler.and(rax, rdx)?;
+            assembler.not(rdx)?;
+            assembler.and(rcx, rdx)?;
+            assembler.or(rax, rcx)?;
+        }
+    }
+    function.push(assembler, rax, ty)
+}
+
+/// Comparisons. Unordered operands (NaN) set ZF, PF and CF, so `lt`, `gt`,
+/// `le` and `ge` are expressed as "above" conditions, which are false when
+/// unordered, and can be fused like integer comparisons. `eq` and `ne`
+/// need PF as well, their result is materialized right away.
+pub(crate) fn compare(
+    assembler: &mut CodeAssembler,
+    function: &mut FunctionContext,
+    ty: Type,
+    condition: Condition,
+) -> Result<(), Error> {
+    pop(assembler, function, xmm1)?;
+    pop(assembler, function, xmm0)?;
+    let ucomis = |a: &mut CodeAssembler, lhs, rhs| match ty {
+        Type::F32 => a.ucomiss(lhs, rhs),
+        _ => a.ucomisd(lhs, rhs),
+    };
+    let fused = match condition {
+        Condition::GtU | Condition::GeU => {
+            ucomis(assembler, xmm0, xmm1)?;
+            condition
+        }
+        // a < b is b > a
+        Condition::LtU => {
+            ucomis(assembler, xmm1, xmm0)?;
+            Condition::GtU
+        }
+        Condition::LeU => {
+            ucomis(assembler, xmm1, xmm0)?;
+            Condition::GeU
+        }
+        Condition::Eq => {
+            ucomis(assembler, xmm0, xmm1)?;
+            assembler.sete(al)?;
+            assembler.setnp(cl)?;
+            assembler.and(al, cl)?;
+            assembler.movzx(eax, al)?;
+            return function.push(assembler, rax, Type::I32);
+        }
+        _ => {
+            ucomis(assembler, xmm0, xmm1)?;
+            assembler.setne(al)?;
+            assembler.setp(cl)?;
+            assembler.or(al, cl)?;
+            assembler.movzx(eax, al)?;
+            return function.push(assembler, rax, Type::I32);
+        }
+    };
+    function.stack.push(Type::I32);
+    function.condition = Some(fused);
+    Ok(())
+}
@@ -0,0 +1,185 @@
+use crate::x86_64::context::{FunctionContext, ModuleContext};
+use crate::x86_64::{instructions, simd, Error};
+use iced_x86::code_asm::{
+    dword_ptr, eax, ptr, qword_ptr, r11, rax, rdi, xmm0, xmmword_ptr, CodeAssembler, CodeLabel,
+};
+use wasmparser_nostd::{InitExpr, Operator, Type};
+
+/// Storage of a global in the module binary
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct GlobalSlot {
+    /// Label of the slot holding the value, or the address of the value
+    /// for imported globals
+    pub(crate) label: CodeLabel,
+    pub(crate) ty: Type,
+    pub(crate) imported: bool,
+}
+
+/// Value of a constant expression, such as the initial value of a global
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Initializer {
+    /// Known at compile time, only v128 values use the upper 64 bits
+    Value(u128),
+    /// Copied from another (imported) global on instantiation
+    Global(u32),
+    /// Reference to a function
+    Function(u32),
+}
+
+/// Evaluates a constant expression
+pub(crate) fn initializer(expr: &InitExpr) -> Result<Initializer, Error> {
+    let mut reader = expr.get_operators_reader();
+    let offset = reader.original_position();
+    let initializer = match reader.read()? {
+        // i32 values are kept zero-extended in memory
+        Operator::I32Const { value } => Initializer::Value(value as u32 as u128),
+        Operator::I64Const { value } => Initializer::Value(value as u64 as u128),
+        Operator::F32Const { value } => Initializer::Value(value.bits() as u128),
+        Operator::F64Const { value } => Initializer::Value(value.bits() as u128),
+        Operator::V128Const { value } => Initializer::Value(value.i128() as u128),
+        Operator::GlobalGet { global_index } => Initializer::Global(global_index),
+        Operator::RefNull { .. } => Initializer::Value(0),
+        Operator::RefFunc { function_index } => Initializer::Function(function_index),
+        op => {
+            return Err(Error::unsupported(
+                instructions::operator_name(&op),
+                None,
+                offset,
+            ))
+        }
+    };
+    // Extended constant expressions aren't evaluated
+    let offset = reader.original_position();
+    match reader.read()? {
+        Operator::End => Ok(initializer),
+        op => Err(Error::unsupported(
+            instructions::operator_name(&op),
+            None,
+            offset,
+        )),
+    }
+}
+
+/// Emits the slot of a global, returning its label. Imported globals get
+/// a slot for the address of their value, to be filled when linking.
+pub(crate) fn slot(
+    assembler: &mut CodeAssembler,
+    module: &mut ModuleContext,
+    ty: Type,
+    value: u128,
+    imported: bool,
+) -> Result<CodeLabel, Error> {
+    let label = assembler.create_label();
+    module.bind(assembler, label);
+    if ty == Type::V128 && !imported {
+        assembler.dq(&[value as u64, (value >> 64) as u64])?;
+    } else {
+        assembler.dq(&[value as u64])?;
+    }
+    module.globals.push(GlobalSlot {
+        label,
+        ty,
+        imported,
+    });
+    Ok(label)
+}
+
+/// Loads an offset given by a constant expression into RDI, zero-extended
+/// if it is an i32
+pub(crate) fn load_offset(
+    assembler: &mut CodeAssembler,
+    module: &ModuleContext,
+    offset: Initializer,
+) -> Result<(), Error> {
+    match offset {
+        Initializer::Value(value) => assembler.mov(rdi, value as u64)?,
+        Initializer::Global(index) => {
+            load(assembler, module, index)?;
+            assembler.mov(rdi, rax)?;
+        }
+        // Offsets are integers
+        Initializer::Function(_) => return Err(module.unsupported("function reference offset")),
+    }
+    Ok(())
+}
+
+/// Leaves the address of the value of global `index` in R11
+fn address(
+    assembler: &mut CodeAssembler,
+    module: &ModuleContext,
+    index: u32,
+) -> Result<GlobalSlot, Error> {
+    let global = module.globals[index as usize];
+    if global.imported {
+        assembler.mov(r11, qword_ptr(global.label))?;
+    } else {
+        assembler.lea(r11, ptr(global.label))?;
+    }
+    Ok(global)
+}
+
+/// Loads global `index` into RAX, or XMM0 for v128 globals. Only as many
+/// bytes as the value has are read, as imported values are stored by the
+/// host in their natural size.
+pub(crate) fn load(
+    assembler: &mut CodeAssembler,
+    module: &ModuleContext,
+    index: u32,
+) -> Result<Type, Error> {
+    let global = address(assembler, module, index)?;
+    match global.ty {
+        Type::I32 | Type::F32 => assembler.mov(eax, dword_ptr(r11))?,
+        Type::I64 | Type::F64 | Type::FuncRef | Type::ExternRef => {
+            assembler.mov(rax, qword_ptr(r11))?
+        }
+        Type::V128 => assembler.movdqu(xmm0, xmmword_ptr(r11))?,
+        ty => return Err(module.unsupported(alloc::format!("{:?} global", ty))),
+    }
+    Ok(global.ty)
+}
+
+/// Stores RAX, or XMM0 for v128 globals, into global `index`
+pub(crate) fn store(
+    assembler: &mut CodeAssembler,
+    module: &ModuleContext,
+    index: u32,
+) -> Result<(), Error> {
+    let global = address(assembler, module, index)?;
+    match global.ty {
+        Type::I32 | Type::F32 => assembler.mov(dword_ptr(r11), eax)?,
+        Type::I64 | Type::F64 | Type::FuncRef | Type::ExternRef => {
+            assembler.mov(qword_ptr(r11), rax)?
+        }
+        Type::V128 => assembler.movdqu(xmmword_ptr(r11), xmm0)?,
+        ty => return Err(module.unsupported(alloc::format!("{:?} global", ty))),
+    }
+    Ok(())
+}
+
+/// `global.get`
+pub(crate) fn get(
+    assembler: &mut CodeAssembler,
+    module: &ModuleContext,
+    function: &mut FunctionContext,
+    index: u32,
+) -> Result<(), Error> {
+    match load(assembler, module, index)? {
+        Type::V128 => simd::push(assembler, function, xmm0),
+        ty => function.push(assembler, rax, ty),
+    }
+}
+
+/// `global.set`
+pub(crate) fn set(
+    assembler: &mut CodeAssembler,
+    module: &ModuleContext,
+    function: &mut FunctionContext,
+    index: u32,
+) -> Result<(), Error> {
+    if module.globals[index as usize].ty == Type::V128 {
+        simd::pop(assembler, function, xmm0)?;
+    } else {
+        function.pop(assembler, rax)?;
+    }
+    store(assembler, module, index)
+}
@@ -0,0 +1,45 @@
+use crate::x86_64::context::ModuleContext;
+use crate::x86_64::global::Initializer;
+use crate::x86_64::Error;
+use crate::x86_64::{data, exception, global, memory, table};
+use iced_x86::code_asm::{CodeAssembler, CodeLabel};
+
+/// Emits the module initializer, returning its label.
+///
+/// It performs the parts of instantiation that depend on the linked
+/// imports, and has to be called once by the embedder after linking the
+/// module and before calling any of its functions.
+pub(crate) fn initializer(
+    assembler: &mut CodeAssembler,
+    module: &mut ModuleContext,
+) -> Result<CodeLabel, Error> {
+    let label = assembler.create_label();
+    module.bind(assembler, label);
+    exception::setup(assembler, module)?;
+    memory::setup(assembler, module)?;
+    table::setup(assembler, module)?;
+    table::setup_signatures(assembler, module)?;
+    table::setup_references(assembler, module)?;
+    for (index, value) in module.global_initializers.iter() {
+        match value {
+            Initializer::Function(function) => {
+                table::function_reference(assembler, module, *function)?
+            }
+            Initializer::Global(source) => {
+                global::load(assembler, module, *source)?;
+            }
+            Initializer::Value(_) => continue,
+        }
+        global::store(assembler, module, *index)?;
+    }
+    // Element items may read globals
+    table::setup_segments(assembler, module)?;
+    for (table, offset, items) in module.active_elements.clone() {
+        table::apply(assembler, module, table, offset, &items)?;
+    }
+    for (segment, memory, offset) in module.active_data.clone() {
+        data::apply(assembler, module, segment, memory, offset)?;
+    }
+    assembler.ret()?;
+    Ok(label)
+}
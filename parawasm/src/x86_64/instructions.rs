@@ -1,627 +1,1217 @@
-use crate::x86_64::Error;
-use alloc::collections::{BTreeMap, VecDeque};
-use alloc::vec;
-use alloc::vec::Vec;
+use crate::x86_64::atomic::{self, Rmw};
+use crate::x86_64::compare::{self, Condition};
+use crate::x86_64::context::{FunctionContext, ModuleContext};
+use crate::x86_64::control::{self, FrameKind};
+use crate::x86_64::convert;
+use crate::x86_64::float::{self, Rounding};
+use crate::x86_64::integer::Shift;
+use crate::x86_64::simd::{self, Shape};
+use crate::x86_64::trap::{self, TrapCode};
+use crate::x86_64::{call, data, exception, global, integer, memory, reference, table, Error};
+use alloc::format;
+use alloc::string::String;
 use iced_x86::code_asm::{
-    eax, ebx, ptr, r10, r8, r9, rax, rbp, rbx, rcx, rdi, rdx, rsi, AsmRegister64, CodeAssembler,
-    CodeLabel,
+    cl, eax, ecx, ptr, rax, rbp, rcx, rsp, xmm0, xmm1, xmmword_ptr, CodeAssembler,
 };
-use wasmparser_nostd::{FuncType, Operator, Type};
+use wasmparser_nostd::{Operator, Type};
 
 pub(crate) fn handle_instruction(
     assembler: &mut CodeAssembler,
-    got: &mut BTreeMap<u32, CodeLabel>,
-    ils: &mut BTreeMap<u32, CodeLabel>,
-    function_typedefs: &mut BTreeMap<u32, FuncType>,
-    function_types: &mut BTreeMap<u32, u32>,
-    locals: &Vec<u32>,
+    module: &mut ModuleContext,
+    function: &mut FunctionContext,
     op: Operator,
 ) -> Result<(), Error> {
+    // Comparisons are fused with instructions consuming their result as a
+    // condition, anything else needs the result on the operand stack
+    match op {
+        Operator::If { .. } | Operator::BrIf { .. } | Operator::Select => (),
+        Operator::TypedSelect { .. } => (),
+        _ => compare::materialize(assembler, function)?,
+    }
+    // Nothing is generated for unreachable code, only control frames are tracked
+    if !function.reachable {
+        match op {
+            Operator::Block { .. }
+            | Operator::Loop { .. }
+            | Operator::If { .. }
+            | Operator::Else
+            | Operator::Try { .. }
+            | Operator::Catch { .. }
+            | Operator::CatchAll
+            | Operator::Delegate { .. }
+            | Operator::End => (),
+            _ => return Ok(()),
+        }
+    }
     match op {
         Operator::I64Const { value } => {
             assembler.mov(rax, value)?;
-            assembler.push(rax)?;
-        }
-        Operator::I64Add => {
-            assembler.pop(rax)?;
-            assembler.pop(rbx)?;
-            assembler.add(rax, rbx)?;
-            assembler.push(rax)?;
-        }
-        Operator::I32Add => {
-            assembler.pop(eax)?;
-            assembler.pop(ebx)?;
-            assembler.add(eax, ebx)?;
-            assembler.push(eax)?;
-        }
-        Operator::I64Sub => {
-            assembler.pop(rax)?;
-            assembler.pop(rbx)?;
-            assembler.sub(rax, rbx)?;
-            assembler.push(rax)?;
-        }
-        Operator::I32Sub => {
-            assembler.pop(eax)?;
-            assembler.pop(ebx)?;
-            assembler.sub(eax, ebx)?;
-            assembler.push(eax)?;
+            function.push(assembler, rax, Type::I64)?;
         }
+        Operator::I64Add => integer::binary(assembler, function, Type::I64, |a| a.add(rax, rcx))?,
+        Operator::I32Add => integer::binary(assembler, function, Type::I32, |a| a.add(eax, ecx))?,
+        Operator::I64Sub => integer::binary(assembler, function, Type::I64, |a| a.sub(rax, rcx))?,
+        Operator::I32Sub => integer::binary(assembler, function, Type::I32, |a| a.sub(eax, ecx))?,
         Operator::Call { function_index } => {
-            let called_function_type = function_types
-                .get(&function_index)
-                .and_then(|i| function_types.get(&i))
-                .and_then(|t| function_typedefs.get(t))
-                .cloned()
-                .unwrap();
-            let mut integer_order: VecDeque<AsmRegister64> = vec![rdi, rsi, rdx, rcx, r8, r9]
-                .drain(0..called_function_type.params.len())
-                .collect();
-            for param in called_function_type.params.iter() {
-                match param {
-                    Type::I64 | Type::I32 => match integer_order.pop_back() {
-                        Some(reg) => assembler.pop(reg)?,
-                        None => todo!(),
-                    },
-                    _ => todo!(),
-                }
+            call::direct(assembler, module, function, function_index, false)?
+        }
+        Operator::ReturnCall { function_index } => {
+            call::direct(assembler, module, function, function_index, true)?
+        }
+        Operator::Unreachable => {
+            trap::trap(assembler, module, TrapCode::UnreachableCodeReached)?;
+            function.reachable = false;
+        }
+        Operator::Nop => assembler.nop()?,
+        Operator::Block { ty } => {
+            control::enter(assembler, module, function, FrameKind::Block, ty, None)?
+        }
+        Operator::Loop { ty } => {
+            control::enter(assembler, module, function, FrameKind::Loop, ty, None)?
+        }
+        Operator::If { ty } => {
+            let condition = if function.reachable {
+                Some(compare::take_condition(assembler, function)?)
+            } else {
+                None
+            };
+            control::enter(assembler, module, function, FrameKind::If, ty, condition)?
+        }
+        Operator::Else => control::else_(assembler, module, function)?,
+        Operator::End => control::end(assembler, module, function)?,
+        Operator::Br { relative_depth } => control::branch(assembler, function, relative_depth)?,
+        Operator::BrIf { relative_depth } => {
+            let condition = compare::take_condition(assembler, function)?;
+            control::branch_if(assembler, module, function, relative_depth, condition)?
+        }
+        Operator::BrTable { table } => {
+            function.pop(assembler, rax)?;
+            control::branch_table(assembler, module, function, table)?
+        }
+        Operator::Return => {
+            let depth = function.frames.len() as u32 - 1;
+            control::branch(assembler, function, depth)?
+        }
+        Operator::Try { ty } => exception::try_(assembler, module, function, ty)?,
+        Operator::Catch { index } => exception::catch(assembler, module, function, Some(index))?,
+        Operator::CatchAll => exception::catch(assembler, module, function, None)?,
+        Operator::Delegate { relative_depth } => {
+            exception::delegate(assembler, module, function, relative_depth)?
+        }
+        Operator::Throw { index } => exception::throw(assembler, module, function, index)?,
+        Operator::Rethrow { relative_depth } => {
+            exception::rethrow(assembler, module, function, relative_depth)?
+        }
+        Operator::CallIndirect { index, table_index } => {
+            call::indirect(assembler, module, function, index, table_index, false)?
+        }
+        Operator::ReturnCallIndirect { index, table_index } => {
+            call::indirect(assembler, module, function, index, table_index, true)?
+        }
+        Operator::Drop => match function.stack.last() {
+            Some(Type::V128) => simd::drop(assembler, function)?,
+            _ => function.pop(assembler, rax)?,
+        },
+        Operator::Select => compare::select(assembler, module, function)?,
+        Operator::TypedSelect { .. } => compare::select(assembler, module, function)?,
+        Operator::LocalGet { local_index } => match function.locals.get(local_index as usize) {
+            Some((offset, Type::V128)) => {
+                assembler.movdqu(xmm0, xmmword_ptr(rbp - *offset))?;
+                simd::push(assembler, function, xmm0)?;
             }
-            match got.get(&function_index) {
-                None => {
-                    if let Some(import_label) = ils.get(&function_index) {
-                        assembler.mov(r10, ptr(*import_label))?;
-                        assembler.call(r10)?;
-                    }
-                }
-                Some(label) => {
-                    assembler.call(*label)?;
-                }
+            Some((offset, ty)) => {
+                let (offset, ty) = (*offset, *ty);
+                assembler.mov(rax, ptr(rbp - offset))?;
+                function.push(assembler, rax, ty)?;
             }
-            let mut integer_order = VecDeque::from([rax, rdx]);
-            for ret in called_function_type.returns.iter() {
-                match ret {
-                    Type::I64 | Type::I32 => match integer_order.pop_front() {
-                        Some(reg) => assembler.push(reg)?,
-                        None => (),
-                    },
-                    _ => todo!(),
-                }
+            None => return Err(module.unsupported(operator_name(&op))),
+        },
+        Operator::LocalSet { local_index } => match function.locals.get(local_index as usize) {
+            Some((offset, Type::V128)) => {
+                let offset = *offset;
+                simd::pop(assembler, function, xmm0)?;
+                assembler.movdqu(xmmword_ptr(rbp - offset), xmm0)?;
             }
-        }
-        Operator::Unreachable => todo!(),
-        Operator::Nop => assembler.nop()?,
-        Operator::Block { .. } => todo!(),
-        Operator::Loop { .. } => todo!(),
-        Operator::If { .. } => todo!(),
-        Operator::Else => todo!(),
-        Operator::Try { .. } => todo!(),
-        Operator::Catch { .. } => todo!(),
-        Operator::Throw { .. } => todo!(),
-        Operator::Rethrow { .. } => todo!(),
-        Operator::End => {}
-        Operator::Br { .. } => todo!(),
-        Operator::BrIf { .. } => todo!(),
-        Operator::BrTable { .. } => todo!(),
-        Operator::Return => todo!(),
-        Operator::CallIndirect { .. } => todo!(),
-        Operator::ReturnCall { .. } => todo!(),
-        Operator::ReturnCallIndirect { .. } => todo!(),
-        Operator::Delegate { .. } => todo!(),
-        Operator::CatchAll => todo!(),
-        Operator::Drop => todo!(),
-        Operator::Select => todo!(),
-        Operator::TypedSelect { .. } => todo!(),
-        Operator::LocalGet { local_index } => match locals.get(local_index as usize) {
-            Some(offset) => {
-                assembler.mov(rax, ptr(rbp - *offset))?;
-                assembler.push(rax)?;
+            Some((offset, _)) => {
+                let offset = *offset;
+                function.pop(assembler, rax)?;
+                assembler.mov(ptr(rbp - offset), rax)?;
             }
-            None => todo!(),
+            None => return Err(module.unsupported(operator_name(&op))),
         },
-        Operator::LocalSet { local_index } => match locals.get(local_index as usize) {
-            Some(offset) => {
-                assembler.pop(rax)?;
-                assembler.mov(ptr(rbp - *offset), rax)?;
+        Operator::LocalTee { local_index } => match function.locals.get(local_index as usize) {
+            Some((offset, Type::V128)) => {
+                assembler.movdqu(xmm0, xmmword_ptr(rsp))?;
+                assembler.movdqu(xmmword_ptr(rbp - *offset), xmm0)?;
             }
-            None => todo!(),
+            Some((offset, ty)) => {
+                let (offset, ty) = (*offset, *ty);
+                function.pop(assembler, rax)?;
+                assembler.mov(ptr(rbp - offset), rax)?;
+                function.push(assembler, rax, ty)?;
+            }
+            None => return Err(module.unsupported(operator_name(&op))),
         },
-        Operator::LocalTee { .. } => todo!(),
-        Operator::GlobalGet { .. } => todo!(),
-        Operator::GlobalSet { .. } => todo!(),
-        Operator::I32Load { .. } => todo!(),
-        Operator::I64Load { .. } => todo!(),
-        Operator::F32Load { .. } => todo!(),
-        Operator::F64Load { .. } => todo!(),
-        Operator::I32Load8S { .. } => todo!(),
-        Operator::I32Load8U { .. } => todo!(),
-        Operator::I32Load16S { .. } => todo!(),
-        Operator::I32Load16U { .. } => todo!(),
-        Operator::I64Load8S { .. } => todo!(),
-        Operator::I64Load8U { .. } => todo!(),
-        Operator::I64Load16S { .. } => todo!(),
-        Operator::I64Load16U { .. } => todo!(),
-        Operator::I64Load32S { .. } => todo!(),
-        Operator::I64Load32U { .. } => todo!(),
-        Operator::I32Store { .. } => todo!(),
-        Operator::I64Store { .. } => todo!(),
-        Operator::F32Store { .. } => todo!(),
-        Operator::F64Store { .. } => todo!(),
-        Operator::I32Store8 { .. } => todo!(),
-        Operator::I32Store16 { .. } => todo!(),
-        Operator::I64Store8 { .. } => todo!(),
-        Operator::I64Store16 { .. } => todo!(),
-        Operator::I64Store32 { .. } => todo!(),
-        Operator::MemorySize { .. } => todo!(),
-        Operator::MemoryGrow { .. } => todo!(),
-        Operator::I32Const { .. } => todo!(),
-        Operator::F32Const { .. } => todo!(),
-        Operator::F64Const { .. } => todo!(),
-        Operator::RefNull { .. } => todo!(),
-        Operator::RefIsNull => todo!(),
-        Operator::RefFunc { .. } => todo!(),
-        Operator::I32Eqz => todo!(),
-        Operator::I32Eq => todo!(),
-        Operator::I32Ne => todo!(),
-        Operator::I32LtS => todo!(),
-        Operator::I32LtU => todo!(),
-        Operator::I32GtS => todo!(),
-        Operator::I32GtU => todo!(),
-        Operator::I32LeS => todo!(),
-        Operator::I32LeU => todo!(),
-        Operator::I32GeS => todo!(),
-        Operator::I32GeU => todo!(),
-        Operator::I64Eqz => todo!(),
-        Operator::I64Eq => todo!(),
-        Operator::I64Ne => todo!(),
-        Operator::I64LtS => todo!(),
-        Operator::I64LtU => todo!(),
-        Operator::I64GtS => todo!(),
-        Operator::I64GtU => todo!(),
-        Operator::I64LeS => todo!(),
-        Operator::I64LeU => todo!(),
-        Operator::I64GeS => todo!(),
-        Operator::I64GeU => todo!(),
-        Operator::F32Eq => todo!(),
-        Operator::F32Ne => todo!(),
-        Operator::F32Lt => todo!(),
-        Operator::F32Gt => todo!(),
-        Operator::F32Le => todo!(),
-        Operator::F32Ge => todo!(),
-        Operator::F64Eq => todo!(),
-        Operator::F64Ne => todo!(),
-        Operator::F64Lt => todo!(),
-        Operator::F64Gt => todo!(),
-        Operator::F64Le => todo!(),
-        Operator::F64Ge => todo!(),
-        Operator::I32Clz => todo!(),
-        Operator::I32Ctz => todo!(),
-        Operator::I32Popcnt => todo!(),
-        Operator::I32Mul => todo!(),
-        Operator::I32DivS => todo!(),
-        Operator::I32DivU => todo!(),
-        Operator::I32RemS => todo!(),
-        Operator::I32RemU => todo!(),
-        Operator::I32And => todo!(),
-        Operator::I32Or => todo!(),
-        Operator::I32Xor => todo!(),
-        Operator::I32Shl => todo!(),
-        Operator::I32ShrS => todo!(),
-        Operator::I32ShrU => todo!(),
-        Operator::I32Rotl => todo!(),
-        Operator::I32Rotr => todo!(),
-        Operator::I64Clz => todo!(),
-        Operator::I64Ctz => todo!(),
-        Operator::I64Popcnt => todo!(),
-        Operator::I64Mul => todo!(),
-        Operator::I64DivS => todo!(),
-        Operator::I64DivU => todo!(),
-        Operator::I64RemS => todo!(),
-        Operator::I64RemU => todo!(),
-        Operator::I64And => todo!(),
-        Operator::I64Or => todo!(),
-        Operator::I64Xor => todo!(),
-        Operator::I64Shl => todo!(),
-        Operator::I64ShrS => todo!(),
-        Operator::I64ShrU => todo!(),
-        Operator::I64Rotl => todo!(),
-        Operator::I64Rotr => todo!(),
-        Operator::F32Abs => todo!(),
-        Operator::F32Neg => todo!(),
-        Operator::F32Ceil => todo!(),
-        Operator::F32Floor => todo!(),
-        Operator::F32Trunc => todo!(),
-        Operator::F32Nearest => todo!(),
-        Operator::F32Sqrt => todo!(),
-        Operator::F32Add => todo!(),
-        Operator::F32Sub => todo!(),
-        Operator::F32Mul => todo!(),
-        Operator::F32Div => todo!(),
-        Operator::F32Min => todo!(),
-        Operator::F32Max => todo!(),
-        Operator::F32Copysign => todo!(),
-        Operator::F64Abs => todo!(),
-        Operator::F64Neg => todo!(),
-        Operator::F64Ceil => todo!(),
-        Operator::F64Floor => todo!(),
-        Operator::F64Trunc => todo!(),
-        Operator::F64Nearest => todo!(),
-        Operator::F64Sqrt => todo!(),
-        Operator::F64Add => todo!(),
-        Operator::F64Sub => todo!(),
-        Operator::F64Mul => todo!(),
-        Operator::F64Div => todo!(),
-        Operator::F64Min => todo!(),
-        Operator::F64Max => todo!(),
-        Operator::F64Copysign => todo!(),
-        Operator::I32WrapI64 => todo!(),
-        Operator::I32TruncF32S => todo!(),
-        Operator::I32TruncF32U => todo!(),
-        Operator::I32TruncF64S => todo!(),
-        Operator::I32TruncF64U => todo!(),
-        Operator::I64ExtendI32S => todo!(),
-        Operator::I64ExtendI32U => todo!(),
-        Operator::I64TruncF32S => todo!(),
-        Operator::I64TruncF32U => todo!(),
-        Operator::I64TruncF64S => todo!(),
-        Operator::I64TruncF64U => todo!(),
-        Operator::F32ConvertI32S => todo!(),
-        Operator::F32ConvertI32U => todo!(),
-        Operator::F32ConvertI64S => todo!(),
-        Operator::F32ConvertI64U => todo!(),
-        Operator::F32DemoteF64 => todo!(),
-        Operator::F64ConvertI32S => todo!(),
-        Operator::F64ConvertI32U => todo!(),
-        Operator::F64ConvertI64S => todo!(),
-        Operator::F64ConvertI64U => todo!(),
-        Operator::F64PromoteF32 => todo!(),
-        Operator::I32ReinterpretF32 => todo!(),
-        Operator::I64ReinterpretF64 => todo!(),
-        Operator::F32ReinterpretI32 => todo!(),
-        Operator::F64ReinterpretI64 => todo!(),
-        Operator::I32Extend8S => todo!(),
-        Operator::I32Extend16S => todo!(),
-        Operator::I64Extend8S => todo!(),
-        Operator::I64Extend16S => todo!(),
-        Operator::I64Extend32S => todo!(),
-        Operator::I32TruncSatF32S => todo!(),
-        Operator::I32TruncSatF32U => todo!(),
-        Operator::I32TruncSatF64S => todo!(),
-        Operator::I32TruncSatF64U => todo!(),
-        Operator::I64TruncSatF32S => todo!(),
-        Operator::I64TruncSatF32U => todo!(),
-        Operator::I64TruncSatF64S => todo!(),
-        Operator::I64TruncSatF64U => todo!(),
-        Operator::MemoryInit { .. } => todo!(),
-        Operator::DataDrop { .. } => todo!(),
-        Operator::MemoryCopy { .. } => todo!(),
-        Operator::MemoryFill { .. } => todo!(),
-        Operator::TableInit { .. } => todo!(),
-        Operator::ElemDrop { .. } => todo!(),
-        Operator::TableCopy { .. } => todo!(),
-        Operator::TableFill { .. } => todo!(),
-        Operator::TableGet { .. } => todo!(),
-        Operator::TableSet { .. } => todo!(),
-        Operator::TableGrow { .. } => todo!(),
-        Operator::TableSize { .. } => todo!(),
-        Operator::MemoryAtomicNotify { .. } => todo!(),
-        Operator::MemoryAtomicWait32 { .. } => todo!(),
-        Operator::MemoryAtomicWait64 { .. } => todo!(),
-        Operator::AtomicFence { .. } => todo!(),
-        Operator::I32AtomicLoad { .. } => todo!(),
-        Operator::I64AtomicLoad { .. } => todo!(),
-        Operator::I32AtomicLoad8U { .. } => todo!(),
-        Operator::I32AtomicLoad16U { .. } => todo!(),
-        Operator::I64AtomicLoad8U { .. } => todo!(),
-        Operator::I64AtomicLoad16U { .. } => todo!(),
-        Operator::I64AtomicLoad32U { .. } => todo!(),
-        Operator::I32AtomicStore { .. } => todo!(),
-        Operator::I64AtomicStore { .. } => todo!(),
-        Operator::I32AtomicStore8 { .. } => todo!(),
-        Operator::I32AtomicStore16 { .. } => todo!(),
-        Operator::I64AtomicStore8 { .. } => todo!(),
-        Operator::I64AtomicStore16 { .. } => todo!(),
-        Operator::I64AtomicStore32 { .. } => todo!(),
-        Operator::I32AtomicRmwAdd { .. } => todo!(),
-        Operator::I64AtomicRmwAdd { .. } => todo!(),
-        Operator::I32AtomicRmw8AddU { .. } => todo!(),
-        Operator::I32AtomicRmw16AddU { .. } => todo!(),
-        Operator::I64AtomicRmw8AddU { .. } => todo!(),
-        Operator::I64AtomicRmw16AddU { .. } => todo!(),
-        Operator::I64AtomicRmw32AddU { .. } => todo!(),
-        Operator::I32AtomicRmwSub { .. } => todo!(),
-        Operator::I64AtomicRmwSub { .. } => todo!(),
-        Operator::I32AtomicRmw8SubU { .. } => todo!(),
-        Operator::I32AtomicRmw16SubU { .. } => todo!(),
-        Operator::I64AtomicRmw8SubU { .. } => todo!(),
-        Operator::I64AtomicRmw16SubU { .. } => todo!(),
-        Operator::I64AtomicRmw32SubU { .. } => todo!(),
-        Operator::I32AtomicRmwAnd { .. } => todo!(),
-        Operator::I64AtomicRmwAnd { .. } => todo!(),
-        Operator::I32AtomicRmw8AndU { .. } => todo!(),
-        Operator::I32AtomicRmw16AndU { .. } => todo!(),
-        Operator::I64AtomicRmw8AndU { .. } => todo!(),
-        Operator::I64AtomicRmw16AndU { .. } => todo!(),
-        Operator::I64AtomicRmw32AndU { .. } => todo!(),
-        Operator::I32AtomicRmwOr { .. } => todo!(),
-        Operator::I64AtomicRmwOr { .. } => todo!(),
-        Operator::I32AtomicRmw8OrU { .. } => todo!(),
-        Operator::I32AtomicRmw16OrU { .. } => todo!(),
-        Operator::I64AtomicRmw8OrU { .. } => todo!(),
-        Operator::I64AtomicRmw16OrU { .. } => todo!(),
-        Operator::I64AtomicRmw32OrU { .. } => todo!(),
-        Operator::I32AtomicRmwXor { .. } => todo!(),
-        Operator::I64AtomicRmwXor { .. } => todo!(),
-        Operator::I32AtomicRmw8XorU { .. } => todo!(),
-        Operator::I32AtomicRmw16XorU { .. } => todo!(),
-        Operator::I64AtomicRmw8XorU { .. } => todo!(),
-        Operator::I64AtomicRmw16XorU { .. } => todo!(),
-        Operator::I64AtomicRmw32XorU { .. } => todo!(),
-        Operator::I32AtomicRmwXchg { .. } => todo!(),
-        Operator::I64AtomicRmwXchg { .. } => todo!(),
-        Operator::I32AtomicRmw8XchgU { .. } => todo!(),
-        Operator::I32AtomicRmw16XchgU { .. } => todo!(),
-        Operator::I64AtomicRmw8XchgU { .. } => todo!(),
-        Operator::I64AtomicRmw16XchgU { .. } => todo!(),
-        Operator::I64AtomicRmw32XchgU { .. } => todo!(),
-        Operator::I32AtomicRmwCmpxchg { .. } => todo!(),
-        Operator::I64AtomicRmwCmpxchg { .. } => todo!(),
-        Operator::I32AtomicRmw8CmpxchgU { .. } => todo!(),
-        Operator::I32AtomicRmw16CmpxchgU { .. } => todo!(),
-        Operator::I64AtomicRmw8CmpxchgU { .. } => todo!(),
-        Operator::I64AtomicRmw16CmpxchgU { .. } => todo!(),
-        Operator::I64AtomicRmw32CmpxchgU { .. } => todo!(),
-        Operator::V128Load { .. } => todo!(),
-        Operator::V128Load8x8S { .. } => todo!(),
-        Operator::V128Load8x8U { .. } => todo!(),
-        Operator::V128Load16x4S { .. } => todo!(),
-        Operator::V128Load16x4U { .. } => todo!(),
-        Operator::V128Load32x2S { .. } => todo!(),
-        Operator::V128Load32x2U { .. } => todo!(),
-        Operator::V128Load8Splat { .. } => todo!(),
-        Operator::V128Load16Splat { .. } => todo!(),
-        Operator::V128Load32Splat { .. } => todo!(),
-        Operator::V128Load64Splat { .. } => todo!(),
-        Operator::V128Load32Zero { .. } => todo!(),
-        Operator::V128Load64Zero { .. } => todo!(),
-        Operator::V128Store { .. } => todo!(),
-        Operator::V128Load8Lane { .. } => todo!(),
-        Operator::V128Load16Lane { .. } => todo!(),
-        Operator::V128Load32Lane { .. } => todo!(),
-        Operator::V128Load64Lane { .. } => todo!(),
-        Operator::V128Store8Lane { .. } => todo!(),
-        Operator::V128Store16Lane { .. } => todo!(),
-        Operator::V128Store32Lane { .. } => todo!(),
-        Operator::V128Store64Lane { .. } => todo!(),
-        Operator::V128Const { .. } => todo!(),
-        Operator::I8x16Shuffle { .. } => todo!(),
-        Operator::I8x16ExtractLaneS { .. } => todo!(),
-        Operator::I8x16ExtractLaneU { .. } => todo!(),
-        Operator::I8x16ReplaceLane { .. } => todo!(),
-        Operator::I16x8ExtractLaneS { .. } => todo!(),
-        Operator::I16x8ExtractLaneU { .. } => todo!(),
-        Operator::I16x8ReplaceLane { .. } => todo!(),
-        Operator::I32x4ExtractLane { .. } => todo!(),
-        Operator::I32x4ReplaceLane { .. } => todo!(),
-        Operator::I64x2ExtractLane { .. } => todo!(),
-        Operator::I64x2ReplaceLane { .. } => todo!(),
-        Operator::F32x4ExtractLane { .. } => todo!(),
-        Operator::F32x4ReplaceLane { .. } => todo!(),
-        Operator::F64x2ExtractLane { .. } => todo!(),
-        Operator::F64x2ReplaceLane { .. } => todo!(),
-        Operator::I8x16Swizzle => todo!(),
-        Operator::I8x16Splat => todo!(),
-        Operator::I16x8Splat => todo!(),
-        Operator::I32x4Splat => todo!(),
-        Operator::I64x2Splat => todo!(),
-        Operator::F32x4Splat => todo!(),
-        Operator::F64x2Splat => todo!(),
-        Operator::I8x16Eq => todo!(),
-        Operator::I8x16Ne => todo!(),
-        Operator::I8x16LtS => todo!(),
-        Operator::I8x16LtU => todo!(),
-        Operator::I8x16GtS => todo!(),
-        Operator::I8x16GtU => todo!(),
-        Operator::I8x16LeS => todo!(),
-        Operator::I8x16LeU => todo!(),
-        Operator::I8x16GeS => todo!(),
-        Operator::I8x16GeU => todo!(),
-        Operator::I16x8Eq => todo!(),
-        Operator::I16x8Ne => todo!(),
-        Operator::I16x8LtS => todo!(),
-        Operator::I16x8LtU => todo!(),
-        Operator::I16x8GtS => todo!(),
-        Operator::I16x8GtU => todo!(),
-        Operator::I16x8LeS => todo!(),
-        Operator::I16x8LeU => todo!(),
-        Operator::I16x8GeS => todo!(),
-        Operator::I16x8GeU => todo!(),
-        Operator::I32x4Eq => todo!(),
-        Operator::I32x4Ne => todo!(),
-        Operator::I32x4LtS => todo!(),
-        Operator::I32x4LtU => todo!(),
-        Operator::I32x4GtS => todo!(),
-        Operator::I32x4GtU => todo!(),
-        Operator::I32x4LeS => todo!(),
-        Operator::I32x4LeU => todo!(),
-        Operator::I32x4GeS => todo!(),
-        Operator::I32x4GeU => todo!(),
-        Operator::I64x2Eq => todo!(),
-        Operator::I64x2Ne => todo!(),
-        Operator::I64x2LtS => todo!(),
-        Operator::I64x2GtS => todo!(),
-        Operator::I64x2LeS => todo!(),
-        Operator::I64x2GeS => todo!(),
-        Operator::F32x4Eq => todo!(),
-        Operator::F32x4Ne => todo!(),
-        Operator::F32x4Lt => todo!(),
-        Operator::F32x4Gt => todo!(),
-        Operator::F32x4Le => todo!(),
-        Operator::F32x4Ge => todo!(),
-        Operator::F64x2Eq => todo!(),
-        Operator::F64x2Ne => todo!(),
-        Operator::F64x2Lt => todo!(),
-        Operator::F64x2Gt => todo!(),
-        Operator::F64x2Le => todo!(),
-        Operator::F64x2Ge => todo!(),
-        Operator::V128Not => todo!(),
-        Operator::V128And => todo!(),
-        Operator::V128AndNot => todo!(),
-        Operator::V128Or => todo!(),
-        Operator::V128Xor => todo!(),
-        Operator::V128Bitselect => todo!(),
-        Operator::V128AnyTrue => todo!(),
-        Operator::I8x16Abs => todo!(),
-        Operator::I8x16Neg => todo!(),
-        Operator::I8x16Popcnt => todo!(),
-        Operator::I8x16AllTrue => todo!(),
-        Operator::I8x16Bitmask => todo!(),
-        Operator::I8x16NarrowI16x8S => todo!(),
-        Operator::I8x16NarrowI16x8U => todo!(),
-        Operator::I8x16Shl => todo!(),
-        Operator::I8x16ShrS => todo!(),
-        Operator::I8x16ShrU => todo!(),
-        Operator::I8x16Add => todo!(),
-        Operator::I8x16AddSatS => todo!(),
-        Operator::I8x16AddSatU => todo!(),
-        Operator::I8x16Sub => todo!(),
-        Operator::I8x16SubSatS => todo!(),
-        Operator::I8x16SubSatU => todo!(),
-        Operator::I8x16MinS => todo!(),
-        Operator::I8x16MinU => todo!(),
-        Operator::I8x16MaxS => todo!(),
-        Operator::I8x16MaxU => todo!(),
-        Operator::I8x16RoundingAverageU => todo!(),
-        Operator::I16x8ExtAddPairwiseI8x16S => todo!(),
-        Operator::I16x8ExtAddPairwiseI8x16U => todo!(),
-        Operator::I16x8Abs => todo!(),
-        Operator::I16x8Neg => todo!(),
-        Operator::I16x8Q15MulrSatS => todo!(),
-        Operator::I16x8AllTrue => todo!(),
-        Operator::I16x8Bitmask => todo!(),
-        Operator::I16x8NarrowI32x4S => todo!(),
-        Operator::I16x8NarrowI32x4U => todo!(),
-        Operator::I16x8ExtendLowI8x16S => todo!(),
-        Operator::I16x8ExtendHighI8x16S => todo!(),
-        Operator::I16x8ExtendLowI8x16U => todo!(),
-        Operator::I16x8ExtendHighI8x16U => todo!(),
-        Operator::I16x8Shl => todo!(),
-        Operator::I16x8ShrS => todo!(),
-        Operator::I16x8ShrU => todo!(),
-        Operator::I16x8Add => todo!(),
-        Operator::I16x8AddSatS => todo!(),
-        Operator::I16x8AddSatU => todo!(),
-        Operator::I16x8Sub => todo!(),
-        Operator::I16x8SubSatS => todo!(),
-        Operator::I16x8SubSatU => todo!(),
-        Operator::I16x8Mul => todo!(),
-        Operator::I16x8MinS => todo!(),
-        Operator::I16x8MinU => todo!(),
-        Operator::I16x8MaxS => todo!(),
-        Operator::I16x8MaxU => todo!(),
-        Operator::I16x8RoundingAverageU => todo!(),
-        Operator::I16x8ExtMulLowI8x16S => todo!(),
-        Operator::I16x8ExtMulHighI8x16S => todo!(),
-        Operator::I16x8ExtMulLowI8x16U => todo!(),
-        Operator::I16x8ExtMulHighI8x16U => todo!(),
-        Operator::I32x4ExtAddPairwiseI16x8S => todo!(),
-        Operator::I32x4ExtAddPairwiseI16x8U => todo!(),
-        Operator::I32x4Abs => todo!(),
-        Operator::I32x4Neg => todo!(),
-        Operator::I32x4AllTrue => todo!(),
-        Operator::I32x4Bitmask => todo!(),
-        Operator::I32x4ExtendLowI16x8S => todo!(),
-        Operator::I32x4ExtendHighI16x8S => todo!(),
-        Operator::I32x4ExtendLowI16x8U => todo!(),
-        Operator::I32x4ExtendHighI16x8U => todo!(),
-        Operator::I32x4Shl => todo!(),
-        Operator::I32x4ShrS => todo!(),
-        Operator::I32x4ShrU => todo!(),
-        Operator::I32x4Add => todo!(),
-        Operator::I32x4Sub => todo!(),
-        Operator::I32x4Mul => todo!(),
-        Operator::I32x4MinS => todo!(),
-        Operator::I32x4MinU => todo!(),
-        Operator::I32x4MaxS => todo!(),
-        Operator::I32x4MaxU => todo!(),
-        Operator::I32x4DotI16x8S => todo!(),
-        Operator::I32x4ExtMulLowI16x8S => todo!(),
-        Operator::I32x4ExtMulHighI16x8S => todo!(),
-        Operator::I32x4ExtMulLowI16x8U => todo!(),
-        Operator::I32x4ExtMulHighI16x8U => todo!(),
-        Operator::I64x2Abs => todo!(),
-        Operator::I64x2Neg => todo!(),
-        Operator::I64x2AllTrue => todo!(),
-        Operator::I64x2Bitmask => todo!(),
-        Operator::I64x2ExtendLowI32x4S => todo!(),
-        Operator::I64x2ExtendHighI32x4S => todo!(),
-        Operator::I64x2ExtendLowI32x4U => todo!(),
-        Operator::I64x2ExtendHighI32x4U => todo!(),
-        Operator::I64x2Shl => todo!(),
-        Operator::I64x2ShrS => todo!(),
-        Operator::I64x2ShrU => todo!(),
-        Operator::I64x2Add => todo!(),
-        Operator::I64x2Sub => todo!(),
-        Operator::I64x2Mul => todo!(),
-        Operator::I64x2ExtMulLowI32x4S => todo!(),
-        Operator::I64x2ExtMulHighI32x4S => todo!(),
-        Operator::I64x2ExtMulLowI32x4U => todo!(),
-        Operator::I64x2ExtMulHighI32x4U => todo!(),
-        Operator::F32x4Ceil => todo!(),
-        Operator::F32x4Floor => todo!(),
-        Operator::F32x4Trunc => todo!(),
-        Operator::F32x4Nearest => todo!(),
-        Operator::F32x4Abs => todo!(),
-        Operator::F32x4Neg => todo!(),
-        Operator::F32x4Sqrt => todo!(),
-        Operator::F32x4Add => todo!(),
-        Operator::F32x4Sub => todo!(),
-        Operator::F32x4Mul => todo!(),
-        Operator::F32x4Div => todo!(),
-        Operator::F32x4Min => todo!(),
-        Operator::F32x4Max => todo!(),
-        Operator::F32x4PMin => todo!(),
-        Operator::F32x4PMax => todo!(),
-        Operator::F64x2Ceil => todo!(),
-        Operator::F64x2Floor => todo!(),
-        Operator::F64x2Trunc => todo!(),
-        Operator::F64x2Nearest => todo!(),
-        Operator::F64x2Abs => todo!(),
-        Operator::F64x2Neg => todo!(),
-        Operator::F64x2Sqrt => todo!(),
-        Operator::F64x2Add => todo!(),
-        Operator::F64x2Sub => todo!(),
-        Operator::F64x2Mul => todo!(),
-        Operator::F64x2Div => todo!(),
-        Operator::F64x2Min => todo!(),
-        Operator::F64x2Max => todo!(),
-        Operator::F64x2PMin => todo!(),
-        Operator::F64x2PMax => todo!(),
-        Operator::I32x4TruncSatF32x4S => todo!(),
-        Operator::I32x4TruncSatF32x4U => todo!(),
-        Operator::F32x4ConvertI32x4S => todo!(),
-        Operator::F32x4ConvertI32x4U => todo!(),
-        Operator::I32x4TruncSatF64x2SZero => todo!(),
-        Operator::I32x4TruncSatF64x2UZero => todo!(),
-        Operator::F64x2ConvertLowI32x4S => todo!(),
-        Operator::F64x2ConvertLowI32x4U => todo!(),
-        Operator::F32x4DemoteF64x2Zero => todo!(),
-        Operator::F64x2PromoteLowF32x4 => todo!(),
-        Operator::I8x16RelaxedSwizzle => todo!(),
-        Operator::I32x4RelaxedTruncSatF32x4S => todo!(),
-        Operator::I32x4RelaxedTruncSatF32x4U => todo!(),
-        Operator::I32x4RelaxedTruncSatF64x2SZero => todo!(),
-        Operator::I32x4RelaxedTruncSatF64x2UZero => todo!(),
-        Operator::F32x4Fma => todo!(),
-        Operator::F32x4Fms => todo!(),
-        Operator::F64x2Fma => todo!(),
-        Operator::F64x2Fms => todo!(),
-        Operator::I8x16LaneSelect => todo!(),
-        Operator::I16x8LaneSelect => todo!(),
-        Operator::I32x4LaneSelect => todo!(),
-        Operator::I64x2LaneSelect => todo!(),
-        Operator::F32x4RelaxedMin => todo!(),
-        Operator::F32x4RelaxedMax => todo!(),
-        Operator::F64x2RelaxedMin => todo!(),
-        Operator::F64x2RelaxedMax => todo!(),
+        Operator::GlobalGet { global_index } => {
+            global::get(assembler, module, function, global_index)?
+        }
+        Operator::GlobalSet { global_index } => {
+            global::set(assembler, module, function, global_index)?
+        }
+        Operator::I32Load { memarg } => {
+            memory::load(assembler, module, function, memarg, Type::I32, 4, false)?
+        }
+        Operator::I64Load { memarg } => {
+            memory::load(assembler, module, function, memarg, Type::I64, 8, false)?
+        }
+        Operator::F32Load { memarg } => {
+            memory::load(assembler, module, function, memarg, Type::F32, 4, false)?
+        }
+        Operator::F64Load { memarg } => {
+            memory::load(assembler, module, function, memarg, Type::F64, 8, false)?
+        }
+        Operator::I32Load8S { memarg } => {
+            memory::load(assembler, module, function, memarg, Type::I32, 1, true)?
+        }
+        Operator::I32Load8U { memarg } => {
+            memory::load(assembler, module, function, memarg, Type::I32, 1, false)?
+        }
+        Operator::I32Load16S { memarg } => {
+            memory::load(assembler, module, function, memarg, Type::I32, 2, true)?
+        }
+        Operator::I32Load16U { memarg } => {
+            memory::load(assembler, module, function, memarg, Type::I32, 2, false)?
+        }
+        Operator::I64Load8S { memarg } => {
+            memory::load(assembler, module, function, memarg, Type::I64, 1, true)?
+        }
+        Operator::I64Load8U { memarg } => {
+            memory::load(assembler, module, function, memarg, Type::I64, 1, false)?
+        }
+        Operator::I64Load16S { memarg } => {
+            memory::load(assembler, module, function, memarg, Type::I64, 2, true)?
+        }
+        Operator::I64Load16U { memarg } => {
+            memory::load(assembler, module, function, memarg, Type::I64, 2, false)?
+        }
+        Operator::I64Load32S { memarg } => {
+            memory::load(assembler, module, function, memarg, Type::I64, 4, true)?
+        }
+        Operator::I64Load32U { memarg } => {
+            memory::load(assembler, module, function, memarg, Type::I64, 4, false)?
+        }
+        Operator::I32Store { memarg } => memory::store(assembler, module, function, memarg, 4)?,
+        Operator::I64Store { memarg } => memory::store(assembler, module, function, memarg, 8)?,
+        Operator::F32Store { memarg } => memory::store(assembler, module, function, memarg, 4)?,
+        Operator::F64Store { memarg } => memory::store(assembler, module, function, memarg, 8)?,
+        Operator::I32Store8 { memarg } => memory::store(assembler, module, function, memarg, 1)?,
+        Operator::I32Store16 { memarg } => memory::store(assembler, module, function, memarg, 2)?,
+        Operator::I64Store8 { memarg } => memory::store(assembler, module, function, memarg, 1)?,
+        Operator::I64Store16 { memarg } => memory::store(assembler, module, function, memarg, 2)?,
+        Operator::I64Store32 { memarg } => memory::store(assembler, module, function, memarg, 4)?,
+        Operator::MemorySize { mem, .. } => memory::size(assembler, module, function, mem)?,
+        Operator::MemoryGrow { mem, .. } => memory::grow(assembler, module, function, mem)?,
+        Operator::I32Const { value } => {
+            assembler.mov(eax, value)?;
+            function.push(assembler, rax, Type::I32)?;
+        }
+        Operator::F32Const { value } => {
+            assembler.mov(eax, value.bits())?;
+            function.push(assembler, rax, Type::F32)?;
+        }
+        Operator::F64Const { value } => {
+            assembler.mov(rax, value.bits())?;
+            function.push(assembler, rax, Type::F64)?;
+        }
+        Operator::I32Eqz => compare::eqz(assembler, function, Type::I32)?,
+        Operator::I32Eq => compare::compare(assembler, function, Type::I32, Condition::Eq)?,
+        Operator::I32Ne => compare::compare(assembler, function, Type::I32, Condition::Ne)?,
+        Operator::I32LtS => compare::compare(assembler, function, Type::I32, Condition::LtS)?,
+        Operator::I32LtU => compare::compare(assembler, function, Type::I32, Condition::LtU)?,
+        Operator::I32GtS => compare::compare(assembler, function, Type::I32, Condition::GtS)?,
+        Operator::I32GtU => compare::compare(assembler, function, Type::I32, Condition::GtU)?,
+        Operator::I32LeS => compare::compare(assembler, function, Type::I32, Condition::LeS)?,
+        Operator::I32LeU => compare::compare(assembler, function, Type::I32, Condition::LeU)?,
+        Operator::I32GeS => compare::compare(assembler, function, Type::I32, Condition::GeS)?,
+        Operator::I32GeU => compare::compare(assembler, function, Type::I32, Condition::GeU)?,
+        Operator::I64Eqz => compare::eqz(assembler, function, Type::I64)?,
+        Operator::I64Eq => compare::compare(assembler, function, Type::I64, Condition::Eq)?,
+        Operator::I64Ne => compare::compare(assembler, function, Type::I64, Condition::Ne)?,
+        Operator::I64LtS => compare::compare(assembler, function, Type::I64, Condition::LtS)?,
+        Operator::I64LtU => compare::compare(assembler, function, Type::I64, Condition::LtU)?,
+        Operator::I64GtS => compare::compare(assembler, function, Type::I64, Condition::GtS)?,
+        Operator::I64GtU => compare::compare(assembler, function, Type::I64, Condition::GtU)?,
+        Operator::I64LeS => compare::compare(assembler, function, Type::I64, Condition::LeS)?,
+        Operator::I64LeU => compare::compare(assembler, function, Type::I64, Condition::LeU)?,
+        Operator::I64GeS => compare::compare(assembler, function, Type::I64, Condition::GeS)?,
+        Operator::I64GeU => compare::compare(assembler, function, Type::I64, Condition::GeU)?,
+        Operator::F32Eq => float::compare(assembler, function, Type::F32, Condition::Eq)?,
+        Operator::F32Ne => float::compare(assembler, function, Type::F32, Condition::Ne)?,
+        Operator::F32Lt => float::compare(assembler, function, Type::F32, Condition::LtU)?,
+        Operator::F32Gt => float::compare(assembler, function, Type::F32, Condition::GtU)?,
+        Operator::F32Le => float::compare(assembler, function, Type::F32, Condition::LeU)?,
+        Operator::F32Ge => float::compare(assembler, function, Type::F32, Condition::GeU)?,
+        Operator::F64Eq => float::compare(assembler, function, Type::F64, Condition::Eq)?,
+        Operator::F64Ne => float::compare(assembler, function, Type::F64, Condition::Ne)?,
+        Operator::F64Lt => float::compare(assembler, function, Type::F64, Condition::LtU)?,
+        Operator::F64Gt => float::compare(assembler, function, Type::F64, Condition::GtU)?,
+        Operator::F64Le => float::compare(assembler, function, Type::F64, Condition::LeU)?,
+        Operator::F64Ge => float::compare(assembler, function, Type::F64, Condition::GeU)?,
+        Operator::I32Clz => integer::clz(assembler, module, function, Type::I32)?,
+        Operator::I32Ctz => integer::ctz(assembler, module, function, Type::I32)?,
+        Operator::I32Popcnt => integer::popcnt(assembler, module, function, Type::I32)?,
+        Operator::I32Mul => {
+            integer::binary(assembler, function, Type::I32, |a| a.imul_2(eax, ecx))?
+        }
+        Operator::I32DivS => integer::div(assembler, module, function, Type::I32, true)?,
+        Operator::I32DivU => integer::div(assembler, module, function, Type::I32, false)?,
+        Operator::I32RemS => integer::rem(assembler, module, function, Type::I32, true)?,
+        Operator::I32RemU => integer::rem(assembler, module, function, Type::I32, false)?,
+        Operator::I32And => integer::binary(assembler, function, Type::I32, |a| a.and(eax, ecx))?,
+        Operator::I32Or => integer::binary(assembler, function, Type::I32, |a| a.or(eax, ecx))?,
+        Operator::I32Xor => integer::binary(assembler, function, Type::I32, |a| a.xor(eax, ecx))?,
+        Operator::I32Shl => integer::shift(assembler, module, function, Type::I32, Shift::Left)?,
+        Operator::I32ShrS => {
+            integer::shift(assembler, module, function, Type::I32, Shift::RightSigned)?
+        }
+        Operator::I32ShrU => {
+            integer::shift(assembler, module, function, Type::I32, Shift::RightUnsigned)?
+        }
+        Operator::I32Rotl => integer::binary(assembler, function, Type::I32, |a| a.rol(eax, cl))?,
+        Operator::I32Rotr => integer::binary(assembler, function, Type::I32, |a| a.ror(eax, cl))?,
+        Operator::I64Clz => integer::clz(assembler, module, function, Type::I64)?,
+        Operator::I64Ctz => integer::ctz(assembler, module, function, Type::I64)?,
+        Operator::I64Popcnt => integer::popcnt(assembler, module, function, Type::I64)?,
+        Operator::I64Mul => {
+            integer::binary(assembler, function, Type::I64, |a| a.imul_2(rax, rcx))?
+        }
+        Operator::I64DivS => integer::div(assembler, module, function, Type::I64, true)?,
+        Operator::I64DivU => integer::div(assembler, module, function, Type::I64, false)?,
+        Operator::I64RemS => integer::rem(assembler, module, function, Type::I64, true)?,
+        Operator::I64RemU => integer::rem(assembler, module, function, Type::I64, false)?,
+        Operator::I64And => integer::binary(assembler, function, Type::I64, |a| a.and(rax, rcx))?,
+        Operator::I64Or => integer::binary(assembler, function, Type::I64, |a| a.or(rax, rcx))?,
+        Operator::I64Xor => integer::binary(assembler, function, Type::I64, |a| a.xor(rax, rcx))?,
+        Operator::I64Shl => integer::shift(assembler, module, function, Type::I64, Shift::Left)?,
+        Operator::I64ShrS => {
+            integer::shift(assembler, module, function, Type::I64, Shift::RightSigned)?
+        }
+        Operator::I64ShrU => {
+            integer::shift(assembler, module, function, Type::I64, Shift::RightUnsigned)?
+        }
+        Operator::I64Rotl => integer::binary(assembler, function, Type::I64, |a| a.rol(rax, cl))?,
+        Operator::I64Rotr => integer::binary(assembler, function, Type::I64, |a| a.ror(rax, cl))?,
+        Operator::F32Abs => float::abs(assembler, function, Type::F32)?,
+        Operator::F32Neg => float::neg(assembler, function, Type::F32)?,
+        Operator::F32Ceil => float::round(assembler, module, function, Type::F32, Rounding::Ceil)?,
+        Operator::F32Floor => {
+            float::round(assembler, module, function, Type::F32, Rounding::Floor)?
+        }
+        Operator::F32Trunc => {
+            float::round(assembler, module, function, Type::F32, Rounding::Trunc)?
+        }
+        Operator::F32Nearest => {
+            float::round(assembler, module, function, Type::F32, Rounding::Nearest)?
+        }
+        Operator::F32Sqrt => {
+            float::unary(assembler, function, Type::F32, |a| a.sqrtss(xmm0, xmm0))?
+        }
+        Operator::F32Add => float::binary(assembler, function, Type::F32, |a| a.addss(xmm0, xmm1))?,
+        Operator::F32Sub => float::binary(assembler, function, Type::F32, |a| a.subss(xmm0, xmm1))?,
+        Operator::F32Mul => float::binary(assembler, function, Type::F32, |a| a.mulss(xmm0, xmm1))?,
+        Operator::F32Div => float::binary(assembler, function, Type::F32, |a| a.divss(xmm0, xmm1))?,
+        Operator::F32Min => float::min_max(assembler, module, function, Type::F32, false)?,
+        Operator::F32Max => float::min_max(assembler, module, function, Type::F32, true)?,
+        Operator::F32Copysign => float::copysign(assembler, function, Type::F32)?,
+        Operator::F64Abs => float::abs(assembler, function, Type::F64)?,
+        Operator::F64Neg => float::neg(assembler, function, Type::F64)?,
+        Operator::F64Ceil => float::round(assembler, module, function, Type::F64, Rounding::Ceil)?,
+        Operator::F64Floor => {
+            float::round(assembler, module, function, Type::F64, Rounding::Floor)?
+        }
+        Operator::F64Trunc => {
+            float::round(assembler, module, function, Type::F64, Rounding::Trunc)?
+        }
+        Operator::F64Nearest => {
+            float::round(assembler, module, function, Type::F64, Rounding::Nearest)?
+        }
+        Operator::F64Sqrt => {
+            float::unary(assembler, function, Type::F64, |a| a.sqrtsd(xmm0, xmm0))?
+        }
+        Operator::F64Add => float::binary(assembler, function, Type::F64, |a| a.addsd(xmm0, xmm1))?,
+        Operator::F64Sub => float::binary(assembler, function, Type::F64, |a| a.subsd(xmm0, xmm1))?,
+        Operator::F64Mul => float::binary(assembler, function, Type::F64, |a| a.mulsd(xmm0, xmm1))?,
+        Operator::F64Div => float::binary(assembler, function, Type::F64, |a| a.divsd(xmm0, xmm1))?,
+        Operator::F64Min => float::min_max(assembler, module, function, Type::F64, false)?,
+        Operator::F64Max => float::min_max(assembler, module, function, Type::F64, true)?,
+        Operator::F64Copysign => float::copysign(assembler, function, Type::F64)?,
+        Operator::I32WrapI64 => convert::retype(function, Type::I32),
+        Operator::I32TruncF32S => {
+            convert::truncate(assembler, module, function, Type::F32, Type::I32, true)?
+        }
+        Operator::I32TruncF32U => {
+            convert::truncate(assembler, module, function, Type::F32, Type::I32, false)?
+        }
+        Operator::I32TruncF64S => {
+            convert::truncate(assembler, module, function, Type::F64, Type::I32, true)?
+        }
+        Operator::I32TruncF64U => {
+            convert::truncate(assembler, module, function, Type::F64, Type::I32, false)?
+        }
+        Operator::I64ExtendI32S => convert::extend_signed(assembler, function, Type::I64, 32)?,
+        Operator::I64ExtendI32U => convert::extend_unsigned(assembler, function)?,
+        Operator::I64TruncF32S => {
+            convert::truncate(assembler, module, function, Type::F32, Type::I64, true)?
+        }
+        Operator::I64TruncF32U => {
+            convert::truncate(assembler, module, function, Type::F32, Type::I64, false)?
+        }
+        Operator::I64TruncF64S => {
+            convert::truncate(assembler, module, function, Type::F64, Type::I64, true)?
+        }
+        Operator::I64TruncF64U => {
+            convert::truncate(assembler, module, function, Type::F64, Type::I64, false)?
+        }
+        Operator::F32ConvertI32S => {
+            convert::convert(assembler, module, function, Type::I32, Type::F32, true)?
+        }
+        Operator::F32ConvertI32U => {
+            convert::convert(assembler, module, function, Type::I32, Type::F32, false)?
+        }
+        Operator::F32ConvertI64S => {
+            convert::convert(assembler, module, function, Type::I64, Type::F32, true)?
+        }
+        Operator::F32ConvertI64U => {
+            convert::convert(assembler, module, function, Type::I64, Type::F32, false)?
+        }
+        Operator::F32DemoteF64 => convert::demote_promote(assembler, function, Type::F32)?,
+        Operator::F64ConvertI32S => {
+            convert::convert(assembler, module, function, Type::I32, Type::F64, true)?
+        }
+        Operator::F64ConvertI32U => {
+            convert::convert(assembler, module, function, Type::I32, Type::F64, false)?
+        }
+        Operator::F64ConvertI64S => {
+            convert::convert(assembler, module, function, Type::I64, Type::F64, true)?
+        }
+        Operator::F64ConvertI64U => {
+            convert::convert(assembler, module, function, Type::I64, Type::F64, false)?
+        }
+        Operator::F64PromoteF32 => convert::demote_promote(assembler, function, Type::F64)?,
+        Operator::I32ReinterpretF32 => convert::retype(function, Type::I32),
+        Operator::I64ReinterpretF64 => convert::retype(function, Type::I64),
+        Operator::F32ReinterpretI32 => convert::retype(function, Type::F32),
+        Operator::F64ReinterpretI64 => convert::retype(function, Type::F64),
+        Operator::I32Extend8S => convert::extend_signed(assembler, function, Type::I32, 8)?,
+        Operator::I32Extend16S => convert::extend_signed(assembler, function, Type::I32, 16)?,
+        Operator::I64Extend8S => convert::extend_signed(assembler, function, Type::I64, 8)?,
+        Operator::I64Extend16S => convert::extend_signed(assembler, function, Type::I64, 16)?,
+        Operator::I64Extend32S => convert::extend_signed(assembler, function, Type::I64, 32)?,
+        Operator::I32TruncSatF32S => {
+            convert::truncate_saturating(assembler, module, function, Type::F32, Type::I32, true)?
+        }
+        Operator::I32TruncSatF32U => {
+            convert::truncate_saturating(assembler, module, function, Type::F32, Type::I32, false)?
+        }
+        Operator::I32TruncSatF64S => {
+            convert::truncate_saturating(assembler, module, function, Type::F64, Type::I32, true)?
+        }
+        Operator::I32TruncSatF64U => {
+            convert::truncate_saturating(assembler, module, function, Type::F64, Type::I32, false)?
+        }
+        Operator::I64TruncSatF32S => {
+            convert::truncate_saturating(assembler, module, function, Type::F32, Type::I64, true)?
+        }
+        Operator::I64TruncSatF32U => {
+            convert::truncate_saturating(assembler, module, function, Type::F32, Type::I64, false)?
+        }
+        Operator::I64TruncSatF64S => {
+            convert::truncate_saturating(assembler, module, function, Type::F64, Type::I64, true)?
+        }
+        Operator::I64TruncSatF64U => {
+            convert::truncate_saturating(assembler, module, function, Type::F64, Type::I64, false)?
+        }
+        Operator::MemoryInit { segment, mem } => {
+            data::init(assembler, module, function, segment, mem)?
+        }
+        Operator::DataDrop { segment } => data::drop(assembler, module, segment)?,
+        Operator::MemoryCopy { dst, src } => memory::copy(assembler, module, function, dst, src)?,
+        Operator::MemoryFill { mem } => memory::fill(assembler, module, function, mem)?,
+        Operator::RefNull { ty } => reference::null(assembler, function, ty)?,
+        Operator::RefIsNull => reference::is_null(assembler, function)?,
+        Operator::RefFunc { function_index } => {
+            reference::func(assembler, module, function, function_index)?
+        }
+        Operator::TableGet { table } => table::get(assembler, module, function, table)?,
+        Operator::TableSet { table } => table::set(assembler, module, function, table)?,
+        Operator::TableSize { table } => table::size(assembler, module, function, table)?,
+        Operator::TableGrow { table } => table::grow(assembler, module, function, table)?,
+        Operator::TableFill { table } => table::fill(assembler, module, function, table)?,
+        Operator::TableCopy {
+            dst_table,
+            src_table,
+        } => table::copy(assembler, module, function, dst_table, src_table)?,
+        Operator::TableInit { segment, table } => {
+            table::init(assembler, module, function, segment, table)?
+        }
+        Operator::ElemDrop { segment } => table::drop(assembler, module, segment)?,
+        Operator::I32AtomicLoad { memarg } => {
+            atomic::load(assembler, module, function, memarg, Type::I32, 4)?
+        }
+        Operator::I32AtomicLoad8U { memarg } => {
+            atomic::load(assembler, module, function, memarg, Type::I32, 1)?
+        }
+        Operator::I32AtomicLoad16U { memarg } => {
+            atomic::load(assembler, module, function, memarg, Type::I32, 2)?
+        }
+        Operator::I64AtomicLoad { memarg } => {
+            atomic::load(assembler, module, function, memarg, Type::I64, 8)?
+        }
+        Operator::I64AtomicLoad8U { memarg } => {
+            atomic::load(assembler, module, function, memarg, Type::I64, 1)?
+        }
+        Operator::I64AtomicLoad16U { memarg } => {
+            atomic::load(assembler, module, function, memarg, Type::I64, 2)?
+        }
+        Operator::I64AtomicLoad32U { memarg } => {
+            atomic::load(assembler, module, function, memarg, Type::I64, 4)?
+        }
+        Operator::I32AtomicStore { memarg } => {
+            atomic::store(assembler, module, function, memarg, 4)?
+        }
+        Operator::I32AtomicStore8 { memarg } => {
+            atomic::store(assembler, module, function, memarg, 1)?
+        }
+        Operator::I32AtomicStore16 { memarg } => {
+            atomic::store(assembler, module, function, memarg, 2)?
+        }
+        Operator::I64AtomicStore { memarg } => {
+            atomic::store(assembler, module, function, memarg, 8)?
+        }
+        Operator::I64AtomicStore8 { memarg } => {
+            atomic::store(assembler, module, function, memarg, 1)?
+        }
+        Operator::I64AtomicStore16 { memarg } => {
+            atomic::store(assembler, module, function, memarg, 2)?
+        }
+        Operator::I64AtomicStore32 { memarg } => {
+            atomic::store(assembler, module, function, memarg, 4)?
+        }
+        Operator::I32AtomicRmwAdd { memarg } => {
+            atomic::rmw(assembler, module, function, memarg, Type::I32, 4, Rmw::Add)?
+        }
+        Operator::I32AtomicRmw8AddU { memarg } => {
+            atomic::rmw(assembler, module, function, memarg, Type::I32, 1, Rmw::Add)?
+        }
+        Operator::I32AtomicRmw16AddU { memarg } => {
+            atomic::rmw(assembler, module, function, memarg, Type::I32, 2, Rmw::Add)?
+        }
+        Operator::I64AtomicRmwAdd { memarg } => {
+            atomic::rmw(assembler, module, function, memarg, Type::I64, 8, Rmw::Add)?
+        }
+        Operator::I64AtomicRmw8AddU { memarg } => {
+            atomic::rmw(assembler, module, function, memarg, Type::I64, 1, Rmw::Add)?
+        }
+        Operator::I64AtomicRmw16AddU { memarg } => {
+            atomic::rmw(assembler, module, function, memarg, Type::I64, 2, Rmw::Add)?
+        }
+        Operator::I64AtomicRmw32AddU { memarg } => {
+            atomic::rmw(assembler, module, function, memarg, Type::I64, 4, Rmw::Add)?
+        }
+        Operator::I32AtomicRmwSub { memarg } => {
+            atomic::rmw(assembler, module, function, memarg, Type::I32, 4, Rmw::Sub)?
+        }
+        Operator::I32AtomicRmw8SubU { memarg } => {
+            atomic::rmw(assembler, module, function, memarg, Type::I32, 1, Rmw::Sub)?
+        }
+        Operator::I32AtomicRmw16SubU { memarg } => {
+            atomic::rmw(assembler, module, function, memarg, Type::I32, 2, Rmw::Sub)?
+        }
+        Operator::I64AtomicRmwSub { memarg } => {
+            atomic::rmw(assembler, module, function, memarg, Type::I64, 8, Rmw::Sub)?
+        }
+        Operator::I64AtomicRmw8SubU { memarg } => {
+            atomic::rmw(assembler, module, function, memarg, Type::I64, 1, Rmw::Sub)?
+        }
+        Operator::I64AtomicRmw16SubU { memarg } => {
+            atomic::rmw(assembler, module, function, memarg, Type::I64, 2, Rmw::Sub)?
+        }
+        Operator::I64AtomicRmw32SubU { memarg } => {
+            atomic::rmw(assembler, module, function, memarg, Type::I64, 4, Rmw::Sub)?
+        }
+        Operator::I32AtomicRmwAnd { memarg } => {
+            atomic::rmw(assembler, module, function, memarg, Type::I32, 4, Rmw::And)?
+        }
+        Operator::I32AtomicRmw8AndU { memarg } => {
+            atomic::rmw(assembler, module, function, memarg, Type::I32, 1, Rmw::And)?
+        }
+        Operator::I32AtomicRmw16AndU { memarg } => {
+            atomic::rmw(assembler, module, function, memarg, Type::I32, 2, Rmw::And)?
+        }
+        Operator::I64AtomicRmwAnd { memarg } => {
+            atomic::rmw(assembler, module, function, memarg, Type::I64, 8, Rmw::And)?
+        }
+        Operator::I64AtomicRmw8AndU { memarg } => {
+            atomic::rmw(assembler, module, function, memarg, Type::I64, 1, Rmw::And)?
+        }
+        Operator::I64AtomicRmw16AndU { memarg } => {
+            atomic::rmw(assembler, module, function, memarg, Type::I64, 2, Rmw::And)?
+        }
+        Operator::I64AtomicRmw32AndU { memarg } => {
+            atomic::rmw(assembler, module, function, memarg, Type::I64, 4, Rmw::And)?
+        }
+        Operator::I32AtomicRmwOr { memarg } => {
+            atomic::rmw(assembler, module, function, memarg, Type::I32, 4, Rmw::Or)?
+        }
+        Operator::I32AtomicRmw8OrU { memarg } => {
+            atomic::rmw(assembler, module, function, memarg, Type::I32, 1, Rmw::Or)?
+        }
+        Operator::I32AtomicRmw16OrU { memarg } => {
+            atomic::rmw(assembler, module, function, memarg, Type::I32, 2, Rmw::Or)?
+        }
+        Operator::I64AtomicRmwOr { memarg } => {
+            atomic::rmw(assembler, module, function, memarg, Type::I64, 8, Rmw::Or)?
+        }
+        Operator::I64AtomicRmw8OrU { memarg } => {
+            atomic::rmw(assembler, module, function, memarg, Type::I64, 1, Rmw::Or)?
+        }
+        Operator::I64AtomicRmw16OrU { memarg } => {
+            atomic::rmw(assembler, module, function, memarg, Type::I64, 2, Rmw::Or)?
+        }
+        Operator::I64AtomicRmw32OrU { memarg } => {
+            atomic::rmw(assembler, module, function, memarg, Type::I64, 4, Rmw::Or)?
+        }
+        Operator::I32AtomicRmwXor { memarg } => {
+            atomic::rmw(assembler, module, function, memarg, Type::I32, 4, Rmw::Xor)?
+        }
+        Operator::I32AtomicRmw8XorU { memarg } => {
+            atomic::rmw(assembler, module, function, memarg, Type::I32, 1, Rmw::Xor)?
+        }
+        Operator::I32AtomicRmw16XorU { memarg } => {
+            atomic::rmw(assembler, module, function, memarg, Type::I32, 2, Rmw::Xor)?
+        }
+        Operator::I64AtomicRmwXor { memarg } => {
+            atomic::rmw(assembler, module, function, memarg, Type::I64, 8, Rmw::Xor)?
+        }
+        Operator::I64AtomicRmw8XorU { memarg } => {
+            atomic::rmw(assembler, module, function, memarg, Type::I64, 1, Rmw::Xor)?
+        }
+        Operator::I64AtomicRmw16XorU { memarg } => {
+            atomic::rmw(assembler, module, function, memarg, Type::I64, 2, Rmw::Xor)?
+        }
+        Operator::I64AtomicRmw32XorU { memarg } => {
+            atomic::rmw(assembler, module, function, memarg, Type::I64, 4, Rmw::Xor)?
+        }
+        Operator::I32AtomicRmwXchg { memarg } => {
+            atomic::rmw(assembler, module, function, memarg, Type::I32, 4, Rmw::Xchg)?
+        }
+        Operator::I32AtomicRmw8XchgU { memarg } => {
+            atomic::rmw(assembler, module, function, memarg, Type::I32, 1, Rmw::Xchg)?
+        }
+        Operator::I32AtomicRmw16XchgU { memarg } => {
+            atomic::rmw(assembler, module, function, memarg, Type::I32, 2, Rmw::Xchg)?
+        }
+        Operator::I64AtomicRmwXchg { memarg } => {
+            atomic::rmw(assembler, module, function, memarg, Type::I64, 8, Rmw::Xchg)?
+        }
+        Operator::I64AtomicRmw8XchgU { memarg } => {
+            atomic::rmw(assembler, module, function, memarg, Type::I64, 1, Rmw::Xchg)?
+        }
+        Operator::I64AtomicRmw16XchgU { memarg } => {
+            atomic::rmw(assembler, module, function, memarg, Type::I64, 2, Rmw::Xchg)?
+        }
+        Operator::I64AtomicRmw32XchgU { memarg } => {
+            atomic::rmw(assembler, module, function, memarg, Type::I64, 4, Rmw::Xchg)?
+        }
+        Operator::I32AtomicRmwCmpxchg { memarg } => {
+            atomic::cmpxchg(assembler, module, function, memarg, Type::I32, 4)?
+        }
+        Operator::I32AtomicRmw8CmpxchgU { memarg } => {
+            atomic::cmpxchg(assembler, module, function, memarg, Type::I32, 1)?
+        }
+        Operator::I32AtomicRmw16CmpxchgU { memarg } => {
+            atomic::cmpxchg(assembler, module, function, memarg, Type::I32, 2)?
+        }
+        Operator::I64AtomicRmwCmpxchg { memarg } => {
+            atomic::cmpxchg(assembler, module, function, memarg, Type::I64, 8)?
+        }
+        Operator::I64AtomicRmw8CmpxchgU { memarg } => {
+            atomic::cmpxchg(assembler, module, function, memarg, Type::I64, 1)?
+        }
+        Operator::I64AtomicRmw16CmpxchgU { memarg } => {
+            atomic::cmpxchg(assembler, module, function, memarg, Type::I64, 2)?
+        }
+        Operator::I64AtomicRmw32CmpxchgU { memarg } => {
+            atomic::cmpxchg(assembler, module, function, memarg, Type::I64, 4)?
+        }
+        Operator::AtomicFence { .. } => atomic::fence(assembler)?,
+        Operator::MemoryAtomicWait32 { memarg } => {
+            atomic::wait(assembler, module, function, memarg, 4)?
+        }
+        Operator::MemoryAtomicWait64 { memarg } => {
+            atomic::wait(assembler, module, function, memarg, 8)?
+        }
+        Operator::MemoryAtomicNotify { memarg } => {
+            atomic::notify(assembler, module, function, memarg)?
+        }
+        Operator::V128Load { memarg } => simd::load(assembler, module, function, memarg)?,
+        Operator::V128Store { memarg } => simd::store(assembler, module, function, memarg)?,
+        Operator::V128Load8x8S { memarg } => {
+            simd::load_extend(assembler, module, function, memarg, Shape::I16x8, true)?
+        }
+        Operator::V128Load8x8U { memarg } => {
+            simd::load_extend(assembler, module, function, memarg, Shape::I16x8, false)?
+        }
+        Operator::V128Load16x4S { memarg } => {
+            simd::load_extend(assembler, module, function, memarg, Shape::I32x4, true)?
+        }
+        Operator::V128Load16x4U { memarg } => {
+            simd::load_extend(assembler, module, function, memarg, Shape::I32x4, false)?
+        }
+        Operator::V128Load32x2S { memarg } => {
+            simd::load_extend(assembler, module, function, memarg, Shape::I64x2, true)?
+        }
+        Operator::V128Load32x2U { memarg } => {
+            simd::load_extend(assembler, module, function, memarg, Shape::I64x2, false)?
+        }
+        Operator::V128Load8Splat { memarg } => {
+            simd::load_splat(assembler, module, function, memarg, 1)?
+        }
+        Operator::V128Load16Splat { memarg } => {
+            simd::load_splat(assembler, module, function, memarg, 2)?
+        }
+        Operator::V128Load32Splat { memarg } => {
+            simd::load_splat(assembler, module, function, memarg, 4)?
+        }
+        Operator::V128Load64Splat { memarg } => {
+            simd::load_splat(assembler, module, function, memarg, 8)?
+        }
+        Operator::V128Load32Zero { memarg } => {
+            simd::load_zero(assembler, module, function, memarg, 4)?
+        }
+        Operator::V128Load64Zero { memarg } => {
+            simd::load_zero(assembler, module, function, memarg, 8)?
+        }
+        Operator::V128Load8Lane { memarg, lane } => {
+            simd::load_lane(assembler, module, function, memarg, 1, lane)?
+        }
+        Operator::V128Load16Lane { memarg, lane } => {
+            simd::load_lane(assembler, module, function, memarg, 2, lane)?
+        }
+        Operator::V128Load32Lane { memarg, lane } => {
+            simd::load_lane(assembler, module, function, memarg, 4, lane)?
+        }
+        Operator::V128Load64Lane { memarg, lane } => {
+            simd::load_lane(assembler, module, function, memarg, 8, lane)?
+        }
+        Operator::V128Store8Lane { memarg, lane } => {
+            simd::store_lane(assembler, module, function, memarg, 1, lane)?
+        }
+        Operator::V128Store16Lane { memarg, lane } => {
+            simd::store_lane(assembler, module, function, memarg, 2, lane)?
+        }
+        Operator::V128Store32Lane { memarg, lane } => {
+            simd::store_lane(assembler, module, function, memarg, 4, lane)?
+        }
+        Operator::V128Store64Lane { memarg, lane } => {
+            simd::store_lane(assembler, module, function, memarg, 8, lane)?
+        }
+        Operator::V128Const { value } => {
+            simd::constant(assembler, module, xmm0, value.i128() as u128)?;
+            simd::push(assembler, function, xmm0)?;
+        }
+        Operator::I8x16Shuffle { lanes } => simd::shuffle(assembler, module, function, lanes)?,
+        Operator::I8x16Swizzle => simd::swizzle(assembler, module, function)?,
+        Operator::I8x16ExtractLaneS { lane } => {
+            simd::extract_lane(assembler, function, Shape::I8x16, lane, true)?
+        }
+        Operator::I8x16ExtractLaneU { lane } => {
+            simd::extract_lane(assembler, function, Shape::I8x16, lane, false)?
+        }
+        Operator::I16x8ExtractLaneS { lane } => {
+            simd::extract_lane(assembler, function, Shape::I16x8, lane, true)?
+        }
+        Operator::I16x8ExtractLaneU { lane } => {
+            simd::extract_lane(assembler, function, Shape::I16x8, lane, false)?
+        }
+        Operator::I32x4ExtractLane { lane } => {
+            simd::extract_lane(assembler, function, Shape::I32x4, lane, false)?
+        }
+        Operator::I64x2ExtractLane { lane } => {
+            simd::extract_lane(assembler, function, Shape::I64x2, lane, false)?
+        }
+        Operator::F32x4ExtractLane { lane } => {
+            simd::extract_lane(assembler, function, Shape::F32x4, lane, false)?
+        }
+        Operator::F64x2ExtractLane { lane } => {
+            simd::extract_lane(assembler, function, Shape::F64x2, lane, false)?
+        }
+        Operator::I8x16ReplaceLane { lane } => {
+            simd::replace_lane(assembler, function, Shape::I8x16, lane)?
+        }
+        Operator::I16x8ReplaceLane { lane } => {
+            simd::replace_lane(assembler, function, Shape::I16x8, lane)?
+        }
+        Operator::I32x4ReplaceLane { lane } => {
+            simd::replace_lane(assembler, function, Shape::I32x4, lane)?
+        }
+        Operator::I64x2ReplaceLane { lane } => {
+            simd::replace_lane(assembler, function, Shape::I64x2, lane)?
+        }
+        Operator::F32x4ReplaceLane { lane } => {
+            simd::replace_lane(assembler, function, Shape::F32x4, lane)?
+        }
+        Operator::F64x2ReplaceLane { lane } => {
+            simd::replace_lane(assembler, function, Shape::F64x2, lane)?
+        }
+        Operator::I8x16Splat => simd::splat(assembler, module, function, Shape::I8x16)?,
+        Operator::I16x8Splat => simd::splat(assembler, module, function, Shape::I16x8)?,
+        Operator::I32x4Splat => simd::splat(assembler, module, function, Shape::I32x4)?,
+        Operator::I64x2Splat => simd::splat(assembler, module, function, Shape::I64x2)?,
+        Operator::F32x4Splat => simd::splat(assembler, module, function, Shape::F32x4)?,
+        Operator::F64x2Splat => simd::splat(assembler, module, function, Shape::F64x2)?,
+        Operator::I8x16Eq => {
+            simd::compare(assembler, module, function, Shape::I8x16, Condition::Eq)?
+        }
+        Operator::I8x16Ne => {
+            simd::compare(assembler, module, function, Shape::I8x16, Condition::Ne)?
+        }
+        Operator::I8x16LtS => {
+            simd::compare(assembler, module, function, Shape::I8x16, Condition::LtS)?
+        }
+        Operator::I8x16LtU => {
+            simd::compare(assembler, module, function, Shape::I8x16, Condition::LtU)?
+        }
+        Operator::I8x16GtS => {
+            simd::compare(assembler, module, function, Shape::I8x16, Condition::GtS)?
+        }
+        Operator::I8x16GtU => {
+            simd::compare(assembler, module, function, Shape::I8x16, Condition::GtU)?
+        }
+        Operator::I8x16LeS => {
+            simd::compare(assembler, module, function, Shape::I8x16, Condition::LeS)?
+        }
+        Operator::I8x16LeU => {
+            simd::compare(assembler, module, function, Shape::I8x16, Condition::LeU)?
+        }
+        Operator::I8x16GeS => {
+            simd::compare(assembler, module, function, Shape::I8x16, Condition::GeS)?
+        }
+        Operator::I8x16GeU => {
+            simd::compare(assembler, module, function, Shape::I8x16, Condition::GeU)?
+        }
+        Operator::I16x8Eq => {
+            simd::compare(assembler, module, function, Shape::I16x8, Condition::Eq)?
+        }
+        Operator::I16x8Ne => {
+            simd::compare(assembler, module, function, Shape::I16x8, Condition::Ne)?
+        }
+        Operator::I16x8LtS => {
+            simd::compare(assembler, module, function, Shape::I16x8, Condition::LtS)?
+        }
+        Operator::I16x8LtU => {
+            simd::compare(assembler, module, function, Shape::I16x8, Condition::LtU)?
+        }
+        Operator::I16x8GtS => {
+            simd::compare(assembler, module, function, Shape::I16x8, Condition::GtS)?
+        }
+        Operator::I16x8GtU => {
+            simd::compare(assembler, module, function, Shape::I16x8, Condition::GtU)?
+        }
+        Operator::I16x8LeS => {
+            simd::compare(assembler, module, function, Shape::I16x8, Condition::LeS)?
+        }
+        Operator::I16x8LeU => {
+            simd::compare(assembler, module, function, Shape::I16x8, Condition::LeU)?
+        }
+        Operator::I16x8GeS => {
+            simd::compare(assembler, module, function, Shape::I16x8, Condition::GeS)?
+        }
+        Operator::I16x8GeU => {
+            simd::compare(assembler, module, function, Shape::I16x8, Condition::GeU)?
+        }
+        Operator::I32x4Eq => {
+            simd::compare(assembler, module, function, Shape::I32x4, Condition::Eq)?
+        }
+        Operator::I32x4Ne => {
+            simd::compare(assembler, module, function, Shape::I32x4, Condition::Ne)?
+        }
+        Operator::I32x4LtS => {
+            simd::compare(assembler, module, function, Shape::I32x4, Condition::LtS)?
+        }
+        Operator::I32x4LtU => {
+            simd::compare(assembler, module, function, Shape::I32x4, Condition::LtU)?
+        }
+        Operator::I32x4GtS => {
+            simd::compare(assembler, module, function, Shape::I32x4, Condition::GtS)?
+        }
+        Operator::I32x4GtU => {
+            simd::compare(assembler, module, function, Shape::I32x4, Condition::GtU)?
+        }
+        Operator::I32x4LeS => {
+            simd::compare(assembler, module, function, Shape::I32x4, Condition::LeS)?
+        }
+        Operator::I32x4LeU => {
+            simd::compare(assembler, module, function, Shape::I32x4, Condition::LeU)?
+        }
+        Operator::I32x4GeS => {
+            simd::compare(assembler, module, function, Shape::I32x4, Condition::GeS)?
+        }
+        Operator::I32x4GeU => {
+            simd::compare(assembler, module, function, Shape::I32x4, Condition::GeU)?
+        }
+        Operator::I64x2Eq => {
+            simd::compare(assembler, module, function, Shape::I64x2, Condition::Eq)?
+        }
+        Operator::I64x2Ne => {
+            simd::compare(assembler, module, function, Shape::I64x2, Condition::Ne)?
+        }
+        Operator::I64x2LtS => {
+            simd::compare(assembler, module, function, Shape::I64x2, Condition::LtS)?
+        }
+        Operator::I64x2GtS => {
+            simd::compare(assembler, module, function, Shape::I64x2, Condition::GtS)?
+        }
+        Operator::I64x2LeS => {
+            simd::compare(assembler, module, function, Shape::I64x2, Condition::LeS)?
+        }
+        Operator::I64x2GeS => {
+            simd::compare(assembler, module, function, Shape::I64x2, Condition::GeS)?
+        }
+        Operator::F32x4Eq => {
+            simd::compare(assembler, module, function, Shape::F32x4, Condition::Eq)?
+        }
+        Operator::F32x4Ne => {
+            simd::compare(assembler, module, function, Shape::F32x4, Condition::Ne)?
+        }
+        Operator::F32x4Lt => {
+            simd::compare(assembler, module, function, Shape::F32x4, Condition::LtU)?
+        }
+        Operator::F32x4Gt => {
+            simd::compare(assembler, module, function, Shape::F32x4, Condition::GtU)?
+        }
+        Operator::F32x4Le => {
+            simd::compare(assembler, module, function, Shape::F32x4, Condition::LeU)?
+        }
+        Operator::F32x4Ge => {
+            simd::compare(assembler, module, function, Shape::F32x4, Condition::GeU)?
+        }
+        Operator::F64x2Eq => {
+            simd::compare(assembler, module, function, Shape::F64x2, Condition::Eq)?
+        }
+        Operator::F64x2Ne => {
+            simd::compare(assembler, module, function, Shape::F64x2, Condition::Ne)?
+        }
+        Operator::F64x2Lt => {
+            simd::compare(assembler, module, function, Shape::F64x2, Condition::LtU)?
+        }
+        Operator::F64x2Gt => {
+            simd::compare(assembler, module, function, Shape::F64x2, Condition::GtU)?
+        }
+        Operator::F64x2Le => {
+            simd::compare(assembler, module, function, Shape::F64x2, Condition::LeU)?
+        }
+        Operator::F64x2Ge => {
+            simd::compare(assembler, module, function, Shape::F64x2, Condition::GeU)?
+        }
+        Operator::V128Not => simd::not(assembler)?,
+        Operator::V128And => simd::binary(assembler, function, |a| a.pand(xmm0, xmm1))?,
+        Operator::V128AndNot => simd::binary(assembler, function, |a| {
+            a.pandn(xmm1, xmm0)?;
+            a.movdqa(xmm0, xmm1)
+        })?,
+        Operator::V128Or => simd::binary(assembler, function, |a| a.por(xmm0, xmm1))?,
+        Operator::V128Xor => simd::binary(assembler, function, |a| a.pxor(xmm0, xmm1))?,
+        Operator::V128Bitselect => simd::bitselect(assembler, function)?,
+        Operator::V128AnyTrue => simd::any_true(assembler, function)?,
+        Operator::I8x16Abs => simd::abs(assembler, module, Shape::I8x16)?,
+        Operator::I8x16Neg => simd::neg(assembler, Shape::I8x16)?,
+        Operator::I8x16AllTrue => simd::all_true(assembler, module, function, Shape::I8x16)?,
+        Operator::I8x16Bitmask => simd::bitmask(assembler, function, Shape::I8x16)?,
+        Operator::I8x16Shl => simd::shift(assembler, function, Shape::I8x16, Shift::Left)?,
+        Operator::I8x16ShrS => simd::shift(assembler, function, Shape::I8x16, Shift::RightSigned)?,
+        Operator::I8x16ShrU => {
+            simd::shift(assembler, function, Shape::I8x16, Shift::RightUnsigned)?
+        }
+        Operator::I8x16Add => simd::binary(assembler, function, |a| a.paddb(xmm0, xmm1))?,
+        Operator::I8x16Sub => simd::binary(assembler, function, |a| a.psubb(xmm0, xmm1))?,
+        Operator::I16x8Abs => simd::abs(assembler, module, Shape::I16x8)?,
+        Operator::I16x8Neg => simd::neg(assembler, Shape::I16x8)?,
+        Operator::I16x8AllTrue => simd::all_true(assembler, module, function, Shape::I16x8)?,
+        Operator::I16x8Bitmask => simd::bitmask(assembler, function, Shape::I16x8)?,
+        Operator::I16x8Shl => simd::shift(assembler, function, Shape::I16x8, Shift::Left)?,
+        Operator::I16x8ShrS => simd::shift(assembler, function, Shape::I16x8, Shift::RightSigned)?,
+        Operator::I16x8ShrU => {
+            simd::shift(assembler, function, Shape::I16x8, Shift::RightUnsigned)?
+        }
+        Operator::I16x8Add => simd::binary(assembler, function, |a| a.paddw(xmm0, xmm1))?,
+        Operator::I16x8Sub => simd::binary(assembler, function, |a| a.psubw(xmm0, xmm1))?,
+        Operator::I32x4Abs => simd::abs(assembler, module, Shape::I32x4)?,
+        Operator::I32x4Neg => simd::neg(assembler, Shape::I32x4)?,
+        Operator::I32x4AllTrue => simd::all_true(assembler, module, function, Shape::I32x4)?,
+        Operator::I32x4Bitmask => simd::bitmask(assembler, function, Shape::I32x4)?,
+        Operator::I32x4Shl => simd::shift(assembler, function, Shape::I32x4, Shift::Left)?,
+        Operator::I32x4ShrS => simd::shift(assembler, function, Shape::I32x4, Shift::RightSigned)?,
+        Operator::I32x4ShrU => {
+            simd::shift(assembler, function, Shape::I32x4, Shift::RightUnsigned)?
+        }
+        Operator::I32x4Add => simd::binary(assembler, function, |a| a.paddd(xmm0, xmm1))?,
+        Operator::I32x4Sub => simd::binary(assembler, function, |a| a.psubd(xmm0, xmm1))?,
+        Operator::I64x2Abs => simd::abs(assembler, module, Shape::I64x2)?,
+        Operator::I64x2Neg => simd::neg(assembler, Shape::I64x2)?,
+        Operator::I64x2AllTrue => simd::all_true(assembler, module, function, Shape::I64x2)?,
+        Operator::I64x2Bitmask => simd::bitmask(assembler, function, Shape::I64x2)?,
+        Operator::I64x2Shl => simd::shift(assembler, function, Shape::I64x2, Shift::Left)?,
+        Operator::I64x2ShrS => simd::shift(assembler, function, Shape::I64x2, Shift::RightSigned)?,
+        Operator::I64x2ShrU => {
+            simd::shift(assembler, function, Shape::I64x2, Shift::RightUnsigned)?
+        }
+        Operator::I64x2Add => simd::binary(assembler, function, |a| a.paddq(xmm0, xmm1))?,
+        Operator::I64x2Sub => simd::binary(assembler, function, |a| a.psubq(xmm0, xmm1))?,
+        Operator::I8x16Popcnt => simd::popcnt(assembler, module)?,
+        Operator::I8x16NarrowI16x8S => {
+            simd::narrow(assembler, module, function, Shape::I8x16, true)?
+        }
+        Operator::I8x16NarrowI16x8U => {
+            simd::narrow(assembler, module, function, Shape::I8x16, false)?
+        }
+        Operator::I16x8NarrowI32x4S => {
+            simd::narrow(assembler, module, function, Shape::I16x8, true)?
+        }
+        Operator::I16x8NarrowI32x4U => {
+            simd::narrow(assembler, module, function, Shape::I16x8, false)?
+        }
+        Operator::I8x16AddSatS => simd::binary(assembler, function, |a| a.paddsb(xmm0, xmm1))?,
+        Operator::I8x16AddSatU => simd::binary(assembler, function, |a| a.paddusb(xmm0, xmm1))?,
+        Operator::I8x16SubSatS => simd::binary(assembler, function, |a| a.psubsb(xmm0, xmm1))?,
+        Operator::I8x16SubSatU => simd::binary(assembler, function, |a| a.psubusb(xmm0, xmm1))?,
+        Operator::I8x16RoundingAverageU => {
+            simd::binary(assembler, function, |a| a.pavgb(xmm0, xmm1))?
+        }
+        Operator::I16x8AddSatS => simd::binary(assembler, function, |a| a.paddsw(xmm0, xmm1))?,
+        Operator::I16x8AddSatU => simd::binary(assembler, function, |a| a.paddusw(xmm0, xmm1))?,
+        Operator::I16x8SubSatS => simd::binary(assembler, function, |a| a.psubsw(xmm0, xmm1))?,
+        Operator::I16x8SubSatU => simd::binary(assembler, function, |a| a.psubusw(xmm0, xmm1))?,
+        Operator::I16x8RoundingAverageU => {
+            simd::binary(assembler, function, |a| a.pavgw(xmm0, xmm1))?
+        }
+        Operator::I8x16MinS => {
+            simd::min_max(assembler, module, function, Shape::I8x16, true, false)?
+        }
+        Operator::I8x16MinU => {
+            simd::min_max(assembler, module, function, Shape::I8x16, false, false)?
+        }
+        Operator::I8x16MaxS => {
+            simd::min_max(assembler, module, function, Shape::I8x16, true, true)?
+        }
+        Operator::I8x16MaxU => {
+            simd::min_max(assembler, module, function, Shape::I8x16, false, true)?
+        }
+        Operator::I16x8MinS => {
+            simd::min_max(assembler, module, function, Shape::I16x8, true, false)?
+        }
+        Operator::I16x8MinU => {
+            simd::min_max(assembler, module, function, Shape::I16x8, false, false)?
+        }
+        Operator::I16x8MaxS => {
+            simd::min_max(assembler, module, function, Shape::I16x8, true, true)?
+        }
+        Operator::I16x8MaxU => {
+            simd::min_max(assembler, module, function, Shape::I16x8, false, true)?
+        }
+        Operator::I32x4MinS => {
+            simd::min_max(assembler, module, function, Shape::I32x4, true, false)?
+        }
+        Operator::I32x4MinU => {
+            simd::min_max(assembler, module, function, Shape::I32x4, false, false)?
+        }
+        Operator::I32x4MaxS => {
+            simd::min_max(assembler, module, function, Shape::I32x4, true, true)?
+        }
+        Operator::I32x4MaxU => {
+            simd::min_max(assembler, module, function, Shape::I32x4, false, true)?
+        }
+        Operator::I16x8Mul => simd::binary(assembler, function, |a| a.pmullw(xmm0, xmm1))?,
+        Operator::I32x4Mul => simd::mul(assembler, module, function, Shape::I32x4)?,
+        Operator::I64x2Mul => simd::mul(assembler, module, function, Shape::I64x2)?,
+        Operator::I16x8Q15MulrSatS => simd::q15mulr_sat(assembler, module, function)?,
+        Operator::I32x4DotI16x8S => simd::binary(assembler, function, |a| a.pmaddwd(xmm0, xmm1))?,
+        Operator::I16x8ExtAddPairwiseI8x16S => {
+            simd::extadd_pairwise(assembler, module, Shape::I16x8, true)?
+        }
+        Operator::I16x8ExtAddPairwiseI8x16U => {
+            simd::extadd_pairwise(assembler, module, Shape::I16x8, false)?
+        }
+        Operator::I32x4ExtAddPairwiseI16x8S => {
+            simd::extadd_pairwise(assembler, module, Shape::I32x4, true)?
+        }
+        Operator::I32x4ExtAddPairwiseI16x8U => {
+            simd::extadd_pairwise(assembler, module, Shape::I32x4, false)?
+        }
+        Operator::I16x8ExtendLowI8x16S => {
+            simd::extend_half(assembler, module, Shape::I16x8, false, true)?
+        }
+        Operator::I16x8ExtendLowI8x16U => {
+            simd::extend_half(assembler, module, Shape::I16x8, false, false)?
+        }
+        Operator::I16x8ExtendHighI8x16S => {
+            simd::extend_half(assembler, module, Shape::I16x8, true, true)?
+        }
+        Operator::I16x8ExtendHighI8x16U => {
+            simd::extend_half(assembler, module, Shape::I16x8, true, false)?
+        }
+        Operator::I16x8ExtMulLowI8x16S => {
+            simd::extmul(assembler, module, function, Shape::I16x8, false, true)?
+        }
+        Operator::I16x8ExtMulLowI8x16U => {
+            simd::extmul(assembler, module, function, Shape::I16x8, false, false)?
+        }
+        Operator::I16x8ExtMulHighI8x16S => {
+            simd::extmul(assembler, module, function, Shape::I16x8, true, true)?
+        }
+        Operator::I16x8ExtMulHighI8x16U => {
+            simd::extmul(assembler, module, function, Shape::I16x8, true, false)?
+        }
+        Operator::I32x4ExtendLowI16x8S => {
+            simd::extend_half(assembler, module, Shape::I32x4, false, true)?
+        }
+        Operator::I32x4ExtendLowI16x8U => {
+            simd::extend_half(assembler, module, Shape::I32x4, false, false)?
+        }
+        Operator::I32x4ExtendHighI16x8S => {
+            simd::extend_half(assembler, module, Shape::I32x4, true, true)?
+        }
+        Operator::I32x4ExtendHighI16x8U => {
+            simd::extend_half(assembler, module, Shape::I32x4, true, false)?
+        }
+        Operator::I32x4ExtMulLowI16x8S => {
+            simd::extmul(assembler, module, function, Shape::I32x4, false, true)?
+        }
+        Operator::I32x4ExtMulLowI16x8U => {
+            simd::extmul(assembler, module, function, Shape::I32x4, false, false)?
+        }
+        Operator::I32x4ExtMulHighI16x8S => {
+            simd::extmul(assembler, module, function, Shape::I32x4, true, true)?
+        }
+        Operator::I32x4ExtMulHighI16x8U => {
+            simd::extmul(assembler, module, function, Shape::I32x4, true, false)?
+        }
+        Operator::I64x2ExtendLowI32x4S => {
+            simd::extend_half(assembler, module, Shape::I64x2, false, true)?
+        }
+        Operator::I64x2ExtendLowI32x4U => {
+            simd::extend_half(assembler, module, Shape::I64x2, false, false)?
+        }
+        Operator::I64x2ExtendHighI32x4S => {
+            simd::extend_half(assembler, module, Shape::I64x2, true, true)?
+        }
+        Operator::I64x2ExtendHighI32x4U => {
+            simd::extend_half(assembler, module, Shape::I64x2, true, false)?
+        }
+        Operator::I64x2ExtMulLowI32x4S => {
+            simd::extmul(assembler, module, function, Shape::I64x2, false, true)?
+        }
+        Operator::I64x2ExtMulLowI32x4U => {
+            simd::extmul(assembler, module, function, Shape::I64x2, false, false)?
+        }
+        Operator::I64x2ExtMulHighI32x4S => {
+            simd::extmul(assembler, module, function, Shape::I64x2, true, true)?
+        }
+        Operator::I64x2ExtMulHighI32x4U => {
+            simd::extmul(assembler, module, function, Shape::I64x2, true, false)?
+        }
+        Operator::F32x4Ceil => simd::round(assembler, module, Shape::F32x4, Rounding::Ceil)?,
+        Operator::F32x4Floor => simd::round(assembler, module, Shape::F32x4, Rounding::Floor)?,
+        Operator::F32x4Trunc => simd::round(assembler, module, Shape::F32x4, Rounding::Trunc)?,
+        Operator::F32x4Nearest => simd::round(assembler, module, Shape::F32x4, Rounding::Nearest)?,
+        Operator::F32x4Abs => simd::abs(assembler, module, Shape::F32x4)?,
+        Operator::F32x4Neg => simd::neg(assembler, Shape::F32x4)?,
+        Operator::F32x4Sqrt => simd::unary(assembler, |a| a.sqrtps(xmm0, xmm0))?,
+        Operator::F32x4Add => simd::binary(assembler, function, |a| a.addps(xmm0, xmm1))?,
+        Operator::F32x4Sub => simd::binary(assembler, function, |a| a.subps(xmm0, xmm1))?,
+        Operator::F32x4Mul => simd::binary(assembler, function, |a| a.mulps(xmm0, xmm1))?,
+        Operator::F32x4Div => simd::binary(assembler, function, |a| a.divps(xmm0, xmm1))?,
+        Operator::F32x4Min => simd::float_min_max(assembler, function, Shape::F32x4, false)?,
+        Operator::F32x4Max => simd::float_min_max(assembler, function, Shape::F32x4, true)?,
+        Operator::F32x4PMin => simd::binary(assembler, function, |a| {
+            a.minps(xmm1, xmm0)?;
+            a.movaps(xmm0, xmm1)
+        })?,
+        Operator::F32x4PMax => simd::binary(assembler, function, |a| {
+            a.maxps(xmm1, xmm0)?;
+            a.movaps(xmm0, xmm1)
+        })?,
+        Operator::F64x2Ceil => simd::round(assembler, module, Shape::F64x2, Rounding::Ceil)?,
+        Operator::F64x2Floor => simd::round(assembler, module, Shape::F64x2, Rounding::Floor)?,
+        Operator::F64x2Trunc => simd::round(assembler, module, Shape::F64x2, Rounding::Trunc)?,
+        Operator::F64x2Nearest => simd::round(assembler, module, Shape::F64x2, Rounding::Nearest)?,
+        Operator::F64x2Abs => simd::abs(assembler, module, Shape::F64x2)?,
+        Operator::F64x2Neg => simd::neg(assembler, Shape::F64x2)?,
+        Operator::F64x2Sqrt => simd::unary(assembler, |a| a.sqrtpd(xmm0, xmm0))?,
+        Operator::F64x2Add => simd::binary(assembler, function, |a| a.addpd(xmm0, xmm1))?,
+        Operator::F64x2Sub => simd::binary(assembler, function, |a| a.subpd(xmm0, xmm1))?,
+        Operator::F64x2Mul => simd::binary(assembler, function, |a| a.mulpd(xmm0, xmm1))?,
+        Operator::F64x2Div => simd::binary(assembler, function, |a| a.divpd(xmm0, xmm1))?,
+        Operator::F64x2Min => simd::float_min_max(assembler, function, Shape::F64x2, false)?,
+        Operator::F64x2Max => simd::float_min_max(assembler, function, Shape::F64x2, true)?,
+        Operator::F64x2PMin => simd::binary(assembler, function, |a| {
+            a.minpd(xmm1, xmm0)?;
+            a.movaps(xmm0, xmm1)
+        })?,
+        Operator::F64x2PMax => simd::binary(assembler, function, |a| {
+            a.maxpd(xmm1, xmm0)?;
+            a.movaps(xmm0, xmm1)
+        })?,
+        Operator::I32x4TruncSatF32x4S => simd::trunc_sat_f32x4(assembler, module, true)?,
+        Operator::I32x4TruncSatF32x4U => simd::trunc_sat_f32x4(assembler, module, false)?,
+        Operator::F32x4ConvertI32x4S => simd::convert_i32x4(assembler, true)?,
+        Operator::F32x4ConvertI32x4U => simd::convert_i32x4(assembler, false)?,
+        Operator::I32x4TruncSatF64x2SZero => simd::trunc_sat_f64x2_zero(assembler, module, true)?,
+        Operator::I32x4TruncSatF64x2UZero => simd::trunc_sat_f64x2_zero(assembler, module, false)?,
+        Operator::F64x2ConvertLowI32x4S => simd::convert_low_i32x4(assembler, module, true)?,
+        Operator::F64x2ConvertLowI32x4U => simd::convert_low_i32x4(assembler, module, false)?,
+        Operator::F32x4DemoteF64x2Zero => simd::unary(assembler, |a| a.cvtpd2ps(xmm0, xmm0))?,
+        Operator::F64x2PromoteLowF32x4 => simd::unary(assembler, |a| a.cvtps2pd(xmm0, xmm0))?,
+        Operator::I8x16RelaxedSwizzle => simd::relaxed_swizzle(assembler, module, function)?,
+        Operator::I32x4RelaxedTruncSatF32x4S => simd::relaxed_trunc_f32x4(assembler, module, true)?,
+        Operator::I32x4RelaxedTruncSatF32x4U => {
+            simd::relaxed_trunc_f32x4(assembler, module, false)?
+        }
+        Operator::I32x4RelaxedTruncSatF64x2SZero => {
+            simd::relaxed_trunc_f64x2_zero(assembler, module, true)?
+        }
+        Operator::I32x4RelaxedTruncSatF64x2UZero => {
+            simd::relaxed_trunc_f64x2_zero(assembler, module, false)?
+        }
+        Operator::F32x4Fma => simd::fma(assembler, module, function, Shape::F32x4, false)?,
+        Operator::F32x4Fms => simd::fma(assembler, module, function, Shape::F32x4, true)?,
+        Operator::F64x2Fma => simd::fma(assembler, module, function, Shape::F64x2, false)?,
+        Operator::F64x2Fms => simd::fma(assembler, module, function, Shape::F64x2, true)?,
+        Operator::I8x16LaneSelect => simd::laneselect(assembler, module, function, Shape::I8x16)?,
+        Operator::I16x8LaneSelect => simd::laneselect(assembler, module, function, Shape::I16x8)?,
+        Operator::I32x4LaneSelect => simd::laneselect(assembler, module, function, Shape::I32x4)?,
+        Operator::I64x2LaneSelect => simd::laneselect(assembler, module, function, Shape::I64x2)?,
+        Operator::F32x4RelaxedMin => {
+            simd::relaxed_min_max(assembler, module, function, Shape::F32x4, false)?
+        }
+        Operator::F32x4RelaxedMax => {
+            simd::relaxed_min_max(assembler, module, function, Shape::F32x4, true)?
+        }
+        Operator::F64x2RelaxedMin => {
+            simd::relaxed_min_max(assembler, module, function, Shape::F64x2, false)?
+        }
+        Operator::F64x2RelaxedMax => {
+            simd::relaxed_min_max(assembler, module, function, Shape::F64x2, true)?
+        }
     }
     Ok(())
 }
+
+/// Name of the variant of `op`, without its immediates
+pub(crate) fn operator_name(op: &Operator) -> String {
+    let mut name = format!("{:?}", op);
+    if let Some(end) = name.find(|c: char| !c.is_ascii_alphanumeric()) {
+        name.truncate(end);
+    }
+    name
+}
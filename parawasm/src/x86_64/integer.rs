@@ -0,0 +1,293 @@
+use crate::x86_64::context::{FunctionContext, ModuleContext};
+use crate::x86_64::trap::{trap_unless, TrapCode};
+use crate::x86_64::Error;
+use iced_x86::code_asm::{cl, eax, ecx, edx, rax, rcx, rdx, CodeAssembler};
+use iced_x86::IcedError;
+use wasmparser_nostd::Type;
+
+/// Pops the operands into RAX (left) and RCX (right), applies `op` and
+/// pushes RAX as the result
+pub(crate) fn binary<F>(
+    assembler: &mut CodeAssembler,
+    function: &mut FunctionContext,
+    ty: Type,
+    op: F,
+) -> Result<(), Error>
+where
+    F: FnOnce(&mut CodeAssembler) -> Result<(), IcedError>,
+{
+    function.pop(assembler, rcx)?;
+    function.pop(assembler, rax)?;
+    op(assembler)?;
+    function.push(assembler, rax, ty)
+}
+
+/// Pops the operand into RAX, applies `op` and pushes RAX as the result
+pub(crate) fn unary<F>(
+    assembler: &mut CodeAssembler,
+    function: &mut FunctionContext,
+    ty: Type,
+    op: F,
+) -> Result<(), Error>
+where
+    F: FnOnce(&mut CodeAssembler) -> Result<(), IcedError>,
+{
+    function.pop(assembler, rax)?;
+    op(assembler)?;
+    function.push(assembler, rax, ty)
+}
+
+/// Traps if the divisor in RCX is zero
+fn check_divisor(
+    assembler: &mut CodeAssembler,
+    module: &mut ModuleContext,
+    ty: Type,
+) -> Result<(), Error> {
+    match ty {
+        Type::I32 => assembler.test(ecx, ecx)?,
+        _ => assembler.test(rcx, rcx)?,
+    }
+    trap_unless(
+        assembler,
+        module,
+        TrapCode::IntegerDivisionByZero,
+        |a, ok| a.jne(ok),
+    )
+}
+
+/// Integer division. Division by zero traps, and so does signed division
+/// overflow (`MIN / -1`), which would otherwise raise #DE.
+pub(crate) fn div(
+    assembler: &mut CodeAssembler,
+    module: &mut ModuleContext,
+    function: &mut FunctionContext,
+    ty: Type,
+    signed: bool,
+) -> Result<(), Error> {
+    function.pop(assembler, rcx)?;
+    function.pop(assembler, rax)?;
+    check_divisor(assembler, module, ty)?;
+    if signed {
+        let no_overflow = assembler.create_label();
+        match ty {
+            Type::I32 => {
+                assembler.cmp(ecx, -1)?;
+                assembler.jne(no_overflow)?;
+                assembler.cmp(eax, i32::MIN)?;
+            }
+            _ => {
+                assembler.cmp(rcx, -1)?;
+                assembler.jne(no_overflow)?;
+                assembler.mov(rdx, i64::MIN)?;
+                assembler.cmp(rax, rdx)?;
+            }
+        }
+        trap_unless(assembler, module, TrapCode::IntegerOverflow, |a, ok| {
+            a.jne(ok)
+        })?;
+        module.bind(assembler, no_overflow);
+        match ty {
+            Type::I32 => {
+                assembler.cdq()?;
+                assembler.idiv(ecx)?;
+            }
+            _ => {
+                assembler.cqo()?;
+                assembler.idiv(rcx)?;
+            }
+        }
+    } else {
+        assembler.xor(edx, edx)?;
+        match ty {
+            Type::I32 => assembler.div(ecx)?,
+            _ => assembler.div(rcx)?,
+        }
+    }
+    function.push(assembler, rax, ty)
+}
+
+/// Integer remainder. Division by zero traps, while `MIN % -1` yields 0
+/// instead of raising #DE.
+pub(crate) fn rem(
+    assembler: &mut CodeAssembler,
+    module: &mut ModuleContext,
+    function: &mut FunctionContext,
+    ty: Type,
+    signed: bool,
+) -> Result<(), Error> {
+    function.pop(assembler, rcx)?;
+    function.pop(assembler, rax)?;
+    check_divisor(assembler, module, ty)?;
+    let done = assembler.create_label();
+    if signed {
+        let divide = assembler.create_label();
+        // Any number modulo -1 is 0
+        match ty {
+            Type::I32 => assembler.cmp(ecx, -1)?,
+            _ => assembler.cmp(rcx, -1)?,
+        }
+        assembler.jne(divide)?;
+        assembler.xor(eax, eax)?;
+        assembler.jmp(done)?;
+        module.bind(assembler, divide);
+        match ty {
+            Type::I32 => {
+                assembler.cdq()?;
+                assembler.idiv(ecx)?;
+            }
+            _ => {
+                assembler.cqo()?;
+                assembler.idiv(rcx)?;
+            }
+        }
+    } else {
+        assembler.xor(edx, edx)?;
+        match ty {
+            Type::I32 => assembler.div(ecx)?,
+            _ => assembler.div(rcx)?,
+        }
+    }
+    assembler.mov(rax, rdx)?;
+    module.bind(assembler, done);
+    function.push(assembler, rax, ty)
+}
+
+/// Count of leading zero bits, using LZCNT or else BSR (undefined for zero
+/// input)
+pub(crate) fn clz(
+    assembler: &mut CodeAssembler,
+    module: &ModuleContext,
+    function: &mut FunctionContext,
+    ty: Type,
+) -> Result<(), Error> {
+    let lzcnt = module.cpu.lzcnt;
+    unary(assembler, function, ty, |a| match ty {
+        Type::I32 if lzcnt => a.lzcnt(eax, eax),
+        _ if lzcnt => a.lzcnt(rax, rax),
+        Type::I32 => {
+            a.mov(ecx, -1)?;
+            a.bsr(eax, eax)?;
+            a.cmovz(eax, ecx)?;
+            a.neg(eax)?;
+            a.add(eax, 31)
+        }
+        _ => {
+            a.mov(rcx, -1i64)?;
+            a.bsr(rax, rax)?;
+            a.cmovz(rax, rcx)?;
+            a.neg(rax)?;
+            a.add(rax, 63)
+        }
+    })
+}
+
+/// Count of trailing zero bits, using TZCNT or else BSF (undefined for zero
+/// input)
+pub(crate) fn ctz(
+    assembler: &mut CodeAssembler,
+    module: &ModuleContext,
+    function: &mut FunctionContext,
+    ty: Type,
+) -> Result<(), Error> {
+    let tzcnt = module.cpu.bmi1;
+    unary(assembler, function, ty, |a| match ty {
+        Type::I32 if tzcnt => a.tzcnt(eax, eax),
+        _ if tzcnt => a.tzcnt(rax, rax),
+        Type::I32 => {
+            a.mov(ecx, 32)?;
+            a.bsf(eax, eax)?;
+            a.cmovz(eax, ecx)
+        }
+        _ => {
+            a.mov(ecx, 64)?;
+            a.bsf(rax, rax)?;
+            a.cmovz(rax, rcx)
+        }
+    })
+}
+
+/// Count of set bits, using POPCNT or else computed in parallel within the
+/// register
+pub(crate) fn popcnt(
+    assembler: &mut CodeAssembler,
+    module: &ModuleContext,
+    function: &mut FunctionContext,
+    ty: Type,
+) -> Result<(), Error> {
+    let popcnt = module.cpu.popcnt;
+    unary(assembler, function, ty, |a| match ty {
+        Type::I32 if popcnt => a.popcnt(eax, eax),
+        _ if popcnt => a.popcnt(rax, rax),
+        Type::I32 => {
+            a.mov(ecx, eax)?;
+            a.shr(ecx, 1)?;
+            a.and(ecx, 0x55555555)?;
+            a.sub(eax, ecx)?;
+            a.mov(ecx, eax)?;
+            a.shr(ecx, 2)?;
+            a.and(eax, 0x33333333)?;
+            a.and(ecx, 0x33333333)?;
+            a.add(eax, ecx)?;
+            a.mov(ecx, eax)?;
+            a.shr(ecx, 4)?;
+            a.add(eax, ecx)?;
+            a.and(eax, 0x0F0F0F0F)?;
+            a.imul_3(eax, eax, 0x01010101)?;
+            a.shr(eax, 24)
+        }
+        _ => {
+            a.mov(rcx, rax)?;
+            a.shr(rcx, 1)?;
+            a.mov(rdx, 0x5555555555555555u64)?;
+            a.and(rcx, rdx)?;
+            a.sub(rax, rcx)?;
+            a.mov(rdx, 0x3333333333333333u64)?;
+            a.mov(rcx, rax)?;
+            a.shr(rcx, 2)?;
+            a.and(rax, rdx)?;
+            a.and(rcx, rdx)?;
+            a.add(rax, rcx)?;
+            a.mov(rcx, rax)?;
+            a.shr(rcx, 4)?;
+            a.add(rax, rcx)?;
+            a.mov(rdx, 0x0F0F0F0F0F0F0F0Fu64)?;
+            a.and(rax, rdx)?;
+            a.mov(rdx, 0x0101010101010101u64)?;
+            a.imul_2(rax, rdx)?;
+            a.shr(rax, 56)
+        }
+    })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Shift {
+    Left,
+    RightSigned,
+    RightUnsigned,
+}
+
+/// `shl`, `shr_s` and `shr_u`, using the BMI2 forms which take the count
+/// in any register when available. Both mask the count like WebAssembly.
+pub(crate) fn shift(
+    assembler: &mut CodeAssembler,
+    module: &ModuleContext,
+    function: &mut FunctionContext,
+    ty: Type,
+    shift: Shift,
+) -> Result<(), Error> {
+    let bmi2 = module.cpu.bmi2;
+    binary(assembler, function, ty, |a| match (ty, shift) {
+        (Type::I32, Shift::Left) if bmi2 => a.shlx(eax, eax, ecx),
+        (Type::I32, Shift::RightSigned) if bmi2 => a.sarx(eax, eax, ecx),
+        (Type::I32, Shift::RightUnsigned) if bmi2 => a.shrx(eax, eax, ecx),
+        (_, Shift::Left) if bmi2 => a.shlx(rax, rax, rcx),
+        (_, Shift::RightSigned) if bmi2 => a.sarx(rax, rax, rcx),
+        (_, Shift::RightUnsigned) if bmi2 => a.shrx(rax, rax, rcx),
+        (Type::I32, Shift::Left) => a.shl(eax, cl),
+        (Type::I32, Shift::RightSigned) => a.sar(eax, cl),
+        (Type::I32, Shift::RightUnsigned) => a.shr(eax, cl),
+        (_, Shift::Left) => a.shl(rax, cl),
+        (_, Shift::RightSigned) => a.sar(rax, cl),
+        (_, Shift::RightUnsigned) => a.shr(rax, cl),
+    })
+}
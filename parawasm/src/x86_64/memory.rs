@@ -0,0 +1,548 @@
+use crate::x86_64::context::{FunctionContext, ModuleContext};
+use crate::x86_64::trap::{trap, trap_unless, TrapCode};
+use crate::x86_64::Error;
+use iced_x86::code_asm::{
+    al, byte_ptr, dl, dword_ptr, dx, eax, ecx, edi, edx, esi, ptr, qword_ptr, r11, rax, rcx, rdi,
+    rdx, rsi, rsp, word_ptr, xmm0, ymm0, AsmRegister32, AsmRegister64, CodeAssembler, CodeLabel,
+};
+use wasmparser_nostd::{MemoryImmediate, MemoryType, Type};
+
+/// Linear memory descriptor, as embedded in the module binary.
+///
+/// Generated code reads it on every access, so the embedder can move or
+/// resize the memory by updating the descriptor.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryDescriptor {
+    /// Address of the first byte of the memory
+    pub base: u64,
+    /// Size of the memory in bytes. Accesses past it trap.
+    pub length: u64,
+    /// Address of the [`GrowHook`] called by `memory.grow`, or zero if the
+    /// memory can't grow
+    pub grow: u64,
+    /// Address of the [`WaitHook`] called by `memory.atomic.wait32` and
+    /// `memory.atomic.wait64`, or zero if they trap
+    pub wait: u64,
+    /// Address of the [`NotifyHook`] called by `memory.atomic.notify`, or
+    /// zero if there can't be any waiters
+    pub notify: u64,
+}
+
+/// Host function growing a memory by the given number of pages.
+///
+/// The hook is only called when the new size is within the memory's maximum.
+/// It must update `base` and `length` of the descriptor and return the old
+/// size in pages, or return -1 if the memory can't be grown.
+pub type GrowHook = extern "sysv64" fn(descriptor: *mut MemoryDescriptor, delta: u64) -> i64;
+
+/// Host function suspending the caller until it is notified at `address`,
+/// like a futex.
+///
+/// `address` is the native address of the `size` bytes (4 or 8) waited on.
+/// If they don't hold `expected` the hook returns 1 right away, and this
+/// comparison has to be atomic with respect to notifications. Otherwise it
+/// returns 0 once notified, or 2 if `timeout` nanoseconds elapse first; a
+/// negative timeout never elapses.
+pub type WaitHook = extern "sysv64" fn(
+    descriptor: *mut MemoryDescriptor,
+    address: u64,
+    expected: u64,
+    timeout: i64,
+    size: u64,
+) -> u32;
+
+/// Host function waking up to `count` waiters at the native `address`,
+/// returning how many were woken up
+pub type NotifyHook =
+    extern "sysv64" fn(descriptor: *mut MemoryDescriptor, address: u64, count: u32) -> u32;
+
+pub(crate) const BASE: i32 = 0;
+pub(crate) const LENGTH: i32 = 8;
+const GROW: i32 = 16;
+pub(crate) const WAIT: i32 = 24;
+pub(crate) const NOTIFY: i32 = 32;
+
+/// Size of a WebAssembly page, as a shift
+const PAGE_SHIFT: i32 = 16;
+
+/// A memory in the module binary
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct MemorySlot {
+    /// The module's own descriptor of the memory
+    pub(crate) descriptor: CodeLabel,
+    /// For imported memories, slot of the address of the descriptor in use,
+    /// which may be the one of the module exporting the memory
+    pub(crate) import: Option<CodeLabel>,
+    pub(crate) ty: MemoryType,
+}
+
+/// Type of memory `memory`
+pub(crate) fn lookup(module: &ModuleContext, memory: u32) -> MemoryType {
+    module.memories[memory as usize].ty
+}
+
+/// Leaves the address of the descriptor of memory `memory` in `register`
+pub(crate) fn load_descriptor(
+    assembler: &mut CodeAssembler,
+    module: &ModuleContext,
+    memory: u32,
+    register: AsmRegister64,
+) -> Result<(), Error> {
+    let slot = module.memories[memory as usize];
+    match slot.import {
+        Some(import) => assembler.mov(register, qword_ptr(import))?,
+        None => assembler.lea(register, ptr(slot.descriptor))?,
+    }
+    Ok(())
+}
+
+/// Type of the addresses of memory `memory`
+pub(crate) fn index_type(module: &ModuleContext, memory: u32) -> Type {
+    if lookup(module, memory).memory64 {
+        Type::I64
+    } else {
+        Type::I32
+    }
+}
+
+/// Zero-extends the i32 operand in the lower half of `register`, unless
+/// it is an i64 for a 64-bit memory
+fn extend(
+    assembler: &mut CodeAssembler,
+    register: AsmRegister32,
+    memory64: bool,
+) -> Result<(), Error> {
+    if !memory64 {
+        assembler.mov(register, register)?;
+    }
+    Ok(())
+}
+
+/// Emits a zeroed memory descriptor, returning its label. Until the
+/// embedder links the memory, every access to it traps.
+fn descriptor(
+    assembler: &mut CodeAssembler,
+    module: &mut ModuleContext,
+) -> Result<CodeLabel, Error> {
+    let label = assembler.create_label();
+    module.bind(assembler, label);
+    assembler.dq(&[0; 5])?;
+    Ok(label)
+}
+
+/// Emits the descriptor of a memory defined by the module
+pub(crate) fn define(
+    assembler: &mut CodeAssembler,
+    module: &mut ModuleContext,
+    ty: MemoryType,
+) -> Result<(), Error> {
+    let descriptor = descriptor(assembler, module)?;
+    module.memories.push(MemorySlot {
+        descriptor,
+        import: None,
+        ty,
+    });
+    Ok(())
+}
+
+/// Emits the descriptor of an imported memory, and the slot of the address
+/// of the descriptor in use, to be linked to the one of the module
+/// exporting the memory
+pub(crate) fn import(
+    assembler: &mut CodeAssembler,
+    module: &mut ModuleContext,
+    ty: MemoryType,
+) -> Result<(), Error> {
+    let descriptor = descriptor(assembler, module)?;
+    let slot = assembler.create_label();
+    module.bind(assembler, slot);
+    assembler.dq(&[0])?;
+    module.memories.push(MemorySlot {
+        descriptor,
+        import: Some(slot),
+        ty,
+    });
+    Ok(())
+}
+
+/// Points the descriptor slots of imported memories at the module's own
+/// descriptors, unless they were linked to other ones
+pub(crate) fn setup(
+    assembler: &mut CodeAssembler,
+    module: &mut ModuleContext,
+) -> Result<(), Error> {
+    for memory in module.memories.clone() {
+        if let Some(slot) = memory.import {
+            let linked = assembler.create_label();
+            assembler.mov(rax, qword_ptr(slot))?;
+            assembler.test(rax, rax)?;
+            assembler.jnz(linked)?;
+            assembler.lea(rax, ptr(memory.descriptor))?;
+            assembler.mov(qword_ptr(slot), rax)?;
+            module.bind(assembler, linked);
+        }
+    }
+    Ok(())
+}
+
+/// Pops the address operand and leaves in RAX the native address of the
+/// `size` bytes accessed through `memarg`, trapping if any of them is out
+/// of bounds, or if an `atomic` access is unaligned. Clobbers RCX and R11.
+pub(crate) fn address(
+    assembler: &mut CodeAssembler,
+    module: &mut ModuleContext,
+    function: &mut FunctionContext,
+    memarg: MemoryImmediate,
+    size: u32,
+    atomic: bool,
+) -> Result<(), Error> {
+    let memory_type = lookup(module, memarg.memory);
+    function.pop(assembler, rax)?;
+    if memory_type.memory64 {
+        // The end of the access is computed first, as it may not fit in 64
+        // bits. Such accesses trap like any other out of bounds.
+        let end = match memarg.offset.checked_add(size as u64) {
+            Some(end) => end,
+            None => {
+                // No address is in bounds, what follows is unreachable
+                trap(assembler, module, TrapCode::MemoryOutOfBounds)?;
+                function.reachable = false;
+                return Ok(());
+            }
+        };
+        assembler.mov(rcx, rax)?;
+        if end > i32::MAX as u64 {
+            assembler.mov(r11, end)?;
+            assembler.add(rcx, r11)?;
+        } else {
+            assembler.add(rcx, end as i32)?;
+        }
+        trap_unless(assembler, module, TrapCode::MemoryOutOfBounds, |a, ok| {
+            a.jnc(ok)
+        })?;
+        assembler.lea(rax, ptr(rcx - size as i32))?;
+    } else {
+        // Only the lower half of an i32 address is defined. Since the offset
+        // of a 32-bit memory fits in 32 bits as well, the sums below can't
+        // overflow.
+        assembler.mov(eax, eax)?;
+        if memarg.offset > i32::MAX as u64 {
+            assembler.mov(rcx, memarg.offset)?;
+            assembler.add(rax, rcx)?;
+        } else if memarg.offset > 0 {
+            assembler.add(rax, memarg.offset as i32)?;
+        }
+        assembler.lea(rcx, ptr(rax + size as i32))?;
+    }
+    if atomic && size > 1 {
+        assembler.test(al, size - 1)?;
+        trap_unless(assembler, module, TrapCode::UnalignedAtomic, |a, ok| {
+            a.jz(ok)
+        })?;
+    }
+    load_descriptor(assembler, module, memarg.memory, r11)?;
+    assembler.cmp(rcx, qword_ptr(r11 + LENGTH))?;
+    trap_unless(assembler, module, TrapCode::MemoryOutOfBounds, |a, ok| {
+        a.jbe(ok)
+    })?;
+    assembler.add(rax, qword_ptr(r11 + BASE))?;
+    Ok(())
+}
+
+/// Loads `size` bytes as a value of type `ty`, sign- or zero-extending
+/// narrow loads. The alignment hint is ignored, as x86 allows unaligned
+/// accesses.
+pub(crate) fn load(
+    assembler: &mut CodeAssembler,
+    module: &mut ModuleContext,
+    function: &mut FunctionContext,
+    memarg: MemoryImmediate,
+    ty: Type,
+    size: u32,
+    signed: bool,
+) -> Result<(), Error> {
+    address(assembler, module, function, memarg, size, false)?;
+    match (ty, size, signed) {
+        (_, 8, _) => assembler.mov(rax, qword_ptr(rax))?,
+        (Type::I64, 4, true) => assembler.movsxd(rax, dword_ptr(rax))?,
+        (_, 4, _) => assembler.mov(eax, dword_ptr(rax))?,
+        (Type::I64, 2, true) => assembler.movsx(rax, word_ptr(rax))?,
+        (_, 2, true) => assembler.movsx(eax, word_ptr(rax))?,
+        (_, 2, false) => assembler.movzx(eax, word_ptr(rax))?,
+        (Type::I64, 1, true) => assembler.movsx(rax, byte_ptr(rax))?,
+        (_, 1, true) => assembler.movsx(eax, byte_ptr(rax))?,
+        _ => assembler.movzx(eax, byte_ptr(rax))?,
+    }
+    function.push(assembler, rax, ty)
+}
+
+/// Stores the lower `size` bytes of the value on top of the stack
+pub(crate) fn store(
+    assembler: &mut CodeAssembler,
+    module: &mut ModuleContext,
+    function: &mut FunctionContext,
+    memarg: MemoryImmediate,
+    size: u32,
+) -> Result<(), Error> {
+    function.pop(assembler, rdx)?;
+    address(assembler, module, function, memarg, size, false)?;
+    match size {
+        8 => assembler.mov(qword_ptr(rax), rdx)?,
+        4 => assembler.mov(dword_ptr(rax), edx)?,
+        2 => assembler.mov(word_ptr(rax), dx)?,
+        _ => assembler.mov(byte_ptr(rax), dl)?,
+    }
+    Ok(())
+}
+
+/// `memory.size`: the current size of the memory in pages, an i64 for
+/// 64-bit memories
+pub(crate) fn size(
+    assembler: &mut CodeAssembler,
+    module: &mut ModuleContext,
+    function: &mut FunctionContext,
+    memory: u32,
+) -> Result<(), Error> {
+    load_descriptor(assembler, module, memory, r11)?;
+    assembler.mov(rax, qword_ptr(r11 + LENGTH))?;
+    assembler.shr(rax, PAGE_SHIFT)?;
+    function.push(assembler, rax, index_type(module, memory))
+}
+
+/// `memory.grow`: checks the new size against the memory's maximum and
+/// calls the memory's grow hook, which updates the descriptor. Pushes the
+/// old size in pages, or -1 on failure.
+pub(crate) fn grow(
+    assembler: &mut CodeAssembler,
+    module: &mut ModuleContext,
+    function: &mut FunctionContext,
+    memory: u32,
+) -> Result<(), Error> {
+    let memory_type = lookup(module, memory);
+    // Memories can't exceed 4 GiB, or for 64-bit ones the most whole pages
+    // the descriptor's length can hold, whatever their declared maximum
+    let limit = if memory_type.memory64 {
+        u64::MAX >> PAGE_SHIFT
+    } else {
+        1 << 16
+    };
+    let maximum = memory_type.maximum.unwrap_or(limit).min(limit);
+    let failed = assembler.create_label();
+    let done = assembler.create_label();
+
+    function.pop(assembler, rsi)?;
+    extend(assembler, esi, memory_type.memory64)?;
+    load_descriptor(assembler, module, memory, rdi)?;
+    assembler.mov(rax, qword_ptr(rdi + LENGTH))?;
+    assembler.shr(rax, PAGE_SHIFT)?;
+    // Growing by zero pages always succeeds
+    assembler.test(rsi, rsi)?;
+    assembler.jz(done)?;
+    // The delta of a 64-bit memory may be past any maximum on its own
+    assembler.mov(rcx, rsi)?;
+    assembler.add(rcx, rax)?;
+    assembler.jc(failed)?;
+    if maximum > i32::MAX as u64 {
+        assembler.mov(r11, maximum)?;
+        assembler.cmp(rcx, r11)?;
+    } else {
+        assembler.cmp(rcx, maximum as i32)?;
+    }
+    assembler.ja(failed)?;
+    assembler.mov(r11, qword_ptr(rdi + GROW))?;
+    assembler.test(r11, r11)?;
+    assembler.jz(failed)?;
+    let aligned = function.is_aligned();
+    if !aligned {
+        assembler.sub(rsp, 8)?;
+    }
+    assembler.call(r11)?;
+    if !aligned {
+        assembler.add(rsp, 8)?;
+    }
+    assembler.jmp(done)?;
+    module.bind(assembler, failed);
+    assembler.mov(rax, -1i64)?;
+    module.bind(assembler, done);
+    function.push(assembler, rax, index_type(module, memory))
+}
+
+/// Traps unless RCX bytes from address `address` fit in memory `memory`,
+/// then turns `address` into a host address. Clobbers RAX and R11.
+pub(crate) fn range(
+    assembler: &mut CodeAssembler,
+    module: &mut ModuleContext,
+    memory: u32,
+    address: AsmRegister64,
+) -> Result<(), Error> {
+    if lookup(module, memory).memory64 {
+        assembler.mov(rax, address)?;
+        assembler.add(rax, rcx)?;
+        trap_unless(assembler, module, TrapCode::MemoryOutOfBounds, |a, ok| {
+            a.jnc(ok)
+        })?;
+    } else {
+        // Operands are 32-bit, so the sum can't overflow
+        assembler.lea(rax, ptr(address + rcx))?;
+    }
+    load_descriptor(assembler, module, memory, r11)?;
+    assembler.cmp(rax, qword_ptr(r11 + LENGTH))?;
+    trap_unless(assembler, module, TrapCode::MemoryOutOfBounds, |a, ok| {
+        a.jbe(ok)
+    })?;
+    assembler.add(address, qword_ptr(r11 + BASE))?;
+    Ok(())
+}
+
+/// Width of the vector loops of bulk memory operations
+fn vector_width(module: &ModuleContext) -> i32 {
+    if module.cpu.avx {
+        32
+    } else {
+        16
+    }
+}
+
+/// Emits a loop running `body` on whole vectors while at least one is left
+/// of the RCX bytes, leaving the remaining bytes in RCX
+fn vector_loop(
+    assembler: &mut CodeAssembler,
+    module: &mut ModuleContext,
+    mut body: impl FnMut(&mut CodeAssembler, i32) -> Result<(), Error>,
+) -> Result<(), Error> {
+    let width = vector_width(module);
+    let repeat = assembler.create_label();
+    let tail = assembler.create_label();
+    assembler.cmp(rcx, width)?;
+    assembler.jb(tail)?;
+    module.bind(assembler, repeat);
+    body(assembler, width)?;
+    assembler.sub(rcx, width)?;
+    assembler.cmp(rcx, width)?;
+    assembler.jae(repeat)?;
+    module.bind(assembler, tail);
+    Ok(())
+}
+
+/// Copies a vector from RSI to RDI
+fn copy_vector(assembler: &mut CodeAssembler, avx: bool) -> Result<(), Error> {
+    if avx {
+        assembler.vmovdqu(ymm0, ptr(rsi))?;
+        assembler.vmovdqu(ptr(rdi), ymm0)?;
+    } else {
+        assembler.movdqu(xmm0, ptr(rsi))?;
+        assembler.movdqu(ptr(rdi), xmm0)?;
+    }
+    Ok(())
+}
+
+/// `memory.copy`: the ranges are checked up front, then bytes are copied
+/// backwards if the destination starts within the source. Without ERMS,
+/// `rep movsb` only copies what is left after the vector loop. The length
+/// is an i64 only if both memories are 64-bit.
+pub(crate) fn copy(
+    assembler: &mut CodeAssembler,
+    module: &mut ModuleContext,
+    function: &mut FunctionContext,
+    dst: u32,
+    src: u32,
+) -> Result<(), Error> {
+    let erms = module.cpu.erms;
+    let avx = module.cpu.avx;
+    let src64 = index_type(module, src) == Type::I64;
+    let dst64 = index_type(module, dst) == Type::I64;
+    function.pop(assembler, rcx)?;
+    function.pop(assembler, rsi)?;
+    function.pop(assembler, rdi)?;
+    extend(assembler, ecx, src64 && dst64)?;
+    extend(assembler, esi, src64)?;
+    extend(assembler, edi, dst64)?;
+    range(assembler, module, src, rsi)?;
+    range(assembler, module, dst, rdi)?;
+
+    let backward = assembler.create_label();
+    let done = assembler.create_label();
+    assembler.mov(rax, rdi)?;
+    assembler.sub(rax, rsi)?;
+    assembler.cmp(rax, rcx)?;
+    assembler.jb(backward)?;
+    if !erms {
+        vector_loop(assembler, module, |a, width| {
+            copy_vector(a, avx)?;
+            a.add(rsi, width)?;
+            a.add(rdi, width)?;
+            Ok(())
+        })?;
+    }
+    assembler.rep().movsb()?;
+    assembler.jmp(done)?;
+
+    module.bind(assembler, backward);
+    assembler.add(rsi, rcx)?;
+    assembler.add(rdi, rcx)?;
+    if !erms {
+        vector_loop(assembler, module, |a, width| {
+            a.sub(rsi, width)?;
+            a.sub(rdi, width)?;
+            copy_vector(a, avx)
+        })?;
+    }
+    assembler.sub(rsi, 1)?;
+    assembler.sub(rdi, 1)?;
+    assembler.std()?;
+    assembler.rep().movsb()?;
+    assembler.cld()?;
+    module.bind(assembler, done);
+    if avx && !erms {
+        assembler.vzeroupper()?;
+    }
+    Ok(())
+}
+
+/// `memory.fill`: the range is checked up front. Without ERMS, the byte is
+/// broadcast to a vector register for the vector loop, and `rep stosb`
+/// only fills what is left.
+pub(crate) fn fill(
+    assembler: &mut CodeAssembler,
+    module: &mut ModuleContext,
+    function: &mut FunctionContext,
+    memory: u32,
+) -> Result<(), Error> {
+    let erms = module.cpu.erms;
+    let avx = module.cpu.avx;
+    let memory64 = index_type(module, memory) == Type::I64;
+    function.pop(assembler, rcx)?;
+    function.pop(assembler, rdx)?;
+    function.pop(assembler, rdi)?;
+    extend(assembler, ecx, memory64)?;
+    extend(assembler, edi, memory64)?;
+    range(assembler, module, memory, rdi)?;
+    assembler.movzx(eax, dl)?;
+    if !erms {
+        // The low byte stays the same for `rep stosb`
+        assembler.imul_3(eax, eax, 0x0101_0101)?;
+        if avx {
+            assembler.vmovd(xmm0, eax)?;
+            assembler.vpshufd(xmm0, xmm0, 0)?;
+            assembler.vinsertf128(ymm0, ymm0, xmm0, 1)?;
+        } else {
+            assembler.movd(xmm0, eax)?;
+            assembler.pshufd(xmm0, xmm0, 0)?;
+        }
+        vector_loop(assembler, module, |a, width| {
+            if avx {
+                a.vmovdqu(ptr(rdi), ymm0)?;
+            } else {
+                a.movdqu(ptr(rdi), xmm0)?;
+            }
+            a.add(rdi, width)?;
+            Ok(())
+        })?;
+    }
+    assembler.rep().stosb()?;
+    if avx && !erms {
+        assembler.vzeroupper()?;
+    }
+    Ok(())
+}
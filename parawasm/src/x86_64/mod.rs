@@ -1,21 +1,46 @@
 use crate::Compiler;
 use alloc::borrow::ToOwned;
-use alloc::collections::{BTreeMap, VecDeque};
+use alloc::collections::BTreeMap;
 use alloc::string::String;
-use alloc::vec;
 use alloc::vec::Vec;
 use byteorder::{ByteOrder, LittleEndian};
 use core::mem::size_of;
 use core::ops::{Deref, DerefMut};
-use iced_x86::code_asm::{
-    dword_ptr, ptr, qword_ptr, r11, r8, r9, rax, rbp, rcx, rdi, rdx, rsi, rsp, AsmRegister64,
-    CodeAssembler,
-};
+use iced_x86::code_asm::{qword_ptr, r11, r11d, rbp, rsp, CodeAssembler, CodeLabel};
 use iced_x86::{BlockEncoderOptions, IcedError};
 use wasmparser_nostd::*;
 
+mod abi;
+mod atomic;
+mod call;
+mod compare;
+mod context;
+mod control;
+mod convert;
+mod cpu;
+mod data;
+mod exception;
+mod float;
+mod global;
+mod init;
 mod instructions;
+mod integer;
+mod memory;
 mod optimizer;
+mod reference;
+mod simd;
+mod table;
+mod trap;
+
+use context::{FunctionContext, ModuleContext};
+use control::ControlFrame;
+
+pub use cpu::CpuFeatures;
+pub use exception::{ExceptionContext, ExceptionContextHook};
+pub use memory::{GrowHook, MemoryDescriptor, NotifyHook, WaitHook};
+pub use reference::ExternRef;
+pub use table::{FunctionReference, TableDescriptor, TableGrowHook};
+pub use trap::{Trap, TrapCode};
 
 trait EncodingSize {
     fn encoding_size(&self) -> u32;
@@ -29,11 +54,10 @@ impl EncodingSize for Type {
             Type::F32 => 4,
             Type::F64 => 8,
             Type::V128 => 16,
-            Type::FuncRef => todo!(),
-            Type::ExternRef => todo!(),
-            Type::ExnRef => todo!(),
-            Type::Func => todo!(),
-            Type::EmptyBlockType => todo!(),
+            // References are pointers
+            Type::FuncRef | Type::ExternRef | Type::ExnRef => 8,
+            // Not value types
+            Type::Func | Type::EmptyBlockType => 0,
         }
     }
 }
@@ -42,6 +66,29 @@ impl EncodingSize for Type {
 pub enum Error {
     WasmReaderError(BinaryReaderError),
     AssemblerError(IcedError),
+    /// The module is valid, but uses something the backend can't compile
+    Unsupported {
+        /// What isn't supported, such as the name of an operator
+        feature: String,
+        /// Function it is used in, if any
+        function_index: Option<u32>,
+        /// Offset in the WebAssembly binary where it is used
+        offset: usize,
+    },
+}
+
+impl Error {
+    pub(crate) fn unsupported(
+        feature: impl Into<String>,
+        function_index: Option<u32>,
+        offset: usize,
+    ) -> Self {
+        Self::Unsupported {
+            feature: feature.into(),
+            function_index,
+            offset,
+        }
+    }
 }
 
 impl From<BinaryReaderError> for Error {
@@ -56,11 +103,90 @@ impl From<IcedError> for Error {
     }
 }
 
-pub struct X86_64Compiler;
+pub struct X86_64Compiler {
+    features: WasmFeatures,
+    cpu: CpuFeatures,
+    deterministic: bool,
+}
+
+impl X86_64Compiler {
+    pub fn builder() -> X86_64CompilerBuilder {
+        X86_64CompilerBuilder {
+            features: Self::supported_features(),
+            cpu: CpuFeatures::baseline(),
+            deterministic: false,
+        }
+    }
+
+    /// The proposals the backend implements, enabled by default
+    pub fn supported_features() -> WasmFeatures {
+        WasmFeatures {
+            mutable_global: true,
+            saturating_float_to_int: true,
+            sign_extension: true,
+            reference_types: true,
+            multi_value: true,
+            bulk_memory: true,
+            module_linking: false,
+            simd: true,
+            relaxed_simd: true,
+            threads: true,
+            tail_call: true,
+            deterministic_only: false,
+            multi_memory: true,
+            exceptions: true,
+            memory64: true,
+            extended_const: false,
+        }
+    }
+}
 
 impl core::default::Default for X86_64Compiler {
     fn default() -> Self {
-        X86_64Compiler
+        Self::builder().build()
+    }
+}
+
+pub struct X86_64CompilerBuilder {
+    features: WasmFeatures,
+    cpu: CpuFeatures,
+    deterministic: bool,
+}
+
+impl X86_64CompilerBuilder {
+    /// Sets the proposals modules may use, others failing validation.
+    /// Enabling proposals the backend doesn't implement lets modules through
+    /// validation, to fail with [`Error::Unsupported`] on what they use.
+    pub fn wasm_features(mut self, features: WasmFeatures) -> Self {
+        self.features = features;
+        self
+    }
+
+    /// Sets the instruction set extensions of the CPU code will run on,
+    /// baseline x86-64 by default. [`CpuFeatures::detect`] gives those of
+    /// the current CPU.
+    pub fn cpu_features(mut self, cpu: CpuFeatures) -> Self {
+        self.cpu = cpu;
+        self
+    }
+
+    /// Makes relaxed SIMD instructions give the results of their
+    /// deterministic counterparts, whatever the CPU features: `swizzle`,
+    /// `trunc_sat`, `bitselect`, `min` and `max`, and multiply-adds rounding
+    /// the product. Code compiled this way behaves the same on every node.
+    /// Otherwise they take the fastest form available, whose results differ
+    /// between CPUs for operands the proposal leaves implementation-defined.
+    pub fn deterministic_relaxed_simd(mut self, deterministic: bool) -> Self {
+        self.deterministic = deterministic;
+        self
+    }
+
+    pub fn build(self) -> X86_64Compiler {
+        X86_64Compiler {
+            features: self.features,
+            cpu: self.cpu,
+            deterministic: self.deterministic,
+        }
     }
 }
 
@@ -71,6 +197,24 @@ pub struct Module {
     exports: BTreeMap<String, u32>,
     imports: BTreeMap<u32, (String, Option<String>, usize)>,
     memories: Vec<MemoryType>,
+    memory_descriptors: Vec<usize>,
+    memory_exports: BTreeMap<String, u32>,
+    memory_imports: BTreeMap<u32, (String, Option<String>, usize)>,
+    table_descriptors: Vec<usize>,
+    table_exports: BTreeMap<String, u32>,
+    globals: BTreeMap<u32, usize>,
+    global_exports: BTreeMap<String, u32>,
+    global_imports: BTreeMap<u32, (String, Option<String>, usize)>,
+    tags: BTreeMap<u32, usize>,
+    tag_exports: BTreeMap<String, u32>,
+    tag_imports: BTreeMap<u32, (String, Option<String>, usize)>,
+    signatures: Vec<(FuncType, usize)>,
+    initializer: usize,
+    stack_limit: usize,
+    exception_context_hook: usize,
+    default_exception_context: usize,
+    default_exception_context_hook: usize,
+    traps: BTreeMap<usize, Trap>,
 }
 
 pub struct FunctionIndex(u32);
@@ -103,6 +247,24 @@ impl Module {
             exports: BTreeMap::new(),
             imports: BTreeMap::new(),
             memories: Vec::new(),
+            memory_descriptors: Vec::new(),
+            memory_exports: BTreeMap::new(),
+            memory_imports: BTreeMap::new(),
+            table_descriptors: Vec::new(),
+            table_exports: BTreeMap::new(),
+            globals: BTreeMap::new(),
+            global_exports: BTreeMap::new(),
+            global_imports: BTreeMap::new(),
+            tags: BTreeMap::new(),
+            tag_exports: BTreeMap::new(),
+            tag_imports: BTreeMap::new(),
+            signatures: Vec::new(),
+            initializer: 0,
+            stack_limit: 0,
+            exception_context_hook: 0,
+            default_exception_context: 0,
+            default_exception_context_hook: 0,
+            traps: BTreeMap::new(),
         }
     }
 
@@ -128,6 +290,100 @@ impl Module {
     pub fn memory_types(&self) -> &[MemoryType] {
         &self.memories
     }
+
+    /// Offset in the binary of the value of an exported global defined by
+    /// the module
+    pub fn global_offset(&self, name: &str) -> Option<usize> {
+        self.global_exports
+            .get(name)
+            .and_then(|index| self.globals.get(index).cloned())
+    }
+
+    /// Offset in the binary of an exported tag defined by the module. The
+    /// address of the tag is its identity, which modules importing it are
+    /// linked to.
+    pub fn tag_offset(&self, name: &str) -> Option<usize> {
+        self.tag_exports
+            .get(name)
+            .and_then(|index| self.tags.get(index).cloned())
+    }
+
+    /// Function types the module has signature records for
+    pub fn signature_types(&self) -> impl Iterator<Item = &FuncType> {
+        self.signatures.iter().map(|(ty, _)| ty)
+    }
+
+    /// Offset in the binary of the signature record of `ty`, whose address
+    /// identifies the signature in [`FunctionReference`]s. Modules calling
+    /// each other's functions through tables are linked to one record per
+    /// signature with [`link_signature`](AssembledModule::link_signature).
+    pub fn signature_offset(&self, ty: &FuncType) -> Option<usize> {
+        self.signatures
+            .iter()
+            .find_map(|(known, offset)| (known == ty).then_some(*offset))
+    }
+
+    /// Offset in the binary of the module's own [`ExceptionContext`], which
+    /// its own hook returns
+    pub fn exception_context_offset(&self) -> usize {
+        self.default_exception_context
+    }
+
+    /// Offset in the binary of the module's own [`ExceptionContextHook`],
+    /// used unless another one is linked. It returns the same context
+    /// whatever the thread, so it only suits modules run by one thread.
+    pub fn exception_context_hook_offset(&self) -> usize {
+        self.default_exception_context_hook
+    }
+
+    /// Entry point of the module initializer, to be called once all
+    /// imports are linked and before any other function
+    pub fn initializer_entry_point(&self) -> usize {
+        self.initializer
+    }
+
+    /// Offset of the descriptor of table `index` in the binary
+    pub fn table_descriptor_offset(&self, index: u32) -> Option<usize> {
+        self.table_descriptors.get(index as usize).cloned()
+    }
+
+    /// Offset in the binary of the [`TableDescriptor`] of an exported table.
+    /// Once the module is initialized, it holds the base and length to give
+    /// to [`link_table`](AssembledModule::link_table) of modules importing
+    /// the table.
+    pub fn table_offset(&self, name: &str) -> Option<usize> {
+        self.table_exports
+            .get(name)
+            .and_then(|index| self.table_descriptor_offset(*index))
+    }
+
+    /// Offset of the descriptor of memory `index` in the binary
+    pub fn memory_descriptor_offset(&self, index: u32) -> Option<usize> {
+        self.memory_descriptors.get(index as usize).cloned()
+    }
+
+    /// Offset in the binary of the descriptor of an exported memory, which
+    /// modules importing the memory are linked to. A re-exported imported
+    /// memory only uses it if it is not linked to another module's.
+    pub fn memory_offset(&self, name: &str) -> Option<usize> {
+        self.memory_exports
+            .get(name)
+            .and_then(|index| self.memory_descriptor_offset(*index))
+    }
+
+    /// Trap raised by the instruction at `offset` in the binary. Traps are
+    /// raised with `UD2`, so this is where an invalid opcode exception
+    /// points to.
+    pub fn trap(&self, offset: usize) -> Option<Trap> {
+        self.traps.get(&offset).cloned()
+    }
+
+    /// Trap raised by the instruction at `rip`, for the module loaded at
+    /// `base`
+    pub fn resolve_trap(&self, base: u64, rip: u64) -> Option<Trap> {
+        rip.checked_sub(base)
+            .and_then(|offset| self.trap(offset as usize))
+    }
 }
 
 pub struct AssembledModule {
@@ -154,70 +410,161 @@ impl AssembledModule {
         &self.assembled
     }
 
+    /// Writes `value` to the 8 bytes at `offset` in the binary
+    fn write_u64(&mut self, offset: usize, value: u64) {
+        LittleEndian::write_u64(
+            &mut self.assembled[offset..offset + size_of::<u64>()],
+            value,
+        );
+    }
+
     pub fn link_import(&mut self, module: &str, name: Option<&str>, addr: u64) {
-        let relocation = self
-            .imports
-            .iter()
-            .find_map(|(_, (module_, name_, offset))| {
-                let names_equal = match (name, name_) {
-                    (None, None) => false,
-                    (None, Some(_)) => false,
-                    (Some(_), None) => false,
-                    (Some(name), Some(name_)) => name == name_,
-                };
-                if module_ == module && names_equal {
-                    Some(*offset)
-                } else {
-                    None
-                }
-            });
-        match relocation {
-            Some(offset) => {
-                let mut mem = &mut self.assembled[offset..offset + size_of::<u64>()];
-                LittleEndian::write_u64(&mut mem, addr);
-            }
-            None => (),
+        if let Some(offset) = find_import(&self.imports, module, name) {
+            self.write_u64(offset, addr);
+        }
+    }
+
+    /// Links an imported global to the address of its value, which the host
+    /// stores in the value's natural size
+    pub fn link_global_import(&mut self, module: &str, name: Option<&str>, addr: u64) {
+        if let Some(offset) = find_import(&self.global_imports, module, name) {
+            self.write_u64(offset, addr);
+        }
+    }
+
+    /// Links an imported tag to the address of the tag in the module
+    /// defining it, see [`Module::tag_offset`]
+    pub fn link_tag_import(&mut self, module: &str, name: Option<&str>, addr: u64) {
+        if let Some(offset) = find_import(&self.tag_imports, module, name) {
+            self.write_u64(offset, addr);
+        }
+    }
+
+    /// Makes the module identify signature `ty` with the record at
+    /// `address` instead of its own, see [`Module::signature_offset`]. This
+    /// has to be done before calling the initializer.
+    pub fn link_signature(&mut self, ty: &FuncType, address: u64) {
+        if let Some(offset) = self.signature_offset(ty) {
+            self.write_u64(offset, address);
+        }
+    }
+
+    /// Makes the module throw exceptions to, and catch them from, the
+    /// [`ExceptionContext`] the [`ExceptionContextHook`] at `hook` returns
+    /// for the calling thread, instead of its own. Modules calling each
+    /// other have to share one hook, or at least the contexts it returns.
+    /// This has to be done before calling the initializer.
+    pub fn link_exception_context_hook(&mut self, hook: u64) {
+        let offset = self.exception_context_hook;
+        self.write_u64(offset, hook);
+    }
+
+    /// Links an imported memory to the descriptor of the memory in the
+    /// module defining it, see [`Module::memory_offset`]. Both modules then
+    /// see the same bytes and hooks, and growing the memory from either
+    /// updates the exporter's descriptor. Otherwise the memory is the one
+    /// given to [`link_memory`](Self::link_memory).
+    pub fn link_memory_import(&mut self, module: &str, name: Option<&str>, addr: u64) {
+        if let Some(offset) = find_import(&self.memory_imports, module, name) {
+            self.write_u64(offset, addr);
+        }
+    }
+
+    /// Points memory `index` at `length` bytes of host memory starting at `base`
+    pub fn link_memory(&mut self, index: u32, base: u64, length: u64) {
+        if let Some(offset) = self.memory_descriptor_offset(index) {
+            self.write_u64(offset, base);
+            self.write_u64(offset + size_of::<u64>(), length);
+        }
+    }
+
+    /// Points imported table `index` at `length` entries starting at `base`
+    pub fn link_table(&mut self, index: u32, base: u64, length: u64) {
+        if let Some(offset) = self.table_descriptor_offset(index) {
+            self.write_u64(offset, base);
+            self.write_u64(offset + size_of::<u64>(), length);
+        }
+    }
+
+    /// Makes functions trap with [`TrapCode::StackOverflow`] rather than
+    /// take the stack below `limit`. Calls to imported functions are not
+    /// accounted for, the limit has to leave enough room for them.
+    pub fn link_stack_limit(&mut self, limit: u64) {
+        let offset = self.stack_limit;
+        self.write_u64(offset, limit);
+    }
+
+    /// Sets the address of the [`GrowHook`] of memory `index`
+    pub fn link_memory_grow(&mut self, index: u32, hook: u64) {
+        if let Some(offset) = self.memory_descriptor_offset(index) {
+            let offset = offset + 2 * size_of::<u64>();
+            self.write_u64(offset, hook);
+        }
+    }
+
+    /// Sets the address of the [`WaitHook`] of memory `index`
+    pub fn link_memory_wait(&mut self, index: u32, hook: u64) {
+        if let Some(offset) = self.memory_descriptor_offset(index) {
+            let offset = offset + 3 * size_of::<u64>();
+            self.write_u64(offset, hook);
+        }
+    }
+
+    /// Sets the address of the [`NotifyHook`] of memory `index`
+    pub fn link_memory_notify(&mut self, index: u32, hook: u64) {
+        if let Some(offset) = self.memory_descriptor_offset(index) {
+            let offset = offset + 4 * size_of::<u64>();
+            self.write_u64(offset, hook);
+        }
+    }
+
+    /// Sets the address of the [`TableGrowHook`] of table `index`
+    pub fn link_table_grow(&mut self, index: u32, hook: u64) {
+        if let Some(offset) = self.table_descriptor_offset(index) {
+            let offset = offset + 2 * size_of::<u64>();
+            self.write_u64(offset, hook);
         }
     }
 }
 
+fn find_import(
+    imports: &BTreeMap<u32, (String, Option<String>, usize)>,
+    module: &str,
+    name: Option<&str>,
+) -> Option<usize> {
+    imports.iter().find_map(|(_, (module_, name_, offset))| {
+        let names_equal = match (name, name_) {
+            (None, None) => false,
+            (None, Some(_)) => false,
+            (Some(_), None) => false,
+            (Some(name), Some(name_)) => name == name_,
+        };
+        if module_ == module && names_equal {
+            Some(*offset)
+        } else {
+            None
+        }
+    })
+}
+
 impl Compiler for X86_64Compiler {
     type Error = Error;
     type Module = AssembledModule;
 
     fn compile(&self, module: &[u8]) -> Result<Self::Module, Self::Error> {
         let mut validator = Validator::default();
-        validator.wasm_features(WasmFeatures {
-            mutable_global: true,
-            saturating_float_to_int: true,
-            sign_extension: true,
-            reference_types: true,
-            multi_value: true,
-            bulk_memory: true,
-            module_linking: true,
-            simd: true,
-            relaxed_simd: true,
-            threads: true,
-            tail_call: true,
-            deterministic_only: true,
-            multi_memory: true,
-            exceptions: true,
-            memory64: true,
-            extended_const: false,
-        });
+        validator.wasm_features(self.features);
         let mut assembler = CodeAssembler::new(64)?;
-        let mut got = BTreeMap::new();
-        let mut ils = BTreeMap::new();
+        let mut context = ModuleContext::new(&mut assembler, self.cpu, self.deterministic);
+        trap::stack_limit(&mut assembler, &mut context)?;
+        exception::context(&mut assembler, &mut context)?;
         let mut parser = wasmparser_nostd::Parser::new(0);
         let mut data: &[u8] = &module;
         let mut eof = false;
         let mut module = Module::new();
         let mut function_index = 0;
         let mut function_body_index = 0;
-        let mut function_typedefs = BTreeMap::new();
         let mut function_type_index = 0;
-        let mut function_types = BTreeMap::new();
-        let mut label_indices = Vec::new();
         let mut function_bodies = Vec::new();
         loop {
             let parsed = parser.parse(&data, eof)?;
@@ -234,6 +581,7 @@ impl Compiler for X86_64Compiler {
                             for m in r {
                                 let mem = m?;
                                 module.memories.push(mem);
+                                memory::define(&mut assembler, &mut context, mem)?;
                             }
                         }
                         Payload::TypeSection(ts) => {
@@ -242,17 +590,35 @@ impl Compiler for X86_64Compiler {
                                 let typedef = t?;
                                 match typedef {
                                     TypeDef::Func(func_type) => {
-                                        function_typedefs.insert(function_type_index, func_type);
+                                        if self.features.tail_call {
+                                            // Types the module can't call don't matter
+                                            if let Ok(signature) =
+                                                abi::signature(&context, &func_type)
+                                            {
+                                                context.stack_arguments = context
+                                                    .stack_arguments
+                                                    .max(signature.stack_size);
+                                            }
+                                        }
+                                        table::signature(
+                                            &mut assembler,
+                                            &mut context,
+                                            func_type.clone(),
+                                        )?;
+                                        context
+                                            .function_typedefs
+                                            .insert(function_type_index, func_type);
                                         function_type_index += 1;
                                     }
                                     _ => {}
                                 }
                             }
                         }
-                        Payload::ImportSection(is) => {
+                        Payload::ImportSection(mut is) => {
                             validator.import_section(&is)?;
-                            for i in is {
-                                let import = i?;
+                            for _ in 0..is.get_count() {
+                                let import_offset = is.original_position();
+                                let import = is.read()?;
                                 let mut current_label = assembler.create_label();
                                 assembler.set_label(&mut current_label)?;
                                 assembler.zero_bytes()?;
@@ -272,28 +638,85 @@ impl Compiler for X86_64Compiler {
                                     ImportSectionEntryType::Function(function_type) => {
                                         module.imports.insert(function_index, reference);
                                         let label = assembler.create_label();
-                                        label_indices.push((assembler.instructions().len(), label));
-                                        assembler.dq(&[0xBADC0FFEE0DDF00D])?;
-                                        ils.insert(function_index, label);
-                                        function_types.insert(function_index, function_type);
+                                        context.bind(&assembler, label);
+                                        // The address slot is also the start of the
+                                        // function reference, whose signature is
+                                        // filled in by the initializer
+                                        assembler.dq(&[0xBADC0FFEE0DDF00D, 0])?;
+                                        context.ils.insert(function_index, label);
+                                        context.function_references.insert(function_index, label);
+                                        context
+                                            .function_types
+                                            .insert(function_index, function_type);
                                         function_index += 1;
                                         function_body_index += 1;
                                     }
-                                    _ => (),
+                                    ImportSectionEntryType::Global(global_type) => {
+                                        // The slot offset is known once the code is assembled
+                                        module.global_imports.insert(
+                                            context.globals.len() as u32,
+                                            (reference.0, reference.1, 0),
+                                        );
+                                        global::slot(
+                                            &mut assembler,
+                                            &mut context,
+                                            global_type.content_type,
+                                            0,
+                                            true,
+                                        )?;
+                                    }
+                                    ImportSectionEntryType::Table(table_type) => {
+                                        table::import(&mut assembler, &mut context, table_type)?;
+                                    }
+                                    ImportSectionEntryType::Memory(memory_type) => {
+                                        // Imported memories come first in the index space.
+                                        // The slot offset is known once the code is assembled.
+                                        module.memory_imports.insert(
+                                            context.memories.len() as u32,
+                                            (reference.0, reference.1, 0),
+                                        );
+                                        module.memories.push(memory_type);
+                                        memory::import(&mut assembler, &mut context, memory_type)?;
+                                    }
+                                    ImportSectionEntryType::Tag(tag_type) => {
+                                        // The slot offset is known once the code is assembled
+                                        module.tag_imports.insert(
+                                            context.tags.len() as u32,
+                                            (reference.0, reference.1, 0),
+                                        );
+                                        exception::tag(
+                                            &mut assembler,
+                                            &mut context,
+                                            tag_type.type_index,
+                                            true,
+                                        )?;
+                                    }
+                                    ImportSectionEntryType::Module(_)
+                                    | ImportSectionEntryType::Instance(_) => {
+                                        return Err(Error::unsupported(
+                                            "module linking import",
+                                            None,
+                                            import_offset,
+                                        ))
+                                    }
                                 }
                             }
                         }
                         Payload::FunctionSection(fs) => {
                             validator.function_section(&fs)?;
                             for function_type in fs.into_iter() {
+                                let function_type = function_type?;
+                                // Function reference, its code address and signature
+                                // are filled in by the initializer
                                 let label = assembler.create_label();
-                                label_indices.push((assembler.instructions().len(), label));
+                                context.bind(&assembler, label);
 
                                 let offset = assembler.instructions().len();
-                                assembler.dq(&[0])?;
+                                assembler.dq(&[0, 0])?;
                                 module.functions.insert(function_index, offset);
-                                got.insert(function_index, assembler.create_label());
-                                function_types.insert(function_index, function_type?);
+                                context.function_references.insert(function_index, label);
+                                context.got.insert(function_index, assembler.create_label());
+                                context.function_types.insert(function_index, function_type);
                                 function_index += 1;
                             }
                         }
@@ -301,100 +724,120 @@ impl Compiler for X86_64Compiler {
                             validator.export_section(&es)?;
                             for e in es.into_iter() {
                                 let export = e?;
-                                module
-                                    .exports
-                                    .insert(String::from(export.field), export.index);
+                                match export.kind {
+                                    ExternalKind::Function => {
+                                        module
+                                            .exports
+                                            .insert(String::from(export.field), export.index);
+                                    }
+                                    ExternalKind::Global => {
+                                        module
+                                            .global_exports
+                                            .insert(String::from(export.field), export.index);
+                                    }
+                                    ExternalKind::Tag => {
+                                        module
+                                            .tag_exports
+                                            .insert(String::from(export.field), export.index);
+                                    }
+                                    ExternalKind::Memory => {
+                                        module
+                                            .memory_exports
+                                            .insert(String::from(export.field), export.index);
+                                    }
+                                    ExternalKind::Table => {
+                                        module
+                                            .table_exports
+                                            .insert(String::from(export.field), export.index);
+                                    }
+                                    _ => (),
+                                }
                             }
                         }
-                        Payload::CodeSectionEntry(cs) => {
+                        Payload::CodeSectionEntry(mut cs) => {
                             let mut func_validator = validator.code_section_entry()?;
-                            let function_type = function_types
-                                .get(&function_body_index)
-                                .and_then(|i| function_types.get(&i))
-                                .and_then(|t| function_typedefs.get(t))
-                                .cloned()
-                                .unwrap();
-                            let fun_label = got.get_mut(&function_body_index).unwrap();
-                            function_bodies.push((*fun_label, function_body_index));
-                            label_indices.push((assembler.instructions().len(), *fun_label));
+                            context.function_index = Some(function_body_index);
+                            context.wasm_offset = cs.get_binary_reader().original_position();
+                            let (function_type, fun_label) = match (
+                                context.function_type(function_body_index).cloned(),
+                                context.got.get(&function_body_index).cloned(),
+                            ) {
+                                (Some(function_type), Some(label)) => (function_type, label),
+                                _ => {
+                                    return Err(context.unsupported("function body without a type"))
+                                }
+                            };
+                            function_bodies.push((fun_label, function_body_index));
+                            context.bind(&assembler, fun_label);
+                            // Offsets of 64-bit memories' accesses are encoded on 64 bits
+                            cs.allow_memarg64(self.features.memory64);
                             let rd = cs.get_operators_reader()?;
                             assembler.push(rbp)?;
                             assembler.mov(rbp, rsp)?;
-                            let mut integer_order: VecDeque<AsmRegister64> =
-                                vec![rdi, rsi, rdx, rcx, r8, r9]
-                                    .drain(0..function_type.params.len())
-                                    .collect();
-
-                            let mut locals = vec![];
-                            let mut locals_size = 0;
+                            let stack_needed = assembler.create_label();
+                            trap::check_stack(&mut assembler, &mut context, stack_needed)?;
+                            let mut function = FunctionContext::new();
 
                             for param in function_type.params.iter() {
-                                let sz = param.encoding_size();
-                                locals.push(locals_size + sz);
-                                locals_size += sz;
+                                function.add_local(*param);
                             }
 
                             for local in cs.get_locals_reader()?.into_iter() {
                                 let offset = cs.get_binary_reader().current_position();
                                 let (count, ty) = local?;
-                                let sz = ty.encoding_size();
-                                for i in 0..count {
-                                    locals.push(locals_size + sz * i);
+                                for _ in 0..count {
+                                    function.add_local(ty);
                                 }
-                                locals_size += sz * count;
                                 func_validator.define_locals(offset, count, ty)?;
                             }
+                            let signature = abi::signature(&context, &function_type)?;
+                            function.stack_arguments = signature.stack_size;
+                            if signature.return_area.is_some() {
+                                function.locals_size += 8;
+                                function.return_area = Some(function.locals_size);
+                            }
 
-                            if locals_size > 0 {
+                            let frame_size = function.frame_size();
+                            if frame_size > 0 {
                                 // Allocate stack for locals
                                 assembler.add_instruction(iced_x86::Instruction::with2(
                                     iced_x86::Code::Sub_rm64_imm32,
                                     iced_x86::Register::RSP,
-                                    locals_size,
+                                    frame_size,
                                 )?)?;
                             }
 
-                            let mut extra_args_offset: u32 = 8; // past return address
-                            for (index, param) in function_type.params.iter().enumerate() {
-                                // We know that we have such a parameter in locals, no need to check
-                                let i = *unsafe { locals.get_unchecked(index) };
-                                match param {
-                                    Type::I64 => match integer_order.pop_back() {
-                                        Some(reg) => assembler.mov(ptr(rbp - i), reg)?,
-                                        None => {
-                                            assembler
-                                                .mov(r11, qword_ptr(rbp + extra_args_offset))?;
-                                            assembler.mov(ptr(rbp - i), r11)?;
-                                            extra_args_offset += param.encoding_size();
-                                        }
-                                    },
-                                    Type::I32 => match integer_order.pop_back() {
-                                        Some(reg) => assembler.mov(ptr(rbp - i), reg)?,
-                                        None => {
-                                            assembler
-                                                .mov(r11, dword_ptr(rbp + extra_args_offset))?;
-                                            assembler.mov(ptr(rbp - i), r11)?;
-                                            extra_args_offset += param.encoding_size();
-                                        }
-                                    },
-                                    _ => todo!(),
+                            abi::prologue(&mut assembler, &function, &signature)?;
+
+                            // Locals start zeroed
+                            if function.locals.len() > function_type.params.len() {
+                                assembler.xor(r11d, r11d)?;
+                            }
+                            for (offset, ty) in function.locals[function_type.params.len()..].iter()
+                            {
+                                for word in (0..context::slot_size(ty)).step_by(8) {
+                                    assembler.mov(qword_ptr(rbp - *offset + word), r11)?;
                                 }
                             }
 
+                            let exit_label = assembler.create_label();
+                            function.frames.push(ControlFrame::function(
+                                exit_label,
+                                function_type.returns.to_vec(),
+                            ));
+
                             let mut height = func_validator.operand_stack_height();
 
                             for op in rd.into_iter_with_offsets() {
                                 let (op, offset) = op?;
                                 func_validator.op(offset, &op)?;
+                                context.wasm_offset = offset;
                                 height =
-                                    std::cmp::max(height, func_validator.operand_stack_height());
+                                    core::cmp::max(height, func_validator.operand_stack_height());
                                 instructions::handle_instruction(
                                     &mut assembler,
-                                    &mut got,
-                                    &mut ils,
-                                    &mut function_typedefs,
-                                    &mut function_types,
-                                    &locals,
+                                    &mut context,
+                                    &mut function,
                                     op,
                                 )?;
                             }
@@ -405,29 +848,29 @@ impl Compiler for X86_64Compiler {
                                 .function_stack_heights
                                 .insert(function_body_index, height);
 
-                            let mut integer_order = VecDeque::from([rax, rdx]);
-                            for ret in function_type.returns.iter() {
-                                match ret {
-                                    Type::I64 | Type::I32 => match integer_order.pop_front() {
-                                        Some(reg) => assembler.pop(reg)?,
-                                        None => (),
-                                    },
-                                    _ => todo!(),
-                                }
-                            }
+                            abi::epilogue(&mut assembler, &function, &function_type, &signature)?;
 
-                            if locals_size > 0 {
+                            if frame_size > 0 {
                                 // Deallocate stack for locals
                                 assembler.add_instruction(iced_x86::Instruction::with2(
                                     iced_x86::Code::Add_rm64_imm32,
                                     iced_x86::Register::RSP,
-                                    locals_size,
+                                    frame_size,
                                 )?)?;
                             }
 
                             assembler.mov(rsp, rbp)?;
                             assembler.pop(rbp)?;
                             assembler.ret()?;
+                            // Locals, operands (at most 16 bytes each) and what
+                            // the validator doesn't see of them, what calls
+                            // reserve, and their return address and saved RBP
+                            context.bind(&assembler, stack_needed);
+                            assembler.dq(&[(frame_size
+                                + height * 16
+                                + function.hidden
+                                + function.outgoing
+                                + 16) as u64])?;
                             function_body_index += 1;
                         }
                         Payload::Version { num, range } => {
@@ -439,26 +882,95 @@ impl Compiler for X86_64Compiler {
                         Payload::InstanceSection(i) => {
                             validator.instance_section(&i)?;
                         }
-                        Payload::TableSection(t) => {
+                        Payload::TableSection(mut t) => {
                             validator.table_section(&t)?;
+                            for _ in 0..t.get_count() {
+                                let offset = t.original_position();
+                                let ty = t.read()?;
+                                table::define(&mut assembler, &mut context, ty, offset)?;
+                            }
                         }
                         Payload::TagSection(t) => {
                             validator.tag_section(&t)?;
+                            for tag in t {
+                                exception::tag(
+                                    &mut assembler,
+                                    &mut context,
+                                    tag?.type_index,
+                                    false,
+                                )?;
+                            }
                         }
                         Payload::GlobalSection(g) => {
                             validator.global_section(&g)?;
+                            for g in g {
+                                let g = g?;
+                                let index = context.globals.len() as u32;
+                                let value = match global::initializer(&g.init_expr)? {
+                                    global::Initializer::Value(value) => value,
+                                    initializer => {
+                                        context.global_initializers.push((index, initializer));
+                                        0
+                                    }
+                                };
+                                global::slot(
+                                    &mut assembler,
+                                    &mut context,
+                                    g.ty.content_type,
+                                    value,
+                                    false,
+                                )?;
+                            }
                         }
                         Payload::StartSection { func, range } => {
                             validator.start_section(func, &range)?;
                         }
                         Payload::ElementSection(e) => {
                             validator.element_section(&e)?;
+                            for e in e {
+                                let e = e?;
+                                let items = table::items(&e)?;
+                                match e.kind {
+                                    ElementKind::Active {
+                                        table_index,
+                                        init_expr,
+                                    } => {
+                                        let offset = global::initializer(&init_expr)?;
+                                        context.active_elements.push((table_index, offset, items));
+                                        table::segment(&mut assembler, &mut context, Vec::new())?;
+                                    }
+                                    ElementKind::Declared => {
+                                        table::segment(&mut assembler, &mut context, Vec::new())?;
+                                    }
+                                    ElementKind::Passive => {
+                                        table::segment(&mut assembler, &mut context, items)?;
+                                    }
+                                }
+                            }
                         }
                         Payload::DataCountSection { count, range } => {
                             validator.data_count_section(count, &range)?;
+                            // Code may refer to segments, which come after it
+                            for _ in 0..count {
+                                let segment = data::DataSegment::new(&mut assembler);
+                                context.data_segments.push(segment);
+                            }
                         }
                         Payload::DataSection(d) => {
                             validator.data_section(&d)?;
+                            for (index, d) in d.into_iter().enumerate() {
+                                let d = d?;
+                                let index = index as u32;
+                                data::segment(&mut assembler, &mut context, index, d.data)?;
+                                if let DataKind::Active {
+                                    memory_index,
+                                    init_expr,
+                                } = d.kind
+                                {
+                                    let offset = global::initializer(&init_expr)?;
+                                    context.active_data.push((index, memory_index, offset));
+                                }
+                            }
                         }
                         Payload::CustomSection { .. } => {}
                         Payload::CodeSectionStart { count, range, .. } => {
@@ -480,13 +992,21 @@ impl Compiler for X86_64Compiler {
                 _ => (),
             }
         }
+        context.function_index = None;
+        context.wasm_offset = 0;
+        let initializer = init::initializer(&mut assembler, &mut context)?;
+        abi::tail_call_thunk(&mut assembler, &mut context)?;
+        simd::constants(&mut assembler, &mut context)?;
         // Optimize code
+        let mut label_indices = context.label_indices;
         for instruction in optimizer::optimize(assembler.take_instructions(), &mut label_indices)? {
             assembler.add_instruction(instruction)?;
         }
         // Bind labels
-        for (idx, instruction) in assembler.take_instructions().into_iter().enumerate() {
-            if let Some((_, label)) = label_indices.iter_mut().find(|(i, _)| *i == idx) {
+        let instructions = assembler.take_instructions();
+        let instruction_count = instructions.len();
+        for (idx, instruction) in instructions.into_iter().enumerate() {
+            for (_, label) in label_indices.iter_mut().filter(|(i, _)| *i == idx) {
                 assembler.set_label(label)?;
                 assembler.zero_bytes()?;
                 // If this is a label pointing to a function, record function body entry point
@@ -506,7 +1026,65 @@ impl Compiler for X86_64Compiler {
             }
             assembler.add_instruction(instruction)?;
         }
-        Ok(module.assembled(assembler.assemble(0)?))
+        // Labels past the last instruction
+        for (_, label) in label_indices
+            .iter_mut()
+            .filter(|(i, _)| *i == instruction_count)
+        {
+            assembler.set_label(label)?;
+            assembler.zero_bytes()?;
+        }
+        let assembled =
+            assembler.assemble_options(0, BlockEncoderOptions::RETURN_NEW_INSTRUCTION_OFFSETS)?;
+        // Only the copies in `label_indices` have been bound
+        let offset = |label: &CodeLabel| -> Result<usize, Error> {
+            let (_, bound) = label_indices.iter().find(|(_, l)| l == label).unwrap();
+            Ok(assembled.label_ip(bound)? as usize)
+        };
+        for (index, memory) in context.memories.iter().enumerate() {
+            module.memory_descriptors.push(offset(&memory.descriptor)?);
+            let index = index as u32;
+            if let (Some(label), Some((_, _, slot))) =
+                (memory.import, module.memory_imports.get_mut(&index))
+            {
+                *slot = offset(&label)?;
+            }
+        }
+        for table in context.tables.iter() {
+            module.table_descriptors.push(offset(&table.descriptor)?);
+        }
+        for (index, global) in context.globals.iter().enumerate() {
+            let index = index as u32;
+            if global.imported {
+                if let Some((_, _, slot)) = module.global_imports.get_mut(&index) {
+                    *slot = offset(&global.label)?;
+                }
+            } else {
+                module.globals.insert(index, offset(&global.label)?);
+            }
+        }
+        for (index, tag) in context.tags.iter().enumerate() {
+            let index = index as u32;
+            if tag.imported {
+                if let Some((_, _, slot)) = module.tag_imports.get_mut(&index) {
+                    *slot = offset(&tag.label)?;
+                }
+            } else {
+                module.tags.insert(index, offset(&tag.label)?);
+            }
+        }
+        for (ty, record) in context.signatures.iter() {
+            module.signatures.push((ty.clone(), offset(record)?));
+        }
+        module.initializer = offset(&initializer)?;
+        module.stack_limit = offset(&context.stack_limit)?;
+        module.exception_context_hook = offset(&context.exception_context_hook)?;
+        module.default_exception_context = offset(&context.default_exception_context)?;
+        module.default_exception_context_hook = offset(&context.default_exception_context_hook)?;
+        for (label, trap) in context.traps.iter() {
+            module.traps.insert(offset(label)?, *trap);
+        }
+        Ok(module.assembled(assembled.inner.code_buffer))
     }
 }
 
@@ -34,7 +34,11 @@ pub(crate) fn optimize_loop(
     let mut head = instructions.as_slice();
     let mut head_idx = 0;
     while head.len() > 0 {
-        if head.len() >= 2 {
+        // Instructions that are branch targets can't be merged with preceding ones
+        let second_is_labeled = labels_
+            .iter()
+            .any(|(original_index, _, _)| *original_index == head_idx + 1);
+        if head.len() >= 2 && !second_is_labeled {
             // PUSH reg + POP reg
             if head[0].code() == Code::Push_r64 && head[1].code() == Code::Pop_r64 {
                 new_instructions.add_instruction(Instruction::with2(
@@ -48,7 +52,7 @@ pub(crate) fn optimize_loop(
                 continue;
             }
 
-            // MOV reg1, reg2 + MOV reg2, reg1
+            // MOV reg1, reg2 + MOV reg2, reg1 (second one is redundant)
             if head[0].code() == Code::Mov_rm64_r64
                 && head[1].code() == Code::Mov_rm64_r64
                 && head[0].op0_kind() == OpKind::Register
@@ -58,9 +62,10 @@ pub(crate) fn optimize_loop(
                 && head[0].op0_register() == head[1].op1_register()
                 && head[1].op0_register() == head[0].op1_register()
             {
+                new_instructions.add_instruction(head[0])?;
                 head = &head[2..];
                 head_idx += 2;
-                update_labels(&mut labels_, head_idx, -2);
+                update_labels(&mut labels_, head_idx, -1);
 
                 continue;
             }
@@ -0,0 +1,66 @@
+use crate::x86_64::context::{FunctionContext, ModuleContext};
+use crate::x86_64::{compare, table, Error};
+use core::num::NonZeroU64;
+use iced_x86::code_asm::{eax, rax, CodeAssembler};
+use wasmparser_nostd::Type;
+
+/// A host object handed to WebAssembly code as an `externref`.
+///
+/// Compiled code sees references as pointer-sized values, null being zero,
+/// so `Option<ExternRef>` has the same representation as a `u64` and can
+/// be used as the type of `externref` parameters and results of host
+/// functions, globals and table entries.
+#[repr(transparent)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ExternRef(NonZeroU64);
+
+impl ExternRef {
+    /// Reference to the object at `pointer`, `None` if it is null
+    pub fn new<T>(pointer: *const T) -> Option<Self> {
+        Self::from_raw(pointer as u64)
+    }
+
+    /// Address of the referenced object
+    pub fn get<T>(self) -> *const T {
+        self.0.get() as *const T
+    }
+
+    /// Reference with the given value, `None` if it is zero
+    pub fn from_raw(value: u64) -> Option<Self> {
+        NonZeroU64::new(value).map(Self)
+    }
+
+    /// Value of the reference, as seen by compiled code
+    pub fn into_raw(reference: Option<Self>) -> u64 {
+        reference.map_or(0, |reference| reference.0.get())
+    }
+}
+
+/// `ref.null`
+pub(crate) fn null(
+    assembler: &mut CodeAssembler,
+    function: &mut FunctionContext,
+    ty: Type,
+) -> Result<(), Error> {
+    assembler.xor(eax, eax)?;
+    function.push(assembler, rax, ty)
+}
+
+/// `ref.is_null`
+pub(crate) fn is_null(
+    assembler: &mut CodeAssembler,
+    function: &mut FunctionContext,
+) -> Result<(), Error> {
+    compare::eqz(assembler, function, Type::I64)
+}
+
+/// `ref.func`
+pub(crate) fn func(
+    assembler: &mut CodeAssembler,
+    module: &ModuleContext,
+    function: &mut FunctionContext,
+    index: u32,
+) -> Result<(), Error> {
+    table::function_reference(assembler, module, index)?;
+    function.push(assembler, rax, Type::FuncRef)
+}
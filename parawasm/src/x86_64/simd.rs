@@ -0,0 +1,1677 @@
+use crate::x86_64::compare::{self, Condition};
+use crate::x86_64::context::{FunctionContext, ModuleContext};
+use crate::x86_64::float::Rounding;
+use crate::x86_64::integer::Shift;
+use crate::x86_64::{memory, Error};
+use iced_x86::code_asm::{
+    al, ax, byte_ptr, cl, dl, dword_ptr, dx, eax, ecx, edx, ptr, qword_ptr, rax, rcx, rdx, rsp,
+    word_ptr, xmm0, xmm1, xmm2, xmm3, xmm4, xmm5, xmm6, xmm7, xmmword_ptr, AsmMemoryOperand,
+    AsmRegisterXmm, CodeAssembler,
+};
+use wasmparser_nostd::{MemoryImmediate, Type};
+
+/// Interpretation of a v128 value as lanes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Shape {
+    I8x16,
+    I16x8,
+    I32x4,
+    I64x2,
+    F32x4,
+    F64x2,
+}
+
+impl Shape {
+    /// Size of a lane in bytes
+    pub(crate) fn lane_size(self) -> u32 {
+        match self {
+            Shape::I8x16 => 1,
+            Shape::I16x8 => 2,
+            Shape::I32x4 | Shape::F32x4 => 4,
+            Shape::I64x2 | Shape::F64x2 => 8,
+        }
+    }
+
+    /// Type of a lane outside of a vector
+    fn lane_type(self) -> Type {
+        match self {
+            Shape::I8x16 | Shape::I16x8 | Shape::I32x4 => Type::I32,
+            Shape::I64x2 => Type::I64,
+            Shape::F32x4 => Type::F32,
+            Shape::F64x2 => Type::F64,
+        }
+    }
+}
+
+/// `value` repeated in every lane of `size` bytes
+fn lanes(value: u64, size: u32) -> u128 {
+    let bits = size * 8;
+    let value = value as u128 & (u128::MAX >> (128 - bits));
+    (0..128 / bits).fold(0, |vector, lane| vector | value << (lane * bits))
+}
+
+/// v128 values take a 16-byte slot on the operand stack. This pops one into
+/// `xmm`.
+pub(crate) fn pop(
+    assembler: &mut CodeAssembler,
+    function: &mut FunctionContext,
+    xmm: AsmRegisterXmm,
+) -> Result<(), Error> {
+    assembler.movdqu(xmm, xmmword_ptr(rsp))?;
+    assembler.add(rsp, 16)?;
+    function.stack.pop();
+    Ok(())
+}
+
+/// Pushes the v128 in `xmm`
+pub(crate) fn push(
+    assembler: &mut CodeAssembler,
+    function: &mut FunctionContext,
+    xmm: AsmRegisterXmm,
+) -> Result<(), Error> {
+    assembler.sub(rsp, 16)?;
+    assembler.movdqu(xmmword_ptr(rsp), xmm)?;
+    function.stack.push(Type::V128);
+    Ok(())
+}
+
+/// Drops the v128 on top of the stack
+pub(crate) fn drop(
+    assembler: &mut CodeAssembler,
+    function: &mut FunctionContext,
+) -> Result<(), Error> {
+    assembler.add(rsp, 16)?;
+    function.stack.pop();
+    Ok(())
+}
+
+/// Applies `op` to the operand on top of the stack, in XMM0, in place
+pub(crate) fn unary<F, E>(assembler: &mut CodeAssembler, op: F) -> Result<(), Error>
+where
+    F: FnOnce(&mut CodeAssembler) -> Result<(), E>,
+    Error: From<E>,
+{
+    assembler.movdqu(xmm0, xmmword_ptr(rsp))?;
+    op(assembler)?;
+    assembler.movdqu(xmmword_ptr(rsp), xmm0)?;
+    Ok(())
+}
+
+/// Pops the right operand into XMM1, applies `op` to the left one in XMM0
+/// and replaces it with XMM0
+pub(crate) fn binary<F, E>(
+    assembler: &mut CodeAssembler,
+    function: &mut FunctionContext,
+    op: F,
+) -> Result<(), Error>
+where
+    F: FnOnce(&mut CodeAssembler) -> Result<(), E>,
+    Error: From<E>,
+{
+    pop(assembler, function, xmm1)?;
+    unary(assembler, op)
+}
+
+/// Loads a constant into `xmm`. Constants are kept in a pool emitted with
+/// the module, unaligned as the module binary may be.
+pub(crate) fn constant(
+    assembler: &mut CodeAssembler,
+    module: &mut ModuleContext,
+    xmm: AsmRegisterXmm,
+    value: u128,
+) -> Result<(), Error> {
+    match value {
+        0 => assembler.pxor(xmm, xmm)?,
+        u128::MAX => assembler.pcmpeqd(xmm, xmm)?,
+        _ => {
+            let label = *module
+                .constants
+                .entry(value)
+                .or_insert_with(|| assembler.create_label());
+            assembler.movdqu(xmm, xmmword_ptr(label))?;
+        }
+    }
+    Ok(())
+}
+
+/// Emits the constant pool
+pub(crate) fn constants(
+    assembler: &mut CodeAssembler,
+    module: &mut ModuleContext,
+) -> Result<(), Error> {
+    for (value, label) in core::mem::take(&mut module.constants) {
+        module.bind(assembler, label);
+        assembler.dq(&[value as u64, (value >> 64) as u64])?;
+    }
+    Ok(())
+}
+
+/// `select` of v128 operands, picking the first one if `condition` holds
+pub(crate) fn select(
+    assembler: &mut CodeAssembler,
+    module: &mut ModuleContext,
+    function: &mut FunctionContext,
+    condition: Condition,
+) -> Result<(), Error> {
+    let done = assembler.create_label();
+    // LEA leaves the flags alone
+    assembler.movdqu(xmm0, xmmword_ptr(rsp))?;
+    assembler.lea(rsp, ptr(rsp + 16))?;
+    function.stack.pop();
+    compare::jump(assembler, condition, done)?;
+    assembler.movdqu(xmmword_ptr(rsp), xmm0)?;
+    module.bind(assembler, done);
+    Ok(())
+}
+
+/// Loads `size` bytes at `address` into RDX, zero-extended
+fn load_lane_value(
+    assembler: &mut CodeAssembler,
+    size: u32,
+    address: AsmMemoryOperand,
+) -> Result<(), Error> {
+    match size {
+        1 => assembler.movzx(edx, byte_ptr(address))?,
+        2 => assembler.movzx(edx, word_ptr(address))?,
+        4 => assembler.mov(edx, dword_ptr(address))?,
+        _ => assembler.mov(rdx, qword_ptr(address))?,
+    }
+    Ok(())
+}
+
+/// Stores the lower `size` bytes of RDX at `address`
+fn store_lane_value(
+    assembler: &mut CodeAssembler,
+    size: u32,
+    address: AsmMemoryOperand,
+) -> Result<(), Error> {
+    match size {
+        1 => assembler.mov(byte_ptr(address), dl)?,
+        2 => assembler.mov(word_ptr(address), dx)?,
+        4 => assembler.mov(dword_ptr(address), edx)?,
+        _ => assembler.mov(qword_ptr(address), rdx)?,
+    }
+    Ok(())
+}
+
+/// `v128.load`
+pub(crate) fn load(
+    assembler: &mut CodeAssembler,
+    module: &mut ModuleContext,
+    function: &mut FunctionContext,
+    memarg: MemoryImmediate,
+) -> Result<(), Error> {
+    memory::address(assembler, module, function, memarg, 16, false)?;
+    assembler.movdqu(xmm0, xmmword_ptr(rax))?;
+    push(assembler, function, xmm0)
+}
+
+/// `v128.store`
+pub(crate) fn store(
+    assembler: &mut CodeAssembler,
+    module: &mut ModuleContext,
+    function: &mut FunctionContext,
+    memarg: MemoryImmediate,
+) -> Result<(), Error> {
+    pop(assembler, function, xmm0)?;
+    memory::address(assembler, module, function, memarg, 16, false)?;
+    assembler.movdqu(xmmword_ptr(rax), xmm0)?;
+    Ok(())
+}
+
+/// `v128.load8x8_s` and friends: loads 8 bytes and extends them to lanes of
+/// `shape`
+pub(crate) fn load_extend(
+    assembler: &mut CodeAssembler,
+    module: &mut ModuleContext,
+    function: &mut FunctionContext,
+    memarg: MemoryImmediate,
+    shape: Shape,
+    signed: bool,
+) -> Result<(), Error> {
+    memory::address(assembler, module, function, memarg, 8, false)?;
+    assembler.movq(xmm0, qword_ptr(rax))?;
+    extend(assembler, module, shape, xmm0, xmm1, false, signed)?;
+    push(assembler, function, xmm0)
+}
+
+/// `v128.load8_splat` and friends
+pub(crate) fn load_splat(
+    assembler: &mut CodeAssembler,
+    module: &mut ModuleContext,
+    function: &mut FunctionContext,
+    memarg: MemoryImmediate,
+    size: u32,
+) -> Result<(), Error> {
+    memory::address(assembler, module, function, memarg, size, false)?;
+    if module.cpu.avx2 {
+        match size {
+            1 => assembler.vpbroadcastb(xmm0, byte_ptr(rax))?,
+            2 => assembler.vpbroadcastw(xmm0, word_ptr(rax))?,
+            4 => assembler.vpbroadcastd(xmm0, dword_ptr(rax))?,
+            _ => assembler.vpbroadcastq(xmm0, qword_ptr(rax))?,
+        }
+    } else {
+        match size {
+            1 => assembler.movzx(eax, byte_ptr(rax))?,
+            2 => assembler.movzx(eax, word_ptr(rax))?,
+            _ => (),
+        }
+        match size {
+            1 | 2 => assembler.movd(xmm0, eax)?,
+            4 => assembler.movd(xmm0, dword_ptr(rax))?,
+            _ => assembler.movq(xmm0, qword_ptr(rax))?,
+        }
+        broadcast(assembler, module, size)?;
+    }
+    push(assembler, function, xmm0)
+}
+
+/// `v128.load32_zero` and `v128.load64_zero`
+pub(crate) fn load_zero(
+    assembler: &mut CodeAssembler,
+    module: &mut ModuleContext,
+    function: &mut FunctionContext,
+    memarg: MemoryImmediate,
+    size: u32,
+) -> Result<(), Error> {
+    memory::address(assembler, module, function, memarg, size, false)?;
+    match size {
+        4 => assembler.movd(xmm0, dword_ptr(rax))?,
+        _ => assembler.movq(xmm0, qword_ptr(rax))?,
+    }
+    push(assembler, function, xmm0)
+}
+
+/// `v128.load8_lane` and friends, replacing the lane in the stack slot
+pub(crate) fn load_lane(
+    assembler: &mut CodeAssembler,
+    module: &mut ModuleContext,
+    function: &mut FunctionContext,
+    memarg: MemoryImmediate,
+    size: u32,
+    lane: u8,
+) -> Result<(), Error> {
+    pop(assembler, function, xmm0)?;
+    memory::address(assembler, module, function, memarg, size, false)?;
+    load_lane_value(assembler, size, rax + 0)?;
+    push(assembler, function, xmm0)?;
+    store_lane_value(assembler, size, rsp + lane as u32 * size)
+}
+
+/// `v128.store8_lane` and friends, reading the lane from the stack slot
+pub(crate) fn store_lane(
+    assembler: &mut CodeAssembler,
+    module: &mut ModuleContext,
+    function: &mut FunctionContext,
+    memarg: MemoryImmediate,
+    size: u32,
+    lane: u8,
+) -> Result<(), Error> {
+    load_lane_value(assembler, size, rsp + lane as u32 * size)?;
+    drop(assembler, function)?;
+    memory::address(assembler, module, function, memarg, size, false)?;
+    store_lane_value(assembler, size, rax + 0)
+}
+
+/// `extract_lane`, reading the lane from the stack slot
+pub(crate) fn extract_lane(
+    assembler: &mut CodeAssembler,
+    function: &mut FunctionContext,
+    shape: Shape,
+    lane: u8,
+    signed: bool,
+) -> Result<(), Error> {
+    let address = rsp + lane as u32 * shape.lane_size();
+    match (shape.lane_size(), signed) {
+        (1, true) => assembler.movsx(eax, byte_ptr(address))?,
+        (1, false) => assembler.movzx(eax, byte_ptr(address))?,
+        (2, true) => assembler.movsx(eax, word_ptr(address))?,
+        (2, false) => assembler.movzx(eax, word_ptr(address))?,
+        (4, _) => assembler.mov(eax, dword_ptr(address))?,
+        _ => assembler.mov(rax, qword_ptr(address))?,
+    }
+    drop(assembler, function)?;
+    function.push(assembler, rax, shape.lane_type())
+}
+
+/// `replace_lane`, writing the lane to the stack slot
+pub(crate) fn replace_lane(
+    assembler: &mut CodeAssembler,
+    function: &mut FunctionContext,
+    shape: Shape,
+    lane: u8,
+) -> Result<(), Error> {
+    function.pop(assembler, rax)?;
+    let address = rsp + lane as u32 * shape.lane_size();
+    match shape.lane_size() {
+        1 => assembler.mov(byte_ptr(address), al)?,
+        2 => assembler.mov(word_ptr(address), ax)?,
+        4 => assembler.mov(dword_ptr(address), eax)?,
+        _ => assembler.mov(qword_ptr(address), rax)?,
+    }
+    Ok(())
+}
+
+/// Copies the lowest lane of `size` bytes of XMM0 to the other ones
+fn broadcast(
+    assembler: &mut CodeAssembler,
+    module: &ModuleContext,
+    size: u32,
+) -> Result<(), Error> {
+    if module.cpu.avx2 {
+        match size {
+            1 => assembler.vpbroadcastb(xmm0, xmm0)?,
+            2 => assembler.vpbroadcastw(xmm0, xmm0)?,
+            4 => assembler.vpbroadcastd(xmm0, xmm0)?,
+            _ => assembler.vpbroadcastq(xmm0, xmm0)?,
+        }
+        return Ok(());
+    }
+    match size {
+        1 => {
+            assembler.punpcklbw(xmm0, xmm0)?;
+            assembler.pshuflw(xmm0, xmm0, 0)?;
+            assembler.pshufd(xmm0, xmm0, 0)?;
+        }
+        2 => {
+            assembler.pshuflw(xmm0, xmm0, 0)?;
+            assembler.pshufd(xmm0, xmm0, 0)?;
+        }
+        4 => assembler.pshufd(xmm0, xmm0, 0)?,
+        _ => assembler.punpcklqdq(xmm0, xmm0)?,
+    }
+    Ok(())
+}
+
+/// `splat`
+pub(crate) fn splat(
+    assembler: &mut CodeAssembler,
+    module: &ModuleContext,
+    function: &mut FunctionContext,
+    shape: Shape,
+) -> Result<(), Error> {
+    function.pop(assembler, rax)?;
+    match shape.lane_size() {
+        8 => assembler.movq(xmm0, rax)?,
+        _ => assembler.movd(xmm0, eax)?,
+    }
+    broadcast(assembler, module, shape.lane_size())?;
+    push(assembler, function, xmm0)
+}
+
+/// `i8x16.shuffle`, with a `PSHUFB` of each operand when SSSE3 is
+/// available, byte by byte from the stack slots otherwise
+pub(crate) fn shuffle(
+    assembler: &mut CodeAssembler,
+    module: &mut ModuleContext,
+    function: &mut FunctionContext,
+    lanes: [u8; 16],
+) -> Result<(), Error> {
+    if module.cpu.ssse3 {
+        // Indices with the top bit set give zero
+        let select = |first: u8| {
+            lanes.iter().enumerate().fold(0u128, |mask, (index, lane)| {
+                let byte = match lane.checked_sub(first) {
+                    Some(byte) if byte < 16 => byte,
+                    _ => 0x80,
+                };
+                mask | (byte as u128) << (index * 8)
+            })
+        };
+        return binary(assembler, function, |a| -> Result<(), Error> {
+            constant(a, module, xmm2, select(0))?;
+            a.pshufb(xmm0, xmm2)?;
+            if lanes.iter().any(|lane| *lane >= 16) {
+                constant(a, module, xmm2, select(16))?;
+                a.pshufb(xmm1, xmm2)?;
+                a.por(xmm0, xmm1)?;
+            }
+            Ok(())
+        });
+    }
+    // The second operand is on top of the first one
+    let source = |lane: u8| match lane {
+        0..=15 => 16 + lane as u32,
+        _ => lane as u32 - 16,
+    };
+    for (index, pair) in lanes.chunks(2).enumerate() {
+        assembler.movzx(eax, byte_ptr(rsp + source(pair[0])))?;
+        assembler.movzx(ecx, byte_ptr(rsp + source(pair[1])))?;
+        assembler.shl(ecx, 8)?;
+        assembler.or(eax, ecx)?;
+        assembler.pinsrw(xmm0, eax, index as i32)?;
+    }
+    drop(assembler, function)?;
+    assembler.movdqu(xmmword_ptr(rsp), xmm0)?;
+    Ok(())
+}
+
+/// `i8x16.swizzle`. Out of range indices select zero, as `PSHUFB` does
+/// with indices of 0x80 and up, to which a saturating add brings them.
+/// Without SSSE3, bytes are picked one by one, over the indices which
+/// aren't needed afterwards.
+pub(crate) fn swizzle(
+    assembler: &mut CodeAssembler,
+    module: &mut ModuleContext,
+    function: &mut FunctionContext,
+) -> Result<(), Error> {
+    if module.cpu.ssse3 {
+        return binary(assembler, function, |a| -> Result<(), Error> {
+            constant(a, module, xmm2, lanes(0x70, 1))?;
+            a.paddusb(xmm1, xmm2)?;
+            a.pshufb(xmm0, xmm1)?;
+            Ok(())
+        });
+    }
+    let next = assembler.create_label();
+    let zero = assembler.create_label();
+    assembler.xor(ecx, ecx)?;
+    module.bind(assembler, next);
+    assembler.movzx(eax, byte_ptr(rsp + rcx))?;
+    assembler.xor(edx, edx)?;
+    assembler.cmp(eax, 16)?;
+    assembler.jae(zero)?;
+    assembler.movzx(edx, byte_ptr(rsp + rax + 16))?;
+    module.bind(assembler, zero);
+    assembler.mov(byte_ptr(rsp + rcx), dl)?;
+    assembler.add(ecx, 1)?;
+    assembler.cmp(ecx, 16)?;
+    assembler.jb(next)?;
+    pop(assembler, function, xmm0)?;
+    assembler.movdqu(xmmword_ptr(rsp), xmm0)?;
+    Ok(())
+}
+
+/// Lane-wise `dst == src` into `dst`. Clobbers `scratch`.
+fn equal(
+    assembler: &mut CodeAssembler,
+    module: &ModuleContext,
+    shape: Shape,
+    dst: AsmRegisterXmm,
+    src: AsmRegisterXmm,
+    scratch: AsmRegisterXmm,
+) -> Result<(), Error> {
+    match shape {
+        Shape::I8x16 => assembler.pcmpeqb(dst, src)?,
+        Shape::I16x8 => assembler.pcmpeqw(dst, src)?,
+        Shape::I64x2 if module.cpu.sse4_1 => assembler.pcmpeqq(dst, src)?,
+        Shape::I64x2 => {
+            // Both halves have to be equal
+            assembler.pcmpeqd(dst, src)?;
+            assembler.pshufd(scratch, dst, 0xB1)?;
+            assembler.pand(dst, scratch)?;
+        }
+        _ => assembler.pcmpeqd(dst, src)?,
+    }
+    Ok(())
+}
+
+/// Lane-wise signed `dst > src` into `dst`. Clobbers the scratch registers.
+fn greater(
+    assembler: &mut CodeAssembler,
+    module: &ModuleContext,
+    shape: Shape,
+    dst: AsmRegisterXmm,
+    src: AsmRegisterXmm,
+    scratch: [AsmRegisterXmm; 2],
+) -> Result<(), Error> {
+    match shape {
+        Shape::I8x16 => assembler.pcmpgtb(dst, src)?,
+        Shape::I16x8 => assembler.pcmpgtw(dst, src)?,
+        Shape::I64x2 if module.cpu.sse4_2 => assembler.pcmpgtq(dst, src)?,
+        Shape::I64x2 => {
+            // Greater upper halves, or equal ones and a borrow from
+            // subtracting the lower halves as unsigned
+            let [difference, halves] = scratch;
+            assembler.movdqa(difference, src)?;
+            assembler.psubq(difference, dst)?;
+            assembler.movdqa(halves, dst)?;
+            assembler.pcmpeqd(halves, src)?;
+            assembler.pand(difference, halves)?;
+            assembler.movdqa(halves, dst)?;
+            assembler.pcmpgtd(halves, src)?;
+            assembler.por(difference, halves)?;
+            assembler.pshufd(dst, difference, 0xF5)?;
+        }
+        _ => assembler.pcmpgtd(dst, src)?,
+    }
+    Ok(())
+}
+
+/// Inverts the bits of `xmm`. Clobbers `scratch`.
+fn not_(
+    assembler: &mut CodeAssembler,
+    xmm: AsmRegisterXmm,
+    scratch: AsmRegisterXmm,
+) -> Result<(), Error> {
+    assembler.pcmpeqd(scratch, scratch)?;
+    assembler.pxor(xmm, scratch)?;
+    Ok(())
+}
+
+/// Lane-wise comparisons, giving all ones for true. Float ones take the
+/// conditions [`float::compare`](crate::x86_64::float::compare) does.
+/// Integer ones only have equality and signed greater-than instructions,
+/// unsigned operands have their sign bits flipped first.
+pub(crate) fn compare(
+    assembler: &mut CodeAssembler,
+    module: &mut ModuleContext,
+    function: &mut FunctionContext,
+    shape: Shape,
+    condition: Condition,
+) -> Result<(), Error> {
+    binary(assembler, function, |a| -> Result<(), Error> {
+        if let Shape::F32x4 | Shape::F64x2 = shape {
+            // a > b is b < a
+            let (swap, predicate) = match condition {
+                Condition::Eq => (false, 0),
+                Condition::Ne => (false, 4),
+                Condition::LtU => (false, 1),
+                Condition::LeU => (false, 2),
+                Condition::GtU => (true, 1),
+                _ => (true, 2),
+            };
+            let (dst, src) = if swap { (xmm1, xmm0) } else { (xmm0, xmm1) };
+            match shape {
+                Shape::F32x4 => a.cmpps(dst, src, predicate)?,
+                _ => a.cmppd(dst, src, predicate)?,
+            }
+            if swap {
+                a.movaps(xmm0, xmm1)?;
+            }
+            return Ok(());
+        }
+        let (swap, negate) = match condition {
+            Condition::Eq | Condition::GtS | Condition::GtU => (false, false),
+            Condition::Ne | Condition::LeS | Condition::LeU => (false, true),
+            Condition::LtS | Condition::LtU => (true, false),
+            Condition::GeS | Condition::GeU => (true, true),
+        };
+        if let Condition::LtU | Condition::GtU | Condition::LeU | Condition::GeU = condition {
+            let bits = shape.lane_size() * 8;
+            constant(a, module, xmm2, lanes(1 << (bits - 1), shape.lane_size()))?;
+            a.pxor(xmm0, xmm2)?;
+            a.pxor(xmm1, xmm2)?;
+        }
+        let (dst, src) = if swap { (xmm1, xmm0) } else { (xmm0, xmm1) };
+        match condition {
+            Condition::Eq | Condition::Ne => equal(a, module, shape, dst, src, xmm2)?,
+            _ => greater(a, module, shape, dst, src, [xmm2, xmm3])?,
+        }
+        if negate {
+            not_(a, dst, xmm2)?;
+        }
+        if swap {
+            a.movdqa(xmm0, xmm1)?;
+        }
+        Ok(())
+    })
+}
+
+/// `v128.not`
+pub(crate) fn not(assembler: &mut CodeAssembler) -> Result<(), Error> {
+    unary(assembler, |a| not_(a, xmm0, xmm1))
+}
+
+/// `v128.bitselect`: bits of the first operand where the mask has ones,
+/// of the second one elsewhere
+pub(crate) fn bitselect(
+    assembler: &mut CodeAssembler,
+    function: &mut FunctionContext,
+) -> Result<(), Error> {
+    pop(assembler, function, xmm2)?;
+    binary(assembler, function, |a| -> Result<(), Error> {
+        a.pand(xmm0, xmm2)?;
+        a.pandn(xmm2, xmm1)?;
+        a.por(xmm0, xmm2)?;
+        Ok(())
+    })
+}
+
+/// `v128.any_true`, leaving the result in the flags like comparisons
+pub(crate) fn any_true(
+    assembler: &mut CodeAssembler,
+    function: &mut FunctionContext,
+) -> Result<(), Error> {
+    assembler.mov(rax, qword_ptr(rsp))?;
+    assembler.or(rax, qword_ptr(rsp + 8))?;
+    // LEA leaves the flags alone
+    assembler.lea(rsp, ptr(rsp + 16))?;
+    function.stack.pop();
+    function.stack.push(Type::I32);
+    function.condition = Some(Condition::Ne);
+    Ok(())
+}
+
+/// `all_true`, leaving the result in the flags like comparisons
+pub(crate) fn all_true(
+    assembler: &mut CodeAssembler,
+    module: &ModuleContext,
+    function: &mut FunctionContext,
+    shape: Shape,
+) -> Result<(), Error> {
+    pop(assembler, function, xmm0)?;
+    // Zero lanes become all ones
+    assembler.pxor(xmm1, xmm1)?;
+    equal(assembler, module, shape, xmm1, xmm0, xmm2)?;
+    assembler.pmovmskb(eax, xmm1)?;
+    assembler.test(eax, eax)?;
+    function.stack.push(Type::I32);
+    function.condition = Some(Condition::Eq);
+    Ok(())
+}
+
+/// `bitmask`: the sign bits of the lanes
+pub(crate) fn bitmask(
+    assembler: &mut CodeAssembler,
+    function: &mut FunctionContext,
+    shape: Shape,
+) -> Result<(), Error> {
+    pop(assembler, function, xmm0)?;
+    match shape {
+        Shape::I8x16 => assembler.pmovmskb(eax, xmm0)?,
+        Shape::I16x8 => {
+            // Saturation keeps the signs
+            assembler.packsswb(xmm0, xmm0)?;
+            assembler.pmovmskb(eax, xmm0)?;
+            assembler.movzx(eax, al)?;
+        }
+        Shape::I32x4 => assembler.movmskps(eax, xmm0)?,
+        _ => assembler.movmskpd(eax, xmm0)?,
+    }
+    function.push(assembler, rax, Type::I32)
+}
+
+/// Lane-wise `dst - src`
+fn subtract(
+    assembler: &mut CodeAssembler,
+    shape: Shape,
+    dst: AsmRegisterXmm,
+    src: AsmRegisterXmm,
+) -> Result<(), Error> {
+    match shape {
+        Shape::I8x16 => assembler.psubb(dst, src)?,
+        Shape::I16x8 => assembler.psubw(dst, src)?,
+        Shape::I32x4 => assembler.psubd(dst, src)?,
+        _ => assembler.psubq(dst, src)?,
+    }
+    Ok(())
+}
+
+/// `abs`. Integer lanes are subtracted from zero where negative, with
+/// `PABS*` when SSSE3 is available. Float ones get their sign cleared.
+pub(crate) fn abs(
+    assembler: &mut CodeAssembler,
+    module: &ModuleContext,
+    shape: Shape,
+) -> Result<(), Error> {
+    let ssse3 = module.cpu.ssse3;
+    unary(assembler, |a| -> Result<(), Error> {
+        match shape {
+            Shape::F32x4 | Shape::F64x2 => {
+                a.pcmpeqd(xmm1, xmm1)?;
+                if shape == Shape::F32x4 {
+                    a.psrld(xmm1, 1)?;
+                } else {
+                    a.psrlq(xmm1, 1)?;
+                }
+                a.pand(xmm0, xmm1)?;
+                return Ok(());
+            }
+            Shape::I8x16 if ssse3 => return Ok(a.pabsb(xmm0, xmm0)?),
+            Shape::I16x8 if ssse3 => return Ok(a.pabsw(xmm0, xmm0)?),
+            Shape::I32x4 if ssse3 => return Ok(a.pabsd(xmm0, xmm0)?),
+            Shape::I8x16 => {
+                a.pxor(xmm1, xmm1)?;
+                a.pcmpgtb(xmm1, xmm0)?;
+            }
+            Shape::I16x8 => {
+                a.movdqa(xmm1, xmm0)?;
+                a.psraw(xmm1, 15)?;
+            }
+            Shape::I32x4 => {
+                a.movdqa(xmm1, xmm0)?;
+                a.psrad(xmm1, 31)?;
+            }
+            Shape::I64x2 => {
+                a.pshufd(xmm1, xmm0, 0xF5)?;
+                a.psrad(xmm1, 31)?;
+            }
+        }
+        // Ones' complement and add one where negative
+        a.pxor(xmm0, xmm1)?;
+        subtract(a, shape, xmm0, xmm1)
+    })
+}
+
+/// `neg`. Integer lanes are subtracted from zero, float ones get their sign
+/// flipped.
+pub(crate) fn neg(assembler: &mut CodeAssembler, shape: Shape) -> Result<(), Error> {
+    unary(assembler, |a| -> Result<(), Error> {
+        match shape {
+            Shape::F32x4 => {
+                a.pcmpeqd(xmm1, xmm1)?;
+                a.pslld(xmm1, 31)?;
+                a.pxor(xmm0, xmm1)?;
+            }
+            Shape::F64x2 => {
+                a.pcmpeqd(xmm1, xmm1)?;
+                a.psllq(xmm1, 63)?;
+                a.pxor(xmm0, xmm1)?;
+            }
+            _ => {
+                a.movdqa(xmm1, xmm0)?;
+                a.pxor(xmm0, xmm0)?;
+                subtract(a, shape, xmm0, xmm1)?;
+            }
+        }
+        Ok(())
+    })
+}
+
+/// `i8x16.popcnt`, looking up the count of each nibble with `PSHUFB` when
+/// SSSE3 is available, with the usual bit twiddling otherwise
+pub(crate) fn popcnt(
+    assembler: &mut CodeAssembler,
+    module: &mut ModuleContext,
+) -> Result<(), Error> {
+    let ssse3 = module.cpu.ssse3;
+    unary(assembler, |a| -> Result<(), Error> {
+        if ssse3 {
+            constant(a, module, xmm2, lanes(0x0F, 1))?;
+            constant(a, module, xmm3, 0x04030302_03020201_03020201_02010100)?;
+            a.movdqa(xmm1, xmm0)?;
+            a.psrlw(xmm1, 4)?;
+            a.pand(xmm0, xmm2)?;
+            a.pand(xmm1, xmm2)?;
+            a.movdqa(xmm2, xmm3)?;
+            a.pshufb(xmm2, xmm0)?;
+            a.pshufb(xmm3, xmm1)?;
+            a.movdqa(xmm0, xmm2)?;
+            a.paddb(xmm0, xmm3)?;
+            return Ok(());
+        }
+        // Word shifts, bits crossing into the next byte are masked out
+        constant(a, module, xmm2, lanes(0x55, 1))?;
+        a.movdqa(xmm1, xmm0)?;
+        a.psrlw(xmm1, 1)?;
+        a.pand(xmm1, xmm2)?;
+        a.psubb(xmm0, xmm1)?;
+        constant(a, module, xmm2, lanes(0x33, 1))?;
+        a.movdqa(xmm1, xmm0)?;
+        a.psrlw(xmm1, 2)?;
+        a.pand(xmm0, xmm2)?;
+        a.pand(xmm1, xmm2)?;
+        a.paddb(xmm0, xmm1)?;
+        constant(a, module, xmm2, lanes(0x0F, 1))?;
+        a.movdqa(xmm1, xmm0)?;
+        a.psrlw(xmm1, 4)?;
+        a.paddb(xmm0, xmm1)?;
+        a.pand(xmm0, xmm2)?;
+        Ok(())
+    })
+}
+
+/// Shifts, with the count masked to the lane width. There are no byte
+/// shifts, bytes are shifted as words and masked, or sign-extended to
+/// words. There is no 64-bit arithmetic shift either, negative lanes are
+/// inverted around a logical one.
+pub(crate) fn shift(
+    assembler: &mut CodeAssembler,
+    function: &mut FunctionContext,
+    shape: Shape,
+    shift: Shift,
+) -> Result<(), Error> {
+    function.pop(assembler, rcx)?;
+    assembler.and(ecx, shape.lane_size() as i32 * 8 - 1)?;
+    unary(assembler, |a| -> Result<(), Error> {
+        match (shape, shift) {
+            (Shape::I8x16, Shift::RightSigned) => {
+                // The byte in the upper half of each word
+                a.movdqa(xmm1, xmm0)?;
+                a.punpckhbw(xmm1, xmm1)?;
+                a.punpcklbw(xmm0, xmm0)?;
+                a.add(ecx, 8)?;
+                a.movd(xmm2, ecx)?;
+                a.psraw(xmm0, xmm2)?;
+                a.psraw(xmm1, xmm2)?;
+                a.packsswb(xmm0, xmm1)?;
+            }
+            (Shape::I8x16, _) => {
+                a.movd(xmm1, ecx)?;
+                a.mov(eax, 0xFF)?;
+                if shift == Shift::Left {
+                    a.psllw(xmm0, xmm1)?;
+                    a.shl(eax, cl)?;
+                } else {
+                    a.psrlw(xmm0, xmm1)?;
+                    a.shr(eax, cl)?;
+                }
+                a.movzx(eax, al)?;
+                a.imul_3(eax, eax, 0x0101_0101)?;
+                a.movd(xmm1, eax)?;
+                a.pshufd(xmm1, xmm1, 0)?;
+                a.pand(xmm0, xmm1)?;
+            }
+            (Shape::I64x2, Shift::RightSigned) => {
+                a.movd(xmm1, ecx)?;
+                a.pshufd(xmm2, xmm0, 0xF5)?;
+                a.psrad(xmm2, 31)?;
+                a.pxor(xmm0, xmm2)?;
+                a.psrlq(xmm0, xmm1)?;
+                a.pxor(xmm0, xmm2)?;
+            }
+            _ => {
+                a.movd(xmm1, ecx)?;
+                match (shape, shift) {
+                    (Shape::I16x8, Shift::Left) => a.psllw(xmm0, xmm1)?,
+                    (Shape::I16x8, Shift::RightSigned) => a.psraw(xmm0, xmm1)?,
+                    (Shape::I16x8, Shift::RightUnsigned) => a.psrlw(xmm0, xmm1)?,
+                    (Shape::I32x4, Shift::Left) => a.pslld(xmm0, xmm1)?,
+                    (Shape::I32x4, Shift::RightSigned) => a.psrad(xmm0, xmm1)?,
+                    (Shape::I32x4, Shift::RightUnsigned) => a.psrld(xmm0, xmm1)?,
+                    (_, Shift::Left) => a.psllq(xmm0, xmm1)?,
+                    _ => a.psrlq(xmm0, xmm1)?,
+                }
+            }
+        }
+        Ok(())
+    })
+}
+
+/// `narrow` to lanes of `shape`, saturating. Without SSE4.1, the unsigned
+/// narrowing of 32-bit lanes clamps them first, then sign-extends their
+/// lower halves so that the signed saturation keeps them.
+pub(crate) fn narrow(
+    assembler: &mut CodeAssembler,
+    module: &mut ModuleContext,
+    function: &mut FunctionContext,
+    shape: Shape,
+    signed: bool,
+) -> Result<(), Error> {
+    let sse4_1 = module.cpu.sse4_1;
+    binary(assembler, function, |a| -> Result<(), Error> {
+        match (shape, signed) {
+            (Shape::I8x16, true) => a.packsswb(xmm0, xmm1)?,
+            (Shape::I8x16, false) => a.packuswb(xmm0, xmm1)?,
+            (_, true) => a.packssdw(xmm0, xmm1)?,
+            (_, false) if sse4_1 => a.packusdw(xmm0, xmm1)?,
+            (_, false) => {
+                a.pxor(xmm2, xmm2)?;
+                constant(a, module, xmm3, lanes(0xFFFF, 4))?;
+                for xmm in [xmm0, xmm1] {
+                    a.movdqa(xmm4, xmm)?;
+                    a.pcmpgtd(xmm4, xmm2)?;
+                    a.pand(xmm, xmm4)?;
+                    a.movdqa(xmm4, xmm)?;
+                    a.pcmpgtd(xmm4, xmm3)?;
+                    a.por(xmm, xmm4)?;
+                    a.pslld(xmm, 16)?;
+                    a.psrad(xmm, 16)?;
+                }
+                a.packssdw(xmm0, xmm1)?;
+            }
+        }
+        Ok(())
+    })
+}
+
+/// Extends the lower or `high` half of the lanes of `xmm` to the twice as
+/// wide lanes of `shape`. Clobbers `scratch`.
+fn extend(
+    assembler: &mut CodeAssembler,
+    module: &ModuleContext,
+    shape: Shape,
+    xmm: AsmRegisterXmm,
+    scratch: AsmRegisterXmm,
+    high: bool,
+    signed: bool,
+) -> Result<(), Error> {
+    if high {
+        assembler.pshufd(xmm, xmm, 0xEE)?;
+    }
+    if module.cpu.sse4_1 {
+        match (shape, signed) {
+            (Shape::I16x8, true) => assembler.pmovsxbw(xmm, xmm)?,
+            (Shape::I16x8, false) => assembler.pmovzxbw(xmm, xmm)?,
+            (Shape::I32x4, true) => assembler.pmovsxwd(xmm, xmm)?,
+            (Shape::I32x4, false) => assembler.pmovzxwd(xmm, xmm)?,
+            (_, true) => assembler.pmovsxdq(xmm, xmm)?,
+            (_, false) => assembler.pmovzxdq(xmm, xmm)?,
+        }
+        return Ok(());
+    }
+    match (shape, signed) {
+        // Into the upper half, then shifted down
+        (Shape::I16x8, true) => {
+            assembler.punpcklbw(xmm, xmm)?;
+            assembler.psraw(xmm, 8)?;
+        }
+        (Shape::I32x4, true) => {
+            assembler.punpcklwd(xmm, xmm)?;
+            assembler.psrad(xmm, 16)?;
+        }
+        (_, true) => {
+            assembler.movdqa(scratch, xmm)?;
+            assembler.psrad(scratch, 31)?;
+            assembler.punpckldq(xmm, scratch)?;
+        }
+        // Interleaved with zeros
+        (shape, false) => {
+            assembler.pxor(scratch, scratch)?;
+            match shape {
+                Shape::I16x8 => assembler.punpcklbw(xmm, scratch)?,
+                Shape::I32x4 => assembler.punpcklwd(xmm, scratch)?,
+                _ => assembler.punpckldq(xmm, scratch)?,
+            }
+        }
+    }
+    Ok(())
+}
+
+/// `extend_low` and `extend_high` to lanes of `shape`
+pub(crate) fn extend_half(
+    assembler: &mut CodeAssembler,
+    module: &ModuleContext,
+    shape: Shape,
+    high: bool,
+    signed: bool,
+) -> Result<(), Error> {
+    unary(assembler, |a| {
+        extend(a, module, shape, xmm0, xmm1, high, signed)
+    })
+}
+
+/// 64-bit lane-wise `XMM0 * XMM1` into XMM0, from 32-bit products. Clobbers
+/// XMM1 to XMM3.
+fn multiply64(assembler: &mut CodeAssembler) -> Result<(), Error> {
+    // Upper halves only contribute to the upper half of the result
+    assembler.movdqa(xmm2, xmm0)?;
+    assembler.psrlq(xmm2, 32)?;
+    assembler.pmuludq(xmm2, xmm1)?;
+    assembler.movdqa(xmm3, xmm1)?;
+    assembler.psrlq(xmm3, 32)?;
+    assembler.pmuludq(xmm3, xmm0)?;
+    assembler.paddq(xmm2, xmm3)?;
+    assembler.psllq(xmm2, 32)?;
+    assembler.pmuludq(xmm0, xmm1)?;
+    assembler.paddq(xmm0, xmm2)?;
+    Ok(())
+}
+
+/// `mul` of 32-bit lanes, with `PMULLD` when SSE4.1 is available, and of
+/// 64-bit ones. Otherwise lanes are multiplied by pairs into 64-bit
+/// products.
+pub(crate) fn mul(
+    assembler: &mut CodeAssembler,
+    module: &ModuleContext,
+    function: &mut FunctionContext,
+    shape: Shape,
+) -> Result<(), Error> {
+    let sse4_1 = module.cpu.sse4_1;
+    binary(assembler, function, |a| -> Result<(), Error> {
+        match shape {
+            Shape::I32x4 if sse4_1 => a.pmulld(xmm0, xmm1)?,
+            Shape::I32x4 => {
+                a.pshufd(xmm2, xmm0, 0xF5)?;
+                a.pshufd(xmm3, xmm1, 0xF5)?;
+                a.pmuludq(xmm0, xmm1)?;
+                a.pmuludq(xmm2, xmm3)?;
+                a.pshufd(xmm0, xmm0, 0xE8)?;
+                a.pshufd(xmm2, xmm2, 0xE8)?;
+                a.punpckldq(xmm0, xmm2)?;
+            }
+            _ => multiply64(a)?,
+        }
+        Ok(())
+    })
+}
+
+/// `extmul_low` and `extmul_high` to lanes of `shape`
+pub(crate) fn extmul(
+    assembler: &mut CodeAssembler,
+    module: &ModuleContext,
+    function: &mut FunctionContext,
+    shape: Shape,
+    high: bool,
+    signed: bool,
+) -> Result<(), Error> {
+    binary(assembler, function, |a| -> Result<(), Error> {
+        match shape {
+            Shape::I16x8 => {
+                extend(a, module, shape, xmm0, xmm2, high, signed)?;
+                extend(a, module, shape, xmm1, xmm2, high, signed)?;
+                a.pmullw(xmm0, xmm1)?;
+            }
+            Shape::I32x4 => {
+                // Lower and upper halves of the products, interleaved
+                a.movdqa(xmm2, xmm0)?;
+                a.pmullw(xmm0, xmm1)?;
+                if signed {
+                    a.pmulhw(xmm2, xmm1)?;
+                } else {
+                    a.pmulhuw(xmm2, xmm1)?;
+                }
+                if high {
+                    a.punpckhwd(xmm0, xmm2)?;
+                } else {
+                    a.punpcklwd(xmm0, xmm2)?;
+                }
+            }
+            _ if signed && !module.cpu.sse4_1 => {
+                extend(a, module, shape, xmm0, xmm2, high, true)?;
+                extend(a, module, shape, xmm1, xmm2, high, true)?;
+                multiply64(a)?;
+            }
+            _ => {
+                // PMULUDQ and PMULDQ multiply even lanes
+                let lanes = if high { 0xFA } else { 0x50 };
+                a.pshufd(xmm0, xmm0, lanes)?;
+                a.pshufd(xmm1, xmm1, lanes)?;
+                if signed {
+                    a.pmuldq(xmm0, xmm1)?;
+                } else {
+                    a.pmuludq(xmm0, xmm1)?;
+                }
+            }
+        }
+        Ok(())
+    })
+}
+
+/// `extadd_pairwise` to lanes of `shape`. Pairs of bytes are multiplied by
+/// one and added by `PMADDUBSW` when SSSE3 is available, pairs of words by
+/// `PMADDWD`, otherwise the halves of each lane are extended in place.
+pub(crate) fn extadd_pairwise(
+    assembler: &mut CodeAssembler,
+    module: &mut ModuleContext,
+    shape: Shape,
+    signed: bool,
+) -> Result<(), Error> {
+    let ssse3 = module.cpu.ssse3;
+    unary(assembler, |a| -> Result<(), Error> {
+        match (shape, signed) {
+            // The first operand of PMADDUBSW is unsigned, the second signed
+            (Shape::I16x8, true) if ssse3 => {
+                constant(a, module, xmm1, lanes(1, 1))?;
+                a.pmaddubsw(xmm1, xmm0)?;
+                a.movdqa(xmm0, xmm1)?;
+            }
+            (Shape::I16x8, false) if ssse3 => {
+                constant(a, module, xmm1, lanes(1, 1))?;
+                a.pmaddubsw(xmm0, xmm1)?;
+            }
+            (Shape::I16x8, _) => {
+                a.movdqa(xmm1, xmm0)?;
+                a.psllw(xmm0, 8)?;
+                if signed {
+                    a.psraw(xmm0, 8)?;
+                    a.psraw(xmm1, 8)?;
+                } else {
+                    a.psrlw(xmm0, 8)?;
+                    a.psrlw(xmm1, 8)?;
+                }
+                a.paddw(xmm0, xmm1)?;
+            }
+            (_, true) => {
+                constant(a, module, xmm1, lanes(1, 2))?;
+                a.pmaddwd(xmm0, xmm1)?;
+            }
+            (_, false) => {
+                a.movdqa(xmm1, xmm0)?;
+                a.pslld(xmm0, 16)?;
+                a.psrld(xmm0, 16)?;
+                a.psrld(xmm1, 16)?;
+                a.paddd(xmm0, xmm1)?;
+            }
+        }
+        Ok(())
+    })
+}
+
+/// Integer `min` and `max`. Without SSE4.1, only unsigned bytes and signed
+/// words have instructions: other bytes and words get their sign bits
+/// flipped around them, 32-bit lanes are picked according to a comparison.
+pub(crate) fn min_max(
+    assembler: &mut CodeAssembler,
+    module: &mut ModuleContext,
+    function: &mut FunctionContext,
+    shape: Shape,
+    signed: bool,
+    max: bool,
+) -> Result<(), Error> {
+    let sse4_1 = module.cpu.sse4_1;
+    binary(assembler, function, |a| -> Result<(), Error> {
+        match (shape, signed, max) {
+            (Shape::I8x16, false, false) => a.pminub(xmm0, xmm1)?,
+            (Shape::I8x16, false, true) => a.pmaxub(xmm0, xmm1)?,
+            (Shape::I16x8, true, false) => a.pminsw(xmm0, xmm1)?,
+            (Shape::I16x8, true, true) => a.pmaxsw(xmm0, xmm1)?,
+            (Shape::I8x16, true, false) if sse4_1 => a.pminsb(xmm0, xmm1)?,
+            (Shape::I8x16, true, true) if sse4_1 => a.pmaxsb(xmm0, xmm1)?,
+            (Shape::I16x8, false, false) if sse4_1 => a.pminuw(xmm0, xmm1)?,
+            (Shape::I16x8, false, true) if sse4_1 => a.pmaxuw(xmm0, xmm1)?,
+            (_, true, false) if sse4_1 => a.pminsd(xmm0, xmm1)?,
+            (_, true, true) if sse4_1 => a.pmaxsd(xmm0, xmm1)?,
+            (_, false, false) if sse4_1 => a.pminud(xmm0, xmm1)?,
+            (_, false, true) if sse4_1 => a.pmaxud(xmm0, xmm1)?,
+            (Shape::I8x16, _, _) => {
+                constant(a, module, xmm2, lanes(0x80, 1))?;
+                a.pxor(xmm0, xmm2)?;
+                a.pxor(xmm1, xmm2)?;
+                if max {
+                    a.pmaxub(xmm0, xmm1)?;
+                } else {
+                    a.pminub(xmm0, xmm1)?;
+                }
+                a.pxor(xmm0, xmm2)?;
+            }
+            (Shape::I16x8, _, _) => {
+                constant(a, module, xmm2, lanes(0x8000, 2))?;
+                a.pxor(xmm0, xmm2)?;
+                a.pxor(xmm1, xmm2)?;
+                if max {
+                    a.pmaxsw(xmm0, xmm1)?;
+                } else {
+                    a.pminsw(xmm0, xmm1)?;
+                }
+                a.pxor(xmm0, xmm2)?;
+            }
+            _ => {
+                // XMM2 has all ones where the first operand is greater
+                a.movdqa(xmm2, xmm0)?;
+                if signed {
+                    a.pcmpgtd(xmm2, xmm1)?;
+                } else {
+                    constant(a, module, xmm4, lanes(0x8000_0000, 4))?;
+                    a.movdqa(xmm3, xmm1)?;
+                    a.pxor(xmm2, xmm4)?;
+                    a.pxor(xmm3, xmm4)?;
+                    a.pcmpgtd(xmm2, xmm3)?;
+                }
+                if max {
+                    a.pand(xmm0, xmm2)?;
+                    a.pandn(xmm2, xmm1)?;
+                    a.por(xmm0, xmm2)?;
+                } else {
+                    a.pand(xmm1, xmm2)?;
+                    a.pandn(xmm2, xmm0)?;
+                    a.por(xmm1, xmm2)?;
+                    a.movdqa(xmm0, xmm1)?;
+                }
+            }
+        }
+        Ok(())
+    })
+}
+
+/// `i16x8.q15mulr_sat_s`. `PMULHRSW` only overflows for -1 * -1, which
+/// gives 0x8000 instead of 0x7FFF. Without SSSE3, the 32-bit products are
+/// rounded and narrowed with saturation.
+pub(crate) fn q15mulr_sat(
+    assembler: &mut CodeAssembler,
+    module: &mut ModuleContext,
+    function: &mut FunctionContext,
+) -> Result<(), Error> {
+    let ssse3 = module.cpu.ssse3;
+    binary(assembler, function, |a| -> Result<(), Error> {
+        if ssse3 {
+            a.pmulhrsw(xmm0, xmm1)?;
+            constant(a, module, xmm1, lanes(0x8000, 2))?;
+            a.pcmpeqw(xmm1, xmm0)?;
+            a.pxor(xmm0, xmm1)?;
+            return Ok(());
+        }
+        a.movdqa(xmm2, xmm0)?;
+        a.pmullw(xmm0, xmm1)?;
+        a.pmulhw(xmm2, xmm1)?;
+        a.movdqa(xmm1, xmm0)?;
+        a.punpcklwd(xmm0, xmm2)?;
+        a.punpckhwd(xmm1, xmm2)?;
+        constant(a, module, xmm2, lanes(0x4000, 4))?;
+        a.paddd(xmm0, xmm2)?;
+        a.paddd(xmm1, xmm2)?;
+        a.psrad(xmm0, 15)?;
+        a.psrad(xmm1, 15)?;
+        a.packssdw(xmm0, xmm1)?;
+        Ok(())
+    })
+}
+
+/// Float `min` and `max`. Like their scalar versions, `MINPS` and friends
+/// return the second operand when either is NaN or both are zeros. Both
+/// orders are combined to propagate NaNs and get -0 below +0, and NaNs are
+/// made canonical.
+pub(crate) fn float_min_max(
+    assembler: &mut CodeAssembler,
+    function: &mut FunctionContext,
+    shape: Shape,
+    max: bool,
+) -> Result<(), Error> {
+    let f32 = shape == Shape::F32x4;
+    binary(assembler, function, |a| -> Result<(), Error> {
+        a.movaps(xmm2, xmm1)?;
+        match (f32, max) {
+            (true, false) => {
+                a.minps(xmm2, xmm0)?;
+                a.minps(xmm0, xmm1)?;
+            }
+            (true, true) => {
+                a.maxps(xmm2, xmm0)?;
+                a.maxps(xmm0, xmm1)?;
+            }
+            (false, false) => {
+                a.minpd(xmm2, xmm0)?;
+                a.minpd(xmm0, xmm1)?;
+            }
+            (false, true) => {
+                a.maxpd(xmm2, xmm0)?;
+                a.maxpd(xmm0, xmm1)?;
+            }
+        }
+        if max {
+            // Where the results differ, XMM2 becomes NaN, or +0 as -0 - +0
+            a.xorps(xmm0, xmm2)?;
+            a.orps(xmm2, xmm0)?;
+            if f32 {
+                a.subps(xmm2, xmm0)?;
+            } else {
+                a.subpd(xmm2, xmm0)?;
+            }
+        } else {
+            a.orps(xmm2, xmm0)?;
+        }
+        // NaNs get their payload cleared, keeping the quiet bit
+        if f32 {
+            a.movaps(xmm0, xmm2)?;
+            a.cmpps(xmm0, xmm2, 3)?;
+            a.orps(xmm2, xmm0)?;
+            a.psrld(xmm0, 10)?;
+        } else {
+            a.movaps(xmm0, xmm2)?;
+            a.cmppd(xmm0, xmm2, 3)?;
+            a.orps(xmm2, xmm0)?;
+            a.psrlq(xmm0, 13)?;
+        }
+        a.andnps(xmm0, xmm2)?;
+        Ok(())
+    })
+}
+
+/// Rounds the lanes of XMM0 with `ROUNDPS`/`ROUNDPD` when SSE4.1 is
+/// available. Otherwise lanes of magnitude below 2^23 (f32) or 2^52 (f64)
+/// are rounded to nearest by adding and subtracting that, adjusted by one
+/// for other roundings, and get their sign back. Others are integers,
+/// infinities or NaNs, kept as they are, NaNs being made quiet. Clobbers
+/// XMM1 to XMM7.
+fn round_xmm0(
+    assembler: &mut CodeAssembler,
+    module: &mut ModuleContext,
+    shape: Shape,
+    rounding: Rounding,
+) -> Result<(), Error> {
+    let f32 = shape == Shape::F32x4;
+    if module.cpu.sse4_1 {
+        if f32 {
+            assembler.roundps(xmm0, xmm0, rounding as i32)?;
+        } else {
+            assembler.roundpd(xmm0, xmm0, rounding as i32)?;
+        }
+        return Ok(());
+    }
+    let float = |value: f64| match f32 {
+        true => lanes((value as f32).to_bits() as u64, 4),
+        false => lanes(value.to_bits(), 8),
+    };
+    let add = |a: &mut CodeAssembler, dst, src| match f32 {
+        true => a.addps(dst, src),
+        false => a.addpd(dst, src),
+    };
+    let sub = |a: &mut CodeAssembler, dst, src| match f32 {
+        true => a.subps(dst, src),
+        false => a.subpd(dst, src),
+    };
+    // Only the less-than predicate is used
+    let less = |a: &mut CodeAssembler, dst, src| match f32 {
+        true => a.cmpps(dst, src, 1),
+        false => a.cmppd(dst, src, 1),
+    };
+    // XMM1 has the signs, XMM2 the magnitudes
+    assembler.pcmpeqd(xmm1, xmm1)?;
+    if f32 {
+        assembler.pslld(xmm1, 31)?;
+    } else {
+        assembler.psllq(xmm1, 63)?;
+    }
+    assembler.andps(xmm1, xmm0)?;
+    assembler.movaps(xmm2, xmm0)?;
+    assembler.xorps(xmm2, xmm1)?;
+    // Rounded magnitudes in XMM4
+    let shift = if f32 { 8388608.0 } else { 4503599627370496.0 };
+    constant(assembler, module, xmm3, float(shift))?;
+    assembler.movaps(xmm4, xmm2)?;
+    add(assembler, xmm4, xmm3)?;
+    sub(assembler, xmm4, xmm3)?;
+    if let Rounding::Nearest = rounding {
+        assembler.orps(xmm4, xmm1)?;
+    } else {
+        // Truncated, then brought away from zero for floor of negative
+        // and ceil of positive non-integers. Subtracting zero keeps -0.
+        constant(assembler, module, xmm5, float(1.0))?;
+        assembler.movaps(xmm6, xmm2)?;
+        less(assembler, xmm6, xmm4)?;
+        assembler.andps(xmm6, xmm5)?;
+        sub(assembler, xmm4, xmm6)?;
+        assembler.orps(xmm4, xmm1)?;
+        match rounding {
+            Rounding::Floor => {
+                assembler.movaps(xmm6, xmm0)?;
+                less(assembler, xmm6, xmm4)?;
+                assembler.andps(xmm6, xmm5)?;
+                sub(assembler, xmm4, xmm6)?;
+            }
+            Rounding::Ceil => {
+                constant(assembler, module, xmm5, float(-1.0))?;
+                assembler.movaps(xmm6, xmm4)?;
+                less(assembler, xmm6, xmm0)?;
+                assembler.andps(xmm6, xmm5)?;
+                sub(assembler, xmm4, xmm6)?;
+            }
+            _ => (),
+        }
+    }
+    // Large lanes are kept, adding zero quiets NaNs
+    less(assembler, xmm2, xmm3)?;
+    assembler.xorps(xmm7, xmm7)?;
+    add(assembler, xmm0, xmm7)?;
+    assembler.andps(xmm4, xmm2)?;
+    assembler.andnps(xmm2, xmm0)?;
+    assembler.orps(xmm2, xmm4)?;
+    assembler.movaps(xmm0, xmm2)?;
+    Ok(())
+}
+
+/// `ceil`, `floor`, `trunc` and `nearest`
+pub(crate) fn round(
+    assembler: &mut CodeAssembler,
+    module: &mut ModuleContext,
+    shape: Shape,
+    rounding: Rounding,
+) -> Result<(), Error> {
+    unary(assembler, |a| round_xmm0(a, module, shape, rounding))
+}
+
+/// `f32x4.convert_i32x4_s` and `f32x4.convert_i32x4_u`. Unsigned lanes are
+/// converted as their lower 16 bits and the rest, halved to be positive,
+/// which are both exact, so that only their sum is rounded.
+pub(crate) fn convert_i32x4(assembler: &mut CodeAssembler, signed: bool) -> Result<(), Error> {
+    unary(assembler, |a| -> Result<(), Error> {
+        if signed {
+            a.cvtdq2ps(xmm0, xmm0)?;
+            return Ok(());
+        }
+        a.movdqa(xmm1, xmm0)?;
+        a.pslld(xmm1, 16)?;
+        a.psrld(xmm1, 16)?;
+        a.psubd(xmm0, xmm1)?;
+        a.cvtdq2ps(xmm1, xmm1)?;
+        a.psrld(xmm0, 1)?;
+        a.cvtdq2ps(xmm0, xmm0)?;
+        a.addps(xmm0, xmm0)?;
+        a.addps(xmm0, xmm1)?;
+        Ok(())
+    })
+}
+
+/// `i32x4.trunc_sat_f32x4_s` and `i32x4.trunc_sat_f32x4_u`. `CVTTPS2DQ`
+/// gives 0x80000000 for NaNs and out of range values. Signed conversions
+/// zero NaNs first and flip positive overflows to 0x7FFFFFFF. Unsigned ones
+/// clamp negative values and NaNs to zero, then add the conversion of what
+/// is above 2^31, saturated, to that of the rest.
+pub(crate) fn trunc_sat_f32x4(
+    assembler: &mut CodeAssembler,
+    module: &ModuleContext,
+    signed: bool,
+) -> Result<(), Error> {
+    let sse4_1 = module.cpu.sse4_1;
+    unary(assembler, |a| -> Result<(), Error> {
+        if signed {
+            a.movaps(xmm1, xmm0)?;
+            a.cmpps(xmm1, xmm1, 0)?;
+            a.andps(xmm0, xmm1)?;
+            // Sign bits of XMM1 are set for positive lanes
+            a.pxor(xmm1, xmm0)?;
+            a.cvttps2dq(xmm0, xmm0)?;
+            a.pand(xmm1, xmm0)?;
+            a.psrad(xmm1, 31)?;
+            a.pxor(xmm0, xmm1)?;
+            return Ok(());
+        }
+        a.xorps(xmm1, xmm1)?;
+        a.maxps(xmm0, xmm1)?;
+        // 2^31
+        a.pcmpeqd(xmm1, xmm1)?;
+        a.psrld(xmm1, 1)?;
+        a.cvtdq2ps(xmm1, xmm1)?;
+        a.movaps(xmm2, xmm0)?;
+        a.subps(xmm2, xmm1)?;
+        a.cmpps(xmm1, xmm2, 2)?;
+        a.cvttps2dq(xmm2, xmm2)?;
+        a.pxor(xmm2, xmm1)?;
+        // Lanes below 2^31 add nothing
+        if sse4_1 {
+            a.pxor(xmm1, xmm1)?;
+            a.pmaxsd(xmm2, xmm1)?;
+        } else {
+            a.movdqa(xmm1, xmm2)?;
+            a.psrad(xmm1, 31)?;
+            a.pandn(xmm1, xmm2)?;
+            a.movdqa(xmm2, xmm1)?;
+        }
+        a.cvttps2dq(xmm0, xmm0)?;
+        a.paddd(xmm0, xmm2)?;
+        Ok(())
+    })
+}
+
+/// `i32x4.trunc_sat_f64x2_s_zero` and `i32x4.trunc_sat_f64x2_u_zero`.
+/// Values are clamped to the range of the result, NaNs to zero. Signed
+/// conversions can rely on `CVTTPD2DQ` giving 0x80000000 below it, unsigned
+/// ones truncate and take the lower bits of the sum with 2^52.
+pub(crate) fn trunc_sat_f64x2_zero(
+    assembler: &mut CodeAssembler,
+    module: &mut ModuleContext,
+    signed: bool,
+) -> Result<(), Error> {
+    unary(assembler, |a| -> Result<(), Error> {
+        if signed {
+            a.movapd(xmm1, xmm0)?;
+            a.cmppd(xmm1, xmm0, 0)?;
+            constant(a, module, xmm2, lanes(2147483647.0f64.to_bits(), 8))?;
+            a.andps(xmm1, xmm2)?;
+            a.minpd(xmm0, xmm1)?;
+            a.cvttpd2dq(xmm0, xmm0)?;
+            return Ok(());
+        }
+        a.xorpd(xmm1, xmm1)?;
+        a.maxpd(xmm0, xmm1)?;
+        constant(a, module, xmm1, lanes(4294967295.0f64.to_bits(), 8))?;
+        a.minpd(xmm0, xmm1)?;
+        round_xmm0(a, module, Shape::F64x2, Rounding::Trunc)?;
+        constant(a, module, xmm1, lanes(4503599627370496.0f64.to_bits(), 8))?;
+        a.addpd(xmm0, xmm1)?;
+        a.xorps(xmm1, xmm1)?;
+        a.shufps(xmm0, xmm1, 0x88)?;
+        Ok(())
+    })
+}
+
+/// `f64x2.convert_low_i32x4_s` and `f64x2.convert_low_i32x4_u`. Unsigned
+/// lanes are made the lower bits of 2^52, which is then subtracted.
+pub(crate) fn convert_low_i32x4(
+    assembler: &mut CodeAssembler,
+    module: &mut ModuleContext,
+    signed: bool,
+) -> Result<(), Error> {
+    unary(assembler, |a| -> Result<(), Error> {
+        if signed {
+            a.cvtdq2pd(xmm0, xmm0)?;
+            return Ok(());
+        }
+        constant(a, module, xmm1, lanes(0x4330_0000, 4))?;
+        a.unpcklps(xmm0, xmm1)?;
+        constant(a, module, xmm1, lanes(4503599627370496.0f64.to_bits(), 8))?;
+        a.subpd(xmm0, xmm1)?;
+        Ok(())
+    })
+}
+
+/// `i8x16.relaxed_swizzle`, a bare `PSHUFB` when SSSE3 is available: indices
+/// from 16 to 127 select modulo 16
+pub(crate) fn relaxed_swizzle(
+    assembler: &mut CodeAssembler,
+    module: &mut ModuleContext,
+    function: &mut FunctionContext,
+) -> Result<(), Error> {
+    if module.deterministic || !module.cpu.ssse3 {
+        return swizzle(assembler, module, function);
+    }
+    binary(assembler, function, |a| a.pshufb(xmm0, xmm1))
+}
+
+/// Relaxed `trunc` of f32x4 lanes, giving 0x80000000 or garbage for NaNs
+/// and out of range values. Unsigned lanes from 2^31 are converted minus
+/// 2^31, 0x80000000 being where that is added back.
+pub(crate) fn relaxed_trunc_f32x4(
+    assembler: &mut CodeAssembler,
+    module: &mut ModuleContext,
+    signed: bool,
+) -> Result<(), Error> {
+    if module.deterministic {
+        return trunc_sat_f32x4(assembler, module, signed);
+    }
+    unary(assembler, |a| -> Result<(), Error> {
+        if signed {
+            a.cvttps2dq(xmm0, xmm0)?;
+            return Ok(());
+        }
+        constant(a, module, xmm2, lanes(0x4F00_0000, 4))?;
+        a.movaps(xmm1, xmm0)?;
+        a.subps(xmm1, xmm2)?;
+        a.cvttps2dq(xmm0, xmm0)?;
+        a.cvttps2dq(xmm1, xmm1)?;
+        a.movdqa(xmm2, xmm0)?;
+        a.psrad(xmm2, 31)?;
+        a.pand(xmm1, xmm2)?;
+        a.por(xmm0, xmm1)?;
+        Ok(())
+    })
+}
+
+/// Relaxed `trunc` of f64x2 lanes, zeroing the upper ones. Unsigned lanes
+/// skip the clamping of the strict form: they are truncated and added to
+/// 2^52, whose lower bits are then the integer.
+pub(crate) fn relaxed_trunc_f64x2_zero(
+    assembler: &mut CodeAssembler,
+    module: &mut ModuleContext,
+    signed: bool,
+) -> Result<(), Error> {
+    if module.deterministic {
+        return trunc_sat_f64x2_zero(assembler, module, signed);
+    }
+    unary(assembler, |a| -> Result<(), Error> {
+        if signed {
+            a.cvttpd2dq(xmm0, xmm0)?;
+            return Ok(());
+        }
+        round_xmm0(a, module, Shape::F64x2, Rounding::Trunc)?;
+        constant(a, module, xmm1, lanes(4503599627370496.0f64.to_bits(), 8))?;
+        a.addpd(xmm0, xmm1)?;
+        a.xorps(xmm1, xmm1)?;
+        a.shufps(xmm0, xmm1, 0x88)?;
+        Ok(())
+    })
+}
+
+/// `laneselect`, picking lanes by their sign bits with SSE4.1 blends, or
+/// bytes by theirs for 8 and 16-bit lanes. Otherwise it is `bitselect`.
+pub(crate) fn laneselect(
+    assembler: &mut CodeAssembler,
+    module: &ModuleContext,
+    function: &mut FunctionContext,
+    shape: Shape,
+) -> Result<(), Error> {
+    if module.deterministic || !module.cpu.sse4_1 {
+        return bitselect(assembler, function);
+    }
+    // The mask of blends is XMM0
+    pop(assembler, function, xmm0)?;
+    pop(assembler, function, xmm1)?;
+    assembler.movdqu(xmm2, xmmword_ptr(rsp))?;
+    match shape {
+        Shape::I32x4 => assembler.blendvps(xmm1, xmm2)?,
+        Shape::I64x2 => assembler.blendvpd(xmm1, xmm2)?,
+        _ => assembler.pblendvb(xmm1, xmm2)?,
+    }
+    assembler.movdqu(xmmword_ptr(rsp), xmm1)?;
+    Ok(())
+}
+
+/// Relaxed `min` and `max`, plain `MINPS` and friends which return the
+/// second operand when either is NaN or both are zeros
+pub(crate) fn relaxed_min_max(
+    assembler: &mut CodeAssembler,
+    module: &ModuleContext,
+    function: &mut FunctionContext,
+    shape: Shape,
+    max: bool,
+) -> Result<(), Error> {
+    if module.deterministic {
+        return float_min_max(assembler, function, shape, max);
+    }
+    binary(assembler, function, |a| match (shape, max) {
+        (Shape::F32x4, false) => a.minps(xmm0, xmm1),
+        (Shape::F32x4, true) => a.maxps(xmm0, xmm1),
+        (_, false) => a.minpd(xmm0, xmm1),
+        (_, true) => a.maxpd(xmm0, xmm1),
+    })
+}
+
+/// `fma` and `fms`, `a + b * c` and `a - b * c`, fused when FMA is
+/// available. Otherwise, or in deterministic mode, the product is rounded.
+pub(crate) fn fma(
+    assembler: &mut CodeAssembler,
+    module: &ModuleContext,
+    function: &mut FunctionContext,
+    shape: Shape,
+    negate: bool,
+) -> Result<(), Error> {
+    let fused = module.cpu.fma && !module.deterministic;
+    pop(assembler, function, xmm2)?;
+    binary(assembler, function, |a| -> Result<(), Error> {
+        match (shape, negate, fused) {
+            (Shape::F32x4, false, true) => a.vfmadd231ps(xmm0, xmm1, xmm2)?,
+            (Shape::F32x4, true, true) => a.vfnmadd231ps(xmm0, xmm1, xmm2)?,
+            (_, false, true) => a.vfmadd231pd(xmm0, xmm1, xmm2)?,
+            (_, true, true) => a.vfnmadd231pd(xmm0, xmm1, xmm2)?,
+            (Shape::F32x4, _, false) => {
+                a.mulps(xmm1, xmm2)?;
+                if negate {
+                    a.subps(xmm0, xmm1)?;
+                } else {
+                    a.addps(xmm0, xmm1)?;
+                }
+            }
+            (_, _, false) => {
+                a.mulpd(xmm1, xmm2)?;
+                if negate {
+                    a.subpd(xmm0, xmm1)?;
+                } else {
+                    a.addpd(xmm0, xmm1)?;
+                }
+            }
+        }
+        Ok(())
+    })
+}
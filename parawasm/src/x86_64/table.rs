@@ -0,0 +1,561 @@
+use crate::x86_64::context::{FunctionContext, ModuleContext};
+use crate::x86_64::global::{self, Initializer};
+use crate::x86_64::trap::{trap_unless, TrapCode};
+use crate::x86_64::Error;
+use alloc::vec::Vec;
+use iced_x86::code_asm::{
+    eax, ecx, edi, esi, ptr, qword_ptr, r11, rax, rcx, rdi, rdx, rsi, rsp, AsmRegister64,
+    CodeAssembler, CodeLabel,
+};
+use wasmparser_nostd::{Element, ElementItem, FuncType, TableType, Type};
+
+/// Table descriptor, as embedded in the module binary.
+///
+/// Entries are references, 8 bytes each: pointers to a
+/// [`FunctionReference`] for `funcref` tables, [`ExternRef`]s for
+/// `externref` ones, and zero for null references.
+///
+/// [`ExternRef`]: crate::x86_64::ExternRef
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TableDescriptor {
+    /// Address of the first entry
+    pub base: u64,
+    /// Number of entries
+    pub length: u64,
+    /// Address of the [`TableGrowHook`] called by `table.grow`, or zero if
+    /// the table can't grow
+    pub grow: u64,
+}
+
+/// Host function growing a table by the given number of entries.
+///
+/// The hook is only called when the new size is within the table's maximum.
+/// It must update `base` and `length` of the descriptor, keeping the
+/// existing entries, and return the old length, or return -1 if the table
+/// can't be grown. New entries are initialized by the caller.
+pub type TableGrowHook = extern "sysv64" fn(descriptor: *mut TableDescriptor, delta: u64) -> i64;
+
+/// What a `funcref` points to. Every function of a module has one in the
+/// module binary; the host can make its own to put host functions in tables.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FunctionReference {
+    /// Address of the function's code
+    pub code: u64,
+    /// Address of the canonical record of the function's signature, see
+    /// [`Module::signature_offset`](crate::x86_64::Module::signature_offset)
+    pub signature: u64,
+}
+
+pub(crate) const BASE: i32 = 0;
+pub(crate) const LENGTH: i32 = 8;
+const GROW: i32 = 16;
+pub(crate) const CODE: i32 = 0;
+pub(crate) const SIGNATURE: i32 = 8;
+
+/// Initial size of the largest table a module can define, 512 KiB of
+/// entries
+pub(crate) const MAX_DEFINED_ENTRIES: u32 = 1 << 16;
+
+/// Table slots in the module binary
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct TableSlot {
+    pub(crate) descriptor: CodeLabel,
+    /// Entries of tables defined by the module
+    pub(crate) entries: Option<CodeLabel>,
+    pub(crate) ty: TableType,
+}
+
+/// An element segment in the module binary
+#[derive(Debug, Clone)]
+pub(crate) struct ElementSegment {
+    /// Current length of the segment, zeroed once it is dropped
+    pub(crate) length: CodeLabel,
+    pub(crate) entries: CodeLabel,
+    /// References the entries are initialized with, filled in by the
+    /// initializer
+    pub(crate) items: Vec<Initializer>,
+}
+
+/// Emits the signature record of `ty`, unless the module already has one.
+///
+/// A record holds the address of the canonical record of its signature,
+/// its own unless linked to another module's, which is what the
+/// `signature` of [`FunctionReference`]s and `call_indirect` compare.
+/// Unlike a hash of the signature, it can't be forged by another one.
+pub(crate) fn signature(
+    assembler: &mut CodeAssembler,
+    module: &mut ModuleContext,
+    ty: FuncType,
+) -> Result<(), Error> {
+    if module.signatures.iter().any(|(known, _)| *known == ty) {
+        return Ok(());
+    }
+    let label = assembler.create_label();
+    module.bind(assembler, label);
+    assembler.dq(&[0])?;
+    module.signatures.push((ty, label));
+    Ok(())
+}
+
+/// Label of the signature record of function type `type_index`
+pub(crate) fn signature_record(module: &ModuleContext, type_index: u32) -> CodeLabel {
+    let ty = &module.function_typedefs[&type_index];
+    module
+        .signatures
+        .iter()
+        .find_map(|(known, label)| (known == ty).then_some(*label))
+        .unwrap()
+}
+
+/// Emits the descriptor of an imported table, to be linked by the embedder
+pub(crate) fn import(
+    assembler: &mut CodeAssembler,
+    module: &mut ModuleContext,
+    ty: TableType,
+) -> Result<(), Error> {
+    let descriptor = assembler.create_label();
+    module.bind(assembler, descriptor);
+    assembler.dq(&[0, 0, 0])?;
+    module.tables.push(TableSlot {
+        descriptor,
+        entries: None,
+        ty,
+    });
+    Ok(())
+}
+
+/// Emits a table defined by the module, with all its entries null. The
+/// initializer points the descriptor at the entries. As they are part of
+/// the binary, tables of more than [`MAX_DEFINED_ENTRIES`] entries are
+/// unsupported, hosts have to provide them as imports. `offset` is the
+/// one of the table type in the WebAssembly binary.
+pub(crate) fn define(
+    assembler: &mut CodeAssembler,
+    module: &mut ModuleContext,
+    ty: TableType,
+    offset: usize,
+) -> Result<(), Error> {
+    if ty.initial > MAX_DEFINED_ENTRIES {
+        return Err(Error::unsupported(
+            alloc::format!("table of {} entries", ty.initial),
+            None,
+            offset,
+        ));
+    }
+    let descriptor = assembler.create_label();
+    module.bind(assembler, descriptor);
+    assembler.dq(&[0, ty.initial as u64, 0])?;
+    let entries = assembler.create_label();
+    module.bind(assembler, entries);
+    // Zero-sized declarations still produce an instruction to bind the label to
+    assembler.dq(&alloc::vec![0; (ty.initial as usize).max(1)])?;
+    module.tables.push(TableSlot {
+        descriptor,
+        entries: Some(entries),
+        ty,
+    });
+    Ok(())
+}
+
+/// Points the descriptors of tables defined by the module at their entries
+pub(crate) fn setup(assembler: &mut CodeAssembler, module: &ModuleContext) -> Result<(), Error> {
+    for table in module.tables.iter() {
+        if let Some(entries) = table.entries {
+            assembler.lea(rax, ptr(entries))?;
+            assembler.mov(qword_ptr(table.descriptor), rax)?;
+        }
+    }
+    Ok(())
+}
+
+/// Leaves in RAX the `funcref` of function `index`
+pub(crate) fn function_reference(
+    assembler: &mut CodeAssembler,
+    module: &ModuleContext,
+    index: u32,
+) -> Result<(), Error> {
+    let reference = module.function_references[&index];
+    assembler.lea(rax, ptr(reference))?;
+    Ok(())
+}
+
+/// Makes the signature records not linked to another module's canonical
+pub(crate) fn setup_signatures(
+    assembler: &mut CodeAssembler,
+    module: &mut ModuleContext,
+) -> Result<(), Error> {
+    for (_, record) in module.signatures.clone() {
+        let linked = assembler.create_label();
+        assembler.mov(rax, qword_ptr(record))?;
+        assembler.test(rax, rax)?;
+        assembler.jnz(linked)?;
+        assembler.lea(rax, ptr(record))?;
+        assembler.mov(qword_ptr(record), rax)?;
+        module.bind(assembler, linked);
+    }
+    Ok(())
+}
+
+/// Fills in the signature of the [`FunctionReference`]s of all functions,
+/// and the code address of those defined by the module. Those of imported
+/// functions start with the address slot linked by the embedder.
+pub(crate) fn setup_references(
+    assembler: &mut CodeAssembler,
+    module: &ModuleContext,
+) -> Result<(), Error> {
+    for (index, reference) in module.function_references.iter() {
+        let record = signature_record(module, module.function_types[index]);
+        assembler.lea(r11, ptr(*reference))?;
+        assembler.mov(rax, qword_ptr(record))?;
+        assembler.mov(qword_ptr(r11 + SIGNATURE), rax)?;
+        if let Some(code) = module.got.get(index) {
+            assembler.lea(rax, ptr(*code))?;
+            assembler.mov(qword_ptr(r11 + CODE), rax)?;
+        }
+    }
+    Ok(())
+}
+
+/// Writes the entries of an active element segment to table `table` at
+/// `offset`, trapping if they don't fit
+pub(crate) fn apply(
+    assembler: &mut CodeAssembler,
+    module: &mut ModuleContext,
+    table: u32,
+    offset: Initializer,
+    items: &[Initializer],
+) -> Result<(), Error> {
+    global::load_offset(assembler, module, offset)?;
+    let descriptor = module.tables[table as usize].descriptor;
+    assembler.lea(r11, ptr(descriptor))?;
+    assembler.lea(rax, ptr(rdi + items.len() as i32))?;
+    assembler.cmp(rax, qword_ptr(r11 + LENGTH))?;
+    trap_unless(assembler, module, TrapCode::TableOutOfBounds, |a, ok| {
+        a.jbe(ok)
+    })?;
+    assembler.shl(rdi, 3)?;
+    assembler.add(rdi, qword_ptr(r11 + BASE))?;
+    for (position, item) in items.iter().enumerate() {
+        let entry = qword_ptr(rdi + (position * 8) as i32);
+        if is_null(*item) {
+            assembler.mov(entry, 0)?;
+        } else {
+            reference(assembler, module, *item)?;
+            assembler.mov(entry, rax)?;
+        }
+    }
+    Ok(())
+}
+
+/// Emits an element segment with the references to `items`. Active and
+/// declarative segments are dropped on instantiation: they are emitted
+/// empty.
+pub(crate) fn segment(
+    assembler: &mut CodeAssembler,
+    module: &mut ModuleContext,
+    items: Vec<Initializer>,
+) -> Result<(), Error> {
+    let length = assembler.create_label();
+    module.bind(assembler, length);
+    assembler.dq(&[items.len() as u64])?;
+    let entries = assembler.create_label();
+    module.bind(assembler, entries);
+    assembler.dq(&alloc::vec![0; items.len().max(1)])?;
+    module.element_segments.push(ElementSegment {
+        length,
+        entries,
+        items,
+    });
+    Ok(())
+}
+
+/// Fills in the entries of element segments
+pub(crate) fn setup_segments(
+    assembler: &mut CodeAssembler,
+    module: &ModuleContext,
+) -> Result<(), Error> {
+    for segment in module.element_segments.iter() {
+        for (position, item) in segment.items.iter().enumerate() {
+            // Entries start out null
+            if !is_null(*item) {
+                reference(assembler, module, *item)?;
+                assembler.lea(r11, ptr(segment.entries))?;
+                assembler.mov(qword_ptr(r11 + (position * 8) as i32), rax)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Whether an element segment item is `ref.null`
+fn is_null(item: Initializer) -> bool {
+    matches!(item, Initializer::Value(0))
+}
+
+/// Leaves in RAX the reference an element segment item evaluates to.
+/// Clobbers R11.
+fn reference(
+    assembler: &mut CodeAssembler,
+    module: &ModuleContext,
+    item: Initializer,
+) -> Result<(), Error> {
+    match item {
+        Initializer::Function(index) => function_reference(assembler, module, index)?,
+        Initializer::Global(index) => {
+            global::load(assembler, module, index)?;
+        }
+        // Items are references, the only constant one being null
+        Initializer::Value(_) => assembler.xor(eax, eax)?,
+    }
+    Ok(())
+}
+
+/// Pops an entry index and leaves in RAX the address of the entry of table
+/// `table`, trapping if it is out of bounds
+fn entry(
+    assembler: &mut CodeAssembler,
+    module: &mut ModuleContext,
+    function: &mut FunctionContext,
+    table: u32,
+) -> Result<(), Error> {
+    let descriptor = module.tables[table as usize].descriptor;
+    function.pop(assembler, rax)?;
+    assembler.mov(eax, eax)?;
+    assembler.lea(r11, ptr(descriptor))?;
+    assembler.cmp(rax, qword_ptr(r11 + LENGTH))?;
+    trap_unless(assembler, module, TrapCode::TableOutOfBounds, |a, ok| {
+        a.jb(ok)
+    })?;
+    assembler.mov(r11, qword_ptr(r11 + BASE))?;
+    assembler.lea(rax, ptr(r11 + rax * 8))?;
+    Ok(())
+}
+
+/// `table.get`
+pub(crate) fn get(
+    assembler: &mut CodeAssembler,
+    module: &mut ModuleContext,
+    function: &mut FunctionContext,
+    table: u32,
+) -> Result<(), Error> {
+    entry(assembler, module, function, table)?;
+    assembler.mov(rax, qword_ptr(rax))?;
+    let ty = module.tables[table as usize].ty.element_type;
+    function.push(assembler, rax, ty)
+}
+
+/// `table.set`
+pub(crate) fn set(
+    assembler: &mut CodeAssembler,
+    module: &mut ModuleContext,
+    function: &mut FunctionContext,
+    table: u32,
+) -> Result<(), Error> {
+    function.pop(assembler, rcx)?;
+    entry(assembler, module, function, table)?;
+    assembler.mov(qword_ptr(rax), rcx)?;
+    Ok(())
+}
+
+/// `table.size`
+pub(crate) fn size(
+    assembler: &mut CodeAssembler,
+    module: &mut ModuleContext,
+    function: &mut FunctionContext,
+    table: u32,
+) -> Result<(), Error> {
+    let descriptor = module.tables[table as usize].descriptor;
+    assembler.lea(r11, ptr(descriptor))?;
+    assembler.mov(rax, qword_ptr(r11 + LENGTH))?;
+    function.push(assembler, rax, Type::I32)
+}
+
+/// `table.grow`: checks the new size against the table's maximum and calls
+/// the table's grow hook, which updates the descriptor, then initializes
+/// the new entries. Pushes the old size, or -1 on failure.
+pub(crate) fn grow(
+    assembler: &mut CodeAssembler,
+    module: &mut ModuleContext,
+    function: &mut FunctionContext,
+    table: u32,
+) -> Result<(), Error> {
+    let TableSlot { descriptor, ty, .. } = module.tables[table as usize];
+    let maximum = ty.maximum.unwrap_or(u32::MAX);
+    let failed = assembler.create_label();
+    let done = assembler.create_label();
+
+    // The initial value of the new entries stays on the stack for now
+    function.pop(assembler, rsi)?;
+    assembler.mov(esi, esi)?;
+    assembler.lea(rdi, ptr(descriptor))?;
+    assembler.mov(rax, qword_ptr(rdi + LENGTH))?;
+    // Growing by zero entries always succeeds
+    assembler.test(rsi, rsi)?;
+    assembler.jz(done)?;
+    assembler.lea(rcx, ptr(rax + rsi))?;
+    assembler.mov(r11, maximum as u64)?;
+    assembler.cmp(rcx, r11)?;
+    assembler.ja(failed)?;
+    assembler.mov(r11, qword_ptr(rdi + GROW))?;
+    assembler.test(r11, r11)?;
+    assembler.jz(failed)?;
+    let aligned = function.is_aligned();
+    if !aligned {
+        assembler.sub(rsp, 8)?;
+    }
+    assembler.call(r11)?;
+    if !aligned {
+        assembler.add(rsp, 8)?;
+    }
+    assembler.test(rax, rax)?;
+    assembler.js(done)?;
+    // Entries from the old length on get the initial value
+    assembler.mov(rdx, rax)?;
+    assembler.lea(r11, ptr(descriptor))?;
+    assembler.mov(rcx, qword_ptr(r11 + LENGTH))?;
+    assembler.sub(rcx, rdx)?;
+    assembler.mov(rdi, qword_ptr(r11 + BASE))?;
+    assembler.lea(rdi, ptr(rdi + rdx * 8))?;
+    assembler.mov(rax, qword_ptr(rsp))?;
+    assembler.rep().stosq()?;
+    assembler.mov(rax, rdx)?;
+    assembler.jmp(done)?;
+    module.bind(assembler, failed);
+    assembler.mov(rax, -1i64)?;
+    module.bind(assembler, done);
+    function.pop(assembler, rcx)?;
+    function.push(assembler, rax, Type::I32)
+}
+
+/// Traps unless RAX entries from index `start` fit in table `table`, then
+/// points `start` at the first of them. Clobbers R11.
+fn range(
+    assembler: &mut CodeAssembler,
+    module: &mut ModuleContext,
+    table: u32,
+    start: AsmRegister64,
+) -> Result<(), Error> {
+    let descriptor = module.tables[table as usize].descriptor;
+    assembler.lea(r11, ptr(descriptor))?;
+    assembler.add(rax, start)?;
+    assembler.cmp(rax, qword_ptr(r11 + LENGTH))?;
+    trap_unless(assembler, module, TrapCode::TableOutOfBounds, |a, ok| {
+        a.jbe(ok)
+    })?;
+    assembler.shl(start, 3)?;
+    assembler.add(start, qword_ptr(r11 + BASE))?;
+    Ok(())
+}
+
+/// Pops the count and the two indices of a bulk table instruction into
+/// RCX, RSI and RDI
+fn pop_range(assembler: &mut CodeAssembler, function: &mut FunctionContext) -> Result<(), Error> {
+    function.pop(assembler, rcx)?;
+    function.pop(assembler, rsi)?;
+    function.pop(assembler, rdi)?;
+    // Operands are 32-bit, so the sums can't overflow
+    assembler.mov(ecx, ecx)?;
+    assembler.mov(esi, esi)?;
+    assembler.mov(edi, edi)?;
+    Ok(())
+}
+
+/// `table.fill`
+pub(crate) fn fill(
+    assembler: &mut CodeAssembler,
+    module: &mut ModuleContext,
+    function: &mut FunctionContext,
+    table: u32,
+) -> Result<(), Error> {
+    function.pop(assembler, rcx)?;
+    function.pop(assembler, rdx)?;
+    function.pop(assembler, rdi)?;
+    assembler.mov(ecx, ecx)?;
+    assembler.mov(edi, edi)?;
+    assembler.mov(rax, rcx)?;
+    range(assembler, module, table, rdi)?;
+    assembler.mov(rax, rdx)?;
+    assembler.rep().stosq()?;
+    Ok(())
+}
+
+/// `table.copy`, which may move entries within a table
+pub(crate) fn copy(
+    assembler: &mut CodeAssembler,
+    module: &mut ModuleContext,
+    function: &mut FunctionContext,
+    dst_table: u32,
+    src_table: u32,
+) -> Result<(), Error> {
+    pop_range(assembler, function)?;
+    assembler.mov(rax, rcx)?;
+    range(assembler, module, src_table, rsi)?;
+    assembler.mov(rax, rcx)?;
+    range(assembler, module, dst_table, rdi)?;
+    // Copying forward would overwrite entries yet to be copied
+    let forward = assembler.create_label();
+    let done = assembler.create_label();
+    assembler.cmp(rdi, rsi)?;
+    assembler.jbe(forward)?;
+    assembler.lea(rsi, ptr(rsi + rcx * 8 - 8))?;
+    assembler.lea(rdi, ptr(rdi + rcx * 8 - 8))?;
+    assembler.std()?;
+    assembler.rep().movsq()?;
+    assembler.cld()?;
+    assembler.jmp(done)?;
+    module.bind(assembler, forward);
+    assembler.rep().movsq()?;
+    module.bind(assembler, done);
+    Ok(())
+}
+
+/// `table.init`
+pub(crate) fn init(
+    assembler: &mut CodeAssembler,
+    module: &mut ModuleContext,
+    function: &mut FunctionContext,
+    segment: u32,
+    table: u32,
+) -> Result<(), Error> {
+    let (length, entries) = {
+        let segment = &module.element_segments[segment as usize];
+        (segment.length, segment.entries)
+    };
+    pop_range(assembler, function)?;
+    assembler.lea(rax, ptr(rsi + rcx))?;
+    assembler.cmp(rax, qword_ptr(length))?;
+    trap_unless(assembler, module, TrapCode::TableOutOfBounds, |a, ok| {
+        a.jbe(ok)
+    })?;
+    assembler.lea(rax, ptr(entries))?;
+    assembler.lea(rsi, ptr(rax + rsi * 8))?;
+    assembler.mov(rax, rcx)?;
+    range(assembler, module, table, rdi)?;
+    assembler.rep().movsq()?;
+    Ok(())
+}
+
+/// `elem.drop`
+pub(crate) fn drop(
+    assembler: &mut CodeAssembler,
+    module: &mut ModuleContext,
+    segment: u32,
+) -> Result<(), Error> {
+    let length = module.element_segments[segment as usize].length;
+    assembler.mov(qword_ptr(length), 0)?;
+    Ok(())
+}
+
+/// Reads the items of an element segment
+pub(crate) fn items(element: &Element) -> Result<Vec<Initializer>, Error> {
+    let mut items = Vec::new();
+    for item in element.items.get_items_reader()? {
+        items.push(match item? {
+            ElementItem::Func(index) => Initializer::Function(index),
+            ElementItem::Expr(expr) => global::initializer(&expr)?,
+        });
+    }
+    Ok(items)
+}
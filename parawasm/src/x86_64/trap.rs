@@ -0,0 +1,114 @@
+use crate::x86_64::context::ModuleContext;
+use crate::x86_64::Error;
+use iced_x86::code_asm::{qword_ptr, rax, rsp, CodeAssembler, CodeLabel};
+use iced_x86::IcedError;
+
+/// Why compiled code trapped
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrapCode {
+    /// The stack limit set with
+    /// [`link_stack_limit`](crate::x86_64::AssembledModule::link_stack_limit)
+    /// was reached
+    StackOverflow,
+    /// Out of bounds access to a memory or a data segment
+    MemoryOutOfBounds,
+    /// Out of bounds access to a table or an element segment
+    TableOutOfBounds,
+    /// `call_indirect` to a null entry
+    IndirectCallToNull,
+    /// `call_indirect` to a function of another signature
+    BadSignature,
+    /// Signed division overflow, or truncation of a value out of the range
+    /// of the result
+    IntegerOverflow,
+    IntegerDivisionByZero,
+    /// Truncation of NaN
+    BadConversionToInteger,
+    /// `unreachable`
+    UnreachableCodeReached,
+    /// Atomic access to an address that isn't a multiple of its size
+    UnalignedAtomic,
+    /// `memory.atomic.wait` on a memory that isn't shared or has no
+    /// [`WaitHook`](crate::x86_64::WaitHook)
+    CannotWait,
+    /// An exception was thrown with no handler, it is left in the
+    /// [`ExceptionContext`](crate::x86_64::ExceptionContext)
+    UncaughtException,
+}
+
+/// A trap of compiled code, as found in the trap table of a module
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Trap {
+    pub code: TrapCode,
+    /// Function the trap is in, `None` for the module initializer
+    pub function_index: Option<u32>,
+    /// Offset in the WebAssembly binary of the trapping instruction
+    pub wasm_offset: usize,
+}
+
+/// Emits a trap: an `UD2` recorded in the trap table
+pub(crate) fn trap(
+    assembler: &mut CodeAssembler,
+    module: &mut ModuleContext,
+    code: TrapCode,
+) -> Result<(), Error> {
+    let label = assembler.create_label();
+    module.bind(assembler, label);
+    assembler.ud2()?;
+    module.traps.push((
+        label,
+        Trap {
+            code,
+            function_index: module.function_index,
+            wasm_offset: module.wasm_offset,
+        },
+    ));
+    Ok(())
+}
+
+/// Emits a trap that is skipped when the conditional jump emitted by `skip`
+/// is taken
+pub(crate) fn trap_unless<F>(
+    assembler: &mut CodeAssembler,
+    module: &mut ModuleContext,
+    code: TrapCode,
+    skip: F,
+) -> Result<(), Error>
+where
+    F: FnOnce(&mut CodeAssembler, CodeLabel) -> Result<(), IcedError>,
+{
+    let ok = assembler.create_label();
+    skip(assembler, ok)?;
+    trap(assembler, module, code)?;
+    module.bind(assembler, ok);
+    Ok(())
+}
+
+/// Emits the stack limit slot, linked by the embedder. It is zero unless
+/// linked, which disables the check.
+pub(crate) fn stack_limit(
+    assembler: &mut CodeAssembler,
+    module: &mut ModuleContext,
+) -> Result<(), Error> {
+    module.bind(assembler, module.stack_limit);
+    assembler.dq(&[0])?;
+    Ok(())
+}
+
+/// Traps if the stack needed by the function, found at label `needed` once
+/// the function is compiled, would go past the stack limit
+pub(crate) fn check_stack(
+    assembler: &mut CodeAssembler,
+    module: &mut ModuleContext,
+    needed: CodeLabel,
+) -> Result<(), Error> {
+    assembler.mov(rax, rsp)?;
+    assembler.sub(rax, qword_ptr(needed))?;
+    trap_unless(assembler, module, TrapCode::StackOverflow, |a, ok| {
+        a.jae(ok)
+    })?;
+    assembler.cmp(rax, qword_ptr(module.stack_limit))?;
+    trap_unless(assembler, module, TrapCode::StackOverflow, |a, ok| {
+        a.jae(ok)
+    })
+}
@@ -78,6 +78,7 @@ impl<'a> Emulator<'a> {
         let module_len = module.binary().len();
         let emu_module = Module {
             offset: self.module_offset,
+            written: module.binary().to_vec(),
             module,
             executed_instructions: BTreeMap::new(),
         };
@@ -87,9 +88,16 @@ impl<'a> Emulator<'a> {
         Ok(new_module)
     }
 
+    /// Rewrites a module that has been relinked since it was last written.
+    /// Other modules are left alone, so that state kept in their binary
+    /// (globals, memory descriptors) persists across calls.
     fn update_module(&mut self, module: Rc<RefCell<Module>>) -> Result<(), Error> {
-        self.emulator
-            .mem_write(module.borrow().offset, module.borrow().module.binary())?;
+        let mut module = module.borrow_mut();
+        if module.written.as_slice() != module.module.binary() {
+            self.emulator
+                .mem_write(module.offset, module.module.binary())?;
+            module.written = module.module.binary().to_vec();
+        }
         Ok(())
     }
 
@@ -100,6 +108,14 @@ impl<'a> Emulator<'a> {
         Ok(offset)
     }
 
+    pub fn read_memory(&self, addr: u64, buf: &mut [u8]) -> Result<(), Error> {
+        Ok(self.emulator.mem_read(addr, buf)?)
+    }
+
+    pub fn write_memory(&mut self, addr: u64, data: &[u8]) -> Result<(), Error> {
+        Ok(self.emulator.mem_write(addr, data)?)
+    }
+
     pub fn call_function<I: FunctionIdentifier>(
         &mut self,
         module: Rc<RefCell<Module>>,
@@ -115,6 +131,16 @@ impl<'a> Emulator<'a> {
             .module
             .function_entry_point(identifier)
             .ok_or(Error::FunctionNotFound)? as u64;
+        self.call(module, function_offset)
+    }
+
+    /// Runs the module initializer
+    pub fn initialize(&mut self, module: Rc<RefCell<Module>>) -> Result<(), Error> {
+        let initializer = module.borrow().module.initializer_entry_point() as u64;
+        self.call(module, initializer)
+    }
+
+    fn call(&mut self, module: Rc<RefCell<Module>>, function_offset: u64) -> Result<(), Error> {
         let module_offset = module.borrow().offset;
         for module in self.modules.clone() {
             self.update_module(module)?;
@@ -188,12 +214,26 @@ impl<'a> Emulator<'a> {
     pub fn write_register(&mut self, register: RegisterX86, value: u64) -> Result<(), Error> {
         Ok(self.emulator.reg_write(register as i32, value)?)
     }
+
+    /// Reads the lower 64 bits of an XMM register
+    pub fn read_xmm(&self, register: RegisterX86) -> Result<u64, Error> {
+        let value = self.emulator.reg_read_long(register as i32)?;
+        Ok(LittleEndian::read_u64(&value[..size_of::<u64>()]))
+    }
+
+    /// Writes the lower 64 bits of an XMM register, zeroing the upper ones
+    pub fn write_xmm(&mut self, register: RegisterX86, value: u64) -> Result<(), Error> {
+        let mut buf = [0; 16];
+        LittleEndian::write_u64(&mut buf, value);
+        Ok(self.emulator.reg_write_long(register as i32, &buf)?)
+    }
 }
 
 pub struct Module {
     offset: u64,
     module: AssembledModule,
     executed_instructions: BTreeMap<usize, usize>,
+    written: Vec<u8>,
 }
 
 impl Deref for Module {
@@ -1,7 +1,12 @@
 use crate::testing;
 use crate::testing::Emulator;
-use parawasm::x86_64::X86_64Compiler;
+use parawasm::wasmparser_nostd::{FuncType, Type, WasmFeatures};
+use parawasm::x86_64::{
+    AssembledModule, CpuFeatures, Error, ExternRef, Trap, TrapCode, X86_64Compiler,
+};
 use parawasm::Compiler;
+use std::cell::RefCell;
+use std::rc::Rc;
 
 #[test]
 fn supports_memory64() {
@@ -30,6 +35,51 @@ fn supports_multiple_memories() {
     assert!(!module.memory_types()[1].memory64);
 }
 
+#[test]
+fn rejects_disabled_proposals() {
+    let src = r#"
+    (module
+      (global i32 (i32.add (i32.const 1) (i32.const 2)))
+    )
+    "#;
+    let binary = wat::parse_str(src).expect("binary module");
+    assert!(matches!(
+        X86_64Compiler::default().compile(&binary),
+        Err(Error::WasmReaderError(_))
+    ));
+    assert!(matches!(
+        X86_64Compiler::builder()
+            .wasm_features(WasmFeatures {
+                extended_const: true,
+                ..X86_64Compiler::supported_features()
+            })
+            .build()
+            .compile(&binary),
+        Err(Error::Unsupported { .. })
+    ));
+}
+
+#[test]
+fn rejects_large_defined_tables() {
+    let src = r#"
+    (module
+      (table 10000000 funcref)
+    )
+    "#;
+    let binary = wat::parse_str(src).expect("binary module");
+    assert!(matches!(
+        X86_64Compiler::default().compile(&binary),
+        Err(Error::Unsupported { .. })
+    ));
+    let src = r#"
+    (module
+      (import "env" "table" (table 10000000 funcref))
+    )
+    "#;
+    let binary = wat::parse_str(src).expect("binary module");
+    assert!(X86_64Compiler::default().compile(&binary).is_ok());
+}
+
 #[test]
 fn return_value() {
     let src = r#"
@@ -308,3 +358,4860 @@ fn function_stack_height() {
 
     assert_eq!(3, foo_module.function_stack_height("foo").unwrap());
 }
+
+#[test]
+fn block_branch_unwinds_stack() {
+    let foo_src = r#"
+    (module
+      (func (export "foo") (param i64) (result i64)
+        (block (result i64)
+          i64.const 1
+          i64.const 2
+          (block
+            local.get 0
+            br 1)
+          i64.add)
+        i64.const 10
+        i64.add
+      )
+    )
+    "#;
+    let foo_binary = wat::parse_str(foo_src).expect("binary module");
+    let foo_module = X86_64Compiler::default()
+        .compile(&foo_binary)
+        .expect("compiled module");
+
+    let mut emulator = Emulator::new().expect("emulator");
+    let emu_mod = emulator.add_module(foo_module).expect("module addition");
+
+    emulator.write_register(testing::RDI, 32).expect("1st arg");
+    emulator
+        .call_function(emu_mod.clone(), "foo")
+        .expect("call");
+
+    assert_eq!(emulator.read_register(testing::RAX).unwrap(), 42);
+}
+
+#[test]
+fn if_else() {
+    let foo_src = r#"
+    (module
+      (func (export "foo") (param i32) (result i64)
+        local.get 0
+        (if (result i64)
+          (then i64.const 10)
+          (else i64.const 20))
+      )
+    )
+    "#;
+    let foo_binary = wat::parse_str(foo_src).expect("binary module");
+    let foo_module = X86_64Compiler::default()
+        .compile(&foo_binary)
+        .expect("compiled module");
+
+    let mut emulator = Emulator::new().expect("emulator");
+    let emu_mod = emulator.add_module(foo_module).expect("module addition");
+
+    for (arg, result) in [(1, 10), (0, 20)] {
+        emulator.write_register(testing::RDI, arg).expect("1st arg");
+        emulator
+            .call_function(emu_mod.clone(), "foo")
+            .expect("call");
+        assert_eq!(emulator.read_register(testing::RAX).unwrap(), result);
+    }
+}
+
+#[test]
+fn loop_br_if() {
+    let foo_src = r#"
+    (module
+      (func (export "foo") (param $n i32) (param $one i32) (result i64) (local $sum i64)
+        (loop $continue
+          local.get $sum
+          i64.const 2
+          i64.add
+          local.set $sum
+          local.get $n
+          local.get $one
+          i32.sub
+          local.tee $n
+          br_if $continue)
+        local.get $sum
+      )
+    )
+    "#;
+    let foo_binary = wat::parse_str(foo_src).expect("binary module");
+    let foo_module = X86_64Compiler::default()
+        .compile(&foo_binary)
+        .expect("compiled module");
+
+    let mut emulator = Emulator::new().expect("emulator");
+    let emu_mod = emulator.add_module(foo_module).expect("module addition");
+
+    emulator.write_register(testing::RDI, 21).expect("1st arg");
+    emulator.write_register(testing::RSI, 1).expect("2nd arg");
+    emulator
+        .call_function(emu_mod.clone(), "foo")
+        .expect("call");
+
+    assert_eq!(emulator.read_register(testing::RAX).unwrap(), 42);
+}
+
+#[test]
+fn br_table() {
+    let foo_src = r#"
+    (module
+      (func (export "foo") (param i32) (result i64)
+        (block
+          (block
+            (block
+              local.get 0
+              br_table 0 1 2)
+            i64.const 10
+            return)
+          i64.const 20
+          return)
+        i64.const 30
+      )
+    )
+    "#;
+    let foo_binary = wat::parse_str(foo_src).expect("binary module");
+    let foo_module = X86_64Compiler::default()
+        .compile(&foo_binary)
+        .expect("compiled module");
+
+    let mut emulator = Emulator::new().expect("emulator");
+    let emu_mod = emulator.add_module(foo_module).expect("module addition");
+
+    for (arg, result) in [(0, 10), (1, 20), (2, 30), (100, 30)] {
+        emulator.write_register(testing::RDI, arg).expect("1st arg");
+        emulator
+            .call_function(emu_mod.clone(), "foo")
+            .expect("call");
+        assert_eq!(emulator.read_register(testing::RAX).unwrap(), result);
+    }
+}
+
+#[test]
+fn br_table_unwinds_stack() {
+    let foo_src = r#"
+    (module
+      (func (export "foo") (param i32) (result i64)
+        (block (result i64)
+          (block (result i64)
+            i64.const 1
+            i64.const 10
+            local.get 0
+            br_table 0 1)
+          i64.const 20
+          i64.add)
+      )
+    )
+    "#;
+    let foo_binary = wat::parse_str(foo_src).expect("binary module");
+    let foo_module = X86_64Compiler::default()
+        .compile(&foo_binary)
+        .expect("compiled module");
+
+    let mut emulator = Emulator::new().expect("emulator");
+    let emu_mod = emulator.add_module(foo_module).expect("module addition");
+
+    for (arg, result) in [(0, 30), (1, 10)] {
+        emulator.write_register(testing::RDI, arg).expect("1st arg");
+        emulator
+            .call_function(emu_mod.clone(), "foo")
+            .expect("call");
+        assert_eq!(emulator.read_register(testing::RAX).unwrap(), result);
+    }
+}
+
+#[test]
+fn return_from_nested_blocks() {
+    let foo_src = r#"
+    (module
+      (func (export "foo") (result i64)
+        (block
+          (loop
+            i64.const 1
+            i64.const 42
+            return))
+        i64.const 0
+      )
+    )
+    "#;
+    let foo_binary = wat::parse_str(foo_src).expect("binary module");
+    let foo_module = X86_64Compiler::default()
+        .compile(&foo_binary)
+        .expect("compiled module");
+
+    let mut emulator = Emulator::new().expect("emulator");
+    let emu_mod = emulator.add_module(foo_module).expect("module addition");
+    emulator
+        .call_function(emu_mod.clone(), "foo")
+        .expect("call");
+
+    assert_eq!(emulator.read_register(testing::RAX).unwrap(), 42);
+}
+
+fn call_binary(ty: &str, op: &str, lhs: u64, rhs: u64) -> Result<u64, testing::Error> {
+    let src = format!(
+        r#"
+    (module
+      (func (export "foo") (param {ty}) (param {ty}) (result {ty})
+        local.get 0
+        local.get 1
+        {ty}.{op}
+      )
+    )
+    "#
+    );
+    let binary = wat::parse_str(src).expect("binary module");
+    let module = X86_64Compiler::default()
+        .compile(&binary)
+        .expect("compiled module");
+
+    let mut emulator = Emulator::new().expect("emulator");
+    let emu_mod = emulator.add_module(module).expect("module addition");
+    emulator.write_register(testing::RDI, lhs).expect("1st arg");
+    emulator.write_register(testing::RSI, rhs).expect("2nd arg");
+    emulator.call_function(emu_mod.clone(), "foo")?;
+    let result = emulator.read_register(testing::RAX).unwrap();
+    Ok(if ty == "i32" {
+        result as u32 as u64
+    } else {
+        result
+    })
+}
+
+fn call_unary(ty: &str, op: &str, arg: u64) -> Result<u64, testing::Error> {
+    let src = format!(
+        r#"
+    (module
+      (func (export "foo") (param {ty}) (result {ty})
+        local.get 0
+        {ty}.{op}
+      )
+    )
+    "#
+    );
+    let binary = wat::parse_str(src).expect("binary module");
+    let module = X86_64Compiler::default()
+        .compile(&binary)
+        .expect("compiled module");
+
+    let mut emulator = Emulator::new().expect("emulator");
+    let emu_mod = emulator.add_module(module).expect("module addition");
+    emulator.write_register(testing::RDI, arg).expect("1st arg");
+    emulator.call_function(emu_mod.clone(), "foo")?;
+    let result = emulator.read_register(testing::RAX).unwrap();
+    Ok(if ty == "i32" {
+        result as u32 as u64
+    } else {
+        result
+    })
+}
+
+#[test]
+fn integer_binary_operators() {
+    let m32 = i32::MIN as u32 as u64;
+    let n32 = u32::MAX as u64;
+    let m64 = i64::MIN as u64;
+    let n64 = u64::MAX;
+    let cases = [
+        ("i32", "add", n32, 2, 1),
+        ("i32", "sub", 1, 2, n32),
+        ("i32", "mul", 6, 7, 42),
+        ("i32", "mul", n32, 2, n32 - 1),
+        (
+            "i32",
+            "div_s",
+            (-84i32) as u32 as u64,
+            2,
+            (-42i32) as u32 as u64,
+        ),
+        ("i32", "div_u", n32, 2, 0x7FFFFFFF),
+        ("i32", "rem_s", (-43i32) as u32 as u64, 2, n32),
+        ("i32", "rem_s", m32, n32, 0),
+        ("i32", "rem_u", 43, 2, 1),
+        ("i32", "and", 0xF0, 0x3C, 0x30),
+        ("i32", "or", 0xF0, 0x0F, 0xFF),
+        ("i32", "xor", 0xFF, 0x0F, 0xF0),
+        ("i32", "shl", 1, 33, 2),
+        ("i32", "shr_s", m32, 31, n32),
+        ("i32", "shr_u", m32, 31, 1),
+        ("i32", "rotl", m32 | 1, 1, 3),
+        ("i32", "rotr", 3, 1, m32 | 1),
+        ("i64", "add", n64, 2, 1),
+        ("i64", "sub", 1, 2, n64),
+        ("i64", "mul", 1 << 32, 1 << 31, 1 << 63),
+        ("i64", "div_s", (-84i64) as u64, 2, (-42i64) as u64),
+        ("i64", "div_u", n64, 2, i64::MAX as u64),
+        ("i64", "rem_s", (-43i64) as u64, 2, n64),
+        ("i64", "rem_s", m64, n64, 0),
+        ("i64", "rem_u", 43, 2, 1),
+        ("i64", "and", 0xF0, 0x3C, 0x30),
+        ("i64", "or", 0xF0, 0x0F, 0xFF),
+        ("i64", "xor", 0xFF, 0x0F, 0xF0),
+        ("i64", "shl", 1, 65, 2),
+        ("i64", "shr_s", m64, 63, n64),
+        ("i64", "shr_u", m64, 63, 1),
+        ("i64", "rotl", m64 | 1, 1, 3),
+        ("i64", "rotr", 3, 1, m64 | 1),
+    ];
+    for (ty, op, lhs, rhs, result) in cases {
+        assert_eq!(
+            call_binary(ty, op, lhs, rhs).expect("call"),
+            result,
+            "{}.{} {} {}",
+            ty,
+            op,
+            lhs,
+            rhs
+        );
+    }
+}
+
+#[test]
+fn integer_unary_operators() {
+    let cases = [
+        ("i32", "clz", 0, 32),
+        ("i32", "clz", 1, 31),
+        ("i32", "clz", u32::MAX as u64, 0),
+        ("i32", "ctz", 0, 32),
+        ("i32", "ctz", 0x80, 7),
+        ("i32", "popcnt", 0, 0),
+        ("i32", "popcnt", u32::MAX as u64, 32),
+        ("i32", "popcnt", 0x8001_0101, 4),
+        ("i64", "clz", 0, 64),
+        ("i64", "clz", 1 << 32, 31),
+        ("i64", "ctz", 0, 64),
+        ("i64", "ctz", 1 << 63, 63),
+        ("i64", "popcnt", u64::MAX, 64),
+        ("i64", "popcnt", 0x8000_0001_0101_0000, 4),
+    ];
+    for (ty, op, arg, result) in cases {
+        assert_eq!(
+            call_unary(ty, op, arg).expect("call"),
+            result,
+            "{}.{} {}",
+            ty,
+            op,
+            arg
+        );
+    }
+}
+
+#[test]
+fn integer_division_traps() {
+    for ty in ["i32", "i64"] {
+        for op in ["div_s", "div_u", "rem_s", "rem_u"] {
+            assert!(call_binary(ty, op, 1, 0).is_err(), "{}.{} by zero", ty, op);
+        }
+    }
+    assert!(call_binary("i32", "div_s", i32::MIN as u32 as u64, u32::MAX as u64).is_err());
+    assert!(call_binary("i64", "div_s", i64::MIN as u64, u64::MAX).is_err());
+}
+
+#[test]
+fn i32_const() {
+    let foo_src = r#"
+    (module
+      (func (export "foo") (result i32)
+        i32.const -1
+      )
+    )
+    "#;
+    let foo_binary = wat::parse_str(foo_src).expect("binary module");
+    let foo_module = X86_64Compiler::default()
+        .compile(&foo_binary)
+        .expect("compiled module");
+
+    let mut emulator = Emulator::new().expect("emulator");
+    let emu_mod = emulator.add_module(foo_module).expect("module addition");
+    emulator
+        .call_function(emu_mod.clone(), "foo")
+        .expect("call");
+
+    assert_eq!(emulator.read_register(testing::RAX).unwrap(), 0xFFFFFFFF);
+}
+
+fn call_compare(ty: &str, op: &str, lhs: u64, rhs: u64) -> u64 {
+    let src = format!(
+        r#"
+    (module
+      (func (export "foo") (param {ty}) (param {ty}) (result i32)
+        local.get 0
+        local.get 1
+        {ty}.{op}
+      )
+    )
+    "#
+    );
+    let binary = wat::parse_str(src).expect("binary module");
+    let module = X86_64Compiler::default()
+        .compile(&binary)
+        .expect("compiled module");
+
+    let mut emulator = Emulator::new().expect("emulator");
+    let emu_mod = emulator.add_module(module).expect("module addition");
+    emulator.write_register(testing::RDI, lhs).expect("1st arg");
+    emulator.write_register(testing::RSI, rhs).expect("2nd arg");
+    emulator
+        .call_function(emu_mod.clone(), "foo")
+        .expect("call");
+    emulator.read_register(testing::RAX).unwrap()
+}
+
+#[test]
+fn integer_comparisons() {
+    let minus_one = u32::MAX as u64;
+    for (ty, minus_one) in [("i32", minus_one), ("i64", u64::MAX)] {
+        let cases = [
+            ("eq", 1, 1, 1),
+            ("eq", 1, 2, 0),
+            ("ne", 1, 2, 1),
+            ("ne", 2, 2, 0),
+            ("lt_s", minus_one, 1, 1),
+            ("lt_u", minus_one, 1, 0),
+            ("gt_s", minus_one, 1, 0),
+            ("gt_u", minus_one, 1, 1),
+            ("le_s", 1, 1, 1),
+            ("le_s", 2, 1, 0),
+            ("le_u", 1, minus_one, 1),
+            ("ge_s", 1, minus_one, 1),
+            ("ge_u", 1, minus_one, 0),
+            ("ge_u", 2, 2, 1),
+        ];
+        for (op, lhs, rhs, result) in cases {
+            assert_eq!(
+                call_compare(ty, op, lhs, rhs),
+                result,
+                "{}.{} {} {}",
+                ty,
+                op,
+                lhs,
+                rhs
+            );
+        }
+    }
+}
+
+#[test]
+fn eqz() {
+    let foo_src = r#"
+    (module
+      (func (export "foo") (param i32) (param i64) (result i32)
+        local.get 0
+        i32.eqz
+        local.get 1
+        i64.eqz
+        i32.add
+      )
+    )
+    "#;
+    let foo_binary = wat::parse_str(foo_src).expect("binary module");
+    let foo_module = X86_64Compiler::default()
+        .compile(&foo_binary)
+        .expect("compiled module");
+
+    let mut emulator = Emulator::new().expect("emulator");
+    let emu_mod = emulator.add_module(foo_module).expect("module addition");
+
+    for (arg0, arg1, result) in [(0, 0, 2), (0, 1 << 32, 1), (1 << 32, 1, 1), (1, 1, 0)] {
+        emulator
+            .write_register(testing::RDI, arg0)
+            .expect("1st arg");
+        emulator
+            .write_register(testing::RSI, arg1)
+            .expect("2nd arg");
+        emulator
+            .call_function(emu_mod.clone(), "foo")
+            .expect("call");
+        assert_eq!(emulator.read_register(testing::RAX).unwrap(), result);
+    }
+}
+
+#[test]
+fn comparison_fused_with_branches() {
+    let foo_src = r#"
+    (module
+      (func (export "foo") (param $n i64) (result i64) (local $i i64)
+        (loop $continue
+          local.get $i
+          i64.const 1
+          i64.add
+          local.set $i
+          local.get $i
+          local.get $n
+          i64.lt_u
+          br_if $continue)
+        local.get $i
+        i64.const 42
+        i64.eq
+        (if (result i64)
+          (then i64.const 1)
+          (else i64.const 0))
+      )
+    )
+    "#;
+    let foo_binary = wat::parse_str(foo_src).expect("binary module");
+    let foo_module = X86_64Compiler::default()
+        .compile(&foo_binary)
+        .expect("compiled module");
+
+    let mut emulator = Emulator::new().expect("emulator");
+    let emu_mod = emulator.add_module(foo_module).expect("module addition");
+
+    for (arg, result) in [(42, 1), (41, 0)] {
+        emulator.write_register(testing::RDI, arg).expect("1st arg");
+        emulator
+            .call_function(emu_mod.clone(), "foo")
+            .expect("call");
+        assert_eq!(emulator.read_register(testing::RAX).unwrap(), result);
+    }
+}
+
+#[test]
+fn select() {
+    let foo_src = r#"
+    (module
+      (func (export "foo") (param i64) (param i64) (result i64)
+        local.get 0
+        local.get 1
+        local.get 0
+        local.get 1
+        i64.gt_s
+        select
+      )
+      (func (export "bar") (param i32) (result i64)
+        i64.const 10
+        i64.const 20
+        local.get 0
+        select
+      )
+    )
+    "#;
+    let foo_binary = wat::parse_str(foo_src).expect("binary module");
+    let foo_module = X86_64Compiler::default()
+        .compile(&foo_binary)
+        .expect("compiled module");
+
+    let mut emulator = Emulator::new().expect("emulator");
+    let emu_mod = emulator.add_module(foo_module).expect("module addition");
+
+    for (arg0, arg1) in [(42, 10), (10, 42), ((-42i64) as u64, 42)] {
+        emulator
+            .write_register(testing::RDI, arg0)
+            .expect("1st arg");
+        emulator
+            .write_register(testing::RSI, arg1)
+            .expect("2nd arg");
+        emulator
+            .call_function(emu_mod.clone(), "foo")
+            .expect("call");
+        assert_eq!(
+            emulator.read_register(testing::RAX).unwrap(),
+            (arg0 as i64).max(arg1 as i64) as u64
+        );
+    }
+
+    for (arg, result) in [(1, 10), (0, 20)] {
+        emulator.write_register(testing::RDI, arg).expect("1st arg");
+        emulator
+            .call_function(emu_mod.clone(), "bar")
+            .expect("call");
+        assert_eq!(emulator.read_register(testing::RAX).unwrap(), result);
+    }
+}
+
+#[test]
+fn memory_load_store() {
+    let foo_src = r#"
+    (module
+      (memory 1)
+      (func (export "store") (param i32) (param i64)
+        local.get 0
+        local.get 1
+        i64.store offset=8
+      )
+      (func (export "narrow_stores") (param i32) (param i64)
+        local.get 0
+        local.get 1
+        i64.store8
+        local.get 0
+        local.get 1
+        i64.store16 offset=2
+        local.get 0
+        local.get 1
+        i64.store32 offset=4
+        local.get 0
+        i32.const 0x04030201
+        i32.store offset=8
+      )
+      (func (export "load") (param i32) (result i64)
+        local.get 0
+        i64.load offset=8
+      )
+      (func (export "load8_s") (param i32) (result i64)
+        local.get 0
+        i64.load8_s
+      )
+      (func (export "load8_u") (param i32) (result i32)
+        local.get 0
+        i32.load8_u
+      )
+      (func (export "load16_s") (param i32) (result i32)
+        local.get 0
+        i32.load16_s
+      )
+      (func (export "load32_u") (param i32) (result i64)
+        local.get 0
+        i64.load32_u
+      )
+      (func (export "load32_s") (param i32) (result i64)
+        local.get 0
+        i64.load32_s
+      )
+    )
+    "#;
+    let foo_binary = wat::parse_str(foo_src).expect("binary module");
+    let foo_module = X86_64Compiler::default()
+        .compile(&foo_binary)
+        .expect("compiled module");
+
+    let mut emulator = Emulator::new().expect("emulator");
+    let memory = emulator.add_memory(&[0; 65536]).expect("memory");
+    let emu_mod = emulator.add_module(foo_module).expect("module addition");
+    emu_mod.borrow_mut().link_memory(0, memory, 65536);
+
+    emulator.write_register(testing::RDI, 16).expect("1st arg");
+    emulator
+        .write_register(testing::RSI, 0x8877665544332211)
+        .expect("2nd arg");
+    emulator
+        .call_function(emu_mod.clone(), "store")
+        .expect("call");
+    let mut stored = [0; 8];
+    emulator
+        .read_memory(memory + 24, &mut stored)
+        .expect("memory read");
+    assert_eq!(u64::from_le_bytes(stored), 0x8877665544332211);
+
+    emulator.write_register(testing::RDI, 16).expect("1st arg");
+    emulator
+        .call_function(emu_mod.clone(), "load")
+        .expect("call");
+    assert_eq!(
+        emulator.read_register(testing::RAX).unwrap(),
+        0x8877665544332211
+    );
+
+    emulator
+        .write_memory(memory, &[0xF0, 0x80, 0xFF, 0xFF, 0x00, 0x00, 0x00, 0x80])
+        .expect("memory write");
+    for (function, address, result) in [
+        ("load8_s", 0, 0xFFFFFFFFFFFFFFF0),
+        ("load8_u", 0, 0xF0),
+        ("load16_s", 0, 0xFFFF80F0),
+        ("load32_u", 4, 0x80000000),
+        ("load32_s", 4, 0xFFFFFFFF80000000),
+    ] {
+        emulator
+            .write_register(testing::RDI, address)
+            .expect("1st arg");
+        emulator
+            .call_function(emu_mod.clone(), function)
+            .expect("call");
+        let mut result_value = emulator.read_register(testing::RAX).unwrap();
+        if function.contains("16") {
+            result_value &= 0xFFFFFFFF;
+        }
+        assert_eq!(result_value, result, "{}", function);
+    }
+
+    emulator
+        .write_register(testing::RDI, 0x100)
+        .expect("1st arg");
+    emulator
+        .write_register(testing::RSI, 0x0807060504030201)
+        .expect("2nd arg");
+    emulator
+        .call_function(emu_mod.clone(), "narrow_stores")
+        .expect("call");
+    let mut stored = [0; 12];
+    emulator
+        .read_memory(memory + 0x100, &mut stored)
+        .expect("memory read");
+    assert_eq!(stored, [1, 0, 1, 2, 1, 2, 3, 4, 1, 2, 3, 4]);
+}
+
+#[test]
+fn memory_out_of_bounds() {
+    let foo_src = r#"
+    (module
+      (memory 1)
+      (func (export "load") (param i32) (result i32)
+        local.get 0
+        i32.load offset=4
+      )
+      (func (export "store") (param i32)
+        local.get 0
+        i32.const 1
+        i32.store8
+      )
+    )
+    "#;
+    let foo_binary = wat::parse_str(foo_src).expect("binary module");
+    let foo_module = X86_64Compiler::default()
+        .compile(&foo_binary)
+        .expect("compiled module");
+
+    let mut emulator = Emulator::new().expect("emulator");
+    let memory = emulator.add_memory(&[0; 65536]).expect("memory");
+    let emu_mod = emulator.add_module(foo_module).expect("module addition");
+
+    // An unlinked memory has no accessible bytes
+    emulator.write_register(testing::RDI, 0).expect("1st arg");
+    assert!(emulator.call_function(emu_mod.clone(), "load").is_err());
+
+    emu_mod.borrow_mut().link_memory(0, memory, 65536);
+
+    for (address, in_bounds) in [
+        (0, true),
+        (65528, true),
+        (65529, false),
+        (0xFFFFFFFC, false),
+        (0xFFFF_FFFF_0000_0000, true),
+    ] {
+        emulator
+            .write_register(testing::RDI, address)
+            .expect("1st arg");
+        assert_eq!(
+            emulator.call_function(emu_mod.clone(), "load").is_ok(),
+            in_bounds,
+            "load at {:x}",
+            address
+        );
+    }
+
+    for (address, in_bounds) in [(65535, true), (65536, false)] {
+        emulator
+            .write_register(testing::RDI, address)
+            .expect("1st arg");
+        assert_eq!(
+            emulator.call_function(emu_mod.clone(), "store").is_ok(),
+            in_bounds,
+            "store at {:x}",
+            address
+        );
+    }
+}
+
+#[test]
+fn memory_size_and_grow() {
+    let foo_src = r#"
+    (module
+      (memory 1 3)
+      (func (export "size") (result i32)
+        memory.size
+      )
+      (func (export "grow") (param i32) (result i32)
+        local.get 0
+        memory.grow
+      )
+      (func (export "grow_and_store") (result i32)
+        i32.const 1
+        memory.grow
+        drop
+        i32.const 65536
+        i32.const 42
+        i32.store
+        i32.const 65536
+        i32.load
+      )
+    )
+    "#;
+    let foo_binary = wat::parse_str(foo_src).expect("binary module");
+    let foo_module = X86_64Compiler::default()
+        .compile(&foo_binary)
+        .expect("compiled module");
+
+    let mut emulator = Emulator::new().expect("emulator");
+    let memory = emulator.add_memory(&[0; 3 * 65536]).expect("memory");
+
+    // Grows in place, the memory is allocated up to its maximum
+    let mut assembler = CodeAssembler::new(64).expect("new assembler");
+    use iced_x86::code_asm::*;
+    assembler.mov(rax, qword_ptr(rdi + 8)).expect("asm");
+    assembler.shr(rax, 16).expect("asm");
+    assembler.shl(rsi, 16).expect("asm");
+    assembler.add(qword_ptr(rdi + 8), rsi).expect("asm");
+    assembler.ret().expect("asm");
+    let assembled = assembler.assemble(0).expect("asm");
+    let grow_hook = emulator.add_memory(&assembled).expect("grow hook");
+
+    let emu_mod = emulator.add_module(foo_module).expect("module addition");
+    emu_mod.borrow_mut().link_memory(0, memory, 65536);
+
+    emulator
+        .call_function(emu_mod.clone(), "size")
+        .expect("call");
+    assert_eq!(emulator.read_register(testing::RAX).unwrap() as u32, 1);
+
+    // Without a hook, only growing by zero pages succeeds
+    for (delta, result) in [(0, 1), (1, -1)] {
+        emulator
+            .write_register(testing::RDI, delta)
+            .expect("1st arg");
+        emulator
+            .call_function(emu_mod.clone(), "grow")
+            .expect("call");
+        assert_eq!(emulator.read_register(testing::RAX).unwrap() as i32, result);
+    }
+
+    emu_mod.borrow_mut().link_memory_grow(0, grow_hook);
+    emulator
+        .call_function(emu_mod.clone(), "grow_and_store")
+        .expect("call");
+    assert_eq!(emulator.read_register(testing::RAX).unwrap() as u32, 42);
+
+    for (delta, result) in [(2, -1), (1, 2), (1, -1), (0, 3)] {
+        emulator
+            .write_register(testing::RDI, delta)
+            .expect("1st arg");
+        emulator
+            .call_function(emu_mod.clone(), "grow")
+            .expect("call");
+        assert_eq!(
+            emulator.read_register(testing::RAX).unwrap() as i32,
+            result,
+            "grow by {}",
+            delta
+        );
+    }
+}
+
+#[test]
+fn memory64() {
+    let foo_src = r#"
+    (module
+      (memory i64 1 3)
+      (data (i64.const 8) "\2a")
+      (data $d "\01\02\03\04")
+      (func (export "load") (param i64) (result i32)
+        local.get 0
+        i32.load offset=4
+      )
+      ;; The end of the access doesn't fit in 64 bits
+      (func (export "load_wrapping") (param i64) (result i32)
+        local.get 0
+        i32.load8_u offset=0xFFFFFFFFFFFFFFF0
+      )
+      ;; Nor does the end of the access past the offset
+      (func (export "load_past_end") (param i64) (result i32)
+        local.get 0
+        i32.load16_u offset=0xFFFFFFFFFFFFFFFF
+        i32.const 1
+        i32.add
+      )
+      (func (export "store") (param i64)
+        local.get 0
+        i64.const 0x0102030405060708
+        i64.store
+      )
+      (func (export "size") (result i64)
+        memory.size
+      )
+      (func (export "grow") (param i64) (result i64)
+        local.get 0
+        memory.grow
+      )
+      (func (export "bulk") (param i64) (result i64)
+        local.get 0
+        i32.const 0
+        i32.const 4
+        memory.init $d
+        i64.const 16
+        local.get 0
+        i64.const 4
+        memory.copy
+        i64.const 20
+        i32.const 0xFF
+        i64.const 2
+        memory.fill
+        i64.const 16
+        i64.load
+      )
+    )
+    "#;
+    let foo_binary = wat::parse_str(foo_src).expect("binary module");
+    let foo_module = X86_64Compiler::default()
+        .compile(&foo_binary)
+        .expect("compiled module");
+
+    let mut emulator = Emulator::new().expect("emulator");
+    let memory = emulator.add_memory(&[0; 3 * 65536]).expect("memory");
+
+    let mut assembler = CodeAssembler::new(64).expect("new assembler");
+    use iced_x86::code_asm::*;
+    assembler.mov(rax, qword_ptr(rdi + 8)).expect("asm");
+    assembler.shr(rax, 16).expect("asm");
+    assembler.shl(rsi, 16).expect("asm");
+    assembler.add(qword_ptr(rdi + 8), rsi).expect("asm");
+    assembler.ret().expect("asm");
+    let assembled = assembler.assemble(0).expect("asm");
+    let grow_hook = emulator.add_memory(&assembled).expect("grow hook");
+
+    let emu_mod = emulator.add_module(foo_module).expect("module addition");
+    emu_mod.borrow_mut().link_memory(0, memory, 65536);
+    emu_mod.borrow_mut().link_memory_grow(0, grow_hook);
+    emulator
+        .initialize(emu_mod.clone())
+        .expect("initialization");
+
+    let mut call = |name: &str, arg: u64| {
+        emulator.write_register(testing::RDI, arg).expect("1st arg");
+        emulator
+            .call_function(emu_mod.clone(), name)
+            .map(|_| emulator.read_register(testing::RAX).unwrap())
+            .map_err(|_| last_trap(&emulator, &emu_mod).map(|trap| trap.code))
+    };
+    let out_of_bounds = Err(Some(TrapCode::MemoryOutOfBounds));
+
+    // Upper address bits are significant
+    assert_eq!(call("load", 4), Ok(42));
+    for address in [65532, 0x1_0000_0000, 0xFFFF_FFFF_0000_0004, u64::MAX - 3] {
+        assert_eq!(
+            call("load", address),
+            out_of_bounds,
+            "load at {:x}",
+            address
+        );
+    }
+    assert_eq!(call("load_wrapping", 0x10), out_of_bounds);
+    assert_eq!(call("load_past_end", 0), out_of_bounds);
+    assert!(call("store", 65528).is_ok());
+    assert_eq!(call("store", 65529), out_of_bounds);
+    assert_eq!(call("load", 65524), Ok(0x0506_0708));
+
+    assert_eq!(call("size", 0), Ok(1));
+    assert_eq!(call("grow", u64::MAX), Ok(u64::MAX));
+    assert_eq!(call("grow", 1 << 32), Ok(u64::MAX));
+    assert_eq!(call("grow", 1), Ok(1));
+    assert_eq!(call("grow", 2), Ok(u64::MAX));
+    assert_eq!(call("size", 0), Ok(2));
+
+    assert_eq!(call("bulk", 65536), Ok(0x0000_FFFF_0403_0201));
+    assert_eq!(call("bulk", 2 * 65536 - 3), out_of_bounds);
+    assert_eq!(call("bulk", u64::MAX - 1), out_of_bounds);
+
+    // Without a maximum, the length in bytes still has to fit in 64 bits
+    let unbounded_src = r#"
+    (module
+      (memory i64 1)
+      (func (export "grow") (param i64) (result i64)
+        local.get 0
+        memory.grow
+      )
+    )
+    "#;
+    let unbounded_binary = wat::parse_str(unbounded_src).expect("binary module");
+    let unbounded_module = X86_64Compiler::default()
+        .compile(&unbounded_binary)
+        .expect("compiled module");
+    let unbounded = emulator
+        .add_module(unbounded_module)
+        .expect("module addition");
+    unbounded.borrow_mut().link_memory(0, memory, 65536);
+    unbounded.borrow_mut().link_memory_grow(0, grow_hook);
+    emulator
+        .initialize(unbounded.clone())
+        .expect("initialization");
+    emulator
+        .write_register(testing::RDI, (1 << 48) - 1)
+        .unwrap();
+    emulator
+        .call_function(unbounded.clone(), "grow")
+        .expect("call");
+    assert_eq!(emulator.read_register(testing::RAX).unwrap(), u64::MAX);
+}
+
+#[test]
+fn multiple_memories() {
+    let heap_src = r#"
+    (module
+      (memory (export "shared") 1 3)
+      (func (export "peek") (param i32) (result i32)
+        local.get 0
+        i32.load
+      )
+      (func (export "size") (result i32)
+        memory.size
+      )
+    )
+    "#;
+    let src = r#"
+    (module
+      (import "heap" "shared" (memory $shared 1 3))
+      (memory $private 1)
+      (memory $wide i64 1)
+      (data (memory $private) (i32.const 16) "\2a")
+      (func (export "store") (param i32 i32)
+        (i32.store $shared (local.get 0) (local.get 1))
+        (i32.store $private (local.get 0) (i32.add (local.get 1) (i32.const 1)))
+      )
+      (func (export "load_private") (param i32) (result i32)
+        (i32.load $private (local.get 0))
+      )
+      (func (export "load_wide") (param i64) (result i32)
+        (i32.load $wide (local.get 0))
+      )
+      (func (export "copy_fill")
+        (memory.copy $wide $private (i64.const 8) (i32.const 16) (i32.const 4))
+        (memory.fill $private (i32.const 32) (i32.const 0x55) (i32.const 4))
+      )
+      (func (export "grow") (param i32) (result i32)
+        (memory.grow $shared (local.get 0))
+      )
+      (func (export "sizes") (result i32)
+        (i32.add
+          (i32.mul (memory.size $shared) (i32.const 100))
+          (i32.add
+            (i32.mul (memory.size $private) (i32.const 10))
+            (i32.wrap_i64 (memory.size $wide))))
+      )
+    )
+    "#;
+    let heap_binary = wat::parse_str(heap_src).expect("binary module");
+    let heap_module = X86_64Compiler::default()
+        .compile(&heap_binary)
+        .expect("compiled module");
+    let binary = wat::parse_str(src).expect("binary module");
+    let module = X86_64Compiler::default()
+        .compile(&binary)
+        .expect("compiled module");
+    assert_eq!(
+        heap_module.memory_offset("shared"),
+        heap_module.memory_descriptor_offset(0)
+    );
+    assert!(heap_module.memory_offset("peek").is_none());
+
+    let mut emulator = Emulator::new().expect("emulator");
+    let shared = emulator.add_memory(&[0; 3 * 65536]).expect("memory");
+    let private = emulator.add_memory(&[0; 65536]).expect("memory");
+    let wide = emulator.add_memory(&[0; 65536]).expect("memory");
+
+    // Grows in place, the memory is allocated up to its maximum
+    let mut assembler = CodeAssembler::new(64).expect("new assembler");
+    use iced_x86::code_asm::*;
+    assembler.mov(rax, qword_ptr(rdi + 8)).expect("asm");
+    assembler.shr(rax, 16).expect("asm");
+    assembler.shl(rsi, 16).expect("asm");
+    assembler.add(qword_ptr(rdi + 8), rsi).expect("asm");
+    assembler.ret().expect("asm");
+    let assembled = assembler.assemble(0).expect("asm");
+    let grow_hook = emulator.add_memory(&assembled).expect("grow hook");
+
+    let heap = emulator.add_module(heap_module).expect("module addition");
+    heap.borrow_mut().link_memory(0, shared, 65536);
+    heap.borrow_mut().link_memory_grow(0, grow_hook);
+    let emu_mod = emulator.add_module(module).expect("module addition");
+    let descriptor = heap.borrow().offset() + heap.borrow().memory_offset("shared").unwrap() as u64;
+    {
+        let mut emu_mod = emu_mod.borrow_mut();
+        emu_mod.link_memory_import("heap", Some("shared"), descriptor);
+        emu_mod.link_memory(1, private, 65536);
+        emu_mod.link_memory(2, wide, 65536);
+    }
+    emulator.initialize(heap.clone()).expect("initializer");
+    emulator.initialize(emu_mod.clone()).expect("initializer");
+
+    let mut call = |module: &Rc<RefCell<testing::Module>>, name: &str, params: &[u64]| {
+        for (register, param) in [testing::RDI, testing::RSI].into_iter().zip(params) {
+            emulator.write_register(register, *param).unwrap();
+        }
+        emulator
+            .call_function(module.clone(), name)
+            .map(|_| emulator.read_register(testing::RAX).unwrap() as u32)
+            .map_err(|_| last_trap(&emulator, module).map(|trap| trap.code))
+    };
+
+    // Each memory has its own base and bound
+    assert!(call(&emu_mod, "store", &[100, 7]).is_ok());
+    assert_eq!(call(&heap, "peek", &[100]), Ok(7));
+    assert_eq!(call(&emu_mod, "load_private", &[100]), Ok(8));
+    assert_eq!(call(&emu_mod, "load_private", &[16]), Ok(42));
+    assert_eq!(call(&heap, "peek", &[16]), Ok(0));
+    assert!(call(&emu_mod, "copy_fill", &[]).is_ok());
+    assert_eq!(call(&emu_mod, "load_wide", &[8]), Ok(42));
+    assert_eq!(call(&emu_mod, "load_private", &[32]), Ok(0x5555_5555));
+    assert_eq!(call(&emu_mod, "sizes", &[]), Ok(111));
+
+    // Growing the imported memory grows the exporter's
+    assert_eq!(call(&emu_mod, "grow", &[1]), Ok(1));
+    assert_eq!(call(&heap, "size", &[]), Ok(2));
+    assert_eq!(call(&emu_mod, "sizes", &[]), Ok(211));
+    assert_eq!(
+        call(&emu_mod, "store", &[65536, 9]),
+        Err(Some(TrapCode::MemoryOutOfBounds))
+    );
+    assert_eq!(call(&heap, "peek", &[65536]), Ok(9));
+}
+
+#[test]
+fn globals() {
+    let foo_src = r#"
+    (module
+      (global $counter (export "counter") (mut i64) (i64.const 40))
+      (global $step i64 (i64.const 1))
+      (global (export "pi") f64 (f64.const 3.141592653589793))
+      (func (export "next") (result i64)
+        global.get $counter
+        global.get $step
+        i64.add
+        global.set $counter
+        global.get $counter
+      )
+    )
+    "#;
+    let foo_binary = wat::parse_str(foo_src).expect("binary module");
+    let foo_module = X86_64Compiler::default()
+        .compile(&foo_binary)
+        .expect("compiled module");
+    assert!(foo_module.global_offset("counter").is_some());
+    assert!(foo_module.global_offset("next").is_none());
+    let pi = foo_module.global_offset("pi").expect("exported global");
+
+    let mut emulator = Emulator::new().expect("emulator");
+    let emu_mod = emulator.add_module(foo_module).expect("module addition");
+
+    let mut value = [0; 8];
+    emulator
+        .read_memory(emu_mod.borrow().offset() + pi as u64, &mut value)
+        .expect("memory read");
+    assert_eq!(f64::from_le_bytes(value), core::f64::consts::PI);
+
+    for result in [41, 42] {
+        emulator
+            .call_function(emu_mod.clone(), "next")
+            .expect("call");
+        assert_eq!(emulator.read_register(testing::RAX).unwrap(), result);
+    }
+}
+
+#[test]
+fn imported_globals() {
+    let foo_src = r#"
+    (module
+      (global $base (import "env" "base") i32)
+      (global $total (import "env" "total") (mut i64))
+      (global $start (mut i32) (global.get $base))
+      (func (export "foo") (param i64) (result i32)
+        global.get $total
+        local.get 0
+        i64.add
+        global.set $total
+        global.get $start
+        i32.const 1
+        i32.add
+        global.set $start
+        global.get $start
+      )
+    )
+    "#;
+    let foo_binary = wat::parse_str(foo_src).expect("binary module");
+    let foo_module = X86_64Compiler::default()
+        .compile(&foo_binary)
+        .expect("compiled module");
+
+    let mut emulator = Emulator::new().expect("emulator");
+    // The i32 is followed by a guard value that must not be overwritten
+    let base = emulator
+        .add_memory(&[10, 0, 0, 0, 0xAA, 0xAA, 0xAA, 0xAA])
+        .expect("base global");
+    let total = emulator.add_memory(&100u64.to_le_bytes()).expect("total");
+    let emu_mod = emulator.add_module(foo_module).expect("module addition");
+    emu_mod
+        .borrow_mut()
+        .link_global_import("env", Some("base"), base);
+    emu_mod
+        .borrow_mut()
+        .link_global_import("env", Some("total"), total);
+    emulator.initialize(emu_mod.clone()).expect("initializer");
+
+    for (arg, result) in [(5, 11), (7, 12)] {
+        emulator.write_register(testing::RDI, arg).expect("1st arg");
+        emulator
+            .call_function(emu_mod.clone(), "foo")
+            .expect("call");
+        assert_eq!(emulator.read_register(testing::RAX).unwrap() as u32, result);
+    }
+
+    let mut value = [0; 8];
+    emulator
+        .read_memory(total, &mut value)
+        .expect("memory read");
+    assert_eq!(u64::from_le_bytes(value), 112);
+    emulator.read_memory(base, &mut value).expect("memory read");
+    assert_eq!(value, [10, 0, 0, 0, 0xAA, 0xAA, 0xAA, 0xAA]);
+}
+
+#[test]
+fn data_segments() {
+    let foo_src = r#"
+    (module
+      (global $offset (import "env" "offset") i32)
+      (memory 1)
+      (data (i32.const 16) "\01\02\03\04")
+      (data (global.get $offset) "hello")
+      (data $passive "world!")
+      (func (export "init") (param i32) (param i32) (param i32)
+        local.get 0
+        local.get 1
+        local.get 2
+        memory.init $passive
+      )
+      (func (export "drop")
+        data.drop $passive
+      )
+    )
+    "#;
+    let foo_binary = wat::parse_str(foo_src).expect("binary module");
+    let foo_module = X86_64Compiler::builder()
+        .wasm_features(WasmFeatures {
+            bulk_memory: true,
+            ..X86_64Compiler::supported_features()
+        })
+        .build()
+        .compile(&foo_binary)
+        .expect("compiled module");
+
+    let mut emulator = Emulator::new().expect("emulator");
+    let memory = emulator.add_memory(&[0; 65536]).expect("memory");
+    let offset = emulator.add_memory(&32u32.to_le_bytes()).expect("offset");
+    let emu_mod = emulator.add_module(foo_module).expect("module addition");
+    emu_mod.borrow_mut().link_memory(0, memory, 65536);
+    emu_mod
+        .borrow_mut()
+        .link_global_import("env", Some("offset"), offset);
+    emulator.initialize(emu_mod.clone()).expect("initializer");
+
+    let mut contents = [0; 48];
+    emulator
+        .read_memory(memory, &mut contents)
+        .expect("memory read");
+    assert_eq!(&contents[16..21], &[1, 2, 3, 4, 0]);
+    assert_eq!(&contents[32..38], b"hello\0");
+
+    let call_init = |emulator: &mut Emulator, dst: u64, src: u64, len: u64| {
+        emulator.write_register(testing::RDI, dst).expect("1st arg");
+        emulator.write_register(testing::RSI, src).expect("2nd arg");
+        emulator.write_register(testing::RDX, len).expect("3rd arg");
+        emulator.call_function(emu_mod.clone(), "init").is_ok()
+    };
+    assert!(call_init(&mut emulator, 64, 2, 4));
+    assert!(call_init(&mut emulator, 65530, 0, 6));
+    assert!(!call_init(&mut emulator, 65531, 0, 6));
+    assert!(!call_init(&mut emulator, 0, 3, 4));
+    assert!(call_init(&mut emulator, 0, 6, 0));
+
+    let mut contents = [0; 6];
+    emulator
+        .read_memory(memory + 64, &mut contents)
+        .expect("memory read");
+    assert_eq!(&contents, b"rld!\0\0");
+    emulator
+        .read_memory(memory + 65530, &mut contents)
+        .expect("memory read");
+    assert_eq!(&contents, b"world!");
+
+    emulator
+        .call_function(emu_mod.clone(), "drop")
+        .expect("call");
+    assert!(!call_init(&mut emulator, 0, 0, 1));
+    assert!(call_init(&mut emulator, 0, 0, 0));
+}
+
+#[test]
+fn call_indirect() {
+    let foo_src = r#"
+    (module
+      (type $binary (func (param i64 i64) (result i64)))
+      (type $unary (func (param i64) (result i64)))
+      (func $forty_two (import "env" "forty_two") (param i64 i64) (result i64))
+      (table 5 funcref)
+      (elem (i32.const 0) $add $sub $forty_two)
+      (elem (i32.const 4) $negate)
+      (func $add (param i64 i64) (result i64)
+        local.get 0
+        local.get 1
+        i64.add
+      )
+      (func $sub (param i64 i64) (result i64)
+        local.get 0
+        local.get 1
+        i64.sub
+      )
+      (func $negate (param i64) (result i64)
+        i64.const 0
+        local.get 0
+        i64.sub
+      )
+      (func (export "foo") (param i32) (result i64)
+        i64.const 50
+        i64.const 8
+        local.get 0
+        call_indirect (type $binary)
+      )
+    )
+    "#;
+    let foo_binary = wat::parse_str(foo_src).expect("binary module");
+    let foo_module = X86_64Compiler::default()
+        .compile(&foo_binary)
+        .expect("compiled module");
+
+    let mut emulator = Emulator::new().expect("emulator");
+    let mut assembler = CodeAssembler::new(64).expect("new assembler");
+    use iced_x86::code_asm::*;
+    assembler.mov(eax, 42).expect("asm");
+    assembler.ret().expect("asm");
+    let assembled = assembler.assemble(0).expect("asm");
+    let forty_two = emulator.add_memory(&assembled).expect("forty_two function");
+
+    let emu_mod = emulator.add_module(foo_module).expect("module addition");
+    emu_mod
+        .borrow_mut()
+        .link_import("env", Some("forty_two"), forty_two);
+    emulator.initialize(emu_mod.clone()).expect("initializer");
+
+    for (index, result) in [(0, 58), (1, 42), (2, 42)] {
+        emulator
+            .write_register(testing::RDI, index)
+            .expect("1st arg");
+        emulator
+            .call_function(emu_mod.clone(), "foo")
+            .expect("call");
+        assert_eq!(emulator.read_register(testing::RAX).unwrap(), result);
+    }
+
+    // Null entry, signature mismatch and out of bounds index
+    for index in [3, 4, 5, 0xFFFFFFFF] {
+        emulator
+            .write_register(testing::RDI, index)
+            .expect("1st arg");
+        assert!(
+            emulator.call_function(emu_mod.clone(), "foo").is_err(),
+            "index {}",
+            index
+        );
+    }
+}
+
+#[test]
+fn call_indirect_imported_table() {
+    let foo_src = r#"
+    (module
+      (type $nullary (func (result i64)))
+      (table (import "env" "table") 2 funcref)
+      (func (export "foo") (param i32) (result i64)
+        local.get 0
+        call_indirect (type $nullary)
+      )
+    )
+    "#;
+    let foo_binary = wat::parse_str(foo_src).expect("binary module");
+    let foo_module = X86_64Compiler::default()
+        .compile(&foo_binary)
+        .expect("compiled module");
+
+    let mut emulator = Emulator::new().expect("emulator");
+    let mut assembler = CodeAssembler::new(64).expect("new assembler");
+    use iced_x86::code_asm::*;
+    assembler.mov(eax, 42).expect("asm");
+    assembler.ret().expect("asm");
+    let assembled = assembler.assemble(0).expect("asm");
+    let forty_two = emulator.add_memory(&assembled).expect("forty_two function");
+
+    let nullary = FuncType {
+        params: Box::new([]),
+        returns: Box::new([Type::I64]),
+    };
+    let emu_mod = emulator.add_module(foo_module).expect("module addition");
+    let record =
+        emu_mod.borrow().offset() + emu_mod.borrow().signature_offset(&nullary).unwrap() as u64;
+    // The second reference has a signature that is not a record
+    let mut references = Vec::new();
+    for signature in [record, 0x1234] {
+        references.extend_from_slice(&forty_two.to_le_bytes());
+        references.extend_from_slice(&signature.to_le_bytes());
+    }
+    let references = emulator.add_memory(&references).expect("references");
+    let mut table = Vec::new();
+    table.extend_from_slice(&references.to_le_bytes());
+    table.extend_from_slice(&(references + 16).to_le_bytes());
+    let table = emulator.add_memory(&table).expect("table");
+
+    emu_mod.borrow_mut().link_table(0, table, 2);
+    emulator.initialize(emu_mod.clone()).expect("initializer");
+
+    emulator.write_register(testing::RDI, 0).expect("1st arg");
+    emulator
+        .call_function(emu_mod.clone(), "foo")
+        .expect("call");
+    assert_eq!(emulator.read_register(testing::RAX).unwrap(), 42);
+
+    emulator.write_register(testing::RDI, 1).expect("1st arg");
+    assert!(emulator.call_function(emu_mod.clone(), "foo").is_err());
+    assert_eq!(
+        last_trap(&emulator, &emu_mod).map(|trap| trap.code),
+        Some(TrapCode::BadSignature)
+    );
+}
+
+#[test]
+fn call_indirect_linked_signatures() {
+    let callee_src = r#"
+    (module
+      (table (import "env" "table") 2 funcref)
+      (func $nullary (result i64) i64.const 42)
+      (func $unary (param i64) (result i64) local.get 0)
+      (elem (i32.const 0) $nullary $unary)
+    )
+    "#;
+    let caller_src = r#"
+    (module
+      (type $nullary (func (result i64)))
+      (table (import "env" "table") 2 funcref)
+      (func (export "foo") (param i32) (result i64)
+        local.get 0
+        call_indirect (type $nullary)
+      )
+    )
+    "#;
+    let compile = |src| {
+        let binary = wat::parse_str(src).expect("binary module");
+        X86_64Compiler::default()
+            .compile(&binary)
+            .expect("compiled module")
+    };
+    let results = |link: bool| {
+        let mut emulator = Emulator::new().expect("emulator");
+        let table = emulator.add_memory(&[0; 16]).expect("table");
+        let callee = emulator.add_module(compile(callee_src)).expect("callee");
+        let caller = emulator.add_module(compile(caller_src)).expect("caller");
+        callee.borrow_mut().link_table(0, table, 2);
+        caller.borrow_mut().link_table(0, table, 2);
+        if link {
+            let callee = callee.borrow();
+            let types: Vec<FuncType> = callee.signature_types().cloned().collect();
+            assert_eq!(types.len(), 2);
+            for ty in types.iter() {
+                let record = callee.offset() + callee.signature_offset(ty).unwrap() as u64;
+                caller.borrow_mut().link_signature(ty, record);
+            }
+        }
+        emulator.initialize(callee.clone()).expect("initializer");
+        emulator.initialize(caller.clone()).expect("initializer");
+        [0, 1].map(|index| {
+            emulator
+                .write_register(testing::RDI, index)
+                .expect("1st arg");
+            emulator
+                .call_function(caller.clone(), "foo")
+                .map(|_| emulator.read_register(testing::RAX).unwrap())
+                .map_err(|_| last_trap(&emulator, &caller).map(|trap| trap.code))
+        })
+    };
+    let bad_signature = Err(Some(TrapCode::BadSignature));
+    assert_eq!(results(true), [Ok(42), bad_signature]);
+    // Identical signatures of modules that are not linked are different
+    assert_eq!(results(false), [bad_signature, bad_signature]);
+}
+
+#[test]
+fn exported_tables() {
+    let exporter_src = r#"
+    (module
+      (table (export "first") 1 funcref)
+      (table (export "second") 2 funcref)
+      (func $forty_two (result i64) i64.const 42)
+      (elem (table 1) (i32.const 1) func $forty_two)
+    )
+    "#;
+    let importer_src = r#"
+    (module
+      (type $nullary (func (result i64)))
+      (table (import "exporter" "second") 2 funcref)
+      (func (export "foo") (param i32) (result i64)
+        local.get 0
+        call_indirect (type $nullary)
+      )
+    )
+    "#;
+    let exporter_binary = wat::parse_str(exporter_src).expect("binary module");
+    let exporter_module = X86_64Compiler::default()
+        .compile(&exporter_binary)
+        .expect("compiled module");
+    assert_eq!(
+        exporter_module.table_offset("second"),
+        exporter_module.table_descriptor_offset(1)
+    );
+    assert!(exporter_module.table_offset("third").is_none());
+    let importer_binary = wat::parse_str(importer_src).expect("binary module");
+    let importer_module = X86_64Compiler::default()
+        .compile(&importer_binary)
+        .expect("compiled module");
+
+    let mut emulator = Emulator::new().expect("emulator");
+    let exporter = emulator
+        .add_module(exporter_module)
+        .expect("module addition");
+    let emu_mod = emulator
+        .add_module(importer_module)
+        .expect("module addition");
+    emulator.initialize(exporter.clone()).expect("initializer");
+
+    let nullary = FuncType {
+        params: Box::new([]),
+        returns: Box::new([Type::I64]),
+    };
+    let (record, descriptor) = {
+        let exporter = exporter.borrow();
+        (
+            exporter.offset() + exporter.signature_offset(&nullary).unwrap() as u64,
+            exporter.offset() + exporter.table_offset("second").unwrap() as u64,
+        )
+    };
+    let mut table = [0; 16];
+    emulator.read_memory(descriptor, &mut table).unwrap();
+    let base = u64::from_le_bytes(table[..8].try_into().unwrap());
+    let length = u64::from_le_bytes(table[8..].try_into().unwrap());
+    assert_eq!(length, 2);
+    {
+        let mut emu_mod = emu_mod.borrow_mut();
+        emu_mod.link_table(0, base, length);
+        emu_mod.link_signature(&nullary, record);
+    }
+    emulator.initialize(emu_mod.clone()).expect("initializer");
+
+    let mut call = |index: u64| {
+        emulator.write_register(testing::RDI, index).unwrap();
+        emulator
+            .call_function(emu_mod.clone(), "foo")
+            .map(|_| emulator.read_register(testing::RAX).unwrap())
+            .map_err(|_| last_trap(&emulator, &emu_mod).map(|trap| trap.code))
+    };
+    assert_eq!(call(1), Ok(42));
+    assert_eq!(call(0), Err(Some(TrapCode::IndirectCallToNull)));
+}
+
+fn call_float(ty: &str, op: &str, params: &[u64]) -> u64 {
+    let param_list = vec![format!("(param {ty})"); params.len()].join(" ");
+    let gets: String = (0..params.len())
+        .map(|i| format!("local.get {i}\n"))
+        .collect();
+    let result = if ["eq", "ne", "lt", "gt", "le", "ge"].contains(&op) {
+        "i32"
+    } else {
+        ty
+    };
+    let src = format!(
+        r#"
+    (module
+      (func (export "foo") {param_list} (result {result})
+        {gets}
+        {ty}.{op}
+      )
+    )
+    "#
+    );
+    let binary = wat::parse_str(src).expect("binary module");
+    let module = X86_64Compiler::default()
+        .compile(&binary)
+        .expect("compiled module");
+
+    let mut emulator = Emulator::new().expect("emulator");
+    let emu_mod = emulator.add_module(module).expect("module addition");
+    for (register, value) in [testing::XMM0, testing::XMM1].into_iter().zip(params) {
+        emulator.write_xmm(register, *value).expect("argument");
+    }
+    emulator
+        .call_function(emu_mod.clone(), "foo")
+        .expect("call");
+    if result == "i32" {
+        emulator.read_register(testing::RAX).unwrap() & 0xFFFFFFFF
+    } else {
+        emulator.read_xmm(testing::XMM0).unwrap()
+    }
+}
+
+fn call_f64(op: &str, params: &[f64]) -> f64 {
+    let params: Vec<u64> = params.iter().map(|value| value.to_bits()).collect();
+    f64::from_bits(call_float("f64", op, &params))
+}
+
+fn call_f32(op: &str, params: &[f32]) -> f32 {
+    let params: Vec<u64> = params.iter().map(|value| value.to_bits() as u64).collect();
+    f32::from_bits(call_float("f32", op, &params) as u32)
+}
+
+#[test]
+fn float_arithmetic() {
+    assert_eq!(call_f64("add", &[1.5, 2.25]), 3.75);
+    assert_eq!(call_f64("sub", &[1.5, 2.25]), -0.75);
+    assert_eq!(call_f64("mul", &[1.5, -2.0]), -3.0);
+    assert_eq!(call_f64("div", &[1.0, 4.0]), 0.25);
+    assert_eq!(call_f64("div", &[1.0, 0.0]), f64::INFINITY);
+    assert_eq!(call_f64("sqrt", &[2.25]), 1.5);
+    assert_eq!(call_f32("add", &[1.5, 2.25]), 3.75);
+    assert_eq!(call_f32("sub", &[1.5, 2.25]), -0.75);
+    assert_eq!(call_f32("mul", &[1.5, -2.0]), -3.0);
+    assert_eq!(call_f32("div", &[1.0, 4.0]), 0.25);
+    assert_eq!(call_f32("sqrt", &[2.25]), 1.5);
+    assert!(call_f32("sqrt", &[-1.0]).is_nan());
+}
+
+#[test]
+fn float_min_max() {
+    assert_eq!(call_f64("min", &[1.0, 2.0]), 1.0);
+    assert_eq!(call_f64("max", &[1.0, 2.0]), 2.0);
+    assert!(call_f64("min", &[f64::NAN, 2.0]).is_nan());
+    assert!(call_f64("min", &[2.0, f64::NAN]).is_nan());
+    assert!(call_f64("max", &[f64::NAN, 2.0]).is_nan());
+    assert!(call_f64("max", &[2.0, f64::NAN]).is_nan());
+    assert!(call_f64("min", &[0.0, -0.0]).is_sign_negative());
+    assert!(call_f64("min", &[-0.0, 0.0]).is_sign_negative());
+    assert!(call_f64("max", &[0.0, -0.0]).is_sign_positive());
+    assert!(call_f64("max", &[-0.0, 0.0]).is_sign_positive());
+    assert_eq!(call_f32("min", &[-1.0, 2.0]), -1.0);
+    assert_eq!(call_f32("max", &[-1.0, 2.0]), 2.0);
+    assert!(call_f32("max", &[f32::NAN, 2.0]).is_nan());
+    assert!(call_f32("min", &[0.0, -0.0]).is_sign_negative());
+    assert!(call_f32("max", &[-0.0, 0.0]).is_sign_positive());
+}
+
+#[test]
+fn float_rounding() {
+    for (value, nearest, ceil, floor, trunc) in [
+        (2.5, 2.0, 3.0, 2.0, 2.0),
+        (3.5, 4.0, 4.0, 3.0, 3.0),
+        (-2.5, -2.0, -2.0, -3.0, -2.0),
+        (-1.25, -1.0, -1.0, -2.0, -1.0),
+        (0.75, 1.0, 1.0, 0.0, 0.0),
+        (0.5, 0.0, 1.0, 0.0, 0.0),
+        (-0.5, -0.0, -0.0, -1.0, -0.0),
+        (
+            4503599627370497.0,
+            4503599627370497.0,
+            4503599627370497.0,
+            4503599627370497.0,
+            4503599627370497.0,
+        ),
+        (
+            f64::NEG_INFINITY,
+            f64::NEG_INFINITY,
+            f64::NEG_INFINITY,
+            f64::NEG_INFINITY,
+            f64::NEG_INFINITY,
+        ),
+    ] {
+        assert_eq!(call_f64("nearest", &[value]), nearest, "nearest {value}");
+        assert_eq!(call_f64("ceil", &[value]), ceil, "ceil {value}");
+        assert_eq!(call_f64("floor", &[value]), floor, "floor {value}");
+        assert_eq!(call_f64("trunc", &[value]), trunc, "trunc {value}");
+        let value = value as f32;
+        assert_eq!(call_f32("nearest", &[value]), nearest as f32);
+        assert_eq!(call_f32("ceil", &[value]), ceil as f32);
+        assert_eq!(call_f32("floor", &[value]), floor as f32);
+        assert_eq!(call_f32("trunc", &[value]), trunc as f32);
+    }
+    assert!(call_f64("ceil", &[-0.5]).is_sign_negative());
+    assert!(call_f64("nearest", &[-0.5]).is_sign_negative());
+    assert!(call_f32("trunc", &[-0.75]).is_sign_negative());
+    assert!(call_f64("floor", &[-0.0]).is_sign_negative());
+    assert!(call_f64("nearest", &[f64::NAN]).is_nan());
+    assert!(call_f32("floor", &[f32::NAN]).is_nan());
+}
+
+#[test]
+fn float_sign() {
+    assert_eq!(call_f64("abs", &[-1.5]), 1.5);
+    assert_eq!(call_f64("neg", &[1.5]), -1.5);
+    assert_eq!(call_f64("neg", &[-0.0]).to_bits(), 0.0f64.to_bits());
+    assert_eq!(call_f64("copysign", &[1.5, -0.0]), -1.5);
+    assert_eq!(call_f64("copysign", &[-1.5, 2.0]), 1.5);
+    // Only the sign bit of NaNs is changed
+    let nan = 0x7FF8_0000_0000_1234u64;
+    assert_eq!(
+        call_float("f64", "neg", &[nan]),
+        nan | 0x8000_0000_0000_0000
+    );
+    assert_eq!(
+        call_float("f64", "abs", &[nan | 0x8000_0000_0000_0000]),
+        nan
+    );
+    assert_eq!(call_f32("abs", &[-1.5]), 1.5);
+    assert_eq!(call_f32("neg", &[1.5]), -1.5);
+    assert_eq!(call_f32("copysign", &[1.5, -2.0]), -1.5);
+    assert_eq!(call_f32("copysign", &[-1.5, 2.0]), 1.5);
+}
+
+#[test]
+fn float_comparisons() {
+    let nan = f64::NAN.to_bits();
+    let one = 1.0f64.to_bits();
+    let two = 2.0f64.to_bits();
+    for (op, lt, eq, gt, unordered) in [
+        ("eq", 0, 1, 0, 0),
+        ("ne", 1, 0, 1, 1),
+        ("lt", 1, 0, 0, 0),
+        ("gt", 0, 0, 1, 0),
+        ("le", 1, 1, 0, 0),
+        ("ge", 0, 1, 1, 0),
+    ] {
+        assert_eq!(call_float("f64", op, &[one, two]), lt, "f64.{op} 1 2");
+        assert_eq!(call_float("f64", op, &[two, two]), eq, "f64.{op} 2 2");
+        assert_eq!(call_float("f64", op, &[two, one]), gt, "f64.{op} 2 1");
+        assert_eq!(
+            call_float("f64", op, &[nan, one]),
+            unordered,
+            "f64.{op} nan 1"
+        );
+        assert_eq!(
+            call_float("f64", op, &[one, nan]),
+            unordered,
+            "f64.{op} 1 nan"
+        );
+        let (one, two, nan) = (
+            1.0f32.to_bits() as u64,
+            2.0f32.to_bits() as u64,
+            f32::NAN.to_bits() as u64,
+        );
+        assert_eq!(call_float("f32", op, &[one, two]), lt, "f32.{op} 1 2");
+        assert_eq!(call_float("f32", op, &[two, two]), eq, "f32.{op} 2 2");
+        assert_eq!(call_float("f32", op, &[two, one]), gt, "f32.{op} 2 1");
+        assert_eq!(
+            call_float("f32", op, &[nan, one]),
+            unordered,
+            "f32.{op} nan 1"
+        );
+    }
+    // -0 and +0 are equal
+    assert_eq!(call_float("f64", "eq", &[(-0.0f64).to_bits(), 0]), 1);
+}
+
+#[test]
+fn float_comparisons_fused_with_branches() {
+    let binary = wat::parse_str(
+        r#"
+    (module
+      (func (export "foo") (param f64) (param f64) (result i32)
+        local.get 0
+        local.get 1
+        f64.lt
+        if (result i32)
+          i32.const 1
+        else
+          i32.const 2
+          local.get 0
+          local.get 1
+          f64.ge
+          br_if 0
+          drop
+          i32.const 3
+        end
+      )
+    )
+    "#,
+    )
+    .expect("binary module");
+    let module = X86_64Compiler::default()
+        .compile(&binary)
+        .expect("compiled module");
+
+    let mut emulator = Emulator::new().expect("emulator");
+    let emu_mod = emulator.add_module(module).expect("module addition");
+    for (lhs, rhs, expected) in [(1.0f64, 2.0f64, 1), (2.0, 1.0, 2), (f64::NAN, 1.0, 3)] {
+        emulator.write_xmm(testing::XMM0, lhs.to_bits()).unwrap();
+        emulator.write_xmm(testing::XMM1, rhs.to_bits()).unwrap();
+        emulator
+            .call_function(emu_mod.clone(), "foo")
+            .expect("call");
+        assert_eq!(
+            emulator.read_register(testing::RAX).unwrap() & 0xFFFFFFFF,
+            expected,
+            "{lhs} {rhs}"
+        );
+    }
+}
+
+#[test]
+fn float_parameters_and_results() {
+    let binary = wat::parse_str(
+        r#"
+    (module
+      (func $mix (param i32) (param f64) (param i64) (param f32) (result f64)
+        (local f64 i64)
+        local.get 0
+        i32.const 2
+        i32.eq
+        local.get 2
+        local.get 5
+        i64.add
+        i64.const 3
+        i64.eq
+        i32.and
+        local.get 3
+        f32.const 0.25
+        f32.eq
+        i32.and
+        if (result f64)
+          local.get 1
+          local.get 4
+          f64.add
+        else
+          f64.const -1
+        end
+      )
+      (func (export "foo") (param f64) (result f64)
+        i32.const 2
+        local.get 0
+        i64.const 3
+        f32.const 0.25
+        call $mix
+      )
+    )
+    "#,
+    )
+    .expect("binary module");
+    let module = X86_64Compiler::default()
+        .compile(&binary)
+        .expect("compiled module");
+
+    let mut emulator = Emulator::new().expect("emulator");
+    let emu_mod = emulator.add_module(module).expect("module addition");
+    emulator.write_xmm(testing::XMM0, 0.5f64.to_bits()).unwrap();
+    emulator
+        .call_function(emu_mod.clone(), "foo")
+        .expect("call");
+    assert_eq!(
+        f64::from_bits(emulator.read_xmm(testing::XMM0).unwrap()),
+        0.5
+    );
+}
+
+#[test]
+fn float_memory() {
+    let binary = wat::parse_str(
+        r#"
+    (module
+      (memory 1)
+      (func (export "foo") (param f32) (param f64) (result f64)
+        i32.const 0
+        local.get 0
+        f32.store
+        i32.const 8
+        local.get 1
+        f64.store offset=8
+        i32.const 16
+        f64.load
+        i32.const 0
+        i32.const 0
+        f32.load
+        f32.const 1
+        f32.add
+        f32.store offset=4
+      )
+    )
+    "#,
+    )
+    .expect("binary module");
+    let module = X86_64Compiler::default()
+        .compile(&binary)
+        .expect("compiled module");
+
+    let mut emulator = Emulator::new().expect("emulator");
+    let memory = emulator.add_memory(&[0; 65536]).expect("memory");
+    let emu_mod = emulator.add_module(module).expect("module addition");
+    emu_mod.borrow_mut().link_memory(0, memory, 65536);
+    emulator
+        .write_xmm(testing::XMM0, 1.5f32.to_bits() as u64)
+        .unwrap();
+    emulator
+        .write_xmm(testing::XMM1, (-2.5f64).to_bits())
+        .unwrap();
+    emulator
+        .call_function(emu_mod.clone(), "foo")
+        .expect("call");
+    assert_eq!(
+        f64::from_bits(emulator.read_xmm(testing::XMM0).unwrap()),
+        -2.5
+    );
+    let mut bytes = [0; 8];
+    emulator.read_memory(memory, &mut bytes).unwrap();
+    assert_eq!(&bytes[..4], &1.5f32.to_le_bytes());
+    assert_eq!(&bytes[4..], &2.5f32.to_le_bytes());
+}
+
+/// Calls `to.op` on `value` of type `from`, `None` meaning it trapped
+fn call_conversion(to: &str, op: &str, from: &str, value: u64) -> Option<u64> {
+    let src = format!(
+        r#"
+    (module
+      (func (export "foo") (param {from}) (result {to})
+        local.get 0
+        {to}.{op}
+      )
+    )
+    "#
+    );
+    let binary = wat::parse_str(src).expect("binary module");
+    let module = X86_64Compiler::default()
+        .compile(&binary)
+        .expect("compiled module");
+
+    let mut emulator = Emulator::new().expect("emulator");
+    let emu_mod = emulator.add_module(module).expect("module addition");
+    if from.starts_with('f') {
+        emulator.write_xmm(testing::XMM0, value).expect("argument");
+    } else {
+        emulator
+            .write_register(testing::RDI, value)
+            .expect("argument");
+    }
+    emulator.call_function(emu_mod.clone(), "foo").ok()?;
+    let result = if to.starts_with('f') {
+        emulator.read_xmm(testing::XMM0).unwrap()
+    } else {
+        emulator.read_register(testing::RAX).unwrap()
+    };
+    Some(if to.ends_with("32") {
+        result & 0xFFFFFFFF
+    } else {
+        result
+    })
+}
+
+#[test]
+fn integer_conversions() {
+    let convert = |to, op, from, value| call_conversion(to, op, from, value).unwrap();
+    assert_eq!(
+        convert("i32", "wrap_i64", "i64", 0x1234_5678_9ABC_DEF0),
+        0x9ABC_DEF0
+    );
+    assert_eq!(
+        convert("i64", "extend_i32_s", "i32", 0xFFFF_FFFE),
+        (-2i64) as u64
+    );
+    assert_eq!(
+        convert("i64", "extend_i32_s", "i32", 0x7FFF_FFFF),
+        0x7FFF_FFFF
+    );
+    // Upper bits of i32 values are not relied upon
+    assert_eq!(
+        convert("i64", "extend_i32_u", "i32", 0x1_FFFF_FFFE),
+        0xFFFF_FFFE
+    );
+    assert_eq!(convert("i32", "extend8_s", "i32", 0x180), 0xFFFF_FF80);
+    assert_eq!(convert("i32", "extend8_s", "i32", 0x17F), 0x7F);
+    assert_eq!(convert("i32", "extend16_s", "i32", 0x1_8000), 0xFFFF_8000);
+    assert_eq!(convert("i64", "extend8_s", "i64", 0x80), (-128i64) as u64);
+    assert_eq!(convert("i64", "extend16_s", "i64", 0x7FFF), 0x7FFF);
+    assert_eq!(
+        convert("i64", "extend16_s", "i64", 0x8000),
+        (-32768i64) as u64
+    );
+    assert_eq!(
+        convert("i64", "extend32_s", "i64", 0x8000_0000),
+        0xFFFF_FFFF_8000_0000
+    );
+}
+
+#[test]
+fn float_truncations() {
+    let f32 = |value: f32| value.to_bits() as u64;
+    let f64 = |value: f64| value.to_bits();
+    for (to, op, from, value, expected) in [
+        ("i32", "trunc_f32_s", "f32", f32(-1.9), Some(0xFFFF_FFFF)),
+        (
+            "i32",
+            "trunc_f32_s",
+            "f32",
+            f32(-2147483648.0),
+            Some(0x8000_0000),
+        ),
+        ("i32", "trunc_f32_s", "f32", f32(2147483648.0), None),
+        ("i32", "trunc_f32_s", "f32", f32(f32::NAN), None),
+        (
+            "i32",
+            "trunc_f64_s",
+            "f64",
+            f64(-2147483648.9),
+            Some(0x8000_0000),
+        ),
+        ("i32", "trunc_f64_s", "f64", f64(-2147483649.0), None),
+        (
+            "i32",
+            "trunc_f64_s",
+            "f64",
+            f64(2147483647.9),
+            Some(0x7FFF_FFFF),
+        ),
+        ("i32", "trunc_f64_s", "f64", f64(2147483648.0), None),
+        ("i32", "trunc_f32_u", "f32", f32(-0.9), Some(0)),
+        ("i32", "trunc_f32_u", "f32", f32(-1.0), None),
+        (
+            "i32",
+            "trunc_f32_u",
+            "f32",
+            f32(4294967040.0),
+            Some(4294967040),
+        ),
+        ("i32", "trunc_f32_u", "f32", f32(4294967296.0), None),
+        (
+            "i32",
+            "trunc_f64_u",
+            "f64",
+            f64(4294967295.9),
+            Some(0xFFFF_FFFF),
+        ),
+        ("i32", "trunc_f64_u", "f64", f64(4294967296.0), None),
+        ("i32", "trunc_f64_u", "f64", f64(f64::NAN), None),
+        (
+            "i64",
+            "trunc_f32_s",
+            "f32",
+            f32(-9223372036854775808.0),
+            Some(1 << 63),
+        ),
+        (
+            "i64",
+            "trunc_f32_s",
+            "f32",
+            f32(9223372036854775808.0),
+            None,
+        ),
+        ("i64", "trunc_f64_s", "f64", f64(-1.5), Some(u64::MAX)),
+        (
+            "i64",
+            "trunc_f64_s",
+            "f64",
+            f64(9223372036854774784.0),
+            Some(9223372036854774784),
+        ),
+        (
+            "i64",
+            "trunc_f64_s",
+            "f64",
+            f64(9223372036854775808.0),
+            None,
+        ),
+        ("i64", "trunc_f64_s", "f64", f64(f64::INFINITY), None),
+        (
+            "i64",
+            "trunc_f32_u",
+            "f32",
+            f32(18446742974197923840.0),
+            Some(18446742974197923840),
+        ),
+        (
+            "i64",
+            "trunc_f32_u",
+            "f32",
+            f32(18446744073709551616.0),
+            None,
+        ),
+        (
+            "i64",
+            "trunc_f64_u",
+            "f64",
+            f64(9223372036854775808.0),
+            Some(1 << 63),
+        ),
+        (
+            "i64",
+            "trunc_f64_u",
+            "f64",
+            f64(18446744073709549568.0),
+            Some(18446744073709549568),
+        ),
+        (
+            "i64",
+            "trunc_f64_u",
+            "f64",
+            f64(18446744073709551616.0),
+            None,
+        ),
+        ("i64", "trunc_f64_u", "f64", f64(-1.0), None),
+        ("i64", "trunc_f64_u", "f64", f64(f64::NAN), None),
+    ] {
+        assert_eq!(
+            call_conversion(to, op, from, value),
+            expected,
+            "{to}.{op} {value:x}"
+        );
+    }
+}
+
+#[test]
+fn saturating_float_truncations() {
+    let f32 = |value: f32| value.to_bits() as u64;
+    let f64 = |value: f64| value.to_bits();
+    for (to, op, from, value, expected) in [
+        ("i32", "trunc_sat_f32_s", "f32", f32(-1.9), 0xFFFF_FFFF),
+        ("i32", "trunc_sat_f32_s", "f32", f32(3e9), 0x7FFF_FFFF),
+        ("i32", "trunc_sat_f32_s", "f32", f32(-3e9), 0x8000_0000),
+        ("i32", "trunc_sat_f32_s", "f32", f32(f32::NAN), 0),
+        (
+            "i32",
+            "trunc_sat_f64_s",
+            "f64",
+            f64(2147483647.9),
+            0x7FFF_FFFF,
+        ),
+        (
+            "i32",
+            "trunc_sat_f64_s",
+            "f64",
+            f64(f64::INFINITY),
+            0x7FFF_FFFF,
+        ),
+        (
+            "i32",
+            "trunc_sat_f64_s",
+            "f64",
+            f64(f64::NEG_INFINITY),
+            0x8000_0000,
+        ),
+        ("i32", "trunc_sat_f32_u", "f32", f32(-1.0), 0),
+        ("i32", "trunc_sat_f32_u", "f32", f32(5e9), 0xFFFF_FFFF),
+        (
+            "i32",
+            "trunc_sat_f64_u",
+            "f64",
+            f64(4294967295.9),
+            0xFFFF_FFFF,
+        ),
+        ("i32", "trunc_sat_f64_u", "f64", f64(1.5), 1),
+        ("i32", "trunc_sat_f64_u", "f64", f64(f64::NAN), 0),
+        ("i64", "trunc_sat_f32_s", "f32", f32(1e19), i64::MAX as u64),
+        ("i64", "trunc_sat_f32_s", "f32", f32(-1e19), i64::MIN as u64),
+        ("i64", "trunc_sat_f64_s", "f64", f64(-2.5), (-2i64) as u64),
+        ("i64", "trunc_sat_f64_s", "f64", f64(f64::NAN), 0),
+        ("i64", "trunc_sat_f32_u", "f32", f32(1e20), u64::MAX),
+        (
+            "i64",
+            "trunc_sat_f64_u",
+            "f64",
+            f64(9223372036854775808.0),
+            1 << 63,
+        ),
+        ("i64", "trunc_sat_f64_u", "f64", f64(1e20), u64::MAX),
+        ("i64", "trunc_sat_f64_u", "f64", f64(-0.5), 0),
+        ("i64", "trunc_sat_f64_u", "f64", f64(f64::NEG_INFINITY), 0),
+    ] {
+        assert_eq!(
+            call_conversion(to, op, from, value),
+            Some(expected),
+            "{to}.{op} {value:x}"
+        );
+    }
+}
+
+#[test]
+fn integer_to_float_conversions() {
+    let f32 = |value: f32| Some(value.to_bits() as u64);
+    let f64 = |value: f64| Some(value.to_bits());
+    for (to, op, from, value, expected) in [
+        ("f32", "convert_i32_s", "i32", 0xFFFF_FFFE, f32(-2.0)),
+        (
+            "f32",
+            "convert_i32_u",
+            "i32",
+            0xFFFF_FFFE,
+            f32(4294967294.0),
+        ),
+        ("f32", "convert_i64_s", "i64", (-3i64) as u64, f32(-3.0)),
+        (
+            "f32",
+            "convert_i64_u",
+            "i64",
+            u64::MAX,
+            f32(18446744073709551615.0),
+        ),
+        // Rounds to nearest even rather than truncating the lowest bit
+        (
+            "f32",
+            "convert_i64_u",
+            "i64",
+            0x8000_0080_0000_0001,
+            f32(9223373136366403584.0),
+        ),
+        (
+            "f64",
+            "convert_i32_s",
+            "i32",
+            0x8000_0000,
+            f64(-2147483648.0),
+        ),
+        (
+            "f64",
+            "convert_i32_u",
+            "i32",
+            0x1_8000_0000,
+            f64(2147483648.0),
+        ),
+        (
+            "f64",
+            "convert_i64_s",
+            "i64",
+            1 << 63,
+            f64(-9223372036854775808.0),
+        ),
+        (
+            "f64",
+            "convert_i64_u",
+            "i64",
+            1 << 63,
+            f64(9223372036854775808.0),
+        ),
+        (
+            "f64",
+            "convert_i64_u",
+            "i64",
+            0x8000_0000_0000_0401,
+            f64(9223372036854777856.0),
+        ),
+        ("f64", "convert_i64_u", "i64", 12345, f64(12345.0)),
+    ] {
+        assert_eq!(
+            call_conversion(to, op, from, value),
+            expected,
+            "{to}.{op} {value:x}"
+        );
+    }
+}
+
+#[test]
+fn float_precision_and_reinterpret_conversions() {
+    let convert = |to, op, from, value| call_conversion(to, op, from, value).unwrap();
+    assert_eq!(
+        convert("f32", "demote_f64", "f64", 1.5f64.to_bits()),
+        1.5f32.to_bits() as u64
+    );
+    assert_eq!(
+        convert("f32", "demote_f64", "f64", 1e300f64.to_bits()),
+        f32::INFINITY.to_bits() as u64
+    );
+    assert_eq!(
+        convert("f64", "promote_f32", "f32", (-0.25f32).to_bits() as u64),
+        (-0.25f64).to_bits()
+    );
+    assert_eq!(
+        convert("i32", "reinterpret_f32", "f32", 1.0f32.to_bits() as u64),
+        0x3F80_0000
+    );
+    assert_eq!(
+        convert("i64", "reinterpret_f64", "f64", 1.0f64.to_bits()),
+        0x3FF0_0000_0000_0000
+    );
+    assert_eq!(
+        convert("f32", "reinterpret_i32", "i32", 0x3F80_0000),
+        1.0f32.to_bits() as u64
+    );
+    assert_eq!(
+        convert("f64", "reinterpret_i64", "i64", 0xBFF0_0000_0000_0000),
+        (-1.0f64).to_bits()
+    );
+}
+
+/// Trap raised by the last call, resolved from where it stopped
+fn last_trap(emulator: &Emulator, module: &Rc<RefCell<testing::Module>>) -> Option<Trap> {
+    let rip = emulator.read_register(testing::RIP).unwrap();
+    let module = module.borrow();
+    module.resolve_trap(module.offset(), rip)
+}
+
+#[test]
+fn traps() {
+    let binary = wat::parse_str(
+        r#"
+    (module
+      (memory 1)
+      (func (export "unreachable")
+        nop
+        unreachable
+      )
+      (func (export "div") (param i32) (param i32) (result i32)
+        local.get 0
+        local.get 1
+        i32.div_s
+      )
+      (func (export "load") (param i32) (result i32)
+        local.get 0
+        i32.load
+      )
+      (func (export "trunc") (param f64) (result i32)
+        local.get 0
+        i32.trunc_f64_s
+      )
+    )
+    "#,
+    )
+    .expect("binary module");
+    // nop, unreachable, end
+    let unreachable_offset = binary
+        .windows(3)
+        .position(|bytes| bytes == [0x01, 0x00, 0x0B])
+        .unwrap()
+        + 1;
+    let module = X86_64Compiler::default()
+        .compile(&binary)
+        .expect("compiled module");
+
+    let mut emulator = Emulator::new().expect("emulator");
+    let memory = emulator.add_memory(&[0; 65536]).expect("memory");
+    let emu_mod = emulator.add_module(module).expect("module addition");
+    emu_mod.borrow_mut().link_memory(0, memory, 65536);
+
+    assert!(emulator
+        .call_function(emu_mod.clone(), "unreachable")
+        .is_err());
+    assert_eq!(
+        last_trap(&emulator, &emu_mod),
+        Some(Trap {
+            code: TrapCode::UnreachableCodeReached,
+            function_index: Some(0),
+            wasm_offset: unreachable_offset,
+        })
+    );
+
+    for (lhs, rhs, code) in [
+        (1, 0, TrapCode::IntegerDivisionByZero),
+        (0x8000_0000, 0xFFFF_FFFF, TrapCode::IntegerOverflow),
+    ] {
+        emulator.write_register(testing::RDI, lhs).unwrap();
+        emulator.write_register(testing::RSI, rhs).unwrap();
+        assert!(emulator.call_function(emu_mod.clone(), "div").is_err());
+        let trap = last_trap(&emulator, &emu_mod).expect("trap");
+        assert_eq!(trap.code, code);
+        assert_eq!(trap.function_index, Some(1));
+    }
+
+    emulator.write_register(testing::RDI, 65533).unwrap();
+    assert!(emulator.call_function(emu_mod.clone(), "load").is_err());
+    assert_eq!(
+        last_trap(&emulator, &emu_mod).map(|trap| trap.code),
+        Some(TrapCode::MemoryOutOfBounds)
+    );
+
+    for (value, code) in [
+        (f64::NAN, TrapCode::BadConversionToInteger),
+        (1e10, TrapCode::IntegerOverflow),
+    ] {
+        emulator.write_xmm(testing::XMM0, value.to_bits()).unwrap();
+        assert!(emulator.call_function(emu_mod.clone(), "trunc").is_err());
+        assert_eq!(
+            last_trap(&emulator, &emu_mod).map(|trap| trap.code),
+            Some(code)
+        );
+    }
+}
+
+#[test]
+fn stack_overflow() {
+    let binary = wat::parse_str(
+        r#"
+    (module
+      (func $recurse (export "recurse") (param i64) (result i64)
+        local.get 0
+        i64.eqz
+        if (result i64)
+          i64.const 0
+        else
+          local.get 0
+          i64.const 1
+          i64.sub
+          call $recurse
+          i64.const 1
+          i64.add
+        end
+      )
+    )
+    "#,
+    )
+    .expect("binary module");
+    let module = X86_64Compiler::default()
+        .compile(&binary)
+        .expect("compiled module");
+
+    let mut emulator = Emulator::new().expect("emulator");
+    let emu_mod = emulator.add_module(module).expect("module addition");
+    let stack = emulator.read_register(testing::RSP).unwrap();
+    emu_mod.borrow_mut().link_stack_limit(stack - 64 * 1024);
+
+    emulator.write_register(testing::RDI, 100).unwrap();
+    emulator
+        .call_function(emu_mod.clone(), "recurse")
+        .expect("call");
+    assert_eq!(emulator.read_register(testing::RAX).unwrap(), 100);
+
+    emulator.write_register(testing::RSP, stack).unwrap();
+    emulator.write_register(testing::RDI, 100_000).unwrap();
+    assert!(emulator.call_function(emu_mod.clone(), "recurse").is_err());
+    assert_eq!(
+        last_trap(&emulator, &emu_mod),
+        Some(Trap {
+            code: TrapCode::StackOverflow,
+            function_index: Some(0),
+            wasm_offset: binary
+                .windows(3)
+                .position(|bytes| bytes == [0x20, 0x00, 0x50])
+                .unwrap()
+                - 1,
+        })
+    );
+}
+
+/// Types of the operands pushed before each instruction, and the
+/// instructions, covering every operator outside of control flow
+const OPERATORS: &[(&str, &[&str])] = &[
+    (
+        "",
+        &[
+            "unreachable",
+            "nop",
+            "br 0",
+            "return",
+            "call $f",
+            "return_call $f",
+            "local.get 0",
+            "global.get $gi",
+            "memory.size",
+            "data.drop $d",
+            "elem.drop $ef",
+            "table.size $tf",
+            "ref.null func",
+            "ref.func $f",
+            "i32.const 0",
+            "i64.const 0",
+            "f32.const 0",
+            "f64.const 0",
+            "atomic.fence",
+            "v128.const i64x2 0 0",
+            "throw $e",
+        ],
+    ),
+    (
+        "f32",
+        &[
+            "f32.abs",
+            "f32.neg",
+            "f32.ceil",
+            "f32.floor",
+            "f32.trunc",
+            "f32.nearest",
+            "f32.sqrt",
+            "i32.trunc_f32_s",
+            "i32.trunc_f32_u",
+            "i64.trunc_f32_s",
+            "i64.trunc_f32_u",
+            "f64.promote_f32",
+            "i32.reinterpret_f32",
+            "i32.trunc_sat_f32_s",
+            "i32.trunc_sat_f32_u",
+            "i64.trunc_sat_f32_s",
+            "i64.trunc_sat_f32_u",
+            "f32x4.splat",
+        ],
+    ),
+    (
+        "f64",
+        &[
+            "f64.abs",
+            "f64.neg",
+            "f64.ceil",
+            "f64.floor",
+            "f64.trunc",
+            "f64.nearest",
+            "f64.sqrt",
+            "i32.trunc_f64_s",
+            "i32.trunc_f64_u",
+            "i64.trunc_f64_s",
+            "i64.trunc_f64_u",
+            "f32.demote_f64",
+            "i64.reinterpret_f64",
+            "i32.trunc_sat_f64_s",
+            "i32.trunc_sat_f64_u",
+            "i64.trunc_sat_f64_s",
+            "i64.trunc_sat_f64_u",
+            "f64x2.splat",
+        ],
+    ),
+    ("funcref", &["ref.is_null"]),
+    (
+        "i32",
+        &[
+            "br_if 0",
+            "br_table 0",
+            "call_indirect (type $t)",
+            "return_call_indirect (type $t)",
+            "drop",
+            "local.set 0",
+            "local.tee 0",
+            "global.set $gi",
+            "table.get $tf",
+            "i32.load",
+            "i64.load",
+            "f32.load",
+            "f64.load",
+            "i32.load8_s",
+            "i32.load8_u",
+            "i32.load16_s",
+            "i32.load16_u",
+            "i64.load8_s",
+            "i64.load8_u",
+            "i64.load16_s",
+            "i64.load16_u",
+            "i64.load32_s",
+            "i64.load32_u",
+            "memory.grow",
+            "i32.clz",
+            "i32.ctz",
+            "i32.popcnt",
+            "i32.eqz",
+            "i64.extend_i32_s",
+            "i64.extend_i32_u",
+            "f32.convert_i32_s",
+            "f32.convert_i32_u",
+            "f64.convert_i32_s",
+            "f64.convert_i32_u",
+            "f32.reinterpret_i32",
+            "i32.extend8_s",
+            "i32.extend16_s",
+            "i32.atomic.load",
+            "i64.atomic.load",
+            "i32.atomic.load8_u",
+            "i32.atomic.load16_u",
+            "i64.atomic.load8_u",
+            "i64.atomic.load16_u",
+            "i64.atomic.load32_u",
+            "v128.load",
+            "v128.load8x8_s",
+            "v128.load8x8_u",
+            "v128.load16x4_s",
+            "v128.load16x4_u",
+            "v128.load32x2_s",
+            "v128.load32x2_u",
+            "v128.load8_splat",
+            "v128.load16_splat",
+            "v128.load32_splat",
+            "v128.load64_splat",
+            "v128.load32_zero",
+            "v128.load64_zero",
+            "i8x16.splat",
+            "i16x8.splat",
+            "i32x4.splat",
+        ],
+    ),
+    (
+        "i64",
+        &[
+            "i64.clz",
+            "i64.ctz",
+            "i64.popcnt",
+            "i64.eqz",
+            "i32.wrap_i64",
+            "f32.convert_i64_s",
+            "f32.convert_i64_u",
+            "f64.convert_i64_s",
+            "f64.convert_i64_u",
+            "f64.reinterpret_i64",
+            "i64.extend8_s",
+            "i64.extend16_s",
+            "i64.extend32_s",
+            "i64x2.splat",
+        ],
+    ),
+    (
+        "v128",
+        &[
+            "i8x16.extract_lane_s 0",
+            "i8x16.extract_lane_u 0",
+            "i16x8.extract_lane_s 0",
+            "i16x8.extract_lane_u 0",
+            "i32x4.extract_lane 0",
+            "i64x2.extract_lane 0",
+            "f32x4.extract_lane 0",
+            "f64x2.extract_lane 0",
+            "v128.not",
+            "v128.any_true",
+            "i8x16.abs",
+            "i8x16.neg",
+            "i8x16.popcnt",
+            "i8x16.all_true",
+            "i8x16.bitmask",
+            "i16x8.extadd_pairwise_i8x16_s",
+            "i16x8.extadd_pairwise_i8x16_u",
+            "i16x8.abs",
+            "i16x8.neg",
+            "i16x8.all_true",
+            "i16x8.bitmask",
+            "i16x8.extend_low_i8x16_s",
+            "i16x8.extend_high_i8x16_s",
+            "i16x8.extend_low_i8x16_u",
+            "i16x8.extend_high_i8x16_u",
+            "i32x4.extadd_pairwise_i16x8_s",
+            "i32x4.extadd_pairwise_i16x8_u",
+            "i32x4.abs",
+            "i32x4.neg",
+            "i32x4.all_true",
+            "i32x4.bitmask",
+            "i32x4.extend_low_i16x8_s",
+            "i32x4.extend_high_i16x8_s",
+            "i32x4.extend_low_i16x8_u",
+            "i32x4.extend_high_i16x8_u",
+            "i64x2.abs",
+            "i64x2.neg",
+            "i64x2.all_true",
+            "i64x2.bitmask",
+            "i64x2.extend_low_i32x4_s",
+            "i64x2.extend_high_i32x4_s",
+            "i64x2.extend_low_i32x4_u",
+            "i64x2.extend_high_i32x4_u",
+            "f32x4.ceil",
+            "f32x4.floor",
+            "f32x4.trunc",
+            "f32x4.nearest",
+            "f32x4.abs",
+            "f32x4.neg",
+            "f32x4.sqrt",
+            "f64x2.ceil",
+            "f64x2.floor",
+            "f64x2.trunc",
+            "f64x2.nearest",
+            "f64x2.abs",
+            "f64x2.neg",
+            "f64x2.sqrt",
+            "i32x4.trunc_sat_f32x4_s",
+            "i32x4.trunc_sat_f32x4_u",
+            "f32x4.convert_i32x4_s",
+            "f32x4.convert_i32x4_u",
+            "i32x4.trunc_sat_f64x2_s_zero",
+            "i32x4.trunc_sat_f64x2_u_zero",
+            "f64x2.convert_low_i32x4_s",
+            "f64x2.convert_low_i32x4_u",
+            "f32x4.demote_f64x2_zero",
+            "f64x2.promote_low_f32x4",
+            "i32x4.trunc_f32x4_s_relaxed",
+            "i32x4.trunc_f32x4_u_relaxed",
+            "i32x4.trunc_f64x2_s_zero_relaxed",
+            "i32x4.trunc_f64x2_u_zero_relaxed",
+        ],
+    ),
+    (
+        "f32 f32",
+        &[
+            "f32.add",
+            "f32.sub",
+            "f32.mul",
+            "f32.div",
+            "f32.min",
+            "f32.max",
+            "f32.copysign",
+            "f32.eq",
+            "f32.ne",
+            "f32.lt",
+            "f32.gt",
+            "f32.le",
+            "f32.ge",
+        ],
+    ),
+    (
+        "f64 f64",
+        &[
+            "f64.add",
+            "f64.sub",
+            "f64.mul",
+            "f64.div",
+            "f64.min",
+            "f64.max",
+            "f64.copysign",
+            "f64.eq",
+            "f64.ne",
+            "f64.lt",
+            "f64.gt",
+            "f64.le",
+            "f64.ge",
+        ],
+    ),
+    ("funcref i32", &["table.grow $tf"]),
+    ("i32 f32", &["f32.store"]),
+    ("i32 f64", &["f64.store"]),
+    ("i32 funcref", &["table.set $tf"]),
+    (
+        "i32 i32",
+        &[
+            "i32.store",
+            "i32.store8",
+            "i32.store16",
+            "i32.add",
+            "i32.sub",
+            "i32.mul",
+            "i32.div_s",
+            "i32.div_u",
+            "i32.rem_s",
+            "i32.rem_u",
+            "i32.and",
+            "i32.or",
+            "i32.xor",
+            "i32.shl",
+            "i32.shr_s",
+            "i32.shr_u",
+            "i32.rotl",
+            "i32.rotr",
+            "i32.eq",
+            "i32.ne",
+            "i32.lt_s",
+            "i32.lt_u",
+            "i32.gt_s",
+            "i32.gt_u",
+            "i32.le_s",
+            "i32.le_u",
+            "i32.ge_s",
+            "i32.ge_u",
+            "memory.atomic.notify",
+            "i32.atomic.store",
+            "i32.atomic.store8",
+            "i32.atomic.store16",
+            "i32.atomic.rmw.add",
+            "i32.atomic.rmw8.add_u",
+            "i32.atomic.rmw16.add_u",
+            "i32.atomic.rmw.sub",
+            "i32.atomic.rmw8.sub_u",
+            "i32.atomic.rmw16.sub_u",
+            "i32.atomic.rmw.and",
+            "i32.atomic.rmw8.and_u",
+            "i32.atomic.rmw16.and_u",
+            "i32.atomic.rmw.or",
+            "i32.atomic.rmw8.or_u",
+            "i32.atomic.rmw16.or_u",
+            "i32.atomic.rmw.xor",
+            "i32.atomic.rmw8.xor_u",
+            "i32.atomic.rmw16.xor_u",
+            "i32.atomic.rmw.xchg",
+            "i32.atomic.rmw8.xchg_u",
+            "i32.atomic.rmw16.xchg_u",
+        ],
+    ),
+    (
+        "i32 i64",
+        &[
+            "i64.store",
+            "i64.store8",
+            "i64.store16",
+            "i64.store32",
+            "i64.atomic.store",
+            "i64.atomic.store8",
+            "i64.atomic.store16",
+            "i64.atomic.store32",
+            "i64.atomic.rmw.add",
+            "i64.atomic.rmw8.add_u",
+            "i64.atomic.rmw16.add_u",
+            "i64.atomic.rmw32.add_u",
+            "i64.atomic.rmw.sub",
+            "i64.atomic.rmw8.sub_u",
+            "i64.atomic.rmw16.sub_u",
+            "i64.atomic.rmw32.sub_u",
+            "i64.atomic.rmw.and",
+            "i64.atomic.rmw8.and_u",
+            "i64.atomic.rmw16.and_u",
+            "i64.atomic.rmw32.and_u",
+            "i64.atomic.rmw.or",
+            "i64.atomic.rmw8.or_u",
+            "i64.atomic.rmw16.or_u",
+            "i64.atomic.rmw32.or_u",
+            "i64.atomic.rmw.xor",
+            "i64.atomic.rmw8.xor_u",
+            "i64.atomic.rmw16.xor_u",
+            "i64.atomic.rmw32.xor_u",
+            "i64.atomic.rmw.xchg",
+            "i64.atomic.rmw8.xchg_u",
+            "i64.atomic.rmw16.xchg_u",
+            "i64.atomic.rmw32.xchg_u",
+        ],
+    ),
+    (
+        "i32 v128",
+        &[
+            "v128.store",
+            "v128.load8_lane 0",
+            "v128.load16_lane 0",
+            "v128.load32_lane 0",
+            "v128.load64_lane 0",
+            "v128.store8_lane 0",
+            "v128.store16_lane 0",
+            "v128.store32_lane 0",
+            "v128.store64_lane 0",
+        ],
+    ),
+    (
+        "i64 i64",
+        &[
+            "i64.add",
+            "i64.sub",
+            "i64.mul",
+            "i64.div_s",
+            "i64.div_u",
+            "i64.rem_s",
+            "i64.rem_u",
+            "i64.and",
+            "i64.or",
+            "i64.xor",
+            "i64.shl",
+            "i64.shr_s",
+            "i64.shr_u",
+            "i64.rotl",
+            "i64.rotr",
+            "i64.eq",
+            "i64.ne",
+            "i64.lt_s",
+            "i64.lt_u",
+            "i64.gt_s",
+            "i64.gt_u",
+            "i64.le_s",
+            "i64.le_u",
+            "i64.ge_s",
+            "i64.ge_u",
+        ],
+    ),
+    ("v128 f32", &["f32x4.replace_lane 0"]),
+    ("v128 f64", &["f64x2.replace_lane 0"]),
+    (
+        "v128 i32",
+        &[
+            "i8x16.replace_lane 0",
+            "i16x8.replace_lane 0",
+            "i32x4.replace_lane 0",
+            "i8x16.shl",
+            "i8x16.shr_s",
+            "i8x16.shr_u",
+            "i16x8.shl",
+            "i16x8.shr_s",
+            "i16x8.shr_u",
+            "i32x4.shl",
+            "i32x4.shr_s",
+            "i32x4.shr_u",
+            "i64x2.shl",
+            "i64x2.shr_s",
+            "i64x2.shr_u",
+        ],
+    ),
+    ("v128 i64", &["i64x2.replace_lane 0"]),
+    (
+        "v128 v128",
+        &[
+            "i8x16.shuffle 0 1 2 3 4 5 6 7 8 9 10 11 12 13 14 15",
+            "i8x16.swizzle",
+            "i8x16.eq",
+            "i8x16.ne",
+            "i8x16.lt_s",
+            "i8x16.lt_u",
+            "i8x16.gt_s",
+            "i8x16.gt_u",
+            "i8x16.le_s",
+            "i8x16.le_u",
+            "i8x16.ge_s",
+            "i8x16.ge_u",
+            "i16x8.eq",
+            "i16x8.ne",
+            "i16x8.lt_s",
+            "i16x8.lt_u",
+            "i16x8.gt_s",
+            "i16x8.gt_u",
+            "i16x8.le_s",
+            "i16x8.le_u",
+            "i16x8.ge_s",
+            "i16x8.ge_u",
+            "i32x4.eq",
+            "i32x4.ne",
+            "i32x4.lt_s",
+            "i32x4.lt_u",
+            "i32x4.gt_s",
+            "i32x4.gt_u",
+            "i32x4.le_s",
+            "i32x4.le_u",
+            "i32x4.ge_s",
+            "i32x4.ge_u",
+            "i64x2.eq",
+            "i64x2.ne",
+            "i64x2.lt_s",
+            "i64x2.gt_s",
+            "i64x2.le_s",
+            "i64x2.ge_s",
+            "f32x4.eq",
+            "f32x4.ne",
+            "f32x4.lt",
+            "f32x4.gt",
+            "f32x4.le",
+            "f32x4.ge",
+            "f64x2.eq",
+            "f64x2.ne",
+            "f64x2.lt",
+            "f64x2.gt",
+            "f64x2.le",
+            "f64x2.ge",
+            "v128.and",
+            "v128.andnot",
+            "v128.or",
+            "v128.xor",
+            "i8x16.narrow_i16x8_s",
+            "i8x16.narrow_i16x8_u",
+            "i8x16.add",
+            "i8x16.add_sat_s",
+            "i8x16.add_sat_u",
+            "i8x16.sub",
+            "i8x16.sub_sat_s",
+            "i8x16.sub_sat_u",
+            "i8x16.min_s",
+            "i8x16.min_u",
+            "i8x16.max_s",
+            "i8x16.max_u",
+            "i8x16.avgr_u",
+            "i16x8.q15mulr_sat_s",
+            "i16x8.narrow_i32x4_s",
+            "i16x8.narrow_i32x4_u",
+            "i16x8.add",
+            "i16x8.add_sat_s",
+            "i16x8.add_sat_u",
+            "i16x8.sub",
+            "i16x8.sub_sat_s",
+            "i16x8.sub_sat_u",
+            "i16x8.mul",
+            "i16x8.min_s",
+            "i16x8.min_u",
+            "i16x8.max_s",
+            "i16x8.max_u",
+            "i16x8.avgr_u",
+            "i16x8.extmul_low_i8x16_s",
+            "i16x8.extmul_high_i8x16_s",
+            "i16x8.extmul_low_i8x16_u",
+            "i16x8.extmul_high_i8x16_u",
+            "i32x4.add",
+            "i32x4.sub",
+            "i32x4.mul",
+            "i32x4.min_s",
+            "i32x4.min_u",
+            "i32x4.max_s",
+            "i32x4.max_u",
+            "i32x4.dot_i16x8_s",
+            "i32x4.extmul_low_i16x8_s",
+            "i32x4.extmul_high_i16x8_s",
+            "i32x4.extmul_low_i16x8_u",
+            "i32x4.extmul_high_i16x8_u",
+            "i64x2.add",
+            "i64x2.sub",
+            "i64x2.mul",
+            "i64x2.extmul_low_i32x4_s",
+            "i64x2.extmul_high_i32x4_s",
+            "i64x2.extmul_low_i32x4_u",
+            "i64x2.extmul_high_i32x4_u",
+            "f32x4.add",
+            "f32x4.sub",
+            "f32x4.mul",
+            "f32x4.div",
+            "f32x4.min",
+            "f32x4.max",
+            "f32x4.pmin",
+            "f32x4.pmax",
+            "f64x2.add",
+            "f64x2.sub",
+            "f64x2.mul",
+            "f64x2.div",
+            "f64x2.min",
+            "f64x2.max",
+            "f64x2.pmin",
+            "f64x2.pmax",
+            "i8x16.swizzle_relaxed",
+            "f32x4.min_relaxed",
+            "f32x4.max_relaxed",
+            "f64x2.min_relaxed",
+            "f64x2.max_relaxed",
+        ],
+    ),
+    ("i32 funcref i32", &["table.fill $tf"]),
+    (
+        "i32 i32 i32",
+        &[
+            "memory.init $d",
+            "memory.copy",
+            "memory.fill",
+            "table.init $ef",
+            "table.copy",
+            "i32.atomic.rmw.cmpxchg",
+            "i32.atomic.rmw8.cmpxchg_u",
+            "i32.atomic.rmw16.cmpxchg_u",
+        ],
+    ),
+    ("i32 i32 i64", &["memory.atomic.wait32"]),
+    (
+        "i32 i64 i64",
+        &[
+            "memory.atomic.wait64",
+            "i64.atomic.rmw.cmpxchg",
+            "i64.atomic.rmw8.cmpxchg_u",
+            "i64.atomic.rmw16.cmpxchg_u",
+            "i64.atomic.rmw32.cmpxchg_u",
+        ],
+    ),
+    (
+        "v128 v128 v128",
+        &[
+            "v128.bitselect",
+            "f32x4.fma_relaxed",
+            "f32x4.fms_relaxed",
+            "f64x2.fma_relaxed",
+            "f64x2.fms_relaxed",
+            "i8x16.laneselect",
+            "i16x8.laneselect",
+            "i32x4.laneselect",
+            "i64x2.laneselect",
+        ],
+    ),
+];
+
+const CONTROL: &[&str] = &[
+    "block (result i32) i32.const 0 end drop",
+    "loop br 0 end",
+    "i32.const 0 if (result i32) i32.const 1 else i32.const 2 end drop",
+    "i32.const 1 i32.const 2 i32.const 0 select drop",
+    "ref.null func ref.null func i32.const 0 select (result funcref) drop",
+    "try catch $e catch_all end",
+    "try catch_all rethrow 0 end",
+    "try try delegate 0 catch_all end",
+];
+
+#[test]
+fn every_operator() {
+    let functions = OPERATORS
+        .iter()
+        .flat_map(|(operands, instructions)| {
+            let operands = operands
+                .split_whitespace()
+                .map(|ty| match ty {
+                    "v128" => "v128.const i64x2 0 0".to_string(),
+                    "funcref" => "ref.null func".to_string(),
+                    ty => format!("{}.const 0", ty),
+                })
+                .collect::<Vec<_>>()
+                .join(" ");
+            instructions
+                .iter()
+                .map(move |instruction| format!("{} {}", operands, instruction))
+        })
+        .chain(CONTROL.iter().map(|body| body.to_string()));
+    let compiler = X86_64Compiler::default();
+    for body in functions {
+        let binary = wat::parse_str(format!(
+            r#"
+    (module
+      (type $t (func))
+      (memory 1 1 shared)
+      (table $tf 1 funcref)
+      (table $te 1 externref)
+      (global $gi (mut i32) (i32.const 0))
+      (tag $e)
+      (data $d "")
+      (elem $ef func $f)
+      (func $f)
+      (func (local i32) {} unreachable)
+    )
+    "#,
+            body
+        ))
+        .expect("binary module");
+        match compiler.compile(&binary) {
+            Ok(_) | Err(Error::Unsupported { .. }) => {}
+            Err(error) => panic!("{}: {:?}", body, error),
+        }
+    }
+}
+
+#[test]
+fn cpu_features() {
+    let src = r#"
+    (module
+      (func (export "foo") (param i64) (param f64) (result i64)
+        local.get 0
+        i64.clz
+        local.get 0
+        i64.ctz
+        i64.shl
+        i64.popcnt
+        local.get 1
+        f64.floor
+        i64.trunc_f64_s
+        i64.add
+      )
+    )
+    "#;
+    let binary = wat::parse_str(src).expect("binary module");
+    let mnemonics = |cpu: CpuFeatures| {
+        let module = X86_64Compiler::builder()
+            .cpu_features(cpu)
+            .build()
+            .compile(&binary)
+            .expect("compiled module");
+        let entry_point = module.function_entry_point("foo").unwrap();
+        let mut decoder = iced_x86::Decoder::new(64, &module.binary()[entry_point..], 0);
+        let mut mnemonics = Vec::new();
+        while decoder.can_decode() {
+            let instruction = decoder.decode();
+            mnemonics.push(instruction.mnemonic());
+            if instruction.mnemonic() == iced_x86::Mnemonic::Ret {
+                break;
+            }
+        }
+        mnemonics
+    };
+    let extensions = [
+        iced_x86::Mnemonic::Lzcnt,
+        iced_x86::Mnemonic::Tzcnt,
+        iced_x86::Mnemonic::Shlx,
+        iced_x86::Mnemonic::Popcnt,
+        iced_x86::Mnemonic::Roundsd,
+    ];
+    let baseline = mnemonics(CpuFeatures::baseline());
+    let v3 = mnemonics(CpuFeatures::x86_64_v3());
+    for mnemonic in extensions {
+        assert!(!baseline.contains(&mnemonic), "{:?}", mnemonic);
+        assert!(v3.contains(&mnemonic), "{:?}", mnemonic);
+    }
+    assert!(v3.len() < baseline.len());
+
+    // Detection reads the features of the host, which is x86-64 anyway
+    mnemonics(CpuFeatures::detect());
+}
+
+#[test]
+fn stack_arguments_and_return_area() {
+    let src = r#"
+    (module
+      (import "env" "host"
+        (func $host (param i64 i64 i64 i64 i64 i64 i64 f64 i64) (result i64 f64 i64)))
+      (func $many (export "many")
+        (param i64 i64 i64 i64 i64 i64 i64 i64 f64 f64 f64 f64 f64 f64 f64 f64 f64 f64)
+        (result i64 f64 i64 i64)
+        local.get 0
+        local.get 7
+        i64.sub
+        local.get 16
+        local.get 8
+        f64.sub
+        local.get 5
+        local.get 17
+        i64.trunc_f64_s
+      )
+      (func $three (result i64 i64 i64)
+        i64.const 1
+        i64.const 2
+        i64.const 3
+      )
+      (func (export "local") (result i64) (local i64 f64 i64 i64)
+        i64.const 5
+        i64.const 1
+        i64.const 2
+        i64.const 3
+        i64.const 4
+        i64.const 5
+        i64.const 6
+        i64.const 7
+        i64.const 8
+        f64.const 1
+        f64.const 2
+        f64.const 3
+        f64.const 4
+        f64.const 5
+        f64.const 6
+        f64.const 7
+        f64.const 8
+        f64.const 9
+        f64.const 10
+        call $many
+        local.set 3
+        local.set 2
+        local.set 1
+        local.set 0
+        local.get 0
+        i64.add
+        local.get 1
+        i64.trunc_f64_s
+        i64.const 100
+        i64.mul
+        i64.add
+        local.get 2
+        i64.const 10000
+        i64.mul
+        i64.add
+        local.get 3
+        i64.const 1000000
+        i64.mul
+        i64.add
+        call $three
+        i64.sub
+        i64.sub
+        i64.add
+      )
+      (func (export "host") (result i64) (local i64 f64)
+        i64.const 1
+        i64.const 2
+        i64.const 3
+        i64.const 4
+        i64.const 5
+        i64.const 6
+        i64.const 7
+        f64.const 2.5
+        i64.const 100
+        call $host
+        i64.const 100
+        i64.mul
+        local.set 0
+        f64.const 2
+        f64.mul
+        i64.trunc_f64_s
+        i64.const 10000
+        i64.mul
+        local.get 0
+        i64.add
+        i64.add
+      )
+    )
+    "#;
+    let binary = wat::parse_str(src).expect("binary module");
+    let module = X86_64Compiler::default()
+        .compile(&binary)
+        .expect("compiled module");
+
+    let mut emulator = Emulator::new().expect("emulator");
+    // The host function checks the stack is aligned, takes its results
+    // from the 6th, 8th and 9th parameters, and returns them in memory
+    let mut assembler = CodeAssembler::new(64).expect("new assembler");
+    use iced_x86::code_asm::*;
+    let mut aligned = assembler.create_label();
+    assembler.lea(rax, ptr(rsp + 8)).expect("asm");
+    assembler.test(al, 15).expect("asm");
+    assembler.jz(aligned).expect("asm");
+    assembler.ud2().expect("asm");
+    assembler.set_label(&mut aligned).expect("asm");
+    assembler.mov(rax, qword_ptr(rsp + 8)).expect("asm");
+    assembler.mov(qword_ptr(rdi), rax).expect("asm");
+    assembler.movq(qword_ptr(rdi + 8), xmm0).expect("asm");
+    assembler.mov(rax, qword_ptr(rsp + 24)).expect("asm");
+    assembler.sub(rax, rsi).expect("asm");
+    assembler.mov(qword_ptr(rdi + 16), rax).expect("asm");
+    assembler.mov(rax, rdi).expect("asm");
+    assembler.ret().expect("asm");
+    let assembled = assembler.assemble(0).expect("asm");
+    let host = emulator.add_memory(&assembled).expect("host function");
+    let return_area = emulator.add_memory(&[0; 32]).expect("return area");
+
+    let emu_mod = emulator.add_module(module).expect("module addition");
+    emu_mod.borrow_mut().link_import("env", Some("host"), host);
+    let stack = emulator.read_register(testing::RSP).unwrap() & !15;
+
+    emulator.write_register(testing::RSP, stack).unwrap();
+    emulator
+        .call_function(emu_mod.clone(), "local")
+        .expect("call");
+    assert_eq!(emulator.read_register(testing::RAX).unwrap(), 10060800);
+
+    emulator.write_register(testing::RSP, stack).unwrap();
+    emulator
+        .call_function(emu_mod.clone(), "host")
+        .expect("call");
+    assert_eq!(emulator.read_register(testing::RAX).unwrap(), 59906);
+
+    // Called from the host: the return area address goes in RDI, so the
+    // 6th integer parameter is the first one on the stack
+    emulator.write_register(testing::RSP, stack).unwrap();
+    for value in [0, 10.0f64.to_bits(), 9.0f64.to_bits(), 8, 7, 6] {
+        emulator.push(value).unwrap();
+    }
+    emulator.write_register(testing::RDI, return_area).unwrap();
+    for (register, value) in [
+        testing::RSI,
+        testing::RDX,
+        testing::RCX,
+        testing::R8,
+        testing::R9,
+    ]
+    .into_iter()
+    .zip(1..)
+    {
+        emulator.write_register(register, value).unwrap();
+    }
+    for (register, value) in [
+        testing::XMM0,
+        testing::XMM1,
+        testing::XMM2,
+        testing::XMM3,
+        testing::XMM4,
+        testing::XMM5,
+        testing::XMM6,
+        testing::XMM7,
+    ]
+    .into_iter()
+    .zip(1..)
+    {
+        emulator
+            .write_xmm(register, (value as f64).to_bits())
+            .unwrap();
+    }
+    emulator
+        .call_function(emu_mod.clone(), "many")
+        .expect("call");
+    assert_eq!(emulator.read_register(testing::RAX).unwrap(), return_area);
+    let mut results = [0; 32];
+    emulator.read_memory(return_area, &mut results).unwrap();
+    let results: Vec<u64> = results
+        .chunks(8)
+        .map(|bytes| u64::from_le_bytes(bytes.try_into().unwrap()))
+        .collect();
+    assert_eq!(results, [-7i64 as u64, 8.0f64.to_bits(), 6, 10]);
+}
+
+#[test]
+fn multi_value() {
+    let src = r#"
+    (module
+      (type $pair (func (param i64 i64) (result i64 i64)))
+      (import "env" "swap" (func $swap (param f64 i64) (result i64 f64)))
+      (func $mixed (export "mixed") (param i64) (result i32 f64 i64)
+        local.get 0
+        i32.wrap_i64
+        local.get 0
+        f64.convert_i64_s
+        f64.const 0.5
+        f64.add
+        local.get 0
+        i64.const 1
+        i64.add
+      )
+      (func (export "calls") (result i64) (local i64)
+        i64.const 5
+        call $mixed
+        call $swap
+        f64.const 2
+        f64.mul
+        i64.trunc_f64_s
+        i64.const 100
+        i64.mul
+        i64.add
+        local.set 0
+        i64.extend_i32_u
+        i64.const 10000
+        i64.mul
+        local.get 0
+        i64.add
+      )
+      (func (export "blocks") (param i32) (result i64) (local i64)
+        i64.const 1000
+        i32.const 7
+        f64.const 2.5
+        block $b (param i32 f64) (result f64 i64 i32)
+          f64.const 4
+          i64.const 11
+          i32.const 13
+          local.get 0
+          br_if $b
+          drop
+          drop
+          drop
+          drop
+          drop
+          f64.const 1
+          i64.const 2
+          i32.const 3
+        end
+        i64.extend_i32_u
+        local.set 1
+        i64.const 100
+        i64.mul
+        local.get 1
+        i64.add
+        local.set 1
+        i64.trunc_f64_s
+        i64.const 10000
+        i64.mul
+        local.get 1
+        i64.add
+        i64.add
+      )
+      (func (export "sum") (param i64) (result i64) (local i64)
+        i64.const 0
+        local.get 0
+        loop $l (param i64 i64) (result i64)
+          local.tee 1
+          i64.eqz
+          if (param i64) (result i64)
+          else
+            local.get 1
+            i64.add
+            local.get 1
+            i64.const 1
+            i64.sub
+            br $l
+          end
+        end
+      )
+      (func (export "cond") (param i32) (result i64)
+        i64.const 3
+        i64.const 4
+        local.get 0
+        if (type $pair)
+          i64.const 10
+          i64.mul
+        else
+          i64.add
+          i64.const 100
+        end
+        i64.sub
+      )
+    )
+    "#;
+    let binary = wat::parse_str(src).expect("binary module");
+    let module = X86_64Compiler::default()
+        .compile(&binary)
+        .expect("compiled module");
+
+    let mut emulator = Emulator::new().expect("emulator");
+    let mut assembler = CodeAssembler::new(64).expect("new assembler");
+    use iced_x86::code_asm::*;
+    assembler.lea(rax, ptr(rdi + rdi)).expect("asm");
+    assembler.ret().expect("asm");
+    let assembled = assembler.assemble(0).expect("asm");
+    let swap = emulator.add_memory(&assembled).expect("swap function");
+    let return_area = emulator.add_memory(&[0; 24]).expect("return area");
+
+    let emu_mod = emulator.add_module(module).expect("module addition");
+    emu_mod.borrow_mut().link_import("env", Some("swap"), swap);
+    let mut call = |name: &str, param: u64| {
+        emulator.write_register(testing::RDI, param).unwrap();
+        emulator.call_function(emu_mod.clone(), name).expect("call");
+        emulator.read_register(testing::RAX).unwrap() as i64
+    };
+    assert_eq!(call("calls", 0), 51112);
+    assert_eq!(call("blocks", 1), 42113);
+    assert_eq!(call("blocks", 0), 11203);
+    assert_eq!(call("sum", 10), 55);
+    assert_eq!(call("cond", 1), -37);
+    assert_eq!(call("cond", 0), -93);
+
+    emulator.write_register(testing::RDI, return_area).unwrap();
+    emulator.write_register(testing::RSI, 5).unwrap();
+    emulator
+        .call_function(emu_mod.clone(), "mixed")
+        .expect("call");
+    let mut results = [0; 24];
+    emulator.read_memory(return_area, &mut results).unwrap();
+    assert_eq!(u32::from_le_bytes(results[0..4].try_into().unwrap()), 5);
+    assert_eq!(f64::from_le_bytes(results[8..16].try_into().unwrap()), 5.5);
+    assert_eq!(u64::from_le_bytes(results[16..24].try_into().unwrap()), 6);
+}
+
+#[test]
+fn tail_calls() {
+    let src = r#"
+    (module
+      (type $state (func (param i64 i64) (result i64)))
+      (table 2 funcref)
+      (elem (i32.const 0) $even $odd)
+      (func $count (export "count") (param i64 i64) (result i64)
+        local.get 0
+        i64.eqz
+        if (result i64)
+          local.get 1
+        else
+          local.get 0
+          i64.const 1
+          i64.sub
+          local.get 1
+          i64.const 2
+          i64.add
+          return_call $count
+        end
+      )
+      (func $even (param i64 i64) (result i64)
+        local.get 0
+        i64.eqz
+        if (result i64)
+          i64.const 1
+        else
+          local.get 0
+          i64.const 1
+          i64.sub
+          local.get 1
+          i32.const 1
+          return_call_indirect (type $state)
+        end
+      )
+      (func $odd (param i64 i64) (result i64)
+        local.get 0
+        i64.eqz
+        if (result i64)
+          i64.const 0
+        else
+          local.get 0
+          i64.const 1
+          i64.sub
+          local.get 1
+          i32.const 0
+          return_call_indirect (type $state)
+        end
+      )
+      (func (export "is_even") (param i64) (result i64)
+        local.get 0
+        i64.const 0
+        return_call $even
+      )
+      (func $rotate (param i64 i64 i64 i64 i64 i64 i64 i64 i64) (result i64)
+        local.get 0
+        i64.eqz
+        if (result i64)
+          local.get 6
+          i64.const 10
+          i64.mul
+          local.get 8
+          i64.add
+        else
+          local.get 0
+          i64.const 1
+          i64.sub
+          local.get 2
+          local.get 3
+          local.get 4
+          local.get 5
+          local.get 6
+          local.get 7
+          local.get 8
+          local.get 1
+          return_call $rotate
+        end
+      )
+      (func (export "rotate") (param i64) (result i64)
+        local.get 0
+        i64.const 1
+        i64.const 2
+        i64.const 3
+        i64.const 4
+        i64.const 5
+        i64.const 6
+        i64.const 7
+        i64.const 8
+        return_call $rotate
+      )
+    )
+    "#;
+    let binary = wat::parse_str(src).expect("binary module");
+    let module = X86_64Compiler::default()
+        .compile(&binary)
+        .expect("compiled module");
+
+    let mut emulator = Emulator::new().expect("emulator");
+    let emu_mod = emulator.add_module(module).expect("module addition");
+    // A million frames would take way more
+    let stack = emulator.read_register(testing::RSP).unwrap() & !15;
+    emu_mod.borrow_mut().link_stack_limit(stack - 4096);
+    emulator.initialize(emu_mod.clone()).expect("initializer");
+
+    let mut call = |name: &str, params: &[u64]| {
+        emulator.write_register(testing::RSP, stack).unwrap();
+        for (register, value) in [testing::RDI, testing::RSI].into_iter().zip(params) {
+            emulator.write_register(register, *value).unwrap();
+        }
+        emulator.call_function(emu_mod.clone(), name).expect("call");
+        emulator.read_register(testing::RAX).unwrap()
+    };
+    assert_eq!(call("count", &[1_000_000, 0]), 2_000_000);
+    assert_eq!(call("is_even", &[1_000_000]), 1);
+    assert_eq!(call("is_even", &[999_999]), 0);
+    for levels in [0, 1, 5, 1_000_000] {
+        let mut params = [1, 2, 3, 4, 5, 6, 7, 8];
+        params.rotate_left(levels % 8);
+        assert_eq!(call("rotate", &[levels as u64]), params[5] * 10 + params[7]);
+    }
+}
+
+#[test]
+fn tail_calls_with_more_stack_arguments() {
+    let src = r#"
+    (module
+      (func $short (param i64 i64 i64 i64 i64 i64 i64) (result i64)
+        local.get 0
+        i64.eqz
+        if (result i64)
+          local.get 6
+          i64.const 10
+          i64.mul
+          local.get 5
+          i64.add
+        else
+          local.get 0
+          i64.const 1
+          i64.sub
+          local.get 1
+          local.get 2
+          local.get 3
+          local.get 4
+          local.get 5
+          i64.const 0
+          i64.const 0
+          i64.const 0
+          i64.const 0
+          i64.const 0
+          local.get 6
+          i64.const 1
+          i64.add
+          return_call $long
+        end
+      )
+      (func $long
+        (param i64 i64 i64 i64 i64 i64 i64 i64 i64 i64 i64 i64) (result i64)
+        local.get 0
+        local.get 1
+        local.get 2
+        local.get 3
+        local.get 4
+        local.get 5
+        local.get 11
+        i64.const 1
+        i64.add
+        return_call $short
+      )
+      (func (export "ping_pong") (param i64) (result i64)
+        local.get 0
+        i64.const 1
+        i64.const 2
+        i64.const 3
+        i64.const 4
+        i64.const 5
+        i64.const 0
+        return_call $short
+      )
+    )
+    "#;
+    let binary = wat::parse_str(src).expect("binary module");
+    let module = X86_64Compiler::default()
+        .compile(&binary)
+        .expect("compiled module");
+
+    let mut emulator = Emulator::new().expect("emulator");
+    let emu_mod = emulator.add_module(module).expect("module addition");
+    // Each of the million calls to $long would take a frame otherwise
+    let stack = emulator.read_register(testing::RSP).unwrap() & !15;
+    emu_mod.borrow_mut().link_stack_limit(stack - 4096);
+    emulator.initialize(emu_mod.clone()).expect("initializer");
+    // The host made no room for stack arguments, its own stack stays as is
+    let canary = [0x5A; 64];
+    emulator.write_memory(stack, &canary).unwrap();
+    for levels in [0, 1, 1_000_000] {
+        emulator.write_register(testing::RSP, stack).unwrap();
+        emulator.write_register(testing::RDI, levels).unwrap();
+        emulator
+            .call_function(emu_mod.clone(), "ping_pong")
+            .expect("call");
+        assert_eq!(
+            emulator.read_register(testing::RAX).unwrap(),
+            levels * 20 + 5
+        );
+        assert_eq!(emulator.read_register(testing::RSP).unwrap(), stack);
+        let mut contents = [0; 64];
+        emulator.read_memory(stack, &mut contents).unwrap();
+        assert_eq!(contents, canary);
+    }
+}
+
+#[test]
+fn exceptions() {
+    let thrower_src = r#"
+    (module
+      (tag $e (export "e") (param i64))
+      (func (export "throw") (param i64)
+        local.get 0
+        throw $e
+      )
+    )
+    "#;
+    let src = r#"
+    (module
+      (tag $e (import "thrower" "e") (param i64))
+      (tag $pair (param i64 f64))
+      (tag $empty)
+      (func $throw (import "thrower" "throw") (param i64))
+      (func $deep (param i64)
+        local.get 0
+        i64.eqz
+        if
+          i64.const 3
+          f64.const 2.5
+          throw $pair
+        end
+        local.get 0
+        i64.const 1
+        i64.sub
+        call $deep
+      )
+      (func (export "local") (param i64) (result i64)
+        local.get 0
+        try (param i64) (result i64)
+          throw $e
+        catch $e
+          i64.const 1
+          i64.add
+        end
+      )
+      (func (export "deep") (param i64) (result i64)
+        try (result i64)
+          local.get 0
+          call $deep
+          i64.const -1
+        catch $pair
+          i64.trunc_f64_s
+          i64.const 10
+          i64.mul
+          i64.add
+          local.get 0
+          i64.add
+        end
+      )
+      (func (export "imported") (param i64) (result i64)
+        try (result i64)
+          local.get 0
+          call $throw
+          i64.const -1
+        catch $empty
+          i64.const -2
+        catch $e
+        end
+      )
+      (func (export "rethrow") (param i64) (result i64)
+        try (result i64)
+          try
+            local.get 0
+            call $throw
+          catch_all
+            try
+              throw $empty
+            catch $empty
+            end
+            rethrow 0
+          end
+          i64.const -1
+        catch $e
+          i64.const 100
+          i64.add
+        end
+      )
+      (func (export "delegate") (param i64) (result i64)
+        try (result i64)
+          try (result i64)
+            try (result i64)
+              local.get 0
+              call $throw
+              i64.const -1
+            delegate 1
+          catch_all
+            i64.const -2
+          end
+        catch $e
+        end
+      )
+      (func $return (param i64) (result i64)
+        try (result i64)
+          local.get 0
+          return
+        catch_all
+          i64.const -1
+        end
+      )
+      (func (export "left") (param i64)
+        block
+          try
+            local.get 0
+            i32.wrap_i64
+            br_if 1
+          catch_all
+          end
+        end
+        local.get 0
+        call $return
+        throw $e
+      )
+    )
+    "#;
+    let thrower_binary = wat::parse_str(thrower_src).expect("binary module");
+    let thrower_module = X86_64Compiler::default()
+        .compile(&thrower_binary)
+        .expect("compiled module");
+    let binary = wat::parse_str(src).expect("binary module");
+    let module = X86_64Compiler::default()
+        .compile(&binary)
+        .expect("compiled module");
+
+    let mut emulator = Emulator::new().expect("emulator");
+    let thrower = emulator
+        .add_module(thrower_module)
+        .expect("module addition");
+    let emu_mod = emulator.add_module(module).expect("module addition");
+    let thrower_offset = thrower.borrow().offset();
+    let context = thrower_offset + thrower.borrow().exception_context_offset() as u64;
+    let hook = thrower_offset + thrower.borrow().exception_context_hook_offset() as u64;
+    let tag = thrower_offset + thrower.borrow().tag_offset("e").unwrap() as u64;
+    let throw = thrower_offset + thrower.borrow().function_entry_point("throw").unwrap() as u64;
+    {
+        let mut emu_mod = emu_mod.borrow_mut();
+        emu_mod.link_exception_context_hook(hook);
+        emu_mod.link_tag_import("thrower", Some("e"), tag);
+        emu_mod.link_import("thrower", Some("throw"), throw);
+    }
+    emulator.initialize(thrower.clone()).expect("initializer");
+    emulator.initialize(emu_mod.clone()).expect("initializer");
+
+    let mut call = |name: &str, param: u64| {
+        emulator.write_register(testing::RDI, param).unwrap();
+        emulator
+            .call_function(emu_mod.clone(), name)
+            .map(|_| emulator.read_register(testing::RAX).unwrap())
+            .map_err(|_| last_trap(&emulator, &emu_mod).map(|trap| trap.code))
+    };
+    assert_eq!(call("local", 41), Ok(42));
+    assert_eq!(call("deep", 100), Ok(123));
+    assert_eq!(call("imported", 42), Ok(42));
+    assert_eq!(call("rethrow", 42), Ok(142));
+    assert_eq!(call("delegate", 42), Ok(42));
+    // Leaving `try` bodies by branching or returning unlinks their handler
+    assert_eq!(call("left", 0), Err(Some(TrapCode::UncaughtException)));
+    assert_eq!(call("left", 1), Err(Some(TrapCode::UncaughtException)));
+
+    // The uncaught exception is left in the context
+    let mut exception = [0; 24];
+    emulator.read_memory(context, &mut exception).unwrap();
+    assert_eq!(&exception[..8], &0u64.to_le_bytes());
+    assert_eq!(&exception[8..16], &tag.to_le_bytes());
+    assert_eq!(&exception[16..], &1u64.to_le_bytes());
+}
+
+#[test]
+fn exceptions_through_host_frames() {
+    let src = r#"
+    (module
+      (tag $e (param i64))
+      (import "env" "host" (func $host (param i64)))
+      (func (export "reenter") (param i64)
+        local.get 0
+        throw $e
+      )
+      (func (export "catch") (param i64) (result i64)
+        try (result i64)
+          local.get 0
+          call $host
+          i64.const 0
+        catch $e
+        end
+      )
+    )
+    "#;
+    let binary = wat::parse_str(src).expect("binary module");
+    let module = X86_64Compiler::default()
+        .compile(&binary)
+        .expect("compiled module");
+
+    let mut emulator = Emulator::new().expect("emulator");
+    let context = emulator.add_memory(&[0; 80]).expect("context");
+    let mut assembler = CodeAssembler::new(64).expect("new assembler");
+    use iced_x86::code_asm::*;
+    assembler.mov(rax, context).expect("asm");
+    assembler.ret().expect("asm");
+    let assembled = assembler.assemble(0).expect("asm");
+    let hook = emulator.add_memory(&assembled).expect("hook");
+
+    let emu_mod = emulator.add_module(module).expect("module addition");
+    let reenter = emu_mod.borrow().offset()
+        + emu_mod.borrow().function_entry_point("reenter").unwrap() as u64;
+    // Calls back into the module with the callee-saved registers changed,
+    // the throw skips restoring them
+    let mut assembler = CodeAssembler::new(64).expect("new assembler");
+    assembler.push(rbx).expect("asm");
+    for register in [rbx, r12, r13, r14, r15] {
+        assembler.mov(register, -1i64).expect("asm");
+    }
+    assembler.mov(rax, reenter).expect("asm");
+    assembler.call(rax).expect("asm");
+    assembler.pop(rbx).expect("asm");
+    assembler.ret().expect("asm");
+    let assembled = assembler.assemble(0).expect("asm");
+    let host = emulator.add_memory(&assembled).expect("host function");
+    {
+        let mut emu_mod = emu_mod.borrow_mut();
+        emu_mod.link_import("env", Some("host"), host);
+        emu_mod.link_exception_context_hook(hook);
+    }
+    emulator.initialize(emu_mod.clone()).expect("initializer");
+
+    let saved = [
+        (testing::RBX, 0x1111),
+        (testing::R12, 0x1212),
+        (testing::R13, 0x1313),
+        (testing::R14, 0x1414),
+        (testing::R15, 0x1515),
+    ];
+    for (register, value) in saved {
+        emulator.write_register(register, value).unwrap();
+    }
+    emulator.write_register(testing::RDI, 42).unwrap();
+    emulator
+        .call_function(emu_mod.clone(), "catch")
+        .expect("call");
+    assert_eq!(emulator.read_register(testing::RAX).unwrap(), 42);
+    for (register, value) in saved {
+        assert_eq!(emulator.read_register(register).unwrap(), value);
+    }
+
+    // Exceptions go to the context the hook returns
+    emulator.write_register(testing::RDI, 7).unwrap();
+    assert!(emulator.call_function(emu_mod.clone(), "reenter").is_err());
+    assert_eq!(
+        last_trap(&emulator, &emu_mod).map(|trap| trap.code),
+        Some(TrapCode::UncaughtException)
+    );
+    let mut exception = [0; 24];
+    emulator.read_memory(context, &mut exception).unwrap();
+    assert_eq!(&exception[..8], &0u64.to_le_bytes());
+    assert_eq!(&exception[16..], &7u64.to_le_bytes());
+    let own = emu_mod.borrow().offset() + emu_mod.borrow().exception_context_offset() as u64;
+    emulator.read_memory(own, &mut exception).unwrap();
+    assert_eq!(exception, [0; 24]);
+}
+
+#[test]
+fn reference_types() {
+    let src = r#"
+    (module
+      (type $nullary (func (result i64)))
+      (table $t (import "env" "table") 1 10 funcref)
+      (table $e 2 externref)
+      (global $g (mut externref) (ref.null extern))
+      (global $f funcref (ref.func $forty_two))
+      (elem $p funcref (ref.func $forty_two) (ref.null func) (ref.func $seven))
+      (func $forty_two (result i64)
+        i64.const 42
+      )
+      (func $seven (result i64)
+        i64.const 7
+      )
+      (func (export "roundtrip") (param externref) (result externref)
+        i32.const 1
+        local.get 0
+        table.set $e
+        i32.const 1
+        table.get $e
+        global.set $g
+        global.get $g
+      )
+      (func (export "is_null") (param externref) (result i32)
+        local.get 0
+        ref.is_null
+      )
+      (func (export "size") (result i32)
+        table.size $t
+      )
+      (func (export "grow") (param i32) (result i32)
+        global.get $f
+        local.get 0
+        table.grow $t
+      )
+      (func (export "call") (param i32) (result i64)
+        local.get 0
+        call_indirect $t (type $nullary)
+      )
+      (func (export "get_null") (param i32) (result i32)
+        local.get 0
+        table.get $t
+        ref.is_null
+      )
+      (func (export "init") (param i32 i32 i32)
+        local.get 0
+        local.get 1
+        local.get 2
+        table.init $t $p
+      )
+      (func (export "drop")
+        elem.drop $p
+      )
+      (func (export "copy") (param i32 i32 i32)
+        local.get 0
+        local.get 1
+        local.get 2
+        table.copy $t $t
+      )
+      (func (export "fill") (param i32 i32)
+        local.get 0
+        ref.func $seven
+        local.get 1
+        table.fill $t
+      )
+    )
+    "#;
+    let binary = wat::parse_str(src).expect("binary module");
+    let module = X86_64Compiler::default()
+        .compile(&binary)
+        .expect("compiled module");
+
+    let mut emulator = Emulator::new().expect("emulator");
+    // Grows in place, the table is allocated up to its maximum
+    let mut assembler = CodeAssembler::new(64).expect("new assembler");
+    use iced_x86::code_asm::*;
+    assembler.mov(rax, qword_ptr(rdi + 8)).expect("asm");
+    assembler.add(qword_ptr(rdi + 8), rsi).expect("asm");
+    assembler.ret().expect("asm");
+    let assembled = assembler.assemble(0).expect("asm");
+    let grow_hook = emulator.add_memory(&assembled).expect("grow hook");
+    let table = emulator.add_memory(&[0; 10 * 8]).expect("table");
+
+    let emu_mod = emulator.add_module(module).expect("module addition");
+    emu_mod.borrow_mut().link_table(0, table, 1);
+    emu_mod.borrow_mut().link_table_grow(0, grow_hook);
+    emulator.initialize(emu_mod.clone()).expect("initializer");
+
+    let mut call = |name: &str, params: &[u64]| {
+        for (register, param) in [testing::RDI, testing::RSI, testing::RDX]
+            .into_iter()
+            .zip(params)
+        {
+            emulator.write_register(register, *param).unwrap();
+        }
+        emulator
+            .call_function(emu_mod.clone(), name)
+            .map(|_| emulator.read_register(testing::RAX).unwrap())
+            .map_err(|_| last_trap(&emulator, &emu_mod).map(|trap| trap.code))
+    };
+    let handle = 0x1234u64 as *const u8;
+    let reference = ExternRef::into_raw(ExternRef::new(handle));
+    let returned = ExternRef::from_raw(call("roundtrip", &[reference]).unwrap());
+    assert_eq!(returned.map(|reference| reference.get()), Some(handle));
+    assert_eq!(call("is_null", &[reference]), Ok(0));
+    assert_eq!(call("is_null", &[ExternRef::into_raw(None)]), Ok(1));
+
+    assert_eq!(call("size", &[]), Ok(1));
+    assert_eq!(call("get_null", &[0]), Ok(1));
+    assert_eq!(call("call", &[0]), Err(Some(TrapCode::IndirectCallToNull)));
+    assert_eq!(
+        call("get_null", &[1]),
+        Err(Some(TrapCode::TableOutOfBounds))
+    );
+
+    // New entries are initialized with the given reference
+    assert_eq!(call("grow", &[2]), Ok(1));
+    assert_eq!(call("size", &[]), Ok(3));
+    assert_eq!(call("call", &[2]), Ok(42));
+    assert_eq!(call("grow", &[8]).map(|size| size as i32), Ok(-1));
+    assert_eq!(call("grow", &[7]), Ok(3));
+    assert_eq!(call("size", &[]), Ok(10));
+
+    assert!(call("init", &[0, 0, 3]).is_ok());
+    assert_eq!(call("call", &[0]), Ok(42));
+    assert_eq!(call("get_null", &[1]), Ok(1));
+    assert_eq!(call("call", &[2]), Ok(7));
+    assert_eq!(
+        call("init", &[8, 0, 3]),
+        Err(Some(TrapCode::TableOutOfBounds))
+    );
+    assert_eq!(
+        call("init", &[0, 2, 2]),
+        Err(Some(TrapCode::TableOutOfBounds))
+    );
+
+    // Overlapping copies, backwards then forwards
+    assert!(call("copy", &[1, 0, 3]).is_ok());
+    assert_eq!(call("call", &[1]), Ok(42));
+    assert_eq!(call("get_null", &[2]), Ok(1));
+    assert_eq!(call("call", &[3]), Ok(7));
+    assert!(call("copy", &[0, 2, 2]).is_ok());
+    assert_eq!(call("get_null", &[0]), Ok(1));
+    assert_eq!(call("call", &[1]), Ok(7));
+    assert_eq!(
+        call("copy", &[0, 9, 2]),
+        Err(Some(TrapCode::TableOutOfBounds))
+    );
+
+    assert!(call("fill", &[5, 5]).is_ok());
+    assert_eq!(call("call", &[9]), Ok(7));
+    assert_eq!(call("fill", &[6, 5]), Err(Some(TrapCode::TableOutOfBounds)));
+
+    // Dropped segments are empty
+    assert!(call("drop", &[]).is_ok());
+    assert!(call("init", &[0, 0, 0]).is_ok());
+    assert_eq!(
+        call("init", &[0, 0, 1]),
+        Err(Some(TrapCode::TableOutOfBounds))
+    );
+}
+
+#[test]
+fn element_items_from_globals() {
+    let src = r#"
+    (module
+      (global $host (import "env" "host") externref)
+      (table $t 4 externref)
+      (elem (table $t) (i32.const 0) externref (global.get $host) (ref.null extern))
+      (elem $p externref (ref.null extern) (global.get $host))
+      (func (export "get") (param i32) (result externref)
+        (table.get $t (local.get 0))
+      )
+      (func (export "init")
+        (table.init $t $p (i32.const 2) (i32.const 0) (i32.const 2))
+      )
+    )
+    "#;
+    let binary = wat::parse_str(src).expect("binary module");
+    let module = X86_64Compiler::default()
+        .compile(&binary)
+        .expect("compiled module");
+
+    let mut emulator = Emulator::new().expect("emulator");
+    let host = emulator
+        .add_memory(&0x1234u64.to_le_bytes())
+        .expect("host global");
+    let emu_mod = emulator.add_module(module).expect("module addition");
+    emu_mod
+        .borrow_mut()
+        .link_global_import("env", Some("host"), host);
+    emulator.initialize(emu_mod.clone()).expect("initializer");
+
+    let mut call = |name: &str, param: u64| {
+        emulator.write_register(testing::RDI, param).unwrap();
+        emulator.call_function(emu_mod.clone(), name).expect("call");
+        emulator.read_register(testing::RAX).unwrap()
+    };
+    assert_eq!(call("get", 0), 0x1234);
+    assert_eq!(call("get", 1), 0);
+    call("init", 0);
+    assert_eq!(call("get", 2), 0);
+    assert_eq!(call("get", 3), 0x1234);
+}
+
+#[test]
+fn bulk_memory() {
+    let src = r#"
+    (module
+      (memory 1 1)
+      (func (export "copy") (param i32 i32 i32)
+        local.get 0
+        local.get 1
+        local.get 2
+        memory.copy
+      )
+      (func (export "fill") (param i32 i32 i32)
+        local.get 0
+        local.get 1
+        local.get 2
+        memory.fill
+      )
+    )
+    "#;
+    let binary = wat::parse_str(src).expect("binary module");
+    let compile = |cpu: CpuFeatures| {
+        X86_64Compiler::builder()
+            .cpu_features(cpu)
+            .build()
+            .compile(&binary)
+            .expect("compiled module")
+    };
+    let mnemonics = |module: &AssembledModule, name: &str| {
+        let entry_point = module.function_entry_point(name).unwrap();
+        let mut decoder = iced_x86::Decoder::new(64, &module.binary()[entry_point..], 0);
+        let mut mnemonics = Vec::new();
+        while decoder.can_decode() {
+            let instruction = decoder.decode();
+            mnemonics.push(instruction.mnemonic());
+            if instruction.mnemonic() == iced_x86::Mnemonic::Ret {
+                break;
+            }
+        }
+        mnemonics
+    };
+    let avx = compile(CpuFeatures::x86_64_v3());
+    assert!(mnemonics(&avx, "copy").contains(&iced_x86::Mnemonic::Vmovdqu));
+    assert!(mnemonics(&avx, "fill").contains(&iced_x86::Mnemonic::Vmovdqu));
+
+    let erms = CpuFeatures {
+        erms: true,
+        ..CpuFeatures::baseline()
+    };
+    for cpu in [CpuFeatures::baseline(), erms] {
+        let mut emulator = Emulator::new().expect("emulator");
+        let initial = (0..256).map(|byte| byte as u8).collect::<Vec<_>>();
+        let memory = emulator.add_memory(&initial).expect("memory");
+        let emu_mod = emulator.add_module(compile(cpu)).expect("module addition");
+        // The last 16 bytes are out of bounds
+        emu_mod.borrow_mut().link_memory(0, memory, 240);
+
+        let mut expected = initial.clone();
+        let mut call = |name: &str, params: [u64; 3]| {
+            emulator.write_register(testing::RDI, params[0]).unwrap();
+            emulator.write_register(testing::RSI, params[1]).unwrap();
+            emulator.write_register(testing::RDX, params[2]).unwrap();
+            let result = emulator
+                .call_function(emu_mod.clone(), name)
+                .map_err(|_| last_trap(&emulator, &emu_mod).map(|trap| trap.code));
+            let mut contents = vec![0; 256];
+            emulator.read_memory(memory, &mut contents).unwrap();
+            (result, contents)
+        };
+        // Overlapping copies both ways, with and without a tail
+        for [dst, src, length] in [[10, 3, 37], [3, 10, 37], [100, 80, 32], [50, 60, 5]] {
+            let (result, contents) = call("copy", [dst, src, length]);
+            assert!(result.is_ok());
+            let (dst, src, length) = (dst as usize, src as usize, length as usize);
+            expected.copy_within(src..src + length, dst);
+            assert_eq!(contents, expected, "copy {} {} {}", dst, src, length);
+        }
+        for [dst, value, length] in [[7, 0x1AB, 53], [200, 1, 9], [240, 2, 0]] {
+            let (result, contents) = call("fill", [dst, value, length]);
+            assert!(result.is_ok());
+            let (dst, length) = (dst as usize, length as usize);
+            expected[dst..dst + length].fill(value as u8);
+            assert_eq!(contents, expected, "fill {} {} {}", dst, value, length);
+        }
+
+        // Nothing is written when any part is out of bounds
+        for params in [[0, 200, 41], [200, 0, 41], [0, 0, 1 << 31]] {
+            let (result, contents) = call("copy", params);
+            assert_eq!(result, Err(Some(TrapCode::MemoryOutOfBounds)));
+            assert_eq!(contents, expected);
+        }
+        let (result, contents) = call("fill", [200, 0, 41]);
+        assert_eq!(result, Err(Some(TrapCode::MemoryOutOfBounds)));
+        assert_eq!(contents, expected);
+    }
+}
+
+#[test]
+fn atomics() {
+    let src = r#"
+    (module
+      (memory 1 1 shared)
+      (func (export "load") (param i32) (result i64)
+        local.get 0
+        i64.atomic.load
+      )
+      (func (export "load8") (param i32) (result i32)
+        local.get 0
+        i32.atomic.load8_u
+      )
+      (func (export "store32") (param i32 i64)
+        local.get 0
+        local.get 1
+        i64.atomic.store32
+      )
+      (func (export "add") (param i32 i64) (result i64)
+        local.get 0
+        local.get 1
+        i64.atomic.rmw.add
+      )
+      (func (export "sub8") (param i32 i32) (result i32)
+        local.get 0
+        local.get 1
+        i32.atomic.rmw8.sub_u
+      )
+      (func (export "and16") (param i32 i64) (result i64)
+        local.get 0
+        local.get 1
+        i64.atomic.rmw16.and_u
+      )
+      (func (export "or") (param i32 i32) (result i32)
+        local.get 0
+        local.get 1
+        i32.atomic.rmw.or
+      )
+      (func (export "xor32") (param i32 i64) (result i64)
+        local.get 0
+        local.get 1
+        i64.atomic.rmw32.xor_u
+      )
+      (func (export "xchg8") (param i32 i64) (result i64)
+        local.get 0
+        local.get 1
+        i64.atomic.rmw8.xchg_u
+      )
+      (func (export "cmpxchg") (param i32 i64 i64) (result i64)
+        local.get 0
+        local.get 1
+        local.get 2
+        i64.atomic.rmw.cmpxchg
+      )
+      (func (export "cmpxchg16") (param i32 i32 i32) (result i32)
+        local.get 0
+        local.get 1
+        local.get 2
+        i32.atomic.rmw16.cmpxchg_u
+      )
+      (func (export "fence")
+        atomic.fence
+      )
+      (func (export "wait32") (param i32 i32 i64) (result i32)
+        local.get 0
+        local.get 1
+        local.get 2
+        memory.atomic.wait32
+      )
+      (func (export "wait64") (param i32 i64 i64) (result i32)
+        local.get 0
+        local.get 1
+        local.get 2
+        memory.atomic.wait64
+      )
+      (func (export "notify") (param i32 i32) (result i32)
+        local.get 0
+        local.get 1
+        memory.atomic.notify
+      )
+    )
+    "#;
+    let binary = wat::parse_str(src).expect("binary module");
+    let module = X86_64Compiler::default()
+        .compile(&binary)
+        .expect("compiled module");
+    let unshared_src = r#"
+    (module
+      (memory 1 1)
+      (func (export "wait32") (param i32 i32 i64) (result i32)
+        local.get 0
+        local.get 1
+        local.get 2
+        memory.atomic.wait32
+      )
+    )
+    "#;
+    let unshared_binary = wat::parse_str(unshared_src).expect("binary module");
+    let unshared_module = X86_64Compiler::default()
+        .compile(&unshared_binary)
+        .expect("compiled module");
+
+    let mut emulator = Emulator::new().expect("emulator");
+    // Returns 1 if the value differs, and the timeout otherwise
+    let mut assembler = CodeAssembler::new(64).expect("new assembler");
+    use iced_x86::code_asm::*;
+    let mut wide = assembler.create_label();
+    let mut compared = assembler.create_label();
+    assembler.cmp(r8, 4).expect("asm");
+    assembler.jne(wide).expect("asm");
+    assembler.cmp(dword_ptr(rsi), edx).expect("asm");
+    assembler.jmp(compared).expect("asm");
+    assembler.set_label(&mut wide).expect("asm");
+    assembler.cmp(qword_ptr(rsi), rdx).expect("asm");
+    assembler.set_label(&mut compared).expect("asm");
+    assembler.mov(eax, 1).expect("asm");
+    assembler.cmovz(eax, ecx).expect("asm");
+    assembler.ret().expect("asm");
+    let assembled = assembler.assemble(0).expect("asm");
+    let wait_hook = emulator.add_memory(&assembled).expect("wait hook");
+    // Returns the count plus the value at the address
+    let mut assembler = CodeAssembler::new(64).expect("new assembler");
+    assembler.mov(eax, edx).expect("asm");
+    assembler.add(eax, dword_ptr(rsi)).expect("asm");
+    assembler.ret().expect("asm");
+    let assembled = assembler.assemble(0).expect("asm");
+    let notify_hook = emulator.add_memory(&assembled).expect("notify hook");
+    let memory = emulator.add_memory(&[0; 65536]).expect("memory");
+
+    let emu_mod = emulator.add_module(module).expect("module addition");
+    emu_mod.borrow_mut().link_memory(0, memory, 65536);
+    let unshared = emulator
+        .add_module(unshared_module)
+        .expect("module addition");
+    unshared.borrow_mut().link_memory(0, memory, 65536);
+
+    let mut call = |module: &Rc<RefCell<testing::Module>>, name: &str, params: &[u64]| {
+        for (register, param) in [testing::RDI, testing::RSI, testing::RDX]
+            .into_iter()
+            .zip(params)
+        {
+            emulator.write_register(register, *param).unwrap();
+        }
+        emulator
+            .call_function(module.clone(), name)
+            .map(|_| emulator.read_register(testing::RAX).unwrap())
+            .map_err(|_| last_trap(&emulator, module).map(|trap| trap.code))
+    };
+    let main = &emu_mod;
+    assert!(call(main, "store32", &[8, 0x1_2345_6789]).is_ok());
+    assert_eq!(call(main, "load", &[8]), Ok(0x2345_6789));
+    assert_eq!(call(main, "add", &[8, 0x1_0000_0000]), Ok(0x2345_6789));
+    assert_eq!(call(main, "load", &[8]), Ok(0x1_2345_6789));
+    assert_eq!(call(main, "sub8", &[8, 0x8A]), Ok(0x89));
+    assert_eq!(call(main, "load8", &[8]), Ok(0xFF));
+    assert_eq!(call(main, "and16", &[8, 0xF0F0]), Ok(0x67FF));
+    assert_eq!(call(main, "or", &[8, 0xF]), Ok(0x2345_60F0));
+    assert_eq!(call(main, "load", &[8]), Ok(0x1_2345_60FF));
+    assert_eq!(call(main, "xor32", &[12, u64::MAX]), Ok(1));
+    assert_eq!(call(main, "xchg8", &[9, 0x1AB]), Ok(0x60));
+    assert_eq!(call(main, "load", &[8]), Ok(0xFFFF_FFFE_2345_ABFF));
+    assert!(call(main, "fence", &[]).is_ok());
+
+    assert_eq!(call(main, "cmpxchg", &[16, 0, 7]), Ok(0));
+    assert_eq!(call(main, "cmpxchg", &[16, 0, 9]), Ok(7));
+    // Only the lower bytes of the expected value are compared
+    assert_eq!(call(main, "cmpxchg16", &[16, 0x1_0007, 5]), Ok(7));
+    assert_eq!(call(main, "load", &[16]), Ok(5));
+
+    assert_eq!(
+        call(main, "load", &[4]),
+        Err(Some(TrapCode::UnalignedAtomic))
+    );
+    assert_eq!(
+        call(main, "add", &[9, 1]),
+        Err(Some(TrapCode::UnalignedAtomic))
+    );
+    assert_eq!(
+        call(main, "load", &[65536]),
+        Err(Some(TrapCode::MemoryOutOfBounds))
+    );
+
+    assert_eq!(
+        call(main, "wait32", &[16, 5, 0]),
+        Err(Some(TrapCode::CannotWait))
+    );
+    assert_eq!(call(main, "notify", &[16, 3]), Ok(0));
+    emu_mod.borrow_mut().link_memory_wait(0, wait_hook);
+    emu_mod.borrow_mut().link_memory_notify(0, notify_hook);
+    assert_eq!(call(main, "wait64", &[16, 5, 2]), Ok(2));
+    assert_eq!(call(main, "wait64", &[16, 6, 2]), Ok(1));
+    assert_eq!(call(main, "wait32", &[16, 0x1_0000_0005, 0]), Ok(0));
+    assert_eq!(
+        call(main, "wait32", &[18, 5, 0]),
+        Err(Some(TrapCode::UnalignedAtomic))
+    );
+    assert_eq!(call(main, "notify", &[16, 3]), Ok(8));
+    assert_eq!(
+        call(&unshared, "wait32", &[16, 5, 0]),
+        Err(Some(TrapCode::CannotWait))
+    );
+}
+
+#[test]
+fn simd() {
+    let src = r#"
+    (module
+      (memory 1 1)
+      (global $g (mut v128) (v128.const i32x4 1 2 3 4))
+      (func $pick (param v128 v128 i32) (result v128)
+        local.get 0
+        local.get 1
+        local.get 2
+        select
+      )
+      ;; The last four vectors are passed on the stack, results in the return area
+      (func $many
+        (param i64 v128 f64 v128 v128 v128 v128 v128 v128 v128 v128 v128 v128)
+        (result v128 i64 v128)
+        local.get 1
+        local.get 12
+        i32x4.add
+        local.get 0
+        local.get 2
+        i64.trunc_f64_s
+        i64.add
+        local.get 10
+        local.get 11
+        v128.xor
+      )
+      (func $sub (param v128 v128) (result v128)
+        local.get 0
+        local.get 1
+        i8x16.sub
+      )
+      (func $tail (param v128 v128) (result v128)
+        local.get 1
+        local.get 0
+        return_call $sub
+      )
+      (func (export "run") (param i32)
+        (local $r0 v128) (local $i i64) (local $r2 v128)
+        (v128.store (i32.const 0)
+          (call $pick (v128.const i32x4 1 1 1 1) (v128.const i32x4 2 2 2 2) (local.get 0)))
+        (call $many
+          (i64.const 40) (v128.const i32x4 1 2 3 4) (f64.const 2.5)
+          (v128.const i32x4 3 3 3 3) (v128.const i32x4 4 4 4 4) (v128.const i32x4 5 5 5 5)
+          (v128.const i32x4 6 6 6 6) (v128.const i32x4 7 7 7 7) (v128.const i32x4 8 8 8 8)
+          (v128.const i32x4 9 9 9 9) (v128.const i32x4 10 10 10 10)
+          (v128.const i32x4 11 11 11 11) (v128.const i32x4 12 12 12 12))
+        local.set $r2
+        local.set $i
+        local.set $r0
+        (v128.store (i32.const 16) (local.tee $r0 (local.get $r0)))
+        (i64.store (i32.const 32) (local.get $i))
+        (v128.store (i32.const 48) (local.get $r2))
+        (global.set $g (i32x4.mul (global.get $g) (global.get $g)))
+        (v128.store (i32.const 64)
+          (block (result v128)
+            (v128.const i32x4 7 7 7 7)
+            (br_if 0 (local.get 0))
+            drop
+            (global.get $g)))
+        (v128.store (i32.const 80)
+          (call $tail (i8x16.splat (i32.const 10)) (v128.const i8x16 3 3 3 3 3 3 3 3 3 3 3 3 3 3 3 3)))
+
+        ;; Edge cases
+        (v128.store (i32.const 96)
+          (i8x16.narrow_i16x8_u
+            (v128.const i16x8 -1 256 255 128 0 1 -32768 32767)
+            (v128.const i16x8 0 0 0 0 0 0 0 0)))
+        (v128.store (i32.const 112)
+          (f32x4.min (v128.const f32x4 -0 nan 1 0) (v128.const f32x4 0 1 nan -0)))
+        (v128.store (i32.const 128) (f32x4.nearest (v128.const f32x4 -0.5 2.5 -1.5 0x1p23)))
+        (v128.store (i32.const 144)
+          (i32x4.trunc_sat_f32x4_u (v128.const f32x4 nan -1 0x1p32 3e9)))
+        (v128.store (i32.const 160)
+          (i16x8.q15mulr_sat_s
+            (v128.const i16x8 -32768 -32768 16384 -1 0 0 0 0)
+            (v128.const i16x8 -32768 16384 16384 1 0 0 0 0)))
+        (v128.store (i32.const 176)
+          (i64x2.gt_s (v128.const i64x2 -1 0x7fffffffffffffff) (v128.const i64x2 1 -1)))
+        (v128.store (i32.const 192)
+          (i8x16.swizzle
+            (v128.const i8x16 0 1 2 3 4 5 6 7 8 9 10 11 12 13 14 15)
+            (v128.const i8x16 15 16 -128 1 255 0 0 0 0 0 0 0 0 0 0 14)))
+        (v128.store (i32.const 208)
+          (i8x16.shuffle 31 0 16 1 0 0 0 0 0 0 0 0 0 0 0 15
+            (v128.const i8x16 0 1 2 3 4 5 6 7 8 9 10 11 12 13 14 15)
+            (v128.const i8x16 16 17 18 19 20 21 22 23 24 25 26 27 28 29 30 31)))
+        (i32.store (i32.const 224)
+          (i8x16.bitmask (v128.const i8x16 -1 0 -128 127 0 0 0 0 0 0 0 0 0 0 0 -2)))
+        (i32.store (i32.const 228)
+          (i32x4.all_true (v128.const i32x4 1 -1 0x100 0x10000)))
+        (i32.store (i32.const 232)
+          (i64x2.all_true (v128.const i64x2 0x100000000 0)))
+      )
+    )
+    "#;
+    let binary = wat::parse_str(src).expect("binary module");
+    let module = X86_64Compiler::default()
+        .compile(&binary)
+        .expect("compiled module");
+
+    let mut emulator = Emulator::new().expect("emulator");
+    let memory = emulator.add_memory(&[0; 65536]).expect("memory");
+    let emu_mod = emulator.add_module(module).expect("module addition");
+    emu_mod.borrow_mut().link_memory(0, memory, 65536);
+    emulator
+        .initialize(emu_mod.clone())
+        .expect("initialization");
+
+    let lanes32 = |lanes: [u32; 4]| {
+        lanes
+            .iter()
+            .flat_map(|lane| lane.to_le_bytes())
+            .collect::<Vec<_>>()
+    };
+    let mut run = |condition: u64| {
+        emulator.write_register(testing::RDI, condition).unwrap();
+        emulator
+            .call_function(emu_mod.clone(), "run")
+            .expect("call");
+        let mut contents = vec![0; 240];
+        emulator.read_memory(memory, &mut contents).unwrap();
+        contents
+    };
+
+    let contents = run(1);
+    assert_eq!(contents[0..16], lanes32([1; 4]));
+    assert_eq!(contents[16..32], lanes32([13, 14, 15, 16]));
+    assert_eq!(contents[32..40], 42u64.to_le_bytes());
+    assert_eq!(contents[48..64], lanes32([1; 4]));
+    assert_eq!(contents[64..80], lanes32([7; 4]));
+    assert_eq!(contents[80..96], [0xF9; 16]);
+    assert_eq!(
+        contents[96..112],
+        [0, 255, 255, 128, 0, 1, 0, 255, 0, 0, 0, 0, 0, 0, 0, 0]
+    );
+    // NaNs are only checked to be NaNs
+    let f32_lanes = contents[112..128]
+        .chunks(4)
+        .map(|lane| f32::from_le_bytes(lane.try_into().unwrap()))
+        .collect::<Vec<_>>();
+    assert_eq!(f32_lanes[0].to_bits(), (-0.0f32).to_bits());
+    assert!(f32_lanes[1].is_nan() && f32_lanes[2].is_nan());
+    assert_eq!(f32_lanes[3].to_bits(), (-0.0f32).to_bits());
+    assert_eq!(
+        contents[128..144],
+        lanes32([
+            (-0.0f32).to_bits(),
+            2.0f32.to_bits(),
+            (-2.0f32).to_bits(),
+            8388608.0f32.to_bits()
+        ])
+    );
+    assert_eq!(contents[144..160], lanes32([0, 0, u32::MAX, 3_000_000_000]));
+    assert_eq!(
+        contents[160..176],
+        [0xFF, 0x7F, 0x00, 0xC0, 0x00, 0x20, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]
+    );
+    assert_eq!(contents[176..192], lanes32([0, 0, u32::MAX, u32::MAX]));
+    assert_eq!(
+        contents[192..208],
+        [15, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 14]
+    );
+    assert_eq!(
+        contents[208..224],
+        [31, 0, 16, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 15]
+    );
+    assert_eq!(contents[224..228], 0x8005u32.to_le_bytes());
+    assert_eq!(contents[228..232], 1u32.to_le_bytes());
+    assert_eq!(contents[232..236], 0u32.to_le_bytes());
+
+    // The global was squared again, and the other operand is picked
+    let contents = run(0);
+    assert_eq!(contents[0..16], lanes32([2; 4]));
+    assert_eq!(contents[64..80], lanes32([1, 16, 81, 256]));
+
+    // Extensions replace the SSE2 sequences
+    let src = r#"
+    (module
+      (func (export "foo") (param v128 v128 i32) (result v128)
+        local.get 0
+        local.get 1
+        i8x16.swizzle
+        f32x4.nearest
+        local.get 1
+        i32x4.min_s
+        local.get 1
+        i64x2.gt_s
+        local.get 2
+        i32x4.splat
+        i32x4.add
+      )
+    )
+    "#;
+    let binary = wat::parse_str(src).expect("binary module");
+    let mnemonics = |cpu: CpuFeatures| {
+        let module = X86_64Compiler::builder()
+            .cpu_features(cpu)
+            .build()
+            .compile(&binary)
+            .expect("compiled module");
+        let entry_point = module.function_entry_point("foo").unwrap();
+        let mut decoder = iced_x86::Decoder::new(64, &module.binary()[entry_point..], 0);
+        let mut mnemonics = Vec::new();
+        while decoder.can_decode() {
+            let instruction = decoder.decode();
+            mnemonics.push(instruction.mnemonic());
+            if instruction.mnemonic() == iced_x86::Mnemonic::Ret {
+                break;
+            }
+        }
+        mnemonics
+    };
+    let extensions = [
+        iced_x86::Mnemonic::Pshufb,
+        iced_x86::Mnemonic::Roundps,
+        iced_x86::Mnemonic::Pminsd,
+        iced_x86::Mnemonic::Pcmpgtq,
+        iced_x86::Mnemonic::Vpbroadcastd,
+    ];
+    let baseline = mnemonics(CpuFeatures::baseline());
+    let v3 = mnemonics(CpuFeatures::x86_64_v3());
+    for mnemonic in extensions {
+        assert!(!baseline.contains(&mnemonic), "{:?}", mnemonic);
+        assert!(v3.contains(&mnemonic), "{:?}", mnemonic);
+    }
+    assert!(v3.len() < baseline.len());
+}
+
+#[test]
+fn relaxed_simd() {
+    let src = r#"
+    (module
+      (memory 1 1)
+      (func (export "run")
+        ;; Results every implementation agrees on
+        (v128.store (i32.const 0)
+          (i8x16.swizzle_relaxed
+            (v128.const i8x16 10 11 12 13 14 15 16 17 18 19 20 21 22 23 24 25)
+            (v128.const i8x16 15 14 13 12 11 10 9 8 7 6 5 4 3 2 1 0)))
+        (v128.store (i32.const 16)
+          (i32x4.trunc_f32x4_u_relaxed (v128.const f32x4 1.5 3e9 0 7.9)))
+        (v128.store (i32.const 32)
+          (i32x4.trunc_f64x2_u_zero_relaxed (v128.const f64x2 1.5 4e9)))
+        (v128.store (i32.const 48)
+          (i32x4.trunc_f32x4_s_relaxed (v128.const f32x4 -1.5 2.5 -7.9 100)))
+        (v128.store (i32.const 64)
+          (i32x4.laneselect
+            (v128.const i32x4 1 2 3 4)
+            (v128.const i32x4 5 6 7 8)
+            (v128.const i32x4 -1 0 -1 0)))
+        (v128.store (i32.const 80)
+          (f32x4.fma_relaxed
+            (v128.const f32x4 2 2 2 2)
+            (v128.const f32x4 3 3 3 3)
+            (v128.const f32x4 4 4 4 4)))
+        (v128.store (i32.const 96)
+          (f64x2.fms_relaxed
+            (v128.const f64x2 2 2)
+            (v128.const f64x2 3 3)
+            (v128.const f64x2 4 4)))
+        (v128.store (i32.const 112)
+          (f32x4.min_relaxed (v128.const f32x4 1 -2 3 -4) (v128.const f32x4 0 5 -6 7)))
+        (v128.store (i32.const 128)
+          (f64x2.max_relaxed (v128.const f64x2 1 -2) (v128.const f64x2 0.5 5)))
+        ;; Implementation-defined results
+        (v128.store (i32.const 144)
+          (i8x16.swizzle_relaxed
+            (v128.const i8x16 10 11 12 13 14 15 16 17 18 19 20 21 22 23 24 25)
+            (v128.const i8x16 1 16 128 200 15 15 15 15 15 15 15 15 15 15 15 15)))
+        (v128.store (i32.const 160)
+          (i32x4.trunc_f32x4_s_relaxed (v128.const f32x4 nan 3e9 -3e9 inf)))
+        (v128.store (i32.const 176)
+          (i32x4.trunc_f64x2_u_zero_relaxed (v128.const f64x2 -1 5e9)))
+        (v128.store (i32.const 192)
+          (i32x4.laneselect
+            (v128.const i32x4 -1 -1 -1 -1)
+            (v128.const i32x4 0 0 0 0)
+            (v128.const i32x4 0xFF 0x80000000 0 -1)))
+        (v128.store (i32.const 208)
+          (f32x4.min_relaxed (v128.const f32x4 nan 0 -0 1) (v128.const f32x4 1 -0 0 nan)))
+        ;; The product rounds to 1 unless fused
+        (v128.store (i32.const 224)
+          (f64x2.fma_relaxed
+            (v128.const f64x2 -1 -1)
+            (v128.const f64x2 0x1.00000004p0 0)
+            (v128.const f64x2 0x1.fffffff8p-1 0)))
+      )
+    )
+    "#;
+    let binary = wat::parse_str(src).expect("binary module");
+    let run = |deterministic: bool| {
+        let module = X86_64Compiler::builder()
+            .deterministic_relaxed_simd(deterministic)
+            .build()
+            .compile(&binary)
+            .expect("compiled module");
+        let mut emulator = Emulator::new().expect("emulator");
+        let memory = emulator.add_memory(&[0; 65536]).expect("memory");
+        let emu_mod = emulator.add_module(module).expect("module addition");
+        emu_mod.borrow_mut().link_memory(0, memory, 65536);
+        emulator
+            .initialize(emu_mod.clone())
+            .expect("initialization");
+        emulator
+            .call_function(emu_mod.clone(), "run")
+            .expect("call");
+        let mut contents = vec![0; 240];
+        emulator.read_memory(memory, &mut contents).unwrap();
+        contents
+    };
+    let lanes32 = |lanes: [u32; 4]| {
+        lanes
+            .iter()
+            .flat_map(|lane| lane.to_le_bytes())
+            .collect::<Vec<_>>()
+    };
+    let f32_lanes = |contents: &[u8]| {
+        contents
+            .chunks(4)
+            .map(|lane| f32::from_le_bytes(lane.try_into().unwrap()))
+            .collect::<Vec<_>>()
+    };
+
+    for deterministic in [false, true] {
+        let contents = run(deterministic);
+        assert_eq!(
+            contents[0..16],
+            [25, 24, 23, 22, 21, 20, 19, 18, 17, 16, 15, 14, 13, 12, 11, 10]
+        );
+        assert_eq!(contents[16..32], lanes32([1, 3_000_000_000, 0, 7]));
+        assert_eq!(contents[32..48], lanes32([1, 4_000_000_000, 0, 0]));
+        assert_eq!(
+            contents[48..64],
+            lanes32([-1i32 as u32, 2, -7i32 as u32, 100])
+        );
+        assert_eq!(contents[64..80], lanes32([1, 6, 3, 8]));
+        assert_eq!(f32_lanes(&contents[80..96]), [14.0; 4]);
+        assert_eq!(contents[96..104], (-10.0f64).to_le_bytes());
+        assert_eq!(contents[104..112], (-10.0f64).to_le_bytes());
+        assert_eq!(f32_lanes(&contents[112..128]), [0.0, -2.0, -6.0, -4.0]);
+        assert_eq!(contents[128..136], 1.0f64.to_le_bytes());
+        assert_eq!(contents[136..144], 5.0f64.to_le_bytes());
+    }
+
+    // Deterministic results are those of the strict instructions
+    let contents = run(true);
+    assert_eq!(
+        contents[144..160],
+        [11, 0, 0, 0, 25, 25, 25, 25, 25, 25, 25, 25, 25, 25, 25, 25]
+    );
+    assert_eq!(
+        contents[160..176],
+        lanes32([0, i32::MAX as u32, i32::MIN as u32, i32::MAX as u32])
+    );
+    assert_eq!(contents[176..192], lanes32([0, u32::MAX, 0, 0]));
+    assert_eq!(
+        contents[192..208],
+        lanes32([0xFF, 0x8000_0000, 0, u32::MAX])
+    );
+    let min = f32_lanes(&contents[208..224]);
+    assert!(min[0].is_nan() && min[3].is_nan());
+    assert_eq!(min[1].to_bits(), (-0.0f32).to_bits());
+    assert_eq!(min[2].to_bits(), (-0.0f32).to_bits());
+    assert_eq!(contents[224..232], 0.0f64.to_le_bytes());
+    assert_eq!(contents[232..240], (-1.0f64).to_le_bytes());
+
+    // Fast forms use FMA and blends, deterministic ones never do
+    let src = r#"
+    (module
+      (func (export "foo") (param v128 v128 v128) (result v128)
+        local.get 0
+        local.get 1
+        local.get 2
+        f32x4.fma_relaxed
+        local.get 1
+        local.get 2
+        i8x16.laneselect
+        local.get 2
+        i8x16.swizzle_relaxed
+      )
+    )
+    "#;
+    let binary = wat::parse_str(src).expect("binary module");
+    let mnemonics = |deterministic: bool| {
+        let module = X86_64Compiler::builder()
+            .cpu_features(CpuFeatures::x86_64_v3())
+            .deterministic_relaxed_simd(deterministic)
+            .build()
+            .compile(&binary)
+            .expect("compiled module");
+        let entry_point = module.function_entry_point("foo").unwrap();
+        let mut decoder = iced_x86::Decoder::new(64, &module.binary()[entry_point..], 0);
+        let mut mnemonics = Vec::new();
+        while decoder.can_decode() {
+            let instruction = decoder.decode();
+            mnemonics.push(instruction.mnemonic());
+            if instruction.mnemonic() == iced_x86::Mnemonic::Ret {
+                break;
+            }
+        }
+        mnemonics
+    };
+    let fast = mnemonics(false);
+    let deterministic = mnemonics(true);
+    for mnemonic in [
+        iced_x86::Mnemonic::Vfmadd231ps,
+        iced_x86::Mnemonic::Pblendvb,
+    ] {
+        assert!(fast.contains(&mnemonic), "{:?}", mnemonic);
+        assert!(!deterministic.contains(&mnemonic), "{:?}", mnemonic);
+    }
+    // Out of range indices are only saturated for the strict swizzle
+    assert!(!fast.contains(&iced_x86::Mnemonic::Paddusb));
+    assert!(deterministic.contains(&iced_x86::Mnemonic::Paddusb));
+}